@@ -0,0 +1,50 @@
+// What resolving on_midi_recv once (see Callbacks in lib.rs) saves over
+// looking it up in the globals for every message, run with:
+//   cargo bench --bench dispatch
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+const SCRIPT: &str = r#"
+local count = 0
+function on_midi_recv(evt)
+    count = count + evt.key
+end
+"#;
+
+// Roughly what dispatch_message builds for a note on
+fn note_on(lua: &mlua::Lua) -> mlua::Table<'_> {
+    let evt = lua.create_table().unwrap();
+    evt.set("event", "note_on").unwrap();
+    evt.set("channel", 1).unwrap();
+    evt.set("key", 60).unwrap();
+    evt.set("vel", 100).unwrap();
+    evt
+}
+
+fn dispatch(c: &mut Criterion) {
+    let lua = mlua::Lua::new();
+    lua.load(SCRIPT).exec().unwrap();
+
+    c.bench_function("looked up per message", |b| b.iter(|| {
+        let f: Option<mlua::Function> = lua.globals().get("on_midi_recv").unwrap();
+        if let Some(f) = f {
+            f.call::<_, ()>(note_on(&lua)).unwrap();
+        }
+    }));
+
+    let key = lua.create_registry_value(lua.globals().get::<_, mlua::Function>("on_midi_recv").unwrap()).unwrap();
+    c.bench_function("resolved once", |b| b.iter(|| {
+        let f: mlua::Function = lua.registry_value(&key).unwrap();
+        f.call::<_, ()>(note_on(&lua)).unwrap();
+    }));
+
+    // Clock ticks with no on_midi_clock, where the lookup was all there was to it
+    c.bench_function("missing, looked up per message", |b| b.iter(|| {
+        lua.globals().get::<_, Option<mlua::Function>>("on_midi_clock").unwrap()
+    }));
+    let missing: Option<mlua::RegistryKey> = None;
+    c.bench_function("missing, resolved once", |b| b.iter(|| missing.is_some()));
+}
+
+criterion_group!(benches, dispatch);
+criterion_main!(benches);
\ No newline at end of file
@@ -0,0 +1,17 @@
+-- Holds space for 100ms on every note, however long the note is held
+-- Timer callbacks run on the same thread as on_midi_recv, so there's no need to lock anything
+
+local keys = require("keys")
+
+function on_script_init()
+    midi.open(0)
+end
+
+function on_midi_recv(evt)
+    if evt.event == "note_on" then
+        keyboard.press(keys.SPACE)
+        misc.schedule(100, function()
+            keyboard.release(keys.SPACE)
+        end)
+    end
+end
\ No newline at end of file
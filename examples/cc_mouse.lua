@@ -0,0 +1,21 @@
+-- Moves the mouse with two knobs
+-- CC 1 drives the X axis and CC 2 drives the Y axis, centred on 64
+
+function on_script_init()
+    midi.open(0)
+end
+
+function on_midi_recv(evt)
+    if evt.event == "control_change" then
+        local speed = (evt.value - 64) / 8
+        if evt.control == 1 then
+            mouse.move(math.floor(speed), 0)
+        elseif evt.control == 2 then
+            mouse.move(0, math.floor(speed))
+        end
+    end
+
+    if evt.event == "note_on" then
+        mouse.click(0)
+    end
+end
@@ -0,0 +1,18 @@
+-- Two knobs (CC 1 and CC 2) as the left stick, centred on 64 with a little dead-zone
+-- so a knob that's roughly in the middle doesn't drift
+
+local pad
+
+function on_script_init()
+    midi.open(0)
+    pad = gamepad.create()
+    for _, axis in ipairs({ gamepad.ABS.X, gamepad.ABS.Y }) do
+        pad.set_axis_config(axis, { center = 64, deadzone = 4, sensitivity = 1.0 })
+    end
+    midi.on_cc(1, function(evt)
+        pad.axis(gamepad.ABS.X, evt.value)
+    end)
+    midi.on_cc(2, function(evt)
+        pad.axis(gamepad.ABS.Y, evt.value)
+    end)
+end
\ No newline at end of file
@@ -0,0 +1,15 @@
+-- Only reacts to notes on the channel given with --arg channel=N (default 1)
+-- e.g. handcake -s examples/channel_filter.lua --arg channel=10
+
+local keys = require("keys")
+local channel = tonumber(args.channel) or 1
+
+function on_script_init()
+    midi.open(0)
+    log.info("Listening on channel", channel)
+    midi.on_channel(channel, function(evt)
+        if evt.is_note then
+            keys.follow(keys.SPACE, evt)
+        end
+    end)
+end
\ No newline at end of file
@@ -0,0 +1,25 @@
+-- Holds the A (south) button for as long as a C major chord (C4, E4, G4 on channel 1) is held
+
+local CHORD = {60, 64, 67}
+local pad
+
+local function chord_held()
+    for _, key in ipairs(CHORD) do
+        if not midi.is_held(1, key) then
+            return false
+        end
+    end
+    return true
+end
+
+function on_script_init()
+    midi.open(0)
+    pad = gamepad.create()
+end
+
+function on_midi_recv(evt)
+    -- Only when it changes, not for every note played on top of the chord
+    if evt.is_note and pad.button_state(gamepad.BTN.SOUTH) ~= chord_held() then
+        pad.button(gamepad.BTN.SOUTH, chord_held())
+    end
+end
\ No newline at end of file
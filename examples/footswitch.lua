@@ -0,0 +1,24 @@
+-- A sustain pedal (CC 64) that holds down space, and a mod wheel (CC 1) that scrolls
+-- Pedals are 0 or 127, but some stop short of that, hence 64 rather than 127
+
+local keys = require("keys")
+
+local PEDAL = 64
+local WHEEL = 1
+
+local wheel = 0
+
+function on_script_init()
+    midi.open(0)
+    midi.on_cc_threshold(PEDAL, 64, "rising", function()
+        keyboard.press(keys.SPACE)
+    end)
+    midi.on_cc_threshold(PEDAL, 64, "falling", function()
+        keyboard.release(keys.SPACE)
+    end)
+    -- Wheels send the same value a lot while they're being held still
+    midi.on_cc_change(WHEEL, function(evt)
+        mouse.scroll(evt.value - wheel)
+        wheel = evt.value
+    end)
+end
\ No newline at end of file
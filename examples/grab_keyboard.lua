@@ -0,0 +1,28 @@
+-- A DAW controller that also shows up as a keyboard, sending shortcuts of its own on top of MIDI.
+-- Its keyboard is grabbed so only handcake's keys get through, the transport notes press space.
+--   handcake --script examples/grab_keyboard.lua --arg keyboard=/dev/input/by-id/usb-Controller-event-kbd
+
+local keys = require("keys")
+
+local KEYBOARD = args.keyboard
+
+function on_script_init()
+    midi.open(0)
+    if KEYBOARD then
+        evdev.grab(KEYBOARD)
+    end
+end
+
+function on_midi_recv(evt)
+    -- Play
+    if evt.key == 94 then
+        keys.follow(keys.SPACE, evt)
+    end
+end
+
+function on_script_exit()
+    -- Happens by itself too, this is only so it's in the log
+    if KEYBOARD then
+        evdev.release(KEYBOARD)
+    end
+end
\ No newline at end of file
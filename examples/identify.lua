@@ -0,0 +1,41 @@
+-- Asks whatever is on --midi-out who it is, and picks a note layout to match
+--   handcake --script examples/identify.lua --midi-out Launchpad
+
+local NOVATION = 0x002029
+
+-- Pads in the bottom row, depending on who answered
+local LAYOUTS = {
+    novation = { 11, 12, 13, 14 },
+    default = { 36, 37, 38, 39 },
+}
+
+local pads = LAYOUTS.default
+
+local function choose_layout(identity)
+    log.info(string.format("%s is manufacturer %06X, family %d, member %d",
+        identity.device, identity.manufacturer_id, identity.device_family, identity.device_member))
+    pads = identity.manufacturer_id == NOVATION and LAYOUTS.novation or LAYOUTS.default
+end
+
+function on_script_init()
+    midi.open(0)
+    midi.on_identity(choose_layout)
+    -- Fails without --midi-out, the default layout is fine then
+    if not pcall(midi.send_identity_request) then
+        log.info("No --midi-out, using the default layout")
+    end
+end
+
+function on_midi_recv(evt)
+    for i, pad in ipairs(pads) do
+        if evt.event == "note_on" and evt.key == pad then
+            log.info("Pad", i)
+        end
+    end
+end
+
+-- For the tests
+return {
+    choose_layout = choose_layout,
+    pads = function() return pads end,
+}
\ No newline at end of file
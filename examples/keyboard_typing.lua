@@ -0,0 +1,17 @@
+-- Types "hello" whenever a note is pressed
+
+local keys = require("keys")
+
+local word = {keys.H, keys.E, keys.L, keys.L, keys.O}
+
+function on_script_init()
+    midi.open(0)
+end
+
+function on_midi_recv(evt)
+    if evt.event == "note_on" then
+        for _, key in ipairs(word) do
+            keyboard.tap(key)
+        end
+    end
+end
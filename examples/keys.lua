@@ -0,0 +1,29 @@
+-- Shared by the keyboard examples, loaded with require("keys")
+-- Keycodes are the ones from linux/input-event-codes.h
+
+local keys = {
+    E = 18,
+    O = 24,
+    A = 30,
+    S = 31,
+    D = 32,
+    F = 33,
+    H = 35,
+    L = 38,
+    SPACE = 57,
+    UP = 103,
+    LEFT = 105,
+    RIGHT = 106,
+    DOWN = 108,
+}
+
+-- Presses key on note_on and lets go of it on note_off
+function keys.follow(key, evt)
+    if evt.event == "note_on" then
+        keyboard.press(key)
+    elseif evt.event == "note_off" then
+        keyboard.release(key)
+    end
+end
+
+return keys
\ No newline at end of file
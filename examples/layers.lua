@@ -0,0 +1,28 @@
+-- Four pads that are arrow keys on one layer and ASDF on the other, program
+-- change 1 or 2 (0 and 1 on the wire) picks which
+
+local keys = require("keys")
+
+local PADS = { 36, 37, 38, 39 }
+
+local LAYERS = {
+    [0] = { keys.LEFT, keys.DOWN, keys.UP, keys.RIGHT },
+    [1] = { keys.A, keys.S, keys.D, keys.F },
+}
+
+function on_script_init()
+    midi.open(0)
+    midi.set_auto_layer(2)
+    -- Called before the switch, so this is still the old layer
+    midi.on_program_change(function()
+        log.info("Leaving layer", midi.get_layer())
+    end)
+end
+
+function on_midi_recv(evt)
+    for i, pad in ipairs(PADS) do
+        if evt.key == pad then
+            keys.follow(LAYERS[evt.layer][i], evt)
+        end
+    end
+end
\ No newline at end of file
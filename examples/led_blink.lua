@@ -0,0 +1,33 @@
+-- Flashes a pad LED at 4Hz while a note is held, by sending notes back to the controller
+-- Run with --midi-out pointed at the controller. Which note lights which pad depends on the controller.
+
+-- Each flash is lit for half of the 250ms between them
+local FLASH_MS = 125
+
+local blinker = nil
+local unlight = nil
+
+function on_script_init()
+    midi.open(0)
+end
+
+function on_midi_recv(evt)
+    if evt.event == "note_on" and blinker == nil then
+        local channel, key = evt.channel, evt.key
+        blinker = misc.interval(250, function()
+            midi_out.note_on(channel, key, 127)
+            unlight = misc.schedule(FLASH_MS, function()
+                unlight = nil
+                midi_out.note_off(channel, key)
+            end)
+        end)
+    elseif evt.event == "note_off" and blinker ~= nil then
+        misc.clear_interval(blinker)
+        blinker = nil
+        if unlight ~= nil then
+            misc.cancel(unlight)
+            unlight = nil
+        end
+        midi_out.note_off(evt.channel, evt.key)
+    end
+end
\ No newline at end of file
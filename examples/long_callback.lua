@@ -0,0 +1,55 @@
+-- Finds which of a long list of phrases was played, without holding everything else up while it looks.
+-- Every phrase is checked against the last notes played, on each note on.
+
+local keys = require("keys")
+
+-- A made up list, as long as a real one loaded from a file might be
+local PHRASES = {}
+for i = 1, 5000 do
+    PHRASES[i] = { 60 + i % 12, 62 + i % 7, 64 + i % 5 }
+end
+
+local played = {}
+
+function on_script_init()
+    midi.open(0)
+end
+
+local function matches(phrase)
+    for i, key in ipairs(phrase) do
+        if played[#played - #phrase + i] ~= key then
+            return false
+        end
+    end
+    return true
+end
+
+-- Returns the index of the first phrase that matches, or nil
+local function find_phrase()
+    for i, phrase in ipairs(PHRASES) do
+        if matches(phrase) then
+            return i
+        end
+        if i % 500 == 0 then
+            -- Shutting down, don't keep on_script_exit waiting
+            if misc.check_signals() then
+                return nil
+            end
+            misc.yield_now()
+        end
+    end
+    return nil
+end
+
+function on_midi_recv(evt)
+    if evt.event ~= "note_on" then
+        return
+    end
+    table.insert(played, evt.key)
+    if #played > 3 then
+        table.remove(played, 1)
+    end
+    if find_phrase() then
+        keyboard.tap(keys.SPACE)
+    end
+end
\ No newline at end of file
@@ -0,0 +1,25 @@
+-- Holds space while whichever pad or button gets touched first after startup is held
+
+local KEY_SPACE = 57
+local learned = nil
+
+function on_script_init()
+    midi.open(0)
+    log.info("Press a pad to map it")
+    midi.learn(function(evt)
+        if evt.is_note then
+            learned = evt.key
+            log.info("Mapped note", midi.note_name(evt.key))
+        end
+    end)
+end
+
+function on_midi_recv(evt)
+    if evt.is_note and evt.key == learned then
+        if evt.event == "note_on" then
+            keyboard.press(KEY_SPACE)
+        else
+            keyboard.release(KEY_SPACE)
+        end
+    end
+end
\ No newline at end of file
@@ -0,0 +1,17 @@
+-- Everything on channels 1 and 2 goes on to a synth, and C4 on any channel also presses space
+--   handcake --script examples/midi_thru.lua --midi-out "Synth"
+
+local keys = require("keys")
+
+function on_script_init()
+    midi.open(0)
+    midi.set_thru(true)
+    -- Bits 0 and 1, channels 1 and 2
+    midi.set_thru_filter(0x0003)
+end
+
+function on_midi_recv(evt)
+    if evt.key == 60 then
+        keys.follow(keys.SPACE, evt)
+    end
+end
\ No newline at end of file
@@ -0,0 +1,32 @@
+-- An MPE controller as a gamepad: the last finger down steers, bending left and right is the left
+-- stick's X, sliding up and down its Y, and pressing harder is the right trigger
+
+local pad
+-- Member channel of the finger that's steering
+local steering
+
+function on_script_init()
+    midi.open(0)
+    pad = gamepad.create()
+    -- Lower zone over all 16 channels, what most controllers do out of the box
+    midi.enable_mpe(1, 2, 16)
+end
+
+function on_mpe_note(note)
+    if note.held then
+        steering = note.channel
+    elseif note.channel == steering then
+        steering = nil
+        pad.axis(gamepad.ABS.X, 0)
+        pad.axis(gamepad.ABS.Y, 0)
+        pad.trigger("right", 0)
+        return
+    end
+    if note.channel ~= steering then
+        return
+    end
+    pad.axis(gamepad.ABS.X, midi.pitch_bend_to_f32(note.pitch_bend))
+    -- Up the key is up on the stick
+    pad.axis(gamepad.ABS.Y, (64 - note.slide) / 64)
+    pad.trigger("right", note.pressure * gamepad.TRIGGER_MAX // 127)
+end
\ No newline at end of file
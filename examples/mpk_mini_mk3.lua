@@ -46,10 +46,10 @@ function on_midi_recv(evt)
             pad.button(gamepad.BTN_LB, evt.event == "note_on")
         end
         if evt.key == 42 then
-            pad.axis(gamepad.AXIS_RTRIGGER, (evt.event == "note_on") and 1.0 or -1.0)
+            pad.axis(gamepad.AXIS_RTRIGGER, (evt.event == "note_on") and 1.0 or 0.0)
         end
         if evt.key == 54 then
-            pad.axis(gamepad.AXIS_LTRIGGER, (evt.event == "note_on") and 1.0 or -1.0)
+            pad.axis(gamepad.AXIS_LTRIGGER, (evt.event == "note_on") and 1.0 or 0.0)
         end
     end
 
@@ -0,0 +1,11 @@
+-- Forwards every note to localhost:9000 as /midi/note_on and /midi/note_off OSC messages
+
+function on_script_init()
+    midi.open(0)
+end
+
+function on_midi_recv(evt)
+    if evt.is_note then
+        osc.send("localhost", 9000, "/midi/" .. evt.event, evt.channel, evt.key, evt.vel)
+    end
+end
\ No newline at end of file
@@ -0,0 +1,23 @@
+-- Drum pads as gamepad buttons: a pad only presses its button when it's hit harder than halfway,
+-- so resting a hand on them (or crosstalk from the next pad) doesn't do anything
+
+local pad
+
+-- Notes the pads on channel 10 send, by button
+local BUTTONS = { [36] = gamepad.BTN.SOUTH, [38] = gamepad.BTN.EAST, [42] = gamepad.BTN.WEST }
+
+function on_script_init()
+    midi.open(0)
+    pad = gamepad.create()
+    midi.set_note_threshold(10, 64)
+    midi.set_note_velocity_mode("gate", 10)
+end
+
+function on_midi_recv(evt)
+    -- Every note that gets here has velocity 127, the soft ones never make it
+    local button = BUTTONS[evt.key]
+    if not button or evt.channel ~= 10 then
+        return
+    end
+    pad.button(button, evt.event == "note_on")
+end
\ No newline at end of file
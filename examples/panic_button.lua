@@ -0,0 +1,32 @@
+-- The top right pad (note 99) as a panic button, for when something's stuck.
+-- From another terminal, handcake --panic --ipc-socket /tmp/handcake.sock does the same.
+--   handcake --script examples/panic_button.lua --midi-out 0 --ipc-socket /tmp/handcake.sock
+
+local keys = require("keys")
+
+local PANIC = 99
+
+local pad
+
+function on_script_init()
+    midi.open(0)
+    pad = gamepad.create()
+end
+
+function on_midi_recv(evt)
+    if evt.key == PANIC then
+        if evt.event == "note_on" then
+            keyboard.release_all()
+            gamepad.release_all()
+            -- Only works with --midi-out
+            pcall(midi.panic)
+        end
+        return
+    end
+    -- Everything else holds down a key or a button, which is how things get stuck
+    if evt.key == 60 then
+        keys.follow(keys.SPACE, evt)
+    elseif evt.key == 62 then
+        pad.button(gamepad.BTN.SOUTH, evt.event == "note_on")
+    end
+end
\ No newline at end of file
@@ -0,0 +1,39 @@
+-- A Launchpad for arrow keys and a KeyStep for ASDF, each with its own handler.
+-- Any other device is a space bar.
+-- Open both, e.g.:
+--   handcake --script examples/per_device.lua --midi-device Launchpad --midi-device KeyStep
+
+local keys = require("keys")
+
+-- Bottom left pads on a Launchpad in programmer mode
+local ARROWS = {
+    [11] = keys.LEFT,
+    [12] = keys.DOWN,
+    [13] = keys.RIGHT,
+    [22] = keys.UP,
+}
+
+local TYPING = {
+    [60] = keys.A,
+    [62] = keys.S,
+    [64] = keys.D,
+    [65] = keys.F,
+}
+
+function on_script_init()
+    -- Lua patterns, matched anywhere in the device's name
+    midi.on_device("Launchpad", function(evt)
+        if ARROWS[evt.key] then
+            keys.follow(ARROWS[evt.key], evt)
+        end
+    end)
+    midi.on_device("KeyStep", function(evt)
+        if TYPING[evt.key] then
+            keys.follow(TYPING[evt.key], evt)
+        end
+    end)
+end
+
+function on_midi_recv(evt)
+    keys.follow(keys.SPACE, evt)
+end
\ No newline at end of file
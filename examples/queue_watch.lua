@@ -0,0 +1,44 @@
+-- Warns when the script is falling behind its input, e.g. because on_midi_recv does too much per message
+--   handcake --script examples/queue_watch.lua --queue-depth 256
+
+local keys = require("keys")
+
+-- A quarter of --queue-depth, well before anything gets dropped
+local WARN_AT = 64
+
+local warned = false
+local last_dropped = 0
+
+function on_script_init()
+    midi.open(0)
+end
+
+-- Warns once the queue gets to warn_at, returning the warning it logged if it logged one
+local function check_queue(warn_at)
+    local stats = misc.queue_stats()
+    local message
+    if stats.dropped > last_dropped then
+        message = string.format("%d MIDI messages dropped", stats.dropped - last_dropped)
+        last_dropped = stats.dropped
+    elseif stats.depth >= warn_at and not warned then
+        message = string.format("%d messages waiting, %d handled so far", stats.depth, stats.processed)
+    end
+    -- Once per backlog, not for every message in it
+    warned = stats.depth >= warn_at
+    if message then
+        log.warn(message)
+    end
+    return message
+end
+
+function on_midi_recv(evt)
+    check_queue(WARN_AT)
+    if evt.key == 60 then
+        keys.follow(keys.SPACE, evt)
+    end
+end
+
+-- For the tests
+return {
+    check_queue = check_queue,
+}
\ No newline at end of file
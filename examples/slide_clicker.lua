@@ -0,0 +1,22 @@
+-- One pad as a presentation clicker: tap it for the next slide, hold it for
+-- half a second or more to go back
+
+local keys = require("keys")
+
+local PAD = 36
+local HOLD_MS = 500
+
+function on_script_init()
+    midi.open(0)
+    -- Goes off when the pad's let go, so it knows how long it was held
+    midi.on_note_duration(function(evt)
+        if evt.key ~= PAD then
+            return
+        end
+        if evt.duration_ms >= HOLD_MS then
+            keyboard.tap(keys.LEFT)
+        else
+            keyboard.tap(keys.RIGHT)
+        end
+    end)
+end
\ No newline at end of file
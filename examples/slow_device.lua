@@ -0,0 +1,11 @@
+-- A controller that needs half a second after it's opened before it listens, so the LED it's sent
+-- from on_script_init doesn't get lost
+--   handcake --script examples/slow_device.lua --midi-out "MPK mini 3"
+
+midi.open(0)
+misc.set_init_delay(500)
+
+function on_script_init()
+    -- Pad 1's LED on, now that the device is ready for it
+    midi_out.note_on(1, 36, 127)
+end
\ No newline at end of file
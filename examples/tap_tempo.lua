@@ -0,0 +1,30 @@
+-- Tap a pad a few times to set a tempo, then space gets pressed on every beat
+
+local keys = require("keys")
+
+local TAP_CHANNEL = 10
+local TAP_NOTE = 36
+
+local beat
+
+function on_script_init()
+    midi.open(0)
+    midi.tap_tempo(TAP_CHANNEL, TAP_NOTE)
+end
+
+function on_midi_recv(evt)
+    if evt.event ~= "note_on" or evt.channel ~= TAP_CHANNEL or evt.key ~= TAP_NOTE then
+        return
+    end
+
+    local bpm = midi.tap_bpm()
+    if bpm then
+        -- Starting it again on the tap keeps it on the beat as well as the tempo
+        if beat then
+            misc.clear_interval(beat)
+        end
+        beat = misc.interval(math.floor(60000 / bpm), function()
+            keyboard.tap(keys.SPACE)
+        end)
+    end
+end
\ No newline at end of file
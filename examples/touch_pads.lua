@@ -0,0 +1,20 @@
+-- Each of the first 10 pads (notes 36 to 45) puts a finger down along the middle of the screen
+-- Velocity sets the pressure
+
+function on_script_init()
+    midi.open(0)
+end
+
+function on_midi_recv(evt)
+    if not evt.is_note or evt.key < 36 or evt.key > 45 then
+        return
+    end
+
+    local id = evt.key - 36
+    if evt.event == "note_on" then
+        local x = math.floor((id + 0.5) * touch.width / 10)
+        touch.down(id, x, touch.height // 2, evt.vel * 2)
+    else
+        touch.up(id)
+    end
+end
@@ -0,0 +1,16 @@
+-- Echoes every note back out a semitone higher
+-- Run with --midi-out to pick where the notes go
+
+function on_script_init()
+    midi.open(0)
+end
+
+function on_midi_recv(evt)
+    if evt.is_note and evt.key < 127 then
+        if evt.event == "note_on" then
+            midi_out.note_on(evt.channel, evt.key + 1, evt.vel)
+        else
+            midi_out.note_off(evt.channel, evt.key + 1, evt.vel)
+        end
+    end
+end
@@ -0,0 +1,17 @@
+-- The right trigger follows how hard the last 8 notes were played, so playing louder
+-- speeds up and a single stray note doesn't slam it
+
+local window = buffer.new(8)
+local pad
+
+function on_script_init()
+    midi.open(0)
+    pad = gamepad.create()
+end
+
+function on_midi_recv(evt)
+    if evt.event == "note_on" and evt.vel > 0 then
+        window:push(evt.vel)
+        pad.trigger("right", math.floor(window:average() * gamepad.TRIGGER_MAX / 127))
+    end
+end
\ No newline at end of file
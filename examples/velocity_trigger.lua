@@ -0,0 +1,23 @@
+-- Racing game pedals: how hard C4 is hit is the throttle (right trigger), D4 the brake (left trigger)
+
+local pad
+
+function on_script_init()
+    midi.open(0)
+    pad = gamepad.create()
+end
+
+local TRIGGERS = { [60] = "right", [62] = "left" }
+
+function on_midi_recv(evt)
+    local which = TRIGGERS[evt.key]
+    if not which then
+        return
+    end
+    if evt.event == "note_on" then
+        -- Velocity is 0-127, triggers are 0-255
+        pad.trigger(which, evt.vel * gamepad.TRIGGER_MAX // 127)
+    elseif evt.event == "note_off" then
+        pad.trigger(which, 0)
+    end
+end
\ No newline at end of file
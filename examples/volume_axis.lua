@@ -0,0 +1,13 @@
+-- Maps the volume knob (CC 7) to the gamepad's left trigger (Z axis), with a curve so small turns are finer
+
+local pad
+
+function on_script_init()
+    midi.open(0)
+    pad = gamepad.create()
+    midi.on_cc(7, function(evt)
+        local v = midi.curve(midi.map(evt.value, 0, 127, 0.0, 1.0), 2.0)
+        -- Triggers go 0.0 to 1.0, not -1.0 to 1.0 like the sticks
+        pad.axis(gamepad.ABS_Z, v)
+    end)
+end
\ No newline at end of file
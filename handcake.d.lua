@@ -0,0 +1,705 @@
+---@meta
+-- Type annotations for the globals handcake gives scripts, for lua-language-server.
+-- Nothing in here runs, see examples/.luarc.json for how to use it.
+-- Keep this in sync with src/api (and api_schema.json, see cargo xtask api-schema) when adding or changing functions.
+
+---@class MidiEvent
+---@field device string The MIDI input (or port, serial device, session) the message came from
+---@field event "note_on"|"note_off"|"poly_aftertouch"|"control_change"|"program_change"|"channel_pressure"|"pitch_bend"|"sysex"|"timing_clock"|"start"|"continue"|"stop"
+---@field channel? integer 1 to 16
+---@field key? integer Note number, for notes and poly_aftertouch
+---@field vel? integer Velocity, after any velocity curve
+---@field is_note? boolean
+---@field control? integer CC number
+---@field value? integer CC value (after smoothing), pressure, or pitch bend from 0 to 16383
+---@field program? integer
+---@field data? integer[] SysEx bytes, without F0 and F7
+---@field layer integer See midi.set_layer
+---@field duration_ms? integer How long the note was held, for note offs
+
+---@class MidiIdentity
+---@field device string Where the reply came from, like evt.device
+---@field manufacturer_id integer One byte, or three for e.g. 0x002029
+---@field device_family integer
+---@field device_member integer
+---@field revision integer[] Four bytes
+
+---@class EvdevEvent
+---@field device string
+---@field type_ integer
+---@field code integer
+---@field value integer
+
+---@class HeldNote
+---@field channel integer
+---@field key integer
+---@field vel integer
+
+---One finger on an MPE controller, see midi.enable_mpe
+---@class MpeNote
+---@field channel integer The member channel the note is on
+---@field key integer
+---@field vel integer
+---@field pressure integer 0-127, channel pressure
+---@field slide integer 0-127, CC 74, 64 until the controller sends one
+---@field pitch_bend integer 0-16383, 8192 is the middle
+---@field held boolean false for the note off
+
+---@alias HandlerMode "exclusive"|"also_global"
+
+-- Callbacks, all optional
+
+---Called once after the script has been loaded
+function on_script_init() end
+
+---Called before shutting down
+function on_script_exit() end
+
+---Called on the old script before a reload replaces it
+function on_script_reload() end
+
+---@param evt MidiEvent
+function on_midi_recv(evt) end
+
+---Called with notes played close together, see midi.set_chord_window
+---@param notes HeldNote[]
+function on_chord(notes) end
+
+---Called for every change to a note in the MPE zone, see midi.enable_mpe
+---@param note MpeNote
+function on_mpe_note(note) end
+
+---Called 24 times per beat while a MIDI clock is running
+---@param evt MidiEvent
+function on_midi_clock(evt) end
+
+---@param evt EvdevEvent
+function on_evdev_recv(evt) end
+
+---@param address string
+---@param args any[]
+function on_osc_recv(address, args) end
+
+---Called for every JSON message read from --fifo-path
+---@param data any
+function on_external(data) end
+
+---Called for every message another instance sent with ipc.send
+---@param data any
+function on_ipc_recv(data) end
+
+---Called when a MIDI device is plugged in, or an open one comes back
+---@param device string
+function on_device_connect(device) end
+
+---Called when a MIDI device is unplugged
+---@param device string
+function on_device_disconnect(device) end
+
+---@param interface string
+---@param member string
+---@param args any[]
+function on_dbus_signal(interface, member, args) end
+
+---Values given with --arg key=value or under [script.arguments]
+---@type table<string, string>
+args = {}
+
+---@class midi
+---@field ANY_CHANNEL integer
+midi = {}
+
+---@return HeldNote[]
+function midi.notes_held() end
+
+---@param channel integer
+---@param key integer
+---@return boolean
+function midi.is_held(channel, key) end
+
+---Maps value from one range to another, without clamping
+---@param value number
+---@param in_min number
+---@param in_max number
+---@param out_min number
+---@param out_max number
+---@return number
+function midi.map(value, in_min, in_max, out_min, out_max) end
+
+---@param value number
+---@param min number
+---@param max number
+---@return number
+function midi.clamp(value, min, max) end
+
+---@param value number 0.0 to 1.0
+---@param gamma number Above 1 makes the low end less sensitive
+---@return number
+function midi.curve(value, gamma) end
+
+---@param value integer 0 to 16383
+---@return number # -1.0 to 1.0
+function midi.pitch_bend_to_f32(value) end
+
+---Shifts every incoming note, on top of set_octave
+---@param semitones integer
+function midi.set_transpose(semitones) end
+
+---Shifts every incoming note by whole octaves, on top of set_transpose
+---@param octaves integer
+function midi.set_octave(octaves) end
+
+---@return integer semitones
+---@return integer octaves
+function midi.get_transpose() end
+
+---@alias ScaleMode "major"|"minor"|"harmonic_minor"|"dorian"|"phrygian"|"lydian"|"mixolydian"|"locrian"|"pentatonic_major"|"pentatonic_minor"|"blues"|"chromatic"
+
+---Snaps every incoming note to the closest one in the scale, calling it without a root turns it off
+---@param root? string e.g. "C" or "F#"
+---@param mode? ScaleMode "major" if not given
+function midi.set_scale(root, mode) end
+
+---Shifts the octave numbers used by note_name and note_number
+---@param offset integer
+function midi.set_octave_offset(offset) end
+
+---@param number integer
+---@return string # e.g. "C4"
+function midi.note_name(number) end
+
+---@param name string e.g. "C#4"
+---@return integer
+function midi.note_number(name) end
+
+---@param number number
+---@return number # Hz
+function midi.note_frequency(number) end
+
+---@param layer integer
+function midi.set_layer(layer) end
+
+---@return integer
+function midi.get_layer() end
+
+---Program changes switch to layer program % count, nil turns it off
+---@param count? integer
+function midi.set_auto_layer(count) end
+
+---Called for every program change, before the layer switches
+---@param f? fun(program: integer, channel: integer)
+function midi.on_program_change(f) end
+
+---Passes everything that comes in on to --midi-out, needs --midi-out
+---@param enabled boolean
+function midi.set_thru(enabled) end
+
+---@param channels integer Bitmask, bit 0 is channel 1. 0xFFFF by default.
+function midi.set_thru_filter(channels) end
+
+---All notes off and reset all controllers on every channel, needs --midi-out
+function midi.panic() end
+
+---Needs --midi-out, replies go to midi.on_identity
+function midi.send_identity_request() end
+
+---@param f? fun(identity: MidiIdentity)
+function midi.on_identity(f) end
+
+---The next MIDI message goes to f instead of the usual callbacks
+---@param f fun(evt: MidiEvent)
+function midi.learn(f) end
+
+---Messages from devices whose name matches pattern, the first one added wins
+---@param pattern string A Lua pattern, found anywhere in the name
+---@param f? fun(evt: MidiEvent) nil removes the handler
+function midi.on_device(pattern, f) end
+
+---@param channel integer 1 to 16, or midi.ANY_CHANNEL
+---@param f? fun(evt: MidiEvent) nil removes the handler
+function midi.on_channel(channel, f) end
+
+---@param f? fun(evt: MidiEvent)
+function midi.on_note(f) end
+
+---@param control integer
+---@param f? fun(evt: MidiEvent)
+function midi.on_cc(control, f) end
+
+---Only when the value isn't the same as the last one
+---@param control integer
+---@param f? fun(evt: MidiEvent)
+function midi.on_cc_change(control, f) end
+
+---Only when the value goes past threshold, up for "rising" and down for "falling"
+---@param control integer
+---@param threshold integer
+---@param direction "rising"|"falling"
+---@param f? fun(evt: MidiEvent) nil removes the handler for this threshold and direction
+function midi.on_cc_threshold(control, threshold, direction, f) end
+
+---@param f? fun(evt: MidiEvent)
+function midi.on_pitch_bend(f) end
+
+---Note offs, with evt.duration_ms
+---@param f? fun(evt: MidiEvent)
+function midi.on_note_duration(f) end
+
+---@param mode HandlerMode
+function midi.set_channel_mode(mode) end
+
+---@param mode HandlerMode
+function midi.set_device_mode(mode) end
+
+---@param mode HandlerMode
+function midi.set_dispatch_mode(mode) end
+
+---@param channel integer 1 to 16, or 0 for every channel
+---@param threshold integer Note ons with a lower velocity are dropped, 0 lets everything through
+function midi.set_note_threshold(channel, threshold) end
+
+---@param mode "normal"|"gate"|"relative" "gate" makes every note 127, "relative" stretches the velocities above the threshold back out to 1-127
+---@param channel? integer Every channel if not given
+function midi.set_note_velocity_mode(mode, channel) end
+
+---@param curve (fun(vel: integer): integer)|integer[]|nil A function or a table of 128 velocities
+---@param channel? integer Every channel if not given
+function midi.set_velocity_curve(curve, channel) end
+
+---@param control integer
+---@param alpha number More than 0.0 up to 1.0, 1.0 turns smoothing off
+function midi.enable_cc_smoothing(control, alpha) end
+
+---@param ms integer 0 turns chord detection off
+function midi.set_chord_window(ms) end
+
+---@param main_channel integer Usually 1 for a lower zone, 16 for an upper one
+---@param zone_start integer First member channel
+---@param zone_end integer Last member channel
+function midi.enable_mpe(main_channel, zone_start, zone_end) end
+
+function midi.disable_mpe() end
+
+---@param notes (integer|HeldNote)[]
+---@return string? # e.g. "Cmaj7"
+function midi.chord_name(notes) end
+
+---@class ArpOptions
+---@field steps? integer Notes per beat, 1 if not given
+---@field gate? number How much of a step each note lasts, 0.5 if not given
+
+---Plays held notes one at a time, as note events from the "arp" device. Follows a MIDI clock if there is one.
+---@param pattern fun(chord: HeldNote[]): integer? The note to play next, nil for a rest
+---@param bpm number
+---@param options? ArpOptions
+function midi.start_arp(pattern, bpm, options) end
+
+function midi.stop_arp() end
+
+---Taps of a note or CC work out a tempo for midi.tap_bpm
+---@param channel integer 1 to 16, or midi.ANY_CHANNEL
+---@param note_or_cc integer
+function midi.tap_tempo(channel, note_or_cc) end
+
+---@return number? # nil until there have been two taps, and after 4 seconds without one
+function midi.tap_bpm() end
+
+---@return number?
+function midi.bpm() end
+
+---@return number? # 0.0 to 1.0 through the current beat
+function midi.beat_phase() end
+
+---@class PlaybackOptions
+---@field loop? boolean
+---@field speed? number
+
+---Plays a standard MIDI file through the usual callbacks, stopping whatever was playing
+---@param path string
+---@param options? PlaybackOptions
+function midi.play_file(path, options) end
+
+function midi.stop_playback() end
+
+---@class RecordingOptions
+---@field bpm? number
+---@field time_signature? integer[] e.g. { 3, 4 }
+
+---Records MIDI input until midi.stop_recording(), which writes it to path
+---@param path string
+---@param options? RecordingOptions
+function midi.start_recording(path, options) end
+
+function midi.stop_recording() end
+
+---@param port integer
+function midi.open(port) end
+
+---@class midi_out
+midi_out = {}
+
+---@param channel integer
+---@param key integer
+---@param vel integer
+function midi_out.note_on(channel, key, vel) end
+
+---@param channel integer
+---@param key integer
+---@param vel? integer
+function midi_out.note_off(channel, key, vel) end
+
+---@param channel integer
+---@param control integer
+---@param value integer
+function midi_out.control_change(channel, control, value) end
+
+---@param channel integer
+---@param value integer 0 to 16383
+function midi_out.pitch_bend(channel, value) end
+
+---@param bytes integer[]
+function midi_out.raw(bytes) end
+
+---@param data integer[] Without F0 and F7, those get added
+function midi_out.sysex(data) end
+
+---@param bytes integer[] Sent exactly as given
+function midi_out.sysex_raw(bytes) end
+
+---What pad.axis() does first: take off center, 0 inside the deadzone, then divided by center
+---(1 if it's 0) and multiplied by sensitivity
+---@class AxisConfig
+---@field center? number 0 by default
+---@field deadzone? number In the same units as the values, 0 by default
+---@field sensitivity? number 1.0 by default
+
+---@class Gamepad
+local Gamepad = {}
+
+---@param button integer
+---@param pressed boolean
+function Gamepad.button(button, pressed) end
+
+---@param axis integer
+---@param value number -1.0 to 1.0, 0.0 to 1.0 for ABS_Z and ABS_RZ, or -1/0/1 for hats
+function Gamepad.axis(axis, value) end
+
+---Same as axis, ignoring set_axis_config
+---@param axis integer
+---@param value number
+function Gamepad.axis_raw(axis, value) end
+
+---@param axis integer
+---@param config? AxisConfig nil sends values to axis() as they are again
+function Gamepad.set_axis_config(axis, config) end
+
+---@param which "left"|"right"
+---@param value integer 0 to gamepad.TRIGGER_MAX, clamped
+function Gamepad.trigger(which, value) end
+
+---@param hat integer
+---@param x integer
+---@param y integer
+function Gamepad.hat(hat, x, y) end
+
+---@param button integer
+---@return boolean
+function Gamepad.button_state(button) end
+
+---@return table<integer, boolean>
+function Gamepad.all_buttons() end
+
+---@param axis integer
+---@return number # 0 if it hasn't been moved
+function Gamepad.axis_state(axis) end
+
+function Gamepad.begin_batch() end
+function Gamepad.end_batch() end
+
+---@class gamepad
+---@field BTN table<string, integer>
+---@field ABS table<string, integer>
+---@field TRIGGER_MAX integer 255, the most a trigger goes to
+---@field [string] integer BTN_* and AXIS_* codes
+gamepad = {}
+
+---@param name string e.g. "SOUTH" or "BTN_SOUTH"
+---@return integer
+function gamepad.btn(name) end
+
+---@param id? string "vendor:product" in hex
+---@param name? string
+---@return Gamepad
+function gamepad.create(id, name) end
+
+---Releases every button that's down, on every gamepad. Axes stay where they are.
+function gamepad.release_all() end
+
+---@class keyboard
+keyboard = {}
+
+---@param code integer
+function keyboard.press(code) end
+
+---@param code integer
+function keyboard.release(code) end
+
+---@param code integer
+function keyboard.tap(code) end
+
+---Press and keep repeating until unhold
+---@param code integer
+function keyboard.hold(code) end
+
+---@param code integer
+function keyboard.unhold(code) end
+
+---Releases every key that's been pressed or held and not let go of
+function keyboard.release_all() end
+
+---@param initial_ms integer
+---@param period_ms integer
+function keyboard.set_repeat(initial_ms, period_ms) end
+
+function keyboard.begin_batch() end
+function keyboard.end_batch() end
+
+---@class mouse
+mouse = {}
+
+---@param dx integer
+---@param dy integer
+function mouse.move(dx, dy) end
+
+---@param delta integer
+function mouse.scroll(delta) end
+
+---@param button integer 0 left, 1 right, 2 middle, 3 side, 4 extra
+function mouse.down(button) end
+
+---@param button integer
+function mouse.up(button) end
+
+---@param button integer
+function mouse.click(button) end
+
+function mouse.begin_batch() end
+function mouse.end_batch() end
+
+---@class touch
+---@field width integer
+---@field height integer
+touch = {}
+
+---@param id integer Finger, 0 to 9
+---@param x integer
+---@param y integer
+---@param pressure? integer
+function touch.down(id, x, y, pressure) end
+
+---@param id integer
+---@param x integer
+---@param y integer
+---@param pressure? integer
+function touch.move(id, x, y, pressure) end
+
+---@param id integer
+function touch.up(id) end
+
+function touch.begin_batch() end
+function touch.end_batch() end
+
+---@class QueueStats
+---@field depth integer Messages waiting for the script
+---@field dropped integer MIDI messages dropped because the queue was full
+---@field processed integer Messages taken off the queue so far
+
+---@class misc
+misc = {}
+
+---Blocks the script, prefer misc.schedule
+---@param seconds number
+function misc.sleep(seconds) end
+
+---@param ms integer
+function misc.sleep_ms(ms) end
+
+---@return number # Seconds since handcake started
+function misc.time() end
+
+---@param name string
+---@param default? string
+---@return string?
+function misc.getenv(name, default) end
+
+---@return number # Seconds since the last call
+function misc.delta_time() end
+
+---@param delay_ms integer
+---@param f fun()
+---@return integer handle
+function misc.schedule(delay_ms, f) end
+
+---@param handle integer
+function misc.cancel(handle) end
+
+---@param period_ms integer
+---@param f fun()
+---@return integer handle
+function misc.interval(period_ms, f) end
+
+---@param handle integer
+function misc.clear_interval(handle) end
+
+---Only works from the top of the script, before on_script_init is called
+---@param ms integer Overrides --init-delay-ms
+function misc.set_init_delay(ms) end
+
+---Lets everything else run before carrying on, does nothing where the script can't wait (e.g. a velocity curve)
+function misc.yield_now() end
+
+---@return boolean stopping Whether handcake has been told to stop, e.g. by SIGTERM
+function misc.check_signals() end
+
+---@return integer depth Messages waiting for the script
+function misc.queue_depth() end
+
+---@return QueueStats
+function misc.queue_stats() end
+
+---@class evdev
+evdev = {}
+
+---@param device_path string e.g. /dev/input/event3
+function evdev.grab(device_path) end
+
+---@param device_path string
+function evdev.release(device_path) end
+
+---@return string[]
+function evdev.grabbed() end
+
+---@class log
+log = {}
+
+---@param ... any
+function log.debug(...) end
+
+---@param ... any
+function log.info(...) end
+
+---@param ... any
+function log.warn(...) end
+
+---@param ... any
+function log.error(...) end
+
+---@class json
+json = {}
+
+---@param value any
+---@return string
+function json.encode(value) end
+
+---@param text string
+---@return any
+function json.decode(text) end
+
+---@class osc
+osc = {}
+
+---@param host string
+---@param port integer
+---@param address string
+---@param ... number|string|boolean
+function osc.send(host, port, address, ...) end
+
+---@class ipc
+ipc = {}
+
+---@param socket_path string Where the other instance's --ipc-socket is
+---@param value any
+function ipc.send(socket_path, value) end
+
+---@class state
+state = {}
+
+---Kept across reloads and restarts, nil forgets the key
+---@param key string
+---@param value any
+function state.save(key, value) end
+
+---@param key string
+---@return any
+function state.load(key) end
+
+---@class buffer
+buffer = {}
+
+---@param size integer How many values it keeps, at least 1
+---@return RingBuffer
+function buffer.new(size) end
+
+---@class RingBuffer
+local RingBuffer = {}
+
+---Drops the oldest value once it's full
+---@param value number
+function RingBuffer:push(value) end
+
+---@return number?
+function RingBuffer:average() end
+
+---@return number
+function RingBuffer:sum() end
+
+---@return number?
+function RingBuffer:min() end
+
+---@return number?
+function RingBuffer:max() end
+
+---@param index integer 1 is the newest
+---@return number?
+function RingBuffer:get(index) end
+
+---Newest first, like get
+---@return number[]
+function RingBuffer:to_table() end
+
+---How many values it has in it right now
+---@return integer
+function RingBuffer:size() end
+
+---@class RecordedEvent
+---@field device string Name the virtual device was created with
+---@field type integer
+---@field code integer
+---@field value integer
+
+---An evdev.grab() or evdev.release(), as the tests see it
+---@class RecordedGrab
+---@field device string
+---@field grabbed boolean
+
+---Only there with --test
+---@class test
+test = {}
+
+---@param condition any
+---@param message? string
+function test.assert(condition, message) end
+
+---Tables are compared by contents
+---@param a any
+---@param b any
+---@param message? string
+function test.assert_eq(a, b, message) end
+
+---Timers don't go off by themselves in tests, this lets ms go by and runs every one that comes due
+---@param ms integer
+function test.advance(ms) end
+
+---Tests to run once the script has loaded, in name order
+---@param tests table<string, fun(inject: fun(event: MidiEvent), recorded_events: fun(): RecordedEvent[], recorded_midi: fun(): integer[][], recorded_grabs: fun(): RecordedGrab[])>
+function test.run(tests) end
\ No newline at end of file
@@ -0,0 +1,189 @@
+use std::{collections::HashMap, time::{Duration, Instant}};
+use futures_util::StreamExt;
+use zbus::{Connection, MessageStream, fdo::{ManagedObjects, ObjectManagerProxy}, zvariant::{OwnedObjectPath, OwnedValue}};
+use crate::{AppState, queue};
+use super::{midi, serial_midi::StreamParser};
+
+// From the Bluetooth SIG MIDI spec
+const MIDI_SERVICE: &str = "03b80e5a-ede8-4b33-a751-6ce34ec4c700";
+const MIDI_CHARACTERISTIC: &str = "7772e5db-3868-4112-a1a9-f2669d106bf3";
+
+const SCAN_TIMEOUT: Duration = Duration::from_secs(30);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn prop<T: TryFrom<OwnedValue>>(props: &HashMap<String, OwnedValue>, name: &str) -> Option<T> {
+    props.get(name).and_then(|v| T::try_from(v.clone()).ok())
+}
+
+// By address if one was given, otherwise the first thing advertising the MIDI service
+fn find_device(objects: &ManagedObjects, address: Option<&str>) -> Option<(OwnedObjectPath, String)> {
+    objects.iter().find_map(|(path, interfaces)| {
+        let device = interfaces.iter().find(|(name, _)| name.as_str() == "org.bluez.Device1")?.1;
+        let device_address = prop::<String>(device, "Address").unwrap_or_default();
+        let matches = match address {
+            Some(address) => device_address.eq_ignore_ascii_case(address),
+            None => prop::<Vec<String>>(device, "UUIDs").unwrap_or_default().iter().any(|u| u == MIDI_SERVICE),
+        };
+        if !matches {
+            return None;
+        }
+        let name = prop::<String>(device, "Alias").unwrap_or(device_address);
+
+        Some((path.clone(), name))
+    })
+}
+
+fn find_characteristic(objects: &ManagedObjects, device: &OwnedObjectPath) -> Option<OwnedObjectPath> {
+    objects.iter().find_map(|(path, interfaces)| {
+        if !path.as_str().starts_with(device.as_str()) {
+            return None;
+        }
+        let characteristic = interfaces.iter().find(|(name, _)| name.as_str() == "org.bluez.GattCharacteristic1")?.1;
+        if prop::<String>(characteristic, "UUID")? != MIDI_CHARACTERISTIC {
+            return None;
+        }
+
+        Some(path.clone())
+    })
+}
+
+/// Every BLE-MIDI packet starts with a header byte, and every message in it
+/// (and the end of a SysEx) gets a timestamp byte in front. Both have the top
+/// bit set, like status bytes. What's left goes through the same parser as
+/// serial MIDI, which keeps running status and SysEx going across packets.
+fn parse_packet(parser: &mut StreamParser, packet: &[u8], mut emit: impl FnMut(&[u8])) {
+    let mut bytes = packet.iter().skip(1).copied();
+    while let Some(byte) = bytes.next() {
+        if byte & 0x80 == 0 {
+            parser.push(byte, &mut emit);
+            continue;
+        }
+        // That was a timestamp, so this is a status byte or running status data
+        match bytes.next() {
+            Some(byte) => parser.push(byte, &mut emit),
+            None => return,
+        }
+    }
+}
+
+async fn run(sender: queue::Sender, address: Option<String>) -> anyhow::Result<()> {
+    let conn = Connection::system().await?;
+    let objects = ObjectManagerProxy::builder(&conn).destination("org.bluez")?.path("/")?.build().await?;
+
+    // Paired devices are already known to BlueZ, only scan if it isn't one of those
+    let started = Instant::now();
+    let mut scanning = None;
+    let (device, name) = loop {
+        let managed = objects.get_managed_objects().await?;
+        if let Some(found) = find_device(&managed, address.as_deref()) {
+            break found;
+        }
+        if scanning.is_none() {
+            let adapter = managed.iter()
+                .find(|(_, interfaces)| interfaces.keys().any(|name| name.as_str() == "org.bluez.Adapter1"))
+                .map(|(path, _)| path.clone())
+                .ok_or_else(|| anyhow::anyhow!("No Bluetooth adapter found"))?;
+            conn.call_method(Some("org.bluez"), adapter.as_str(), Some("org.bluez.Adapter1"), "StartDiscovery", &()).await?;
+            info!("Scanning for BLE-MIDI devices");
+            scanning = Some(adapter);
+        }
+        if started.elapsed() > SCAN_TIMEOUT {
+            anyhow::bail!("No BLE-MIDI device found after {} seconds", SCAN_TIMEOUT.as_secs());
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    };
+    if let Some(adapter) = scanning {
+        let _ = conn.call_method(Some("org.bluez"), adapter.as_str(), Some("org.bluez.Adapter1"), "StopDiscovery", &()).await;
+    }
+
+    info!("Connecting to BLE-MIDI device {:?}", name);
+    conn.call_method(Some("org.bluez"), device.as_str(), Some("org.bluez.Device1"), "Connect", &()).await?;
+
+    // The GATT objects only show up once BlueZ has gone through the device's services
+    let started = Instant::now();
+    let characteristic = loop {
+        if let Some(characteristic) = find_characteristic(&objects.get_managed_objects().await?, &device) {
+            break characteristic;
+        }
+        if started.elapsed() > CONNECT_TIMEOUT {
+            anyhow::bail!("{:?} doesn't have a MIDI characteristic", name);
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    };
+
+    // Notifications come in as changes to the characteristic's Value property
+    let rule = format!(
+        "type='signal',sender='org.bluez',interface='org.freedesktop.DBus.Properties',member='PropertiesChanged',path='{}'",
+        characteristic.as_str(),
+    );
+    let mut stream = MessageStream::for_match_rule(rule.as_str(), &conn, None).await?;
+    conn.call_method(Some("org.bluez"), characteristic.as_str(), Some("org.bluez.GattCharacteristic1"), "StartNotify", &()).await?;
+    info!("Receiving MIDI from {:?}", name);
+
+    let mut parser = StreamParser::default();
+    while let Some(msg) = stream.next().await {
+        let msg = msg?;
+        let (_, changed, _) = match msg.body().deserialize::<(String, HashMap<String, OwnedValue>, Vec<String>)>() {
+            Ok(body) => body,
+            Err(e) => {
+                debug!("Bad PropertiesChanged signal from BlueZ: {}", e);
+                continue;
+            },
+        };
+        if let Some(packet) = prop::<Vec<u8>>(&changed, "Value") {
+            parse_packet(&mut parser, &packet, |message| midi::forward(&name, message, &sender));
+        }
+    }
+
+    Ok(())
+}
+
+/// Finds a BLE-MIDI device through BlueZ (scanning if needed) and reads MIDI
+/// from it in the background. Gives up with an error in the log rather than
+/// stopping handcake, since Bluetooth devices come and go.
+pub fn connect(state: &AppState, address: Option<String>) {
+    let sender = state.sender.clone();
+    tokio::spawn(async move {
+        if let Err(e) = run(sender, address).await {
+            error!("BLE-MIDI: {}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(parser: &mut StreamParser, packet: &[u8]) -> Vec<Vec<u8>> {
+        let mut messages = Vec::new();
+        parse_packet(parser, packet, |message| messages.push(message.to_vec()));
+        messages
+    }
+
+    #[test]
+    fn running_status() {
+        let mut parser = StreamParser::default();
+        // Header, timestamp, note on, then another timestamp and just the data
+        let messages = parse(&mut parser, &[0x80, 0x80, 0x90, 60, 100, 0x81, 62, 90]);
+        assert_eq!(messages, [vec![0x90, 60, 100], vec![0x90, 62, 90]]);
+        // The timestamp can be left out too
+        let messages = parse(&mut parser, &[0x80, 0x80, 0x90, 60, 0, 62, 0]);
+        assert_eq!(messages, [vec![0x90, 60, 0], vec![0x90, 62, 0]]);
+    }
+
+    #[test]
+    fn several_messages() {
+        let mut parser = StreamParser::default();
+        let messages = parse(&mut parser, &[0x80, 0x80, 0x90, 60, 100, 0x80, 0xB0, 7, 64, 0x81, 0xC0, 5]);
+        assert_eq!(messages, [vec![0x90, 60, 100], vec![0xB0, 7, 64], vec![0xC0, 5]]);
+    }
+
+    #[test]
+    fn sysex_across_packets() {
+        let mut parser = StreamParser::default();
+        assert!(parse(&mut parser, &[0x80, 0x80, 0xF0, 0x7E, 0x7F]).is_empty());
+        // Carries on straight after the header, the end gets a timestamp of its own
+        let messages = parse(&mut parser, &[0x80, 0x06, 0x01, 0x81, 0xF7]);
+        assert_eq!(messages, [vec![0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7]]);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,67 @@
+use std::collections::VecDeque;
+
+use super::ApiProvider;
+
+api_entries! { "buffer",
+    "new" => "fun(size: integer): RingBuffer", "A buffer of the last size numbers pushed, for averages over a sliding window";
+    "RingBuffer:push" => "fun(value: number)", "Adds a value, dropping the oldest one if the buffer is full";
+    "RingBuffer:average" => "fun(): number?", "The mean of what's in the buffer, nil if it's empty";
+    "RingBuffer:sum" => "fun(): number", "Everything in the buffer added up";
+    "RingBuffer:min" => "fun(): number?", "The smallest value, nil if it's empty";
+    "RingBuffer:max" => "fun(): number?", "The largest value, nil if it's empty";
+    "RingBuffer:get" => "fun(index: integer): number?", "1 is the newest value, 2 the one before and so on";
+    "RingBuffer:to_table" => "fun(): number[]", "Everything in the buffer, newest first like get";
+    "RingBuffer:size" => "fun(): integer", "How many values are in the buffer, up to the size it was made with";
+}
+
+// Newest at the front, so get(1) is values[0]
+struct RingBuffer {
+    values: VecDeque<f64>,
+    size: usize,
+}
+
+impl mlua::UserData for RingBuffer {
+    fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method_mut("push", |_l, buf, (value,): (f64,)| {
+            if buf.values.len() == buf.size {
+                buf.values.pop_back();
+            }
+            buf.values.push_front(value);
+            Ok(())
+        });
+        methods.add_method("average", |_l, buf, _: ()| {
+            Ok(match buf.values.len() {
+                0 => None,
+                len => Some(buf.values.iter().sum::<f64>() / len as f64),
+            })
+        });
+        methods.add_method("sum", |_l, buf, _: ()| Ok(buf.values.iter().sum::<f64>()));
+        methods.add_method("min", |_l, buf, _: ()| Ok(buf.values.iter().copied().reduce(f64::min)));
+        methods.add_method("max", |_l, buf, _: ()| Ok(buf.values.iter().copied().reduce(f64::max)));
+        methods.add_method("get", |_l, buf, (index,): (i64,)| {
+            Ok(usize::try_from(index - 1).ok().and_then(|i| buf.values.get(i).copied()))
+        });
+        methods.add_method("to_table", |_l, buf, _: ()| Ok(buf.values.iter().copied().collect::<Vec<_>>()));
+        methods.add_method("size", |_l, buf, _: ()| Ok(buf.values.len()));
+    }
+}
+
+pub struct Buffer;
+impl ApiProvider for Buffer {
+    type Arguments = ();
+
+    fn register_api(l: &mlua::Lua, _args: Self::Arguments) -> anyhow::Result<()> {
+        let tab = l.create_table()?;
+
+        tab.set("new", l.create_function(|_l, (size,): (usize,)| {
+            if size == 0 {
+                return Err(mlua::Error::RuntimeError("A buffer needs room for at least 1 value".to_owned()));
+            }
+            Ok(RingBuffer { values: VecDeque::with_capacity(size), size })
+        })?)?;
+
+        l.globals().set("buffer", tab)?;
+
+        Ok(())
+    }
+}
\ No newline at end of file
@@ -0,0 +1,94 @@
+use futures_util::StreamExt;
+use zbus::{Connection, MessageStream, zvariant::{OwnedValue, Structure, Value}};
+use crate::{AppState, Message};
+
+/// Subscribes to signals on the session bus matching each rule, e.g.
+/// "type='signal',interface='org.freedesktop.ScreenSaver',member='ActiveChanged'",
+/// and passes them to on_dbus_signal.
+pub async fn subscribe(state: &AppState, rules: &[String]) -> anyhow::Result<()> {
+    let conn = Connection::session().await?;
+
+    for rule in rules {
+        let mut stream = MessageStream::for_match_rule(rule.as_str(), &conn, None).await?;
+        info!("Subscribed to D-Bus signals matching {:?}", rule);
+
+        let sender = state.sender.clone();
+        tokio::spawn(async move {
+            while let Some(msg) = stream.next().await {
+                let msg = match msg {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        warn!("Error receiving D-Bus signal: {}", e);
+                        continue;
+                    },
+                };
+
+                let header = msg.header();
+                let interface = header.interface().map(|i| i.to_string()).unwrap_or_default();
+                let member = header.member().map(|m| m.to_string()).unwrap_or_default();
+                // Signals without arguments have an empty body, which isn't a structure
+                let body = msg.body();
+                let args = match body.deserialize::<Structure>() {
+                    Ok(s) => s.into_fields().iter().filter_map(|v| v.try_to_owned().ok()).collect(),
+                    Err(_) => vec![],
+                };
+
+                if sender.send(Message::DBus { interface, member, args }).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn value_to_lua<'lua>(l: &'lua mlua::Lua, value: &Value) -> mlua::Result<mlua::Value<'lua>> {
+    use mlua::ToLua;
+
+    match value {
+        Value::U8(n) => n.to_lua(l),
+        Value::Bool(b) => b.to_lua(l),
+        Value::I16(n) => n.to_lua(l),
+        Value::U16(n) => n.to_lua(l),
+        Value::I32(n) => n.to_lua(l),
+        Value::U32(n) => n.to_lua(l),
+        Value::I64(n) => n.to_lua(l),
+        Value::U64(n) => (*n as f64).to_lua(l),
+        Value::F64(n) => n.to_lua(l),
+        Value::Str(s) => s.to_string().to_lua(l),
+        Value::Signature(s) => s.to_string().to_lua(l),
+        Value::ObjectPath(p) => p.as_str().to_lua(l),
+        Value::Value(v) => value_to_lua(l, v),
+        Value::Array(a) => values_to_table(l, a.inner())?.to_lua(l),
+        Value::Structure(s) => values_to_table(l, s.fields())?.to_lua(l),
+        Value::Dict(d) => {
+            let tab = l.create_table()?;
+            for (k, v) in d.iter() {
+                tab.set(value_to_lua(l, k)?, value_to_lua(l, v)?)?;
+            }
+            tab.to_lua(l)
+        },
+        // File descriptors aren't any use to a script
+        _ => Ok(mlua::Value::Nil),
+    }
+}
+
+fn values_to_table<'lua>(l: &'lua mlua::Lua, values: &[Value]) -> mlua::Result<mlua::Table<'lua>> {
+    let tab = l.create_table_with_capacity(values.len() as i32, 0)?;
+    for (i, v) in values.iter().enumerate() {
+        tab.set(i + 1, value_to_lua(l, v)?)?;
+    }
+
+    Ok(tab)
+}
+
+/// Signal arguments as a Lua list, containers become nested tables
+pub fn args_to_table<'lua>(l: &'lua mlua::Lua, args: &[OwnedValue]) -> mlua::Result<mlua::Table<'lua>> {
+    let tab = l.create_table_with_capacity(args.len() as i32, 0)?;
+    for (i, v) in args.iter().enumerate() {
+        tab.set(i + 1, value_to_lua(l, v)?)?;
+    }
+
+    Ok(tab)
+}
\ No newline at end of file
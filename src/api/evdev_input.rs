@@ -0,0 +1,181 @@
+use std::{collections::{HashMap, HashSet}, fs::File, io, path::{Path, PathBuf}, sync::atomic::Ordering};
+use input_linux::{EvdevHandle, sys};
+use parking_lot::Mutex;
+use crate::{AppState, Message};
+
+use super::{ApiProvider, DRY_RUN, RECORDED_GRABS};
+
+api_entries! { "evdev",
+    "grab" => "fun(device_path: string)", "Stops a device's events from reaching anything but handcake, until evdev.release";
+    "release" => "fun(device_path: string)", "Lets the rest of the system see a grabbed device again";
+    "grabbed" => "fun(): string[]", "Every device evdev.grab has grabbed, sorted";
+}
+
+lazy_static::lazy_static! {
+    // Everything --evdev-device has open (and grabbed), resolved so a symlink
+    // in /dev/input/by-id matches the eventN it points to
+    static ref OPENED: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+}
+
+fn resolve(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Grabs an evdev device (/dev/input/eventN) so nothing else sees its events,
+/// and forwards everything but SYN reports to on_evdev_recv.
+/// Like MIDI inputs, the device stays open across reloads.
+pub fn open_device(state: &AppState, path: &Path) -> anyhow::Result<()> {
+    let handle = EvdevHandle::new(File::open(path)?);
+    handle.grab(true)?;
+    OPENED.lock().insert(resolve(path));
+    let device = path.to_string_lossy().into_owned();
+    info!("Grabbed evdev device {}", device);
+
+    let sender = state.sender.clone();
+    std::thread::spawn(move || {
+        let mut events = [sys::input_event {
+            time: sys::timeval { tv_sec: 0, tv_usec: 0 },
+            type_: 0,
+            code: 0,
+            value: 0,
+        }; 64];
+
+        loop {
+            let n = match handle.read(&mut events) {
+                Ok(n) => n,
+                Err(e) => {
+                    warn!("Stopped reading evdev device {}: {}", device, e);
+                    return;
+                },
+            };
+
+            for event in events.iter().take(n).filter(|e| e.type_ != sys::EV_SYN as u16) {
+                let message = Message::Evdev {
+                    device: device.clone(),
+                    kind: event.type_,
+                    code: event.code,
+                    value: event.value,
+                };
+                if sender.send(message).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+// The real thing does EVIOCGRAB, with --dry-run the device is only opened
+trait Grab: Send {
+    fn grab(&self, grab: bool) -> io::Result<()>;
+}
+
+impl Grab for EvdevHandle<File> {
+    fn grab(&self, grab: bool) -> io::Result<()> { EvdevHandle::grab(self, grab) }
+}
+
+// Prints every grab and release as a line of JSON, or keeps them for the tests
+// with --test, like the dry run virtual devices do with their events
+struct DryRunGrab {
+    device: String,
+}
+
+impl Grab for DryRunGrab {
+    fn grab(&self, grab: bool) -> io::Result<()> {
+        if let Some(recorded) = RECORDED_GRABS.lock().as_mut() {
+            recorded.push((self.device.clone(), grab));
+            return Ok(());
+        }
+        println!("{}", serde_json::json!({ "device": self.device, "grab": grab }));
+        Ok(())
+    }
+}
+
+// --evdev-device has had it grabbed since startup, and keeps it grabbed even
+// after evdev.release(). A second grab would only fail with EBUSY.
+struct AlreadyGrabbed;
+
+impl Grab for AlreadyGrabbed {
+    fn grab(&self, _grab: bool) -> io::Result<()> { Ok(()) }
+}
+
+// Devices grabbed with evdev.grab(), per VM so a reload starts without any.
+// Closing the file lets go of the grab too, release() just doesn't wait for that.
+#[derive(Default)]
+struct Grabs(HashMap<PathBuf, Box<dyn Grab>>);
+
+fn open_grab(path: &Path) -> io::Result<Box<dyn Grab>> {
+    if OPENED.lock().contains(&resolve(path)) {
+        debug!("Evdev device {:?} is already grabbed by --evdev-device", path);
+        return Ok(Box::new(AlreadyGrabbed));
+    }
+    let file = File::open(path)?;
+    if DRY_RUN.load(Ordering::Relaxed) {
+        return Ok(Box::new(DryRunGrab { device: path.to_string_lossy().into_owned() }));
+    }
+    Ok(Box::new(EvdevHandle::new(file)))
+}
+
+/// Lets go of everything the script grabbed with evdev.grab(), for when it's
+/// shutting down or being replaced
+pub fn release_all(l: &mlua::Lua) {
+    let grabs = match l.app_data_mut::<Grabs>() {
+        Some(mut grabs) => std::mem::take(&mut grabs.0),
+        None => return,
+    };
+    for (path, handle) in grabs {
+        if let Err(e) = handle.grab(false) {
+            warn!("Could not release evdev device {:?}: {}", path, e);
+        }
+    }
+}
+
+/// evdev.grab() and evdev.release(), for keeping a device's own events away
+/// from everything else. Reading from a device is --evdev-device's job.
+pub struct Evdev;
+impl ApiProvider for Evdev {
+    type Arguments = ();
+
+    fn register_api(l: &mlua::Lua, _args: Self::Arguments) -> anyhow::Result<()> {
+        let tab = l.create_table()?;
+        l.set_app_data(Grabs::default());
+
+        // Grabbing the same device twice does nothing the second time
+        tab.set("grab", l.create_function(|l, (path,): (String,)| {
+            let path = PathBuf::from(path);
+            if l.app_data_ref::<Grabs>().unwrap().0.contains_key(&path) {
+                return Ok(());
+            }
+            let handle = open_grab(&path)
+                .and_then(|handle| handle.grab(true).map(|_| handle))
+                .map_err(|e| mlua::Error::RuntimeError(format!("Could not grab evdev device {:?}: {}", path, e)))?;
+            info!("Grabbed evdev device {:?}", path);
+            l.app_data_mut::<Grabs>().unwrap().0.insert(path, handle);
+            Ok(())
+        })?)?;
+
+        tab.set("release", l.create_function(|l, (path,): (String,)| {
+            let path = PathBuf::from(path);
+            let handle = match l.app_data_mut::<Grabs>().unwrap().0.remove(&path) {
+                Some(handle) => handle,
+                None => return Err(mlua::Error::RuntimeError(format!("Evdev device {:?} isn't grabbed", path))),
+            };
+            handle.grab(false).map_err(|e| mlua::Error::RuntimeError(format!("Could not release evdev device {:?}: {}", path, e)))?;
+            info!("Released evdev device {:?}", path);
+            Ok(())
+        })?)?;
+
+        tab.set("grabbed", l.create_function(|l, _: ()| {
+            let mut paths: Vec<String> = l.app_data_ref::<Grabs>().unwrap().0.keys()
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect();
+            paths.sort();
+            Ok(paths)
+        })?)?;
+
+        l.globals().set("evdev", tab)?;
+
+        Ok(())
+    }
+}
\ No newline at end of file
@@ -0,0 +1,61 @@
+use std::{ffi::CString, fs::File, io::{BufRead, BufReader}, os::unix::{ffi::OsStrExt, fs::FileTypeExt}, path::{Path, PathBuf}};
+use crate::{AppState, Message};
+
+fn mkfifo(path: &Path) -> std::io::Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Reads newline separated JSON from a FIFO (made if it doesn't exist yet) and
+/// passes each object to on_external. Anything can write to it, e.g.
+/// `echo '{"event":"reload"}' > /tmp/handcake`
+pub fn listen(state: &AppState, path: &Path) -> anyhow::Result<()> {
+    match std::fs::metadata(path) {
+        Ok(meta) if !meta.file_type().is_fifo() => anyhow::bail!("{:?} exists and isn't a FIFO", path),
+        Ok(_) => {},
+        Err(_) => mkfifo(path)?,
+    }
+    info!("Reading messages from {:?}", path);
+
+    let path = PathBuf::from(path);
+    let sender = state.sender.clone();
+    std::thread::spawn(move || loop {
+        // Blocks until something opens the other end, and hits EOF once every
+        // writer has closed it, at which point it just gets opened again
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("Stopped reading {:?}: {}", path, e);
+                return;
+            },
+        };
+
+        for line in BufReader::new(file).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    warn!("Error reading {:?}: {}", path, e);
+                    break;
+                },
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match serde_json::from_str(&line) {
+                Ok(value) => {
+                    if sender.send(Message::External(value)).is_err() {
+                        return;
+                    }
+                },
+                Err(e) => warn!("Ignoring bad JSON from {:?}: {}", path, e),
+            }
+        }
+    });
+
+    Ok(())
+}
\ No newline at end of file
@@ -1,6 +1,5 @@
-use std::{sync::Arc, fs::File};
+use std::{collections::HashMap, sync::Arc, path::PathBuf};
 use input_linux::{
-    UInputHandle,
     EventKind,
     Key,
     AbsoluteAxis,
@@ -11,50 +10,297 @@ use input_linux::{
     KeyEvent,
     KeyState,
     EventTime,
-    SynchronizeEvent,
-    SynchronizeKind, AbsoluteEvent
+    AbsoluteEvent,
 };
 use parking_lot::Mutex;
-use super::ApiProvider;
+use super::{ApiProvider, DeviceInfo, SyncedDevice, UInput};
+
+mod constants;
+
+use constants::{AXES, BUTTONS};
+
+api_entries! { "gamepad",
+    "btn" => "fun(name: string): integer", "The code for a button name, e.g. \"SOUTH\" or \"BTN_SOUTH\"";
+    "create" => "fun(id?: string, name?: string): Gamepad", "Makes another virtual gamepad, id is \"vendor:product\" in hex";
+    "release_all" => "fun()", "Releases every button that's down, on every gamepad";
+    "Gamepad:button" => "fun(button: integer, pressed: boolean)", "Presses or releases a button";
+    "Gamepad:axis" => "fun(axis: integer, value: number)", "Moves an axis, -1.0 to 1.0, 0.0 to 1.0 for ABS_Z and ABS_RZ or -1/0/1 for hats";
+    "Gamepad:axis_raw" => "fun(axis: integer, value: number)", "Same as axis, skipping anything set_axis_config set up";
+    "Gamepad:set_axis_config" => "fun(axis: integer, config?: AxisConfig)", "Centres, dead-zones and scales what axis() is given for this axis, nil turns it off";
+    "Gamepad:trigger" => "fun(which: \"left\"|\"right\", value: integer)", "Presses a trigger, 0 to gamepad.TRIGGER_MAX";
+    "Gamepad:hat" => "fun(hat: integer, x: integer, y: integer)", "Moves a hat";
+    "Gamepad:button_state" => "fun(button: integer): boolean", "Whether a button was last pressed or released";
+    "Gamepad:all_buttons" => "fun(): table<integer, boolean>", "Every button that's been pressed or released, and which it was last";
+    "Gamepad:axis_state" => "fun(axis: integer): number", "Where an axis was last moved to, 0 if it hasn't been";
+    "Gamepad:begin_batch" => "fun()", "Holds back writes until end_batch";
+    "Gamepad:end_batch" => "fun()", "Sends everything since begin_batch at once";
+}
 
 fn i32_to_key(a: i32) -> Key {
-    match a {
-        _ if a == (Key::ButtonSouth as i32) => Key::ButtonSouth, 
-        _ if a == (Key::ButtonEast as i32) => Key::ButtonEast, 
-        _ if a == (Key::ButtonWest as i32) => Key::ButtonWest, 
-        _ if a == (Key::ButtonNorth as i32) => Key::ButtonNorth, 
-        _ if a == (Key::ButtonStart as i32) => Key::ButtonStart, 
-        _ if a == (Key::ButtonSelect as i32) => Key::ButtonSelect, 
-        _ if a == (Key::ButtonMode as i32) => Key::ButtonMode, 
-        _ if a == (Key::ButtonTL as i32) => Key::ButtonTL, 
-        _ if a == (Key::ButtonTR as i32) => Key::ButtonTR, 
-        _ if a == (Key::ButtonThumbl as i32) => Key::ButtonThumbl, 
-        _ if a == (Key::ButtonThumbr as i32) => Key::ButtonThumbr,
-        _ => Key::Unknown
-    }
+    BUTTONS.iter()
+        .find(|(_, code)| *code as i32 == a)
+        .and_then(|(_, code)| Key::from_code(*code).ok())
+        .unwrap_or(Key::Unknown)
 }
 
 fn i32_to_absaxis(a: i32) -> AbsoluteAxis {
-    match a {
-        _ if a == (AbsoluteAxis::X as i32) => AbsoluteAxis::X,
-        _ if a == (AbsoluteAxis::Y as i32) => AbsoluteAxis::Y,
-        _ if a == (AbsoluteAxis::RX as i32) => AbsoluteAxis::RX,
-        _ if a == (AbsoluteAxis::RY as i32) => AbsoluteAxis::RY,
-        _ if a == (AbsoluteAxis::Hat2Y as i32) => AbsoluteAxis::Hat2Y,
-        _ if a == (AbsoluteAxis::Hat2X as i32) => AbsoluteAxis::Hat2X,
-        _ if a == (AbsoluteAxis::Hat0Y as i32) => AbsoluteAxis::Hat0Y,
-        _ if a == (AbsoluteAxis::Hat0X as i32) => AbsoluteAxis::Hat0X,
-        _ => AbsoluteAxis::Reserved,
+    AXES.iter()
+        .find(|(_, code)| *code as i32 == a)
+        .and_then(|(_, code)| AbsoluteAxis::from_code(*code).ok())
+        .unwrap_or(AbsoluteAxis::Reserved)
+}
+
+// Hat 2 is taken by the analogue triggers, so only these two are real hats
+const HATS: [(AbsoluteAxis, AbsoluteAxis); 2] = [
+    (AbsoluteAxis::Hat0X, AbsoluteAxis::Hat0Y),
+    (AbsoluteAxis::Hat1X, AbsoluteAxis::Hat1Y),
+];
+
+fn is_hat(axis: AbsoluteAxis) -> bool {
+    HATS.iter().any(|(x, y)| *x == axis || *y == axis)
+}
+
+// Sticks go from -STICK_MAX to STICK_MAX
+const STICK_MAX: i32 = 32767;
+
+// Pressure triggers, 0 to 255 like an Xbox controller instead of the sticks' range
+const TRIGGER_MAX: i32 = 255;
+
+fn is_trigger(axis: AbsoluteAxis) -> bool {
+    axis == AbsoluteAxis::Z || axis == AbsoluteAxis::RZ
+}
+
+// What each gamepad was last told, so scripts can ask without keeping track
+// themselves. Keyed by the codes the script passed in.
+#[derive(Default)]
+struct PadState {
+    buttons: HashMap<u16, bool>,
+    axes: HashMap<u16, f32>,
+    axis_config: HashMap<u16, AxisConfig>,
+}
+
+// From pad.set_axis_config, what pad.axis() does to a value before sending it
+struct AxisConfig {
+    center: f32,
+    deadzone: f32,
+    sensitivity: f32,
+}
+
+impl AxisConfig {
+    fn from_table(config: &mlua::Table) -> mlua::Result<Self> {
+        let config = AxisConfig {
+            center: config.get::<_, Option<f32>>("center")?.unwrap_or(0.0),
+            deadzone: config.get::<_, Option<f32>>("deadzone")?.unwrap_or(0.0),
+            sensitivity: config.get::<_, Option<f32>>("sensitivity")?.unwrap_or(1.0),
+        };
+        if config.deadzone < 0.0 {
+            return Err(mlua::Error::RuntimeError(format!("Invalid deadzone {}, expected 0 or more", config.deadzone)));
+        }
+
+        Ok(config)
+    }
+
+    // Centred and divided by center, so a CC centred on 64 ends up -1.0 to about 1.0.
+    // The deadzone is in the same units as the value coming in.
+    fn apply(&self, value: f32) -> f32 {
+        let value = value - self.center;
+        if value.abs() < self.deadzone {
+            return 0.0;
+        }
+        let range = if self.center.abs() > 0.0 { self.center.abs() } else { 1.0 };
+
+        (value / range * self.sensitivity).clamp(-1.0, 1.0)
+    }
+}
+
+// Sends an axis value, -1.0 to 1.0 for sticks, 0.0 to 1.0 for triggers and -1/0/1 for hats
+fn write_axis(uinput: &Mutex<SyncedDevice>, pad_state: &Mutex<PadState>, code: i32, value: f32) -> mlua::Result<()> {
+    let ui = uinput.lock();
+    const ZERO: EventTime = EventTime::new(0, 0);
+    let axis = i32_to_absaxis(code);
+    let axis_value: i32 = if is_hat(axis) {
+        let value = value.round().clamp(-1.0, 1.0);
+        pad_state.lock().axes.insert(code as u16, value);
+        value as i32
+    } else if is_trigger(axis) {
+        let value = value.clamp(0.0, 1.0);
+        pad_state.lock().axes.insert(code as u16, value);
+        (TRIGGER_MAX as f32 * value).round() as i32
+    } else {
+        let value = value.clamp(-1.0, 1.0);
+        pad_state.lock().axes.insert(code as u16, value);
+        (STICK_MAX as f32 * value).round() as i32
+    };
+    let event = [
+        *InputEvent::from(AbsoluteEvent::new(ZERO, axis, axis_value)).as_raw(),
+    ];
+    ui.write(&event)?;
+
+    Ok(())
+}
+
+// A gamepad's device and what it was last told
+type Pad = (Arc<Mutex<SyncedDevice>>, Arc<Mutex<PadState>>);
+
+/// Every gamepad the script has created, so they can be torn down on exit
+struct Gamepads(Vec<Pad>);
+
+/// Removes every virtual gamepad the script created. Dropping the VM closes
+/// the handles too, but this doesn't wait for the garbage collector.
+pub fn destroy_all(l: &mlua::Lua) {
+    if let Some(gamepads) = l.app_data_ref::<Gamepads>() {
+        for (uinput, _) in &gamepads.0 {
+            if let Err(e) = uinput.lock().uinput.dev_destroy() {
+                warn!("Could not destroy virtual gamepad: {}", e);
+            }
+        }
+    }
+}
+
+/// Releases every button that's down on every gamepad, for the panic button.
+/// Axes stay where they are.
+pub fn release_all(l: &mlua::Lua) -> mlua::Result<()> {
+    let gamepads = match l.app_data_ref::<Gamepads>() {
+        Some(gamepads) => gamepads.0.clone(),
+        None => return Ok(()),
+    };
+    const ZERO: EventTime = EventTime::new(0, 0);
+    for (uinput, pad_state) in gamepads {
+        let mut pad_state = pad_state.lock();
+        let events: Vec<_> = pad_state.buttons.iter_mut()
+            .filter(|(_, pressed)| **pressed)
+            .map(|(code, pressed)| {
+                *pressed = false;
+                *InputEvent::from(KeyEvent::new(ZERO, i32_to_key(*code as i32), KeyState::RELEASED)).as_raw()
+            })
+            .collect();
+        if !events.is_empty() {
+            uinput.lock().write(&events)?;
+        }
     }
+
+    Ok(())
+}
+
+// Sets up the buttons and axes and creates the device
+fn create_device(uinput: &dyn UInput, info: &DeviceInfo) -> std::io::Result<()> {
+    // https://docs.kernel.org/input/gamepad.html
+
+    // Buttons
+    uinput.set_evbit(EventKind::Key)?;
+    for (_, code) in BUTTONS {
+        uinput.set_keybit(i32_to_key(*code as i32))?;
+    }
+
+    // Axes
+    uinput.set_evbit(EventKind::Absolute)?;
+    uinput.set_absbit(AbsoluteAxis::X)?; // LS X
+    uinput.set_absbit(AbsoluteAxis::Y)?; // LS Y
+    uinput.set_absbit(AbsoluteAxis::Z)?; // Left trigger (pressure)
+    uinput.set_absbit(AbsoluteAxis::RX)?; // RS X
+    uinput.set_absbit(AbsoluteAxis::RY)?; // RS Y
+    uinput.set_absbit(AbsoluteAxis::RZ)?; // Right trigger (pressure)
+
+    uinput.set_absbit(AbsoluteAxis::Hat0X)?; // D-pad left/right (-/+)
+    uinput.set_absbit(AbsoluteAxis::Hat0Y)?; // D-pad up/down (-/+)
+    uinput.set_absbit(AbsoluteAxis::Hat1X)?;
+    uinput.set_absbit(AbsoluteAxis::Hat1Y)?;
+
+    // Create the uinput device
+    let input_id = InputId {
+        bustype: input_linux::sys::BUS_USB,
+        vendor: info.vendor,
+        product: info.product,
+        version: 0,
+    };
+
+    const JOYSTICK: AbsoluteInfo = AbsoluteInfo {
+        flat: 128, // Deadzone
+        value: 0,
+        minimum: -STICK_MAX,
+        maximum: STICK_MAX,
+        fuzz: 16,
+        resolution: 10,
+    };
+
+    const PRESSURE: AbsoluteInfo = AbsoluteInfo {
+        flat: 0,
+        value: 0,
+        minimum: 0,
+        maximum: TRIGGER_MAX,
+        fuzz: 0,
+        resolution: 0,
+    };
+
+    // Hats are digital, -1/0/1 only
+    const HAT: AbsoluteInfo = AbsoluteInfo {
+        flat: 0,
+        value: 0,
+        minimum: -1,
+        maximum: 1,
+        fuzz: 0,
+        resolution: 0,
+    };
+
+    uinput.create(&input_id, info.name.as_bytes(), 0, &[
+        AbsoluteInfoSetup {
+            axis: AbsoluteAxis::X,
+            info: JOYSTICK,
+        },
+        AbsoluteInfoSetup {
+            axis: AbsoluteAxis::Y,
+            info: JOYSTICK,
+        },
+        AbsoluteInfoSetup {
+            axis: AbsoluteAxis::Z,
+            info: PRESSURE,
+        },
+        AbsoluteInfoSetup {
+            axis: AbsoluteAxis::RX,
+            info: JOYSTICK,
+        },
+        AbsoluteInfoSetup {
+            axis: AbsoluteAxis::RY,
+            info: JOYSTICK,
+        },
+        AbsoluteInfoSetup {
+            axis: AbsoluteAxis::RZ,
+            info: PRESSURE,
+        },
+        AbsoluteInfoSetup {
+            axis: AbsoluteAxis::Hat0X,
+            info: HAT,
+        },
+        AbsoluteInfoSetup {
+            axis: AbsoluteAxis::Hat0Y,
+            info: HAT,
+        },
+        AbsoluteInfoSetup {
+            axis: AbsoluteAxis::Hat1X,
+            info: HAT,
+        },
+        AbsoluteInfoSetup {
+            axis: AbsoluteAxis::Hat1Y,
+            info: HAT,
+        },
+    ])?;
+
+    Ok(())
 }
 
 pub struct Gamepad;
+impl Gamepad {
+    pub fn default_device() -> DeviceInfo {
+        // Microsoft Corp., Xbox Wireless Controller
+        DeviceInfo::new("handcake Virtual Controller", 0x045e, 0x0b12)
+    }
+}
+
 impl ApiProvider for Gamepad {
-    type Arguments = (UInputHandle<File>,);
+    type Arguments = (PathBuf, DeviceInfo, usize);
 
     fn register_api(l: &mlua::Lua, args: Self::Arguments) -> anyhow::Result<()> {
-        let (uinput,) = args;
-        let outest = Arc::new(Mutex::new(uinput));
+        let (uinput_path, info, max_gamepads) = args;
 
         let tab = l.create_table()?;
 
@@ -107,123 +353,78 @@ impl ApiProvider for Gamepad {
         tab.set("AXIS_LSTICK_Y", AbsoluteAxis::Y as i32)?;
         tab.set("AXIS_RSTICK_X", AbsoluteAxis::RX as i32)?;
         tab.set("AXIS_RSTICK_Y", AbsoluteAxis::RY as i32)?;
-        tab.set("AXIS_LTRIGGER", AbsoluteAxis::Hat2Y as i32)?;
-        tab.set("AXIS_RTRIGGER", AbsoluteAxis::Hat2X as i32)?;
+        tab.set("AXIS_LTRIGGER", AbsoluteAxis::Z as i32)?;
+        tab.set("AXIS_RTRIGGER", AbsoluteAxis::RZ as i32)?;
         tab.set("AXIS_DPAD_X", AbsoluteAxis::Hat0X as i32)?;
         tab.set("AXIS_DPAD_Y", AbsoluteAxis::Hat0Y as i32)?;
 
+        // Raw kernel names
+        tab.set("ABS_X", AbsoluteAxis::X as i32)?;
+        tab.set("ABS_Y", AbsoluteAxis::Y as i32)?;
+        tab.set("ABS_Z", AbsoluteAxis::Z as i32)?;
+        tab.set("ABS_RX", AbsoluteAxis::RX as i32)?;
+        tab.set("ABS_RY", AbsoluteAxis::RY as i32)?;
+        tab.set("ABS_RZ", AbsoluteAxis::RZ as i32)?;
+        tab.set("TRIGGER_MAX", TRIGGER_MAX)?;
+
+        // gamepad.BTN.SOUTH, gamepad.ABS.X etc, see constants.rs for the full list
+        let btn = l.create_table()?;
+        for (name, code) in BUTTONS {
+            btn.set(*name, *code)?;
+        }
+        tab.set("BTN", btn)?;
+        let abs = l.create_table()?;
+        for (name, code) in AXES {
+            abs.set(*name, *code)?;
+        }
+        tab.set("ABS", abs)?;
+
+        tab.set("release_all", l.create_function(|l, _: ()| release_all(l))?)?;
+
+        tab.set("btn", l.create_function(|_l, (name,): (String,)| {
+            constants::lookup(BUTTONS, "BTN_", &name)
+                .ok_or_else(|| mlua::Error::RuntimeError(format!("Unknown gamepad button {:?}", name)))
+        })?)?;
+
         {
-            let outer = outest.clone();
-            tab.set("create", l.create_function(move |l, (id,): (Option<String>,)| {
-                let uinput = outer.lock();
-
-                // https://docs.kernel.org/input/gamepad.html
-
-                // Buttons
-                uinput.set_evbit(EventKind::Key)?;
-                uinput.set_keybit(Key::ButtonSouth)?; // A
-                uinput.set_keybit(Key::ButtonEast)?; // B
-                uinput.set_keybit(Key::ButtonNorth)?; // Y
-                uinput.set_keybit(Key::ButtonWest)?; // X
-
-                uinput.set_keybit(Key::ButtonStart)?; // Start/Menu
-                uinput.set_keybit(Key::ButtonSelect)?; // Select/View
-
-                uinput.set_keybit(Key::ButtonTL)?; // LB
-                uinput.set_keybit(Key::ButtonTR)?; // RB
-                uinput.set_keybit(Key::ButtonTL2)?; // LT
-                uinput.set_keybit(Key::ButtonTR2)?; // RT
-
-                uinput.set_keybit(Key::ButtonThumbl)?; // LS button (left stick click)
-                uinput.set_keybit(Key::ButtonThumbr)?; // RS button (right stick click)
-
-                uinput.set_keybit(Key::ButtonMode)?; // Xbox button
-                
-                // Axes
-                uinput.set_evbit(EventKind::Absolute)?;
-                uinput.set_absbit(AbsoluteAxis::X)?; // LS X
-                uinput.set_absbit(AbsoluteAxis::Y)?; // LS Y
-                uinput.set_absbit(AbsoluteAxis::RX)?; // RS X
-                uinput.set_absbit(AbsoluteAxis::RY)?; // RS Y
-
-                uinput.set_absbit(AbsoluteAxis::Hat2Y)?; // Left trigger (analogue)
-                uinput.set_absbit(AbsoluteAxis::Hat2X)?; // Right trigger (analogue)
-
-                uinput.set_absbit(AbsoluteAxis::Hat0X)?; // D-pad left/right (-/+)
-                uinput.set_absbit(AbsoluteAxis::Hat0Y)?; // D-pad up/down (-/+)
-
-                let mut vendor = 0x045e; // Microsoft Corp.
-                let mut product = 0x0b12; // Xbox Wireless Controller
+            tab.set("create", l.create_function(move |l, (id, name): (Option<String>, Option<String>)| {
+                let count = l.app_data_ref::<Gamepads>().map(|g| g.0.len()).unwrap_or(0);
+                if count >= max_gamepads {
+                    return Err(mlua::Error::RuntimeError(format!("Can't create more than {} gamepads, see --max-gamepads", max_gamepads)));
+                }
+
+                let mut info = info.clone();
                 if let Some(id) = id {
                     let a = id.split(":").collect::<Vec<&str>>();
                     let (ven, prd) = (a[0usize], a[1usize]);
-                    vendor = u16::from_str_radix(ven, 16).unwrap();
-                    product = u16::from_str_radix(prd, 16).unwrap();
+                    info.vendor = u16::from_str_radix(ven, 16).unwrap();
+                    info.product = u16::from_str_radix(prd, 16).unwrap();
                 }
 
-                // Create the uinput device
-                let input_id = InputId {
-                    bustype: input_linux::sys::BUS_USB,
-                    vendor,
-                    product,
-                    version: 0,
-                };
-                let device_name = b"handcake Virtual Controller";
-
-                const JOYSTICK: AbsoluteInfo = AbsoluteInfo {
-                    flat: 0, // Deadzone
-                    value: 0,
-                    minimum: -32767,
-                    maximum: 32767,
-                    fuzz: 0,
-                    resolution: 10,
+                // Extra gamepads get numbered so they can be told apart
+                info.name = match name {
+                    Some(name) => name,
+                    None if count > 0 => format!("{} {}", info.name, count + 1),
+                    None => info.name,
                 };
 
-                const TRIGGER: AbsoluteInfo = AbsoluteInfo {
-                    flat: 0,
-                    ..JOYSTICK
-                };
-
-                uinput.create(&input_id, device_name, 0, &[
-                    AbsoluteInfoSetup {
-                        axis: AbsoluteAxis::X,
-                        info: JOYSTICK,
-                    },
-                    AbsoluteInfoSetup {
-                        axis: AbsoluteAxis::Y,
-                        info: JOYSTICK,
-                    },
-                    AbsoluteInfoSetup {
-                        axis: AbsoluteAxis::RX,
-                        info: JOYSTICK,
-                    },
-                    AbsoluteInfoSetup {
-                        axis: AbsoluteAxis::RY,
-                        info: JOYSTICK,
-                    },
-                    AbsoluteInfoSetup {
-                        axis: AbsoluteAxis::Hat2Y,
-                        info: TRIGGER,
-                    },
-                    AbsoluteInfoSetup {
-                        axis: AbsoluteAxis::Hat2X,
-                        info: TRIGGER,
-                    },
-                    AbsoluteInfoSetup {
-                        axis: AbsoluteAxis::Hat0X,
-                        info: JOYSTICK,
-                    },
-                    AbsoluteInfoSetup {
-                        axis: AbsoluteAxis::Hat0Y,
-                        info: JOYSTICK,
-                    },
-                ])?;
+                let uinput = super::open_uinput(&uinput_path)
+                    .map_err(|e| mlua::Error::RuntimeError(format!("Could not open {}: {}", uinput_path.display(), e)))?;
+                create_device(uinput.as_ref(), &info)?;
+                let outest = Arc::new(Mutex::new(SyncedDevice::new(uinput)));
+                let pad_state = Arc::new(Mutex::new(PadState::default()));
+                let pad = (outest.clone(), pad_state.clone());
+                match l.app_data_mut::<Gamepads>() {
+                    Some(mut gamepads) => gamepads.0.push(pad),
+                    None => { l.set_app_data(Gamepads(vec![pad])); },
+                }
 
                 let tab = l.create_table()?;
 
                 {
-                    let uinput = outest.clone();
+                    let (uinput, pad_state) = (outest.clone(), pad_state.clone());
                     tab.set("button", l.create_function(move |_l, (key, state): (i32, bool)| {
+                        pad_state.lock().buttons.insert(key as u16, state);
                         let ui = uinput.lock();
                         const ZERO: EventTime = EventTime::new(0, 0);
                         let event = [
@@ -231,7 +432,6 @@ impl ApiProvider for Gamepad {
                                 true => KeyState::PRESSED,
                                 false => KeyState::RELEASED
                             })).as_raw(),
-                            *InputEvent::from(SynchronizeEvent::new(ZERO, SynchronizeKind::Report, 0)).as_raw(),
                         ];
                         ui.write(&event)?;
     
@@ -240,20 +440,104 @@ impl ApiProvider for Gamepad {
                 }
 
                 {
-                    let uinput = outest.clone();
-                    tab.set("axis", l.create_function(move |_l, (axis, value): (i32, f32)| {
+                    let (uinput, pad_state) = (outest.clone(), pad_state.clone());
+                    tab.set("axis", l.create_function(move |_l, (code, value): (i32, f32)| {
+                        let value = match pad_state.lock().axis_config.get(&(code as u16)) {
+                            Some(config) => config.apply(value),
+                            None => value,
+                        };
+                        write_axis(&uinput, &pad_state, code, value)
+                    })?)?;
+                }
+
+                {
+                    let (uinput, pad_state) = (outest.clone(), pad_state.clone());
+                    tab.set("axis_raw", l.create_function(move |_l, (code, value): (i32, f32)| {
+                        write_axis(&uinput, &pad_state, code, value)
+                    })?)?;
+                }
+
+                {
+                    // nil goes back to passing values straight through
+                    let pad_state = pad_state.clone();
+                    tab.set("set_axis_config", l.create_function(move |_l, (code, config): (i32, Option<mlua::Table>)| {
+                        let mut pad_state = pad_state.lock();
+                        match config {
+                            Some(config) => pad_state.axis_config.insert(code as u16, AxisConfig::from_table(&config)?),
+                            None => pad_state.axis_config.remove(&(code as u16)),
+                        };
+
+                        Ok(())
+                    })?)?;
+                }
+
+                {
+                    // Same as axis() on ABS_Z or ABS_RZ, but in the kernel's units
+                    let (uinput, pad_state) = (outest.clone(), pad_state.clone());
+                    tab.set("trigger", l.create_function(move |_l, (which, value): (String, i32)| {
+                        let axis = match which.as_str() {
+                            "left" => AbsoluteAxis::Z,
+                            "right" => AbsoluteAxis::RZ,
+                            _ => return Err(mlua::Error::RuntimeError(format!("Invalid trigger {:?}, expected \"left\" or \"right\"", which))),
+                        };
+                        let value = value.clamp(0, TRIGGER_MAX);
+                        pad_state.lock().axes.insert(axis as u16, value as f32 / TRIGGER_MAX as f32);
                         let ui = uinput.lock();
                         const ZERO: EventTime = EventTime::new(0, 0);
-                        let axis_value: i32 = (32768.0 * value).round() as i32;
                         let event = [
-                            *InputEvent::from(AbsoluteEvent::new(ZERO, i32_to_absaxis(axis), axis_value)).as_raw(),
-                            *InputEvent::from(SynchronizeEvent::new(ZERO, SynchronizeKind::Report, 0)).as_raw(),
+                            *InputEvent::from(AbsoluteEvent::new(ZERO, axis, value)).as_raw(),
                         ];
                         ui.write(&event)?;
 
                         Ok(())
                     })?)?;
                 }
+
+                {
+                    // Like button() and axis() this syncs by itself, unless it is part of a batch
+                    let (uinput, pad_state) = (outest.clone(), pad_state.clone());
+                    tab.set("hat", l.create_function(move |_l, (hat, x, y): (usize, i32, i32)| {
+                        let (hat_x, hat_y) = match HATS.get(hat) {
+                            Some(axes) => *axes,
+                            None => return Err(mlua::Error::RuntimeError(format!("Invalid hat {}, expected 0 to {}", hat, HATS.len() - 1))),
+                        };
+                        {
+                            let mut pad_state = pad_state.lock();
+                            pad_state.axes.insert(hat_x as u16, x.signum() as f32);
+                            pad_state.axes.insert(hat_y as u16, y.signum() as f32);
+                        }
+                        let ui = uinput.lock();
+                        const ZERO: EventTime = EventTime::new(0, 0);
+                        let event = [
+                            *InputEvent::from(AbsoluteEvent::new(ZERO, hat_x, x.signum())).as_raw(),
+                            *InputEvent::from(AbsoluteEvent::new(ZERO, hat_y, y.signum())).as_raw(),
+                        ];
+                        ui.write(&event)?;
+
+                        Ok(())
+                    })?)?;
+                }
+
+                // Only read what the calls above kept track of, nothing gets sent
+                {
+                    let pad_state = pad_state.clone();
+                    tab.set("button_state", l.create_function(move |_l, (key,): (i32,)| {
+                        Ok(pad_state.lock().buttons.get(&(key as u16)).copied().unwrap_or(false))
+                    })?)?;
+                }
+
+                {
+                    let pad_state = pad_state.clone();
+                    tab.set("all_buttons", l.create_function(move |_l, _: ()| {
+                        Ok(pad_state.lock().buttons.clone())
+                    })?)?;
+                }
+
+                tab.set("axis_state", l.create_function(move |_l, (axis,): (i32,)| {
+                    Ok(pad_state.lock().axes.get(&(axis as u16)).copied().unwrap_or(0.0))
+                })?)?;
+
+                super::register_batch(l, &tab, &outest)?;
     
                 Ok(tab)
             })?)?;
@@ -0,0 +1,43 @@
+use input_linux::{AbsoluteAxis, Key};
+
+/// Buttons on the virtual gamepad, exposed to Lua as gamepad.BTN.<name>, with
+/// the kernel's names minus the BTN_ prefix:
+/// SOUTH, EAST, NORTH, WEST, START, SELECT, MODE, TL, TR, TL2, TR2, THUMBL, THUMBR
+pub const BUTTONS: &[(&str, u16)] = &[
+    ("SOUTH", Key::ButtonSouth as u16),
+    ("EAST", Key::ButtonEast as u16),
+    ("NORTH", Key::ButtonNorth as u16),
+    ("WEST", Key::ButtonWest as u16),
+    ("START", Key::ButtonStart as u16),
+    ("SELECT", Key::ButtonSelect as u16),
+    ("MODE", Key::ButtonMode as u16),
+    ("TL", Key::ButtonTL as u16),
+    ("TR", Key::ButtonTR as u16),
+    ("TL2", Key::ButtonTL2 as u16),
+    ("TR2", Key::ButtonTR2 as u16),
+    ("THUMBL", Key::ButtonThumbl as u16),
+    ("THUMBR", Key::ButtonThumbr as u16),
+];
+
+/// Axes on the virtual gamepad, exposed to Lua as gamepad.ABS.<name>, with
+/// the kernel's names minus the ABS_ prefix:
+/// X, Y, Z, RX, RY, RZ, HAT0X, HAT0Y, HAT1X, HAT1Y
+pub const AXES: &[(&str, u16)] = &[
+    ("X", AbsoluteAxis::X as u16),
+    ("Y", AbsoluteAxis::Y as u16),
+    ("Z", AbsoluteAxis::Z as u16),
+    ("RX", AbsoluteAxis::RX as u16),
+    ("RY", AbsoluteAxis::RY as u16),
+    ("RZ", AbsoluteAxis::RZ as u16),
+    ("HAT0X", AbsoluteAxis::Hat0X as u16),
+    ("HAT0Y", AbsoluteAxis::Hat0Y as u16),
+    ("HAT1X", AbsoluteAxis::Hat1X as u16),
+    ("HAT1Y", AbsoluteAxis::Hat1Y as u16),
+];
+
+/// Looks a name up in BUTTONS or AXES. Case doesn't matter, and the BTN_/ABS_ prefix is optional.
+pub fn lookup(table: &[(&str, u16)], prefix: &str, name: &str) -> Option<u16> {
+    let name = name.to_ascii_uppercase();
+    let name = name.strip_prefix(prefix).unwrap_or(&name);
+    table.iter().find(|(n, _)| *n == name).map(|(_, code)| *code)
+}
\ No newline at end of file
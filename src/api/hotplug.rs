@@ -0,0 +1,162 @@
+use std::{collections::HashMap, path::{Path, PathBuf}, sync::Arc, time::Duration};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use parking_lot::Mutex;
+use crate::{AppState, Message};
+
+use super::midi;
+
+// Where the kernel puts raw MIDI devices, one midiCnDn per device
+const DEVICE_DIR: &str = "/dev/snd";
+
+const FIRST_RETRY: Duration = Duration::from_millis(100);
+const MAX_RETRY: Duration = Duration::from_secs(5);
+
+/// Whether a device node showed up or went away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceEvent {
+    Added,
+    Removed,
+}
+
+lazy_static::lazy_static! {
+    // Card names by device path, since by the time a device is removed its
+    // /proc/asound entry is already gone
+    static ref KNOWN: Mutex<HashMap<PathBuf, String>> = Mutex::new(HashMap::new());
+
+    // Ports that were open when their card was unplugged, by card name
+    static ref WAITING: Mutex<HashMap<String, Vec<String>>> = Mutex::new(HashMap::new());
+}
+
+fn is_midi_device(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with("midiC"))
+}
+
+// 100 ms, 200 ms, 400 ms... up to 5 s
+fn backoff(attempt: u32) -> Duration {
+    FIRST_RETRY.saturating_mul(2u32.saturating_pow(attempt)).min(MAX_RETRY)
+}
+
+fn card_name(path: &Path) -> Option<String> {
+    let name = midi::alsa_enumerate().into_iter()
+        .find(|device| device.path == path)
+        .map(|device| device.name)?;
+    KNOWN.lock().insert(path.to_path_buf(), name.clone());
+
+    Some(name)
+}
+
+fn tell_script(state: &AppState, message: Message) {
+    // Same as evdev and OSC, these wait for room in the queue rather than get lost
+    let _ = state.sender.send(message);
+}
+
+// Opens the ports a card had before it was unplugged, for as long as its device
+// is still there. The device node usually shows up a little before the
+// sequencer port does, hence the retries.
+fn reconnect(state: Arc<AppState>, path: PathBuf, card: String, ports: Vec<String>) {
+    std::thread::spawn(move || {
+        let mut pending = ports;
+        let mut attempt = 0;
+        while !pending.is_empty() {
+            std::thread::sleep(backoff(attempt));
+            attempt += 1;
+            if !path.exists() {
+                // Gone again, the next add event starts over
+                WAITING.lock().entry(card.clone()).or_default().append(&mut pending);
+                return;
+            }
+
+            pending.retain(|port| match midi::open_device(&state, port) {
+                Ok(()) => {
+                    info!("MIDI device {:?} is back", port);
+                    tell_script(&state, Message::DeviceConnected(port.clone()));
+                    false
+                },
+                Err(e) => {
+                    debug!("MIDI device {:?} isn't ready yet: {}", port, e);
+                    true
+                },
+            });
+        }
+    });
+}
+
+/// Handles a MIDI device node appearing or disappearing. Anything that was open
+/// on a removed card gets closed, and opened again once the same card is back.
+/// The script hears about both through on_device_connect and on_device_disconnect.
+pub fn device_event(state: &Arc<AppState>, path: &Path, event: DeviceEvent) {
+    match event {
+        DeviceEvent::Removed => {
+            let card = KNOWN.lock().remove(path);
+            let device = card.clone().unwrap_or_else(|| path.to_string_lossy().into_owned());
+            warn!("MIDI device {:?} was unplugged", device);
+
+            let closed = card.as_deref().map(midi::close_card).unwrap_or_default();
+            if closed.is_empty() {
+                tell_script(state, Message::DeviceDisconnected(device));
+            }
+            for port in &closed {
+                tell_script(state, Message::DeviceDisconnected(port.clone()));
+            }
+            if let Some(card) = card.filter(|_| !closed.is_empty()) {
+                WAITING.lock().entry(card).or_default().extend(closed);
+            }
+        },
+        DeviceEvent::Added => {
+            let card = match card_name(path) {
+                Some(card) => card,
+                None => {
+                    tell_script(state, Message::DeviceConnected(path.to_string_lossy().into_owned()));
+                    return;
+                },
+            };
+            info!("MIDI device {:?} was plugged in", card);
+
+            match WAITING.lock().remove(&card) {
+                Some(ports) => reconnect(state.clone(), path.to_path_buf(), card, ports),
+                // Not one that was open, it's up to the script whether to midi.open() it
+                None => tell_script(state, Message::DeviceConnected(card)),
+            }
+        },
+    }
+}
+
+/// Watches /dev/snd for MIDI devices coming and going, for as long as handcake runs
+pub fn watch(state: &Arc<AppState>) -> anyhow::Result<()> {
+    for device in midi::alsa_enumerate() {
+        KNOWN.lock().insert(device.path, device.name);
+    }
+
+    let (send, recv) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                warn!("Error watching {}: {}", DEVICE_DIR, e);
+                return;
+            },
+        };
+        let kind = match event.kind {
+            EventKind::Create(_) => DeviceEvent::Added,
+            EventKind::Remove(_) => DeviceEvent::Removed,
+            _ => return,
+        };
+        for path in event.paths.into_iter().filter(|path| is_midi_device(path)) {
+            let _ = send.send((path, kind));
+        }
+    })?;
+    watcher.watch(Path::new(DEVICE_DIR), RecursiveMode::NonRecursive)?;
+
+    let state = state.clone();
+    std::thread::spawn(move || {
+        // Keep the watcher alive for as long as the thread runs
+        let _watcher = watcher;
+        while let Ok((path, kind)) = recv.recv() {
+            device_event(&state, &path, kind);
+        }
+    });
+
+    Ok(())
+}
\ No newline at end of file
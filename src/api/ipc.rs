@@ -0,0 +1,115 @@
+use std::{io::{Read, Write}, os::unix::net::{UnixListener, UnixStream}, path::Path};
+use mlua::LuaSerdeExt;
+use crate::{AppState, Message};
+use super::ApiProvider;
+
+// Nothing sensible is anywhere near this big, it's only there so a bad length can't eat all the memory
+const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+
+// Every message is a 4 byte big endian length, then that many bytes of JSON
+fn read_message(stream: &mut UnixStream) -> std::io::Result<Option<serde_json::Value>> {
+    let mut len = [0; 4];
+    match stream.read_exact(&mut len) {
+        Ok(()) => {},
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_MESSAGE_LEN {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Message of {} bytes is too big", len)));
+    }
+    let mut data = vec![0; len];
+    stream.read_exact(&mut data)?;
+
+    Ok(Some(serde_json::from_slice(&data)?))
+}
+
+fn write_message(stream: &mut UnixStream, value: &serde_json::Value) -> std::io::Result<()> {
+    let data = serde_json::to_vec(value)?;
+    let mut frame = Vec::with_capacity(4 + data.len());
+    frame.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&data);
+    stream.write_all(&frame)
+}
+
+// What handcake --panic sends, handled by handcake itself instead of going to on_ipc_recv
+fn panic_message() -> serde_json::Value {
+    serde_json::json!({ "handcake": "panic" })
+}
+
+/// Tells the instance listening on path to panic, see Message::Panic
+pub fn send_panic(path: &Path) -> anyhow::Result<()> {
+    let mut stream = UnixStream::connect(path)
+        .map_err(|e| anyhow::anyhow!("Could not connect to {:?}: {}", path, e))?;
+    write_message(&mut stream, &panic_message())?;
+
+    Ok(())
+}
+
+/// Accepts connections from other handcake instances (or anything else that
+/// speaks the same framing) on a Unix socket and passes what they send to
+/// on_ipc_recv. A socket file left behind by an instance that's gone is replaced.
+pub fn listen(state: &AppState, path: &Path) -> anyhow::Result<()> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            anyhow::bail!("Another instance is already listening on {:?}", path);
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    info!("Listening for IPC messages on {:?}", path);
+
+    let sender = state.sender.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Could not accept IPC connection: {}", e);
+                    continue;
+                },
+            };
+            let sender = sender.clone();
+            // One thread per connection, there's only ever going to be a handful
+            std::thread::spawn(move || loop {
+                match read_message(&mut stream) {
+                    Ok(Some(value)) => {
+                        let message = if value == panic_message() { Message::Panic } else { Message::Ipc(value) };
+                        if sender.send(message).is_err() {
+                            return;
+                        }
+                    },
+                    Ok(None) => return,
+                    Err(e) => {
+                        warn!("Dropping IPC connection: {}", e);
+                        return;
+                    },
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+pub struct Ipc;
+impl ApiProvider for Ipc {
+    type Arguments = ();
+
+    fn register_api(l: &mlua::Lua, _args: Self::Arguments) -> anyhow::Result<()> {
+        let tab = l.create_table()?;
+
+        // ipc.send("/tmp/handcake-left.sock", { event = "sync" })
+        tab.set("send", l.create_function(|l, (path, value): (String, mlua::Value)| {
+            let value = l.from_value::<serde_json::Value>(value)?;
+            let mut stream = UnixStream::connect(&path)
+                .map_err(|e| mlua::Error::RuntimeError(format!("Could not connect to {:?}: {}", path, e)))?;
+            write_message(&mut stream, &value)
+                .map_err(|e| mlua::Error::RuntimeError(format!("Could not send to {:?}: {}", path, e)))
+        })?)?;
+
+        l.globals().set("ipc", tab)?;
+
+        Ok(())
+    }
+}
\ No newline at end of file
@@ -0,0 +1,196 @@
+use std::{collections::{HashMap, HashSet}, sync::Arc, time::Duration};
+use input_linux::{
+    EventKind,
+    Key,
+    InputId,
+    InputEvent,
+    KeyEvent,
+    KeyState,
+    EventTime,
+};
+use parking_lot::Mutex;
+use crate::AppState;
+use super::{ApiProvider, DeviceInfo, SyncedDevice, UInput, misc};
+
+fn code_to_key(code: u16) -> mlua::Result<Key> {
+    match Key::from_code(code) {
+        Ok(Key::Reserved) | Err(_) => Err(mlua::Error::RuntimeError(format!("Invalid keycode {}", code))),
+        Ok(key) => Ok(key),
+    }
+}
+
+fn write_key(ui: &SyncedDevice, key: Key, state: KeyState) -> std::io::Result<()> {
+    const ZERO: EventTime = EventTime::new(0, 0);
+    let event = [
+        *InputEvent::from(KeyEvent::new(ZERO, key, state)).as_raw(),
+    ];
+    ui.write(&event)?;
+
+    Ok(())
+}
+
+// Keys that have been pressed (or held) and not released yet, for release_all
+struct Pressed {
+    uinput: Arc<Mutex<SyncedDevice>>,
+    keys: Arc<Mutex<HashSet<u16>>>,
+    // Held keys and the interval that's repeating each one
+    held: Arc<Mutex<HashMap<u16, u64>>>,
+}
+
+/// Lets go of every key the script pressed or held, for the panic button
+pub fn release_all(l: &mlua::Lua) -> mlua::Result<()> {
+    let (uinput, keys, handles) = match l.app_data_ref::<Pressed>() {
+        Some(pressed) => {
+            let (mut keys, mut handles) = (HashSet::new(), Vec::new());
+            for (code, handle) in pressed.held.lock().drain() {
+                keys.insert(code);
+                handles.push(handle);
+            }
+            keys.extend(pressed.keys.lock().drain());
+            (pressed.uinput.clone(), keys, handles)
+        },
+        None => return Ok(()),
+    };
+    for handle in handles {
+        misc::clear_interval(l, handle)?;
+    }
+    let ui = uinput.lock();
+    for code in keys {
+        write_key(&ui, code_to_key(code)?, KeyState::RELEASED)?;
+    }
+
+    Ok(())
+}
+
+pub struct Keyboard;
+impl Keyboard {
+    pub fn default_device() -> DeviceInfo {
+        // Logitech, Inc. Keyboard K120
+        DeviceInfo::new("handcake Virtual Keyboard", 0x046d, 0xc31c)
+    }
+}
+
+impl ApiProvider for Keyboard {
+    type Arguments = (Box<dyn UInput>, DeviceInfo, Arc<AppState>);
+
+    fn register_api(l: &mlua::Lua, args: Self::Arguments) -> anyhow::Result<()> {
+        let (uinput, info, state) = args;
+
+        // Every keyboard key, no mouse/gamepad buttons
+        uinput.set_evbit(EventKind::Key)?;
+        for key in Key::iter().filter(|k| k.is_key() && *k != Key::Reserved) {
+            uinput.set_keybit(key)?;
+        }
+
+        let input_id = InputId {
+            bustype: input_linux::sys::BUS_USB,
+            vendor: info.vendor,
+            product: info.product,
+            version: 0,
+        };
+        uinput.create(&input_id, info.name.as_bytes(), 0, &[])?;
+
+        let outest = Arc::new(Mutex::new(SyncedDevice::new(uinput)));
+        let tab = l.create_table()?;
+        let pressed = Arc::new(Mutex::new(HashSet::<u16>::new()));
+
+        {
+            let (uinput, pressed) = (outest.clone(), pressed.clone());
+            tab.set("press", l.create_function(move |_l, (code,): (u16,)| {
+                let key = code_to_key(code)?;
+                write_key(&uinput.lock(), key, KeyState::PRESSED)?;
+                pressed.lock().insert(code);
+
+                Ok(())
+            })?)?;
+        }
+
+        {
+            let (uinput, pressed) = (outest.clone(), pressed.clone());
+            tab.set("release", l.create_function(move |_l, (code,): (u16,)| {
+                let key = code_to_key(code)?;
+                write_key(&uinput.lock(), key, KeyState::RELEASED)?;
+                pressed.lock().remove(&code);
+
+                Ok(())
+            })?)?;
+        }
+
+        {
+            let uinput = outest.clone();
+            tab.set("tap", l.create_function(move |_l, (code,): (u16,)| {
+                let key = code_to_key(code)?;
+                let ui = uinput.lock();
+                write_key(&ui, key, KeyState::PRESSED)?;
+                write_key(&ui, key, KeyState::RELEASED)?;
+
+                Ok(())
+            })?)?;
+        }
+
+        let held = Arc::new(Mutex::new(HashMap::<u16, u64>::new()));
+        // Initial delay and period, same as the usual X/Wayland defaults
+        let repeat = Arc::new(Mutex::new((Duration::from_millis(250), Duration::from_millis(33))));
+
+        {
+            let repeat = repeat.clone();
+            tab.set("set_repeat", l.create_function(move |_l, (initial_ms, period_ms): (u64, u64)| {
+                if period_ms == 0 {
+                    return Err(mlua::Error::RuntimeError("Repeat period has to be more than 0".into()));
+                }
+                // Keys that are already held keep their old rate
+                *repeat.lock() = (Duration::from_millis(initial_ms), Duration::from_millis(period_ms));
+
+                Ok(())
+            })?)?;
+        }
+
+        {
+            // Like press(), but keeps sending repeats like a real keyboard would until unhold()
+            let uinput = outest.clone();
+            let held = held.clone();
+            tab.set("hold", l.create_function(move |l, (code,): (u16,)| {
+                let key = code_to_key(code)?;
+                if held.lock().contains_key(&code) {
+                    return Ok(());
+                }
+                write_key(&uinput.lock(), key, KeyState::PRESSED)?;
+
+                let ui = uinput.clone();
+                let tick = l.create_function(move |_l, _: ()| {
+                    write_key(&ui.lock(), key, KeyState::AUTOREPEAT)?;
+                    Ok(())
+                })?;
+                let (initial, period) = *repeat.lock();
+                let handle = misc::start_interval(l, &state, initial, period, tick)?;
+                held.lock().insert(code, handle);
+
+                Ok(())
+            })?)?;
+        }
+
+        {
+            let (uinput, held) = (outest.clone(), held.clone());
+            tab.set("unhold", l.create_function(move |l, (code,): (u16,)| {
+                let key = code_to_key(code)?;
+                let handle = held.lock().remove(&code);
+                if let Some(handle) = handle {
+                    misc::clear_interval(l, handle)?;
+                    write_key(&uinput.lock(), key, KeyState::RELEASED)?;
+                }
+
+                Ok(())
+            })?)?;
+        }
+
+        // Same as unhold() and release() on everything that's down
+        tab.set("release_all", l.create_function(|l, _: ()| release_all(l))?)?;
+        l.set_app_data(Pressed { uinput: outest.clone(), keys: pressed, held });
+
+        super::register_batch(l, &tab, &outest)?;
+
+        l.globals().set("keyboard", tab)?;
+
+        Ok(())
+    }
+}
@@ -1,14 +1,71 @@
-use std::{sync::{Arc, mpsc::Sender}};
-use midi_control::MidiMessage;
-use midir::{Ignore, MidiInputConnection};
+use std::{collections::{HashMap, HashSet, VecDeque}, path::{Path, PathBuf}, sync::{Arc, atomic::Ordering, mpsc::TrySendError}, time::{Duration, Instant}};
+use midi_control::{MidiMessage, SysExEvent, message::SysExType};
+use midir::{Ignore, MidiInput, MidiInputConnection, MidiInputPort, os::unix::VirtualInput};
 use mlua::{Error::ExternalError};
 use parking_lot::Mutex;
-use crate::Message;
+use crate::{AppState, Message, MidiRealtime, queue, util};
 
 use super::ApiProvider;
 
+api_entries! { "midi",
+    "notes_held" => "fun(): HeldNote[]", "Every note that's currently down";
+    "is_held" => "fun(channel: integer, key: integer): boolean", "Whether a note is currently down";
+    "map" => "fun(value: number, in_min: number, in_max: number, out_min: number, out_max: number): number", "Maps value from one range to another, without clamping";
+    "clamp" => "fun(value: number, min: number, max: number): number", "Keeps value between min and max";
+    "curve" => "fun(value: number, gamma: number): number", "Bends a 0.0 to 1.0 value, above 1 makes the low end less sensitive";
+    "pitch_bend_to_f32" => "fun(value: integer): number", "Turns a 0 to 16383 pitch bend into -1.0 to 1.0";
+    "set_transpose" => "fun(semitones: integer)", "Shifts every incoming note, on top of set_octave";
+    "set_octave" => "fun(octaves: integer)", "Shifts every incoming note by whole octaves, on top of set_transpose";
+    "get_transpose" => "fun(): integer, integer", "The current transpose, in semitones and octaves";
+    "set_scale" => "fun(root?: string, mode?: string)", "Snaps every incoming note to the closest one in the scale, no root turns it off";
+    "set_octave_offset" => "fun(offset: integer)", "Shifts the octave numbers used by note_name and note_number";
+    "note_name" => "fun(number: integer): string", "Names a note number, e.g. \"C4\"";
+    "note_number" => "fun(name: string): integer", "The note number for a name like \"C#4\"";
+    "note_frequency" => "fun(number: number): number", "A note's frequency in Hz";
+    "set_layer" => "fun(layer: integer)", "Switches layer, which every MIDI event has as evt.layer";
+    "get_layer" => "fun(): integer", "The current layer, 0 to start with";
+    "set_auto_layer" => "fun(count?: integer)", "Program changes switch to layer program % count, nil turns it off";
+    "on_program_change" => "fun(f?: fun(program: integer, channel: integer))", "Called for program changes, before any layer switch";
+    "panic" => "fun()", "Sends all notes off and reset all controllers on every channel of --midi-out";
+    "set_thru" => "fun(enabled: boolean)", "Passes every MIDI message that comes in on to --midi-out as well";
+    "set_thru_filter" => "fun(channels: integer)", "Which channels thru passes on, bit 0 being channel 1";
+    "send_identity_request" => "fun()", "Asks the devices on --midi-out who they are, the answers go to midi.on_identity";
+    "on_identity" => "fun(f?: fun(identity: MidiIdentity))", "Called for identity replies, with the manufacturer, family, member and revision";
+    "learn" => "fun(f: fun(evt: MidiEvent))", "The next MIDI message goes to f instead of the usual callbacks";
+    "on_channel" => "fun(channel: integer, f?: fun(evt: MidiEvent))", "Handles a channel, or every channel with midi.ANY_CHANNEL. nil removes the handler";
+    "on_device" => "fun(pattern: string, f?: fun(evt: MidiEvent))", "Handles messages from devices whose name matches a Lua pattern, first match wins";
+    "on_note" => "fun(f?: fun(evt: MidiEvent))", "Handles note on and off";
+    "on_cc" => "fun(control: integer, f?: fun(evt: MidiEvent))", "Handles one CC";
+    "on_cc_change" => "fun(control: integer, f?: fun(evt: MidiEvent))", "Handles one CC, only when its value changes";
+    "on_cc_threshold" => "fun(control: integer, threshold: integer, direction: \"rising\"|\"falling\", f?: fun(evt: MidiEvent))", "Handles one CC, only when its value crosses threshold";
+    "on_pitch_bend" => "fun(f?: fun(evt: MidiEvent))", "Handles pitch bend";
+    "on_note_duration" => "fun(f?: fun(evt: MidiEvent))", "Handles note offs, with how long the note was held as evt.duration_ms";
+    "set_channel_mode" => "fun(mode: HandlerMode)", "Whether channel handlers also let on_midi_recv see their messages";
+    "set_device_mode" => "fun(mode: HandlerMode)", "Whether device handlers also let on_midi_recv see their messages";
+    "set_dispatch_mode" => "fun(mode: HandlerMode)", "Whether note, CC and pitch bend handlers also let on_midi_recv see their messages";
+    "enable_mpe" => "fun(main_channel: integer, zone_start: integer, zone_end: integer)", "Turns on on_mpe_note for an MPE zone";
+    "disable_mpe" => "fun()", "Turns off on_mpe_note";
+    "set_note_threshold" => "fun(channel: integer, threshold: integer)", "Drops note ons softer than threshold, channel 0 being all of them";
+    "set_note_velocity_mode" => "fun(mode: \"normal\"|\"gate\"|\"relative\", channel?: integer)", "What happens to the velocity of notes that get past the threshold";
+    "set_velocity_curve" => "fun(curve: (fun(vel: integer): integer)|integer[]|nil, channel?: integer)", "Remaps note velocities, with a function or a table of 128";
+    "enable_cc_smoothing" => "fun(control: integer, alpha: number)", "Smooths a CC's values, 1.0 turns smoothing off";
+    "set_chord_window" => "fun(ms: integer)", "Turns on on_chord, 0 turns it off";
+    "chord_name" => "fun(notes: (integer|HeldNote)[]): string?", "Names a chord, e.g. \"Cmaj7\"";
+    "start_arp" => "fun(pattern: fun(chord: HeldNote[]): integer?, bpm: number, options?: ArpOptions)", "Plays held notes one at a time, following a MIDI clock if there is one";
+    "stop_arp" => "fun()", "Stops the arpeggiator";
+    "tap_tempo" => "fun(channel: integer, note_or_cc: integer)", "Works out a tempo from a note or CC being tapped, see midi.tap_bpm";
+    "tap_bpm" => "fun(): number?", "Tempo of the last few taps, nil if there haven't been any for 4 seconds";
+    "bpm" => "fun(): number?", "Tempo of the incoming MIDI clock";
+    "beat_phase" => "fun(): number?", "How far through the current beat the MIDI clock is, 0.0 to 1.0";
+    "play_file" => "fun(path: string, options?: PlaybackOptions)", "Plays a standard MIDI file through the usual callbacks";
+    "stop_playback" => "fun()", "Stops midi.play_file";
+    "start_recording" => "fun(path: string, options?: RecordingOptions)", "Records MIDI input until midi.stop_recording";
+    "stop_recording" => "fun()", "Writes what's been recorded to the file";
+    "open" => "fun(port: integer)", "Opens another MIDI input";
+}
+
 #[derive(Debug)]
-struct MidiError(pub String);
+pub(super) struct MidiError(pub String);
 impl std::error::Error for MidiError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         None
@@ -24,21 +81,1808 @@ impl std::fmt::Display for MidiError {
     }
 }
 
+type MidiConnection = (String, MidiInputConnection<queue::Sender>);
+
+// Timing clock runs at 24 ticks per quarter note
+const CLOCKS_PER_BEAT: usize = 24;
+
+// Tempo tracking for midi.bpm() and midi.beat_phase(). Ticks are timestamped
+// as they arrive, before they wait in the message queue.
+#[derive(Default)]
+struct MidiClock {
+    ticks: VecDeque<Instant>,
+    // Ticks since the last start message
+    count: usize,
+}
+
+impl MidiClock {
+    fn tick(&mut self, at: Instant) {
+        if self.ticks.len() > CLOCKS_PER_BEAT {
+            self.ticks.pop_front();
+        }
+        self.ticks.push_back(at);
+        self.count += 1;
+    }
+
+    fn start(&mut self) {
+        self.count = 0;
+    }
+
+    fn interval(&self, now: Instant) -> Option<Duration> {
+        if self.ticks.len() < 2 {
+            return None;
+        }
+        let (first, last) = (self.ticks.front()?, self.ticks.back()?);
+        let interval = (*last - *first) / (self.ticks.len() - 1) as u32;
+        // A clock that's gone quiet for a whole beat has stopped, not slowed down
+        if now.saturating_duration_since(*last) > interval * CLOCKS_PER_BEAT as u32 {
+            return None;
+        }
+
+        Some(interval)
+    }
+
+    fn bpm(&self, now: Instant) -> Option<f64> {
+        self.interval(now).map(|i| 60.0 / (i.as_secs_f64() * CLOCKS_PER_BEAT as f64))
+    }
+
+    fn beat_phase(&self, now: Instant) -> Option<f64> {
+        let interval = self.interval(now)?;
+        let since_tick = now.saturating_duration_since(*self.ticks.back()?).as_secs_f64() / interval.as_secs_f64();
+        let ticks = ((self.count + CLOCKS_PER_BEAT - 1) % CLOCKS_PER_BEAT) as f64 + since_tick.min(1.0);
+
+        Some((ticks / CLOCKS_PER_BEAT as f64).min(1.0))
+    }
+}
+
 lazy_static::lazy_static! {
-    static ref MIDI_CONN: Arc<Mutex<Option<MidiInputConnection<Sender<Message>>>>> = Arc::new(Mutex::new(None));
+    // Connections outlive the Lua VM so they survive script reloads
+    static ref MIDI_CONN: Arc<Mutex<Vec<MidiConnection>>> = Arc::new(Mutex::new(vec![]));
+
+    static ref MIDI_CLOCK: Arc<Mutex<MidiClock>> = Arc::new(Mutex::new(MidiClock::default()));
+}
+
+fn new_input() -> Result<MidiInput, MidiError> {
+    let mut midi_in = MidiInput::new("handcake MIDI input").map_err(|e| MidiError(e.to_string()))?;
+    midi_in.ignore(Ignore::None);
+
+    Ok(midi_in)
+}
+
+fn forward_with(device: &str, data: &[u8], send: impl FnOnce(Message)) {
+    crate::record::record(device, data);
+    if data.len() == 1 {
+        if let Some(rt) = MidiRealtime::from_byte(data[0]) {
+            match rt {
+                MidiRealtime::TimingClock => MIDI_CLOCK.lock().tick(Instant::now()),
+                MidiRealtime::Start => MIDI_CLOCK.lock().start(),
+                _ => {},
+            }
+            send(Message::MidiRealtime { device: device.to_string(), message: rt });
+        }
+        return;
+    }
+
+    super::midi_file::record(data);
+    send(Message::Midi { device: device.to_string(), message: util::parse_midi(data) });
+}
+
+/// Tags a message with the port it came from and sends it to the dispatch thread.
+/// If the script is so far behind that the queue is full, the message is dropped
+/// rather than piling up, see --queue-depth.
+pub fn forward(device: &str, data: &[u8], sender: &queue::Sender) {
+    forward_with(device, data, |message| match sender.try_send(message) {
+        Ok(()) => {},
+        Err(TrySendError::Full(_)) => {
+            let dropped = crate::metrics::midi_event_dropped();
+            if dropped % 1000 == 1 {
+                warn!("Script can't keep up with MIDI input, {} messages dropped so far", dropped);
+            }
+        },
+        Err(TrySendError::Disconnected(_)) => panic!("Dispatch thread is gone"),
+    });
+}
+
+/// Same as forward, but waits for room in the queue instead, for --replay
+pub fn forward_blocking(device: &str, data: &[u8], sender: &queue::Sender) {
+    forward_with(device, data, |message| sender.send(message).unwrap());
+}
+
+// Every connection gets its own reader thread from midir, so all that's left
+// to do is tag messages with the port they came from
+fn connect_port(state: &AppState, midi_in: MidiInput, port: &MidiInputPort) -> Result<(), MidiError> {
+    let name = midi_in.port_name(port).map_err(|e| MidiError(e.to_string()))?;
+    if MIDI_CONN.as_ref().lock().iter().any(|(n, _)| *n == name) {
+        debug!("MIDI device {:?} is already open", name);
+        return Ok(());
+    }
+    let sender = state.sender.clone();
+
+    let device = name.clone();
+    let conn = midi_in.connect(port, &name, move |_ts, data, sender| forward(&device, data, sender), sender)
+        .map_err(|e| MidiError(e.to_string()))?;
+
+    MIDI_CONN.as_ref().lock().push((name.clone(), conn));
+    info!("Listening to MIDI device {:?}", name);
+
+    Ok(())
+}
+
+/// Creates an ALSA sequencer port called `name` that other clients can be
+/// routed into, e.g. with `aconnect`. Messages from it look like they came
+/// from a device called `name`.
+pub fn open_virtual(state: &AppState, name: &str) -> anyhow::Result<()> {
+    let midi_in = new_input()?;
+    let sender = state.sender.clone();
+
+    let device = name.to_string();
+    let conn = midi_in.create_virtual(name, move |_ts, data, sender| forward(&device, data, sender), sender)
+        .map_err(|e| MidiError(e.to_string()))?;
+
+    MIDI_CONN.as_ref().lock().push((name.to_string(), conn));
+    info!("Created MIDI sequencer port {:?}", name);
+
+    Ok(())
+}
+
+/// Names of every MIDI input, in port number order.
+pub fn list_devices() -> anyhow::Result<Vec<String>> {
+    let midi_in = new_input()?;
+    let names = midi_in.ports().iter()
+        .map(|p| midi_in.port_name(p).unwrap_or_else(|_| "<unknown>".into()))
+        .collect();
+
+    Ok(names)
+}
+
+/// Opens a MIDI input by port index or by (part of) its name.
+pub fn open_device(state: &AppState, selector: &str) -> anyhow::Result<()> {
+    let midi_in = new_input()?;
+    let in_ports = midi_in.ports();
+    let port = match selector.parse::<usize>() {
+        Ok(portno) => in_ports.get(portno),
+        Err(_) => in_ports.iter().find(|p| {
+            midi_in.port_name(p).map(|n| n.contains(selector)).unwrap_or(false)
+        }),
+    };
+    let port = match port {
+        Some(port) => port,
+        None => return Err(MidiError(format!("No MIDI device matching {:?}", selector)).into()),
+    };
+
+    connect_port(state, midi_in, port)?;
+
+    Ok(())
+}
+
+/// Closes every port of a card that's gone away, going by the card's name
+/// (ALSA names its ports "card:port"). Gives back the ports' names, to
+/// open again if it comes back.
+pub fn close_card(card: &str) -> Vec<String> {
+    let prefix = format!("{}:", card);
+    let mut conns = MIDI_CONN.as_ref().lock();
+    let (closed, open): (Vec<_>, Vec<_>) = conns.drain(..).partition(|(name, _)| name.starts_with(&prefix));
+    *conns = open;
+
+    closed.into_iter().map(|(name, _)| name).collect()
+}
+
+/// Closes every MIDI input, for shutting down
+pub fn close_all() {
+    for (name, conn) in MIDI_CONN.as_ref().lock().drain(..) {
+        debug!("Closing MIDI device {:?}", name);
+        conn.close();
+    }
+}
+
+/// A MIDI input the kernel knows about, from /proc/asound
+#[derive(Debug, Clone)]
+pub struct MidiDeviceInfo {
+    /// The raw MIDI device, e.g. /dev/snd/midiC1D0
+    pub path: PathBuf,
+    /// The card's name, which is also what its sequencer port is called
+    pub name: String,
+    pub subdevice_count: u8,
+}
+
+// Card lines in /proc/asound/cards look like " 1 [mini3          ]: USB-Audio - MPK mini 3",
+// followed by a line with the long name that doesn't start with a number
+fn parse_cards(text: &str) -> Vec<(u32, String)> {
+    text.lines()
+        .filter_map(|line| {
+            let (number, rest) = line.trim_start().split_once(' ')?;
+            let number = number.parse().ok()?;
+            let (_, name) = rest.split_once("]: ")?;
+            let name = name.split_once(" - ").map_or(name, |(_, name)| name);
+            Some((number, name.trim().to_owned()))
+        })
+        .collect()
+}
+
+fn enumerate_in(root: &Path) -> Vec<MidiDeviceInfo> {
+    let cards = match std::fs::read_to_string(root.join("cards")) {
+        Ok(text) => parse_cards(&text),
+        Err(_) => return Vec::new(),
+    };
+
+    let mut devices = Vec::new();
+    for (card, name) in cards {
+        let entries = match std::fs::read_dir(root.join(format!("card{}", card))) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        let mut numbers: Vec<u32> = entries
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.strip_prefix("midi")?.parse().ok())
+            .collect();
+        numbers.sort_unstable();
+
+        for number in numbers {
+            // Lists an "Input N" and/or "Output N" section for each subdevice
+            let info = std::fs::read_to_string(root.join(format!("card{}/midi{}", card, number))).unwrap_or_default();
+            let inputs = info.lines().filter(|line| line.starts_with("Input ")).count();
+            // Output only, nothing to listen to
+            if inputs == 0 {
+                continue;
+            }
+            devices.push(MidiDeviceInfo {
+                path: PathBuf::from(format!("/dev/snd/midiC{}D{}", card, number)),
+                name: name.clone(),
+                subdevice_count: inputs.min(u8::MAX as usize) as u8,
+            });
+        }
+    }
+
+    devices
+}
+
+/// Every MIDI input on every sound card, in card order
+pub fn alsa_enumerate() -> Vec<MidiDeviceInfo> {
+    enumerate_in(Path::new("/proc/asound"))
+}
+
+/// Which raw MIDI device each of `ports` (names from list_devices()) is, if any.
+/// A card's sequencer ports are named after it, e.g. "MPK mini 3:MPK mini 3 MIDI 1 20:0",
+/// one per subdevice in the same order /proc/asound lists them. Software ports like
+/// Midi Through don't have one.
+pub fn port_paths(ports: &[String], devices: &[MidiDeviceInfo]) -> Vec<Option<PathBuf>> {
+    let mut seen = HashMap::<&str, usize>::new();
+    ports.iter()
+        .map(|port| {
+            let card = port.split(':').next().unwrap_or_default();
+            let nth = seen.entry(card).or_default();
+            let path = devices.iter()
+                .filter(|device| device.name == card)
+                .flat_map(|device| std::iter::repeat_n(&device.path, device.subdevice_count as usize))
+                .nth(*nth)
+                .cloned();
+            *nth += 1;
+            path
+        })
+        .collect()
+}
+
+/// For when no MIDI input was given at all: opens the only one there is, or the
+/// first with `first`. With more than one, lists them so one can be picked instead
+/// of quietly going with whichever happens to be first.
+pub fn open_discovered(state: &AppState, first: bool) {
+    let devices = alsa_enumerate();
+    let device = match devices.as_slice() {
+        [] => {
+            debug!("No MIDI devices found to open");
+            return;
+        },
+        [device] => device,
+        [device, ..] if first => device,
+        devices => {
+            warn!("Found {} MIDI devices, pass --midi-device to pick one (or --auto-first for the first):", devices.len());
+            for device in devices {
+                warn!("    {:?} ({})", device.name, device.path.display());
+            }
+            return;
+        },
+    };
+
+    info!("No MIDI device given, using {:?} ({})", device.name, device.path.display());
+    // Through its sequencer port like any other, so midi.open() on the same one doesn't open it twice
+    if let Err(e) = open_device(state, &device.name) {
+        warn!("Could not open MIDI device {:?}: {}", device.name, e);
+    }
+}
+
+// Semitones up from the root, for midi.set_scale()
+const SCALES: &[(&str, &[u8])] = &[
+    ("chromatic", &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]),
+    ("major", &[0, 2, 4, 5, 7, 9, 11]),
+    ("minor", &[0, 2, 3, 5, 7, 8, 10]),
+    ("harmonic_minor", &[0, 2, 3, 5, 7, 8, 11]),
+    ("dorian", &[0, 2, 3, 5, 7, 9, 10]),
+    ("phrygian", &[0, 1, 3, 5, 7, 8, 10]),
+    ("lydian", &[0, 2, 4, 6, 7, 9, 11]),
+    ("mixolydian", &[0, 2, 4, 5, 7, 9, 10]),
+    ("locrian", &[0, 1, 3, 5, 6, 8, 10]),
+    ("pentatonic_major", &[0, 2, 4, 7, 9]),
+    ("pentatonic_minor", &[0, 3, 5, 7, 10]),
+    ("blues", &[0, 3, 5, 6, 7, 10]),
+];
+
+// Moves a note to the closest one in the scale, going down when it's right in between
+fn snap_to_scale(note: i32, root: u8, intervals: &[u8]) -> i32 {
+    let semitone = (note - root as i32).rem_euclid(12);
+    let shift = intervals.iter()
+        // The octave above (or below) can be closer, e.g. B for C in C major
+        .flat_map(|i| [*i as i32 - 12, *i as i32, *i as i32 + 12])
+        .map(|i| i - semitone)
+        .min_by_key(|shift| (shift.abs(), *shift > 0))
+        .unwrap_or(0);
+    note + shift
+}
+
+// Set with midi.set_transpose(), midi.set_octave() and midi.set_scale()
+#[derive(Default)]
+struct Transpose {
+    semitones: i8,
+    octave: i8,
+    // Root semitone (0 is C) and intervals
+    scale: Option<(u8, &'static [u8])>,
+    // Where each held note went when it was pressed, so its note off goes to the
+    // same place even if the transpose changed in between
+    held: HashMap<(u8, u8), u8>,
+}
+
+/// Shifts the key of note and poly pressure messages by the current transpose,
+/// then snaps it to the scale if there is one. Returns false for notes that would end up outside 0-127, which should be dropped.
+pub fn transpose(l: &mlua::Lua, message: &mut MidiMessage) -> bool {
+    let mut transpose = l.app_data_mut::<Transpose>().unwrap();
+    let offset = transpose.semitones as i32 + transpose.octave as i32 * 12;
+    // Note on, note off, or pressure on a note that's (hopefully) held
+    let (channel, key, on, off) = match message {
+        MidiMessage::NoteOn(channel, key) => {
+            let on = key.value > 0;
+            (channel, key, on, !on)
+        },
+        MidiMessage::NoteOff(channel, key) => (channel, key, false, true),
+        MidiMessage::PolyKeyPressure(channel, key) => (channel, key, false, false),
+        _ => return true,
+    };
+    let id = (*channel as u8, key.key);
+
+    let moved = match (on, transpose.held.get(&id)) {
+        (false, Some(moved)) => *moved as i32,
+        _ => match transpose.scale {
+            Some((root, intervals)) => snap_to_scale(key.key as i32 + offset, root, intervals),
+            None => key.key as i32 + offset,
+        },
+    };
+    if off {
+        transpose.held.remove(&id);
+    }
+    if !(0..=127).contains(&moved) {
+        debug!("Dropping note {} transposed out of range to {}", key.key, moved);
+        return false;
+    }
+    if on {
+        transpose.held.insert(id, moved as u8);
+    }
+    key.key = moved as u8;
+
+    true
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum VelocityMode {
+    Normal,
+    // Every note that gets through the threshold is full velocity
+    Gate,
+    // What's left above the threshold is stretched back out to 1-127
+    Relative,
+}
+
+impl VelocityMode {
+    fn parse(mode: &str) -> mlua::Result<Self> {
+        match mode {
+            "normal" => Ok(VelocityMode::Normal),
+            "gate" => Ok(VelocityMode::Gate),
+            "relative" => Ok(VelocityMode::Relative),
+            _ => Err(mlua::Error::RuntimeError(format!("Invalid velocity mode {:?}, expected \"normal\", \"gate\" or \"relative\"", mode))),
+        }
+    }
+}
+
+// Set with midi.set_note_threshold() and midi.set_note_velocity_mode(). Index 0 is
+// every channel, 1-16 override it for one channel.
+#[derive(Default)]
+struct NoteGates {
+    thresholds: [Option<u8>; 17],
+    modes: [Option<VelocityMode>; 17],
+    // Notes whose note on was too soft, so their note off (and pressure) goes too
+    suppressed: HashSet<(u8, u8)>,
+}
+
+impl NoteGates {
+    fn threshold(&self, channel: u8) -> u8 {
+        let channel = channel as usize + 1;
+        self.thresholds[channel].or(self.thresholds[0]).unwrap_or(0)
+    }
+
+    fn mode(&self, channel: u8) -> VelocityMode {
+        let channel = channel as usize + 1;
+        self.modes[channel].or(self.modes[0]).unwrap_or(VelocityMode::Normal)
+    }
+}
+
+fn gate_channel(channel: i8) -> mlua::Result<usize> {
+    if !(0..=16).contains(&channel) {
+        return Err(mlua::Error::RuntimeError(format!("Invalid MIDI channel {}, expected 0 to 16", channel)));
+    }
+    Ok(channel as usize)
+}
+
+/// Drops note ons softer than their channel's threshold, along with the rest of
+/// those notes, and applies the velocity mode to the ones that get through.
+/// Works on the velocity as it came in, before any velocity curve. Returns
+/// false for messages that should be dropped.
+pub fn note_gate(l: &mlua::Lua, message: &mut MidiMessage) -> bool {
+    let mut gates = l.app_data_mut::<NoteGates>().unwrap();
+    let (channel, key) = match message {
+        MidiMessage::NoteOn(channel, key) if key.value > 0 => (*channel as u8, key),
+        MidiMessage::NoteOn(channel, key) | MidiMessage::NoteOff(channel, key) => {
+            return !gates.suppressed.remove(&(*channel as u8, key.key));
+        },
+        MidiMessage::PolyKeyPressure(channel, key) => return !gates.suppressed.contains(&(*channel as u8, key.key)),
+        _ => return true,
+    };
+
+    let threshold = gates.threshold(channel);
+    if key.value < threshold {
+        gates.suppressed.insert((channel, key.key));
+        return false;
+    }
+    // In case the same key was retriggered without a note off in between
+    gates.suppressed.remove(&(channel, key.key));
+    key.value = match gates.mode(channel) {
+        VelocityMode::Normal => key.value,
+        VelocityMode::Gate => 127,
+        VelocityMode::Relative => {
+            let low = threshold.max(1) as u32;
+            if low >= 127 {
+                127
+            } else {
+                (1 + (key.value as u32 - low) * 126 / (127 - low)) as u8
+            }
+        },
+    };
+
+    true
+}
+
+// Velocity and when it was pressed, for each (channel, key) that's down
+type HeldNotes = HashMap<(i8, u8), (u8, Instant)>;
+
+/// Keeps midi.notes_held() and midi.is_held() up to date. Called for every
+/// message before it goes to the script. For note offs, gives back how long
+/// the note was held, zero if it never got a note on. Goes by the timers'
+/// clock, like tap tempo.
+pub fn track_notes(l: &mlua::Lua, state: &AppState, message: &MidiMessage) -> Option<Duration> {
+    let mut held = l.app_data_mut::<HeldNotes>()?;
+
+    match message {
+        // Plenty of controllers send note on with velocity 0 instead of note off
+        MidiMessage::NoteOn(channel, key) if key.value > 0 => {
+            held.insert((util::midi_channel_to_num(channel), key.key), (key.value, state.timers.now()));
+            None
+        },
+        MidiMessage::NoteOn(channel, key) | MidiMessage::NoteOff(channel, key) => {
+            let pressed = held.remove(&(util::midi_channel_to_num(channel), key.key));
+            Some(pressed.map_or(Duration::ZERO, |(_, at)| state.timers.now().saturating_duration_since(at)))
+        },
+        _ => None,
+    }
+}
+
+fn notes_table<'lua>(l: &'lua mlua::Lua, notes: impl Iterator<Item = (i8, u8, u8)>) -> mlua::Result<mlua::Table<'lua>> {
+    let tab = l.create_table()?;
+    for (i, (channel, key, vel)) in notes.enumerate() {
+        let note = l.create_table()?;
+        note.set("channel", channel)?;
+        note.set("key", key)?;
+        note.set("vel", vel)?;
+        tab.set(i + 1, note)?;
+    }
+
+    Ok(tab)
+}
+
+// Notes that arrive within `window` of the first one go to on_chord together
+#[derive(Default)]
+struct ChordDetector {
+    window: Option<Duration>,
+    notes: Vec<(i8, u8, u8)>,
+    // The timer that closes the current group, if one is open
+    timer: Option<u64>,
+}
+
+/// Feeds note ons to the chord detector, if midi.set_chord_window() turned it on
+pub fn track_chords(l: &mlua::Lua, state: &AppState, message: &MidiMessage) -> mlua::Result<()> {
+    let note = match message {
+        MidiMessage::NoteOn(channel, key) if key.value > 0 => (util::midi_channel_to_num(channel), key.key, key.value),
+        _ => return Ok(()),
+    };
+    let window = {
+        let mut chords = l.app_data_mut::<ChordDetector>().unwrap();
+        let window = match chords.window {
+            Some(window) => window,
+            None => return Ok(()),
+        };
+        chords.notes.push(note);
+        if chords.timer.is_some() {
+            return Ok(());
+        }
+        window
+    };
+
+    let close = l.create_async_function(|l, _: ()| async move {
+        let notes = {
+            let mut chords = l.app_data_mut::<ChordDetector>().unwrap();
+            chords.timer = None;
+            std::mem::take(&mut chords.notes)
+        };
+        match l.globals().get::<_, Option<mlua::Function>>("on_chord")? {
+            Some(on_chord) => on_chord.call_async::<_, ()>(notes_table(l, notes.into_iter())?).await,
+            None => Ok(()),
+        }
+    })?;
+    let handle = super::misc::schedule(l, state, window, close)?;
+    l.app_data_mut::<ChordDetector>().unwrap().timer = Some(handle);
+
+    Ok(())
+}
+
+// How many taps the tempo is averaged over
+const TAPS: usize = 4;
+// A gap this long means the next tap starts over
+const TAP_TIMEOUT: Duration = Duration::from_secs(4);
+
+// For midi.tap_tempo(), taps are the note or CC from `source`. Times come from
+// the timers' clock, so test.advance() can space taps out.
+#[derive(Default)]
+struct TapTempo {
+    // Channel (or ANY_CHANNEL) and note or CC number
+    source: Option<(i8, u8)>,
+    // The last few, oldest first
+    taps: VecDeque<Instant>,
+}
+
+impl TapTempo {
+    fn tap(&mut self, now: Instant) {
+        if self.taps.back().is_some_and(|last| now - *last > TAP_TIMEOUT) {
+            self.taps.clear();
+        }
+        if self.taps.len() == TAPS {
+            self.taps.pop_front();
+        }
+        self.taps.push_back(now);
+    }
+
+    fn bpm(&self, now: Instant) -> Option<f64> {
+        let (first, last) = (self.taps.front()?, self.taps.back()?);
+        if self.taps.len() < 2 || now.saturating_duration_since(*last) > TAP_TIMEOUT {
+            return None;
+        }
+        let interval = (*last - *first).as_secs_f64() / (self.taps.len() - 1) as f64;
+
+        Some(60.0 / interval)
+    }
+}
+
+/// Counts note ons and CCs (above 0, so not the release of a button) from
+/// whatever midi.tap_tempo() is listening to as taps
+pub fn track_taps(l: &mlua::Lua, state: &AppState, message: &MidiMessage) {
+    let mut tempo = match l.app_data_mut::<TapTempo>() {
+        Some(tempo) => tempo,
+        None => return,
+    };
+    let (channel, number) = match tempo.source {
+        Some(source) => source,
+        None => return,
+    };
+
+    let tapped = match message {
+        MidiMessage::NoteOn(c, key) => key.key == number && key.value > 0 && (channel == 0 || channel == util::midi_channel_to_num(c)),
+        MidiMessage::ControlChange(c, control) => control.control == number && control.value > 0 && (channel == 0 || channel == util::midi_channel_to_num(c)),
+        _ => false,
+    };
+    if tapped {
+        tempo.tap(state.timers.now());
+    }
+}
+
+// For midi.set_layer() and midi.get_layer(), handed to the script as evt.layer
+#[derive(Default)]
+struct Layers {
+    current: u8,
+    // Set with midi.set_auto_layer(), program changes then pick the layer
+    auto: Option<u8>,
+}
+
+// The function from midi.on_program_change()
+const PROGRAM_CHANGE_KEY: &str = "handcake_program_change";
+
+// From midi.set_thru and midi.set_thru_filter, bit 0 of channels being channel 1
+struct Thru {
+    enabled: bool,
+    channels: u16,
+}
+
+/// Sends a message straight on to --midi-out if midi.set_thru() turned that on,
+/// as it came in. Messages without a channel, like SysEx, always go through.
+pub fn thru(l: &mlua::Lua, message: &MidiMessage) {
+    let passes = match l.app_data_ref::<Thru>() {
+        Some(thru) if thru.enabled => match util::midi_message_channel(message) {
+            Some(channel) => thru.channels & (1 << (channel - 1)) != 0,
+            None => true,
+        },
+        _ => false,
+    };
+    if passes {
+        if let Err(e) = super::midi_out::send_from(l, &util::midi_to_bytes(message)) {
+            warn!("Could not send MIDI thru: {}", e);
+        }
+    }
+}
+
+const ALL_NOTES_OFF: u8 = 123;
+const RESET_ALL_CONTROLLERS: u8 = 121;
+
+/// Sends all notes off and reset all controllers on every channel of --midi-out
+pub fn panic(l: &mlua::Lua) -> mlua::Result<()> {
+    for channel in 1..=16 {
+        let status = super::midi_out::status(midi_control::consts::CONTROL_CHANGE, channel)?;
+        super::midi_out::send_from(l, &[status, ALL_NOTES_OFF, 0])?;
+        super::midi_out::send_from(l, &[status, RESET_ALL_CONTROLLERS, 0])?;
+    }
+
+    Ok(())
+}
+
+// Set by midi.on_identity
+const IDENTITY_KEY: &str = "handcake_identity";
+
+// F0 7E 7F 06 01 F7, "all devices, tell me who you are"
+const IDENTITY_REQUEST: [u8; 6] = [0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7];
+
+/// What a device says about itself in reply to an identity request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    /// One byte, or three with the first being 0 (e.g. 0x002029 for Novation)
+    pub manufacturer_id: u32,
+    pub device_family: u16,
+    pub device_member: u16,
+    /// Four bytes, what they mean is up to the manufacturer
+    pub revision: [u8; 4],
+}
+
+/// Pulls the identity out of an identity reply, F0 7E <device> 06 02 ... F7.
+/// Anything else, including replies that are too short, is None.
+pub fn parse_identity_reply(sysex: &SysExEvent) -> Option<Identity> {
+    if !matches!(sysex.get_type(), SysExType::NonRealTime(_, [0x06, 0x02])) {
+        return None;
+    }
+    let data = sysex.get_data();
+    let (manufacturer_id, rest) = match data.first()? {
+        0 => (((*data.get(1)? as u32) << 8) | *data.get(2)? as u32, data.get(3..)?),
+        id => (*id as u32, &data[1..]),
+    };
+    // Family and member are 14 bits each, LSB first
+    let word = |i: usize| -> Option<u16> { Some(*rest.get(i)? as u16 | ((*rest.get(i + 1)? as u16) << 7)) };
+
+    Some(Identity {
+        manufacturer_id,
+        device_family: word(0)?,
+        device_member: word(2)?,
+        revision: rest.get(4..8)?.try_into().ok()?,
+    })
+}
+
+/// The midi.on_identity handler and what to call it with, if this is an identity reply
+pub fn identity_reply<'lua>(l: &'lua mlua::Lua, device: &str, sysex: &SysExEvent) -> mlua::Result<Option<(mlua::Function<'lua>, mlua::Table<'lua>)>> {
+    let f = match l.named_registry_value::<_, Option<mlua::Function>>(IDENTITY_KEY)? {
+        Some(f) => f,
+        None => return Ok(None),
+    };
+    let identity = match parse_identity_reply(sysex) {
+        Some(identity) => identity,
+        None => return Ok(None),
+    };
+
+    let tab = l.create_table()?;
+    tab.set("device", device)?;
+    tab.set("manufacturer_id", identity.manufacturer_id)?;
+    tab.set("device_family", identity.device_family)?;
+    tab.set("device_member", identity.device_member)?;
+    tab.set("revision", identity.revision.to_vec())?;
+
+    Ok(Some((f, tab)))
+}
+
+// MPE's brightness/timbre CC, what most controllers send for sliding a finger up and down
+const MPE_SLIDE_CC: u8 = 74;
+
+// Everything about the note on one member channel of an MPE zone. The expression
+// is the channel's, controllers usually send it just before the note on.
+#[derive(Clone, Copy)]
+struct MpeNote {
+    key: Option<u8>,
+    vel: u8,
+    pressure: u8,
+    slide: u8,
+    pitch_bend: u16,
+}
+
+impl Default for MpeNote {
+    fn default() -> Self {
+        MpeNote { key: None, vel: 0, pressure: 0, slide: 64, pitch_bend: 8192 }
+    }
+}
+
+// Set with midi.enable_mpe()
+#[derive(Default)]
+struct Mpe {
+    // Main channel and first and last member channel, 1-16
+    zone: Option<(u8, u8, u8)>,
+    // By member channel, 1-16
+    notes: HashMap<u8, MpeNote>,
+}
+
+/// on_mpe_note and what to call it with, if this is a note on, note off, pitch bend,
+/// channel pressure or slide on a member channel of the MPE zone. Whatever's on the
+/// main channel applies to every note, so it only goes to on_midi_recv.
+pub fn mpe_note<'lua>(l: &'lua mlua::Lua, message: &MidiMessage) -> mlua::Result<Option<(mlua::Function<'lua>, mlua::Table<'lua>)>> {
+    let channel = match util::midi_message_channel(message) {
+        Some(channel) => channel as u8,
+        None => return Ok(None),
+    };
+    let (note, held) = {
+        let mut mpe = l.app_data_mut::<Mpe>().unwrap();
+        match mpe.zone {
+            Some((_, start, end)) if (start..=end).contains(&channel) => {},
+            _ => return Ok(None),
+        }
+        let note = mpe.notes.entry(channel).or_default();
+        match message {
+            MidiMessage::NoteOn(_, key) if key.value > 0 => {
+                note.key = Some(key.key);
+                note.vel = key.value;
+            },
+            // Only the note that's held, a stray note off for another key doesn't end it
+            MidiMessage::NoteOn(_, key) | MidiMessage::NoteOff(_, key) if note.key == Some(key.key) => {
+                let released = *note;
+                note.key = None;
+                drop(mpe);
+                return mpe_table(l, channel, released, false);
+            },
+            MidiMessage::ChannelPressure(_, value) => note.pressure = *value,
+            MidiMessage::ControlChange(_, cc) if cc.control == MPE_SLIDE_CC => note.slide = cc.value,
+            MidiMessage::PitchBend(_, lsb, msb) => note.pitch_bend = util::pitch_bend_value(*lsb, *msb),
+            _ => return Ok(None),
+        }
+        (*note, note.key.is_some())
+    };
+    // The expression before a note on is kept for it, but there's no note to tell the script about yet
+    if !held {
+        return Ok(None);
+    }
+
+    mpe_table(l, channel, note, true)
+}
+
+fn mpe_table(l: &mlua::Lua, channel: u8, note: MpeNote, held: bool) -> mlua::Result<Option<(mlua::Function<'_>, mlua::Table<'_>)>> {
+    let f = match l.globals().get::<_, Option<mlua::Function>>("on_mpe_note")? {
+        Some(f) => f,
+        None => return Ok(None),
+    };
+    let tab = l.create_table()?;
+    tab.set("channel", channel)?;
+    tab.set("key", note.key)?;
+    tab.set("vel", apply_velocity_curve(l, channel as i8, note.vel)?)?;
+    tab.set("pressure", note.pressure)?;
+    tab.set("slide", note.slide)?;
+    tab.set("pitch_bend", note.pitch_bend)?;
+    tab.set("held", held)?;
+
+    Ok(Some((f, tab)))
+}
+
+pub fn layer(l: &mlua::Lua) -> u8 {
+    l.app_data_ref::<Layers>().map_or(0, |layers| layers.current)
+}
+
+pub fn program_change_handler(l: &mlua::Lua) -> mlua::Result<Option<mlua::Function<'_>>> {
+    l.named_registry_value::<_, Option<mlua::Function>>(PROGRAM_CHANGE_KEY)
+}
+
+/// Switches layer for a program change, if midi.set_auto_layer() turned that on
+pub fn auto_layer(l: &mlua::Lua, program: u8) {
+    if let Some(mut layers) = l.app_data_mut::<Layers>() {
+        if let Some(count) = layers.auto {
+            layers.current = program % count;
+        }
+    }
+}
+
+/// Where notes played by the arpeggiator say they came from
+pub const ARP_DEVICE: &str = "arp";
+
+// The pattern function given to midi.start_arp()
+const ARP_KEY: &str = "handcake_arp";
+// What steps the arpeggiator when there's no MIDI clock
+const ARP_TICK_KEY: &str = "handcake_arp_tick";
+
+// Only in the VM's app data while the arpeggiator is running
+struct Arpeggiator {
+    // Notes per beat
+    steps: u32,
+    // How much of a step each note is held for
+    gate: f64,
+    // Used when there's no MIDI clock to follow
+    step: Duration,
+    interval: u64,
+    // Clock ticks since the last step, when there is one
+    ticks: u32,
+}
+
+fn send_arp_note(sender: &queue::Sender, data: [u8; 3]) {
+    let message = Message::Midi { device: ARP_DEVICE.to_owned(), message: util::parse_midi(&data) };
+    // Waiting for room would mean waiting on ourselves, this is called from the dispatch thread
+    if let Err(TrySendError::Full(_)) = sender.try_send(message) {
+        crate::metrics::midi_event_dropped();
+    }
+}
+
+// Asks the pattern which of the held notes comes next and plays it for `length`
+async fn arp_step(l: &mlua::Lua, state: &AppState, length: Duration) -> mlua::Result<()> {
+    let mut chord: Vec<(i8, u8, u8)> = l.app_data_ref::<HeldNotes>().unwrap()
+        .iter()
+        .map(|((channel, key), (vel, _))| (*channel, *key, *vel))
+        .collect();
+    if chord.is_empty() {
+        return Ok(());
+    }
+    chord.sort_by_key(|(channel, key, _)| (*key, *channel));
+    let pattern = match l.named_registry_value::<_, Option<mlua::Function>>(ARP_KEY)? {
+        Some(pattern) => pattern,
+        None => return Ok(()),
+    };
+    // nil is a rest
+    let key = match pattern.call_async::<_, Option<u8>>(notes_table(l, chord.iter().copied())?).await? {
+        Some(key) => key.min(127),
+        None => return Ok(()),
+    };
+
+    // Same channel and velocity as the held note, or the lowest one if it's not held
+    let (channel, _, vel) = chord.iter().find(|(_, k, _)| *k == key).unwrap_or(&chord[0]);
+    let channel = (*channel).max(1) as u8 - 1;
+    send_arp_note(&state.sender, [0x90 | channel, key, *vel]);
+    let sender = state.sender.clone();
+    let off = l.create_function(move |_l, _: ()| {
+        send_arp_note(&sender, [0x80 | channel, key, 0]);
+        Ok(())
+    })?;
+    // Still goes out after midi.stop_arp(), so nothing gets stuck
+    super::misc::schedule(l, state, length, off)?;
+
+    Ok(())
+}
+
+/// Steps the arpeggiator on MIDI clock ticks instead of its own timer, so it
+/// follows whatever's sending the clock. Called for every tick.
+pub async fn arp_clock(l: &mlua::Lua, state: &AppState) -> mlua::Result<()> {
+    let length = {
+        let mut arp = match l.app_data_mut::<Arpeggiator>() {
+            Some(arp) => arp,
+            None => return Ok(()),
+        };
+        let ticks_per_step = (CLOCKS_PER_BEAT as u32 / arp.steps).max(1);
+        arp.ticks = (arp.ticks + 1) % ticks_per_step;
+        if arp.ticks != 0 {
+            return Ok(());
+        }
+        match MIDI_CLOCK.lock().interval(Instant::now()) {
+            Some(interval) => (interval * ticks_per_step).mul_f64(arp.gate),
+            None => return Ok(()),
+        }
+    };
+
+    arp_step(l, state, length).await
+}
+
+fn stop_arp(l: &mlua::Lua) -> mlua::Result<()> {
+    if let Some(arp) = l.remove_app_data::<Arpeggiator>() {
+        super::misc::clear_interval(l, arp.interval)?;
+    }
+    l.set_named_registry_value(ARP_KEY, mlua::Value::Nil)
+}
+
+// Intervals above the root as a bitmask, in the order they're tried
+const CHORDS: &[(u16, &str)] = &[
+    (1 << 0 | 1 << 4 | 1 << 7 | 1 << 11, "maj7"),
+    (1 << 0 | 1 << 4 | 1 << 7 | 1 << 10, "7"),
+    (1 << 0 | 1 << 3 | 1 << 7 | 1 << 10, "min7"),
+    (1 << 0 | 1 << 3 | 1 << 7 | 1 << 11, "minmaj7"),
+    (1 << 0 | 1 << 3 | 1 << 6 | 1 << 10, "m7b5"),
+    (1 << 0 | 1 << 3 | 1 << 6 | 1 << 9, "dim7"),
+    (1 << 0 | 1 << 4 | 1 << 7 | 1 << 9, "6"),
+    (1 << 0 | 1 << 3 | 1 << 7 | 1 << 9, "min6"),
+    (1 << 0 | 1 << 2 | 1 << 4 | 1 << 7, "add9"),
+    (1 << 0 | 1 << 4 | 1 << 7, "maj"),
+    (1 << 0 | 1 << 3 | 1 << 7, "min"),
+    (1 << 0 | 1 << 3 | 1 << 6, "dim"),
+    (1 << 0 | 1 << 4 | 1 << 8, "aug"),
+    (1 << 0 | 1 << 2 | 1 << 7, "sus2"),
+    (1 << 0 | 1 << 5 | 1 << 7, "sus4"),
+    (1 << 0 | 1 << 7, "5"),
+];
+
+/// Names the chord made of `keys`, octaves and doublings don't matter.
+/// The lowest note gets the first try at being the root, so inversions
+/// are still named after their actual root.
+fn chord_name(keys: &[u8]) -> Option<String> {
+    let lowest = *keys.iter().min()? as u32;
+    let classes = keys.iter().fold(0u16, |set, key| set | 1 << (key % 12));
+    let roots = (0..12).map(|i| (lowest + i) % 12).filter(|root| classes & 1 << root != 0);
+    for root in roots {
+        let intervals = ((classes >> root) | (classes << (12 - root))) & 0xFFF;
+        if let Some((_, name)) = CHORDS.iter().find(|(chord, _)| *chord == intervals) {
+            return Some(format!("{}{}", NOTE_NAMES[root as usize], name));
+        }
+    }
+
+    None
+}
+
+// Registry slot for the function waiting on midi.learn()
+const LEARN_KEY: &str = "handcake_learn";
+
+pub fn is_learning(l: &mlua::Lua) -> bool {
+    matches!(l.named_registry_value::<_, mlua::Value>(LEARN_KEY), Ok(mlua::Value::Function(_)))
+}
+
+/// Takes the midi.learn() function, if there is one, so it only gets a single event
+pub fn take_learn(l: &mlua::Lua) -> mlua::Result<Option<mlua::Function<'_>>> {
+    let f = l.named_registry_value::<_, Option<mlua::Function>>(LEARN_KEY)?;
+    l.set_named_registry_value(LEARN_KEY, mlua::Value::Nil)?;
+
+    Ok(f)
+}
+
+// Registry table of channel -> function from midi.on_channel, 0 being any channel
+const CHANNEL_HANDLERS_KEY: &str = "handcake_channel_handlers";
+// Registry list of { pattern, f } from midi.on_device, in the order they were added
+const DEVICE_HANDLERS_KEY: &str = "handcake_device_handlers";
+// Registry table for midi.on_note/on_pitch_bend/on_note_duration ("note",
+// "pitch_bend", "note_duration") and midi.on_cc ("cc", itself a table of control number -> function).
+// midi.on_cc_change is "cc_change", the same shape as "cc", and midi.on_cc_threshold is
+// "cc_threshold", control number -> list of { threshold, direction, f }.
+const EVENT_HANDLERS_KEY: &str = "handcake_event_handlers";
+
+/// Whether handlers are called instead of on_midi_recv, or before it
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum HandlerMode {
+    Exclusive,
+    AlsoGlobal,
+}
+
+impl HandlerMode {
+    fn parse(mode: &str) -> mlua::Result<Self> {
+        match mode {
+            "exclusive" => Ok(HandlerMode::Exclusive),
+            "also_global" => Ok(HandlerMode::AlsoGlobal),
+            _ => Err(mlua::Error::RuntimeError(format!("Invalid mode {:?}, expected \"exclusive\" or \"also_global\"", mode))),
+        }
+    }
+}
+
+// Channel and device handlers replace on_midi_recv by default, event handlers don't
+struct HandlerModes {
+    device: HandlerMode,
+    channel: HandlerMode,
+    event: HandlerMode,
+}
+
+fn is_empty(table: &mlua::Table) -> bool {
+    table.clone().pairs::<mlua::Value, mlua::Value>().next().is_none()
+}
+
+pub fn has_handlers(l: &mlua::Lua) -> bool {
+    let (device, channel, event) = match (
+        l.named_registry_value::<_, mlua::Table>(DEVICE_HANDLERS_KEY),
+        l.named_registry_value::<_, mlua::Table>(CHANNEL_HANDLERS_KEY),
+        l.named_registry_value::<_, mlua::Table>(EVENT_HANDLERS_KEY),
+    ) {
+        (Ok(device), Ok(channel), Ok(event)) => (device, channel, event),
+        _ => return false,
+    };
+    // The cc tables are always there, so look inside them instead
+    let cc = |key: &str| event.get::<_, mlua::Table>(key).map(|cc| !is_empty(&cc)).unwrap_or(false);
+
+    !is_empty(&device)
+        || !is_empty(&channel)
+        || event.contains_key("note").unwrap_or(false)
+        || event.contains_key("pitch_bend").unwrap_or(false)
+        || event.contains_key("note_duration").unwrap_or(false)
+        || cc("cc")
+        || cc("cc_change")
+        || cc("cc_threshold")
+}
+
+// The last value seen per (channel, control), for midi.on_cc_change and midi.on_cc_threshold
+#[derive(Default)]
+struct CcHistory(HashMap<(i8, u8), u8>);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Crossing {
+    Rising,
+    Falling,
+}
+
+impl Crossing {
+    fn parse(direction: &str) -> mlua::Result<Self> {
+        match direction {
+            "rising" => Ok(Crossing::Rising),
+            "falling" => Ok(Crossing::Falling),
+            _ => Err(mlua::Error::RuntimeError(format!("Invalid direction {:?}, expected \"rising\" or \"falling\"", direction))),
+        }
+    }
+
+    fn crossed(self, threshold: u8, previous: u8, value: u8) -> bool {
+        match self {
+            Crossing::Rising => previous < threshold && value >= threshold,
+            Crossing::Falling => previous >= threshold && value < threshold,
+        }
+    }
+}
+
+// The on_cc_change and on_cc_threshold handlers this CC value sets off
+fn cc_triggers<'lua>(l: &'lua mlua::Lua, handlers: &mlua::Table<'lua>, evt: &mlua::Table<'lua>) -> mlua::Result<Vec<mlua::Function<'lua>>> {
+    let channel = evt.get::<_, i8>("channel")?;
+    let control = evt.get::<_, u8>("control")?;
+    let value = evt.get::<_, u8>("value")?;
+    // Nothing seen yet counts as 0, where pedals and most knobs start
+    let previous = l.app_data_mut::<CcHistory>().unwrap().0.insert((channel, control), value);
+    let mut found = Vec::new();
+
+    if previous != Some(value) {
+        if let Some(f) = handlers.get::<_, mlua::Table>("cc_change")?.get::<_, Option<mlua::Function>>(control)? {
+            found.push(f);
+        }
+    }
+    let thresholds = handlers.get::<_, mlua::Table>("cc_threshold")?.get::<_, Option<mlua::Table>>(control)?;
+    for trigger in thresholds.iter().flat_map(|t| t.clone().sequence_values::<mlua::Table>()) {
+        let trigger = trigger?;
+        let direction = Crossing::parse(&trigger.get::<_, String>("direction")?)?;
+        if direction.crossed(trigger.get("threshold")?, previous.unwrap_or(0), value) {
+            found.push(trigger.get("f")?);
+        }
+    }
+
+    Ok(found)
+}
+
+// The first midi.on_device handler whose pattern is somewhere in the device's name
+fn device_handler<'lua>(l: &'lua mlua::Lua, device: &str) -> mlua::Result<Option<mlua::Function<'lua>>> {
+    let find = l.globals().get::<_, mlua::Table>("string")?.get::<_, mlua::Function>("find")?;
+    for handler in l.named_registry_value::<_, mlua::Table>(DEVICE_HANDLERS_KEY)?.sequence_values::<mlua::Table>() {
+        let handler = handler?;
+        let pattern = handler.get::<_, String>("pattern")?;
+        if find.call::<_, Option<i64>>((device, pattern))?.is_some() {
+            return Ok(Some(handler.get("f")?));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Every handler that wants this message, in the order they should be called:
+/// the device's, the channel's own, any channel, then the one for the kind of
+/// event. Also says whether on_midi_recv still gets the message afterwards.
+pub fn handlers_for<'lua>(l: &'lua mlua::Lua, evt: &mlua::Table<'lua>) -> mlua::Result<(Vec<mlua::Function<'lua>>, bool)> {
+    let handlers = l.named_registry_value::<_, mlua::Table>(EVENT_HANDLERS_KEY)?;
+    let event = evt.get::<_, String>("event")?;
+    // Before borrowing the modes, this needs the CC history
+    let triggers = match event.as_str() {
+        "control_change" => cc_triggers(l, &handlers, evt)?,
+        _ => Vec::new(),
+    };
+
+    let modes = l.app_data_ref::<HandlerModes>().unwrap();
+    let mut found = Vec::new();
+    let mut skip_global = false;
+
+    if let Some(f) = device_handler(l, &evt.get::<_, String>("device")?)? {
+        found.push(f);
+        skip_global |= modes.device == HandlerMode::Exclusive;
+    }
+
+    // SysEx has no channel
+    if let Some(channel) = evt.get::<_, Option<i8>>("channel")? {
+        let handlers = l.named_registry_value::<_, mlua::Table>(CHANNEL_HANDLERS_KEY)?;
+        for channel in [channel, 0] {
+            if let Some(f) = handlers.get::<_, Option<mlua::Function>>(channel)? {
+                found.push(f);
+                skip_global |= modes.channel == HandlerMode::Exclusive;
+            }
+        }
+    }
+
+    let f = match event.as_str() {
+        "note_on" | "note_off" => handlers.get::<_, Option<mlua::Function>>("note")?,
+        "pitch_bend" => handlers.get::<_, Option<mlua::Function>>("pitch_bend")?,
+        "control_change" => handlers.get::<_, mlua::Table>("cc")?.get::<_, Option<mlua::Function>>(evt.get::<_, u8>("control")?)?,
+        _ => None,
+    };
+    for f in f.into_iter().chain(triggers) {
+        found.push(f);
+        skip_global |= modes.event == HandlerMode::Exclusive;
+    }
+    // Only note offs have a duration, including note ons with velocity 0
+    if evt.contains_key("duration_ms")? {
+        if let Some(f) = handlers.get::<_, Option<mlua::Function>>("note_duration")? {
+            found.push(f);
+            skip_global |= modes.event == HandlerMode::Exclusive;
+        }
+    }
+
+    Ok((found, !skip_global))
+}
+
+// Registry table of channel -> function or lookup table from midi.set_velocity_curve, 0 being every channel
+const VELOCITY_CURVES_KEY: &str = "handcake_velocity_curves";
+
+/// Runs a note velocity through the curve for its channel, if there is one
+pub fn apply_velocity_curve(l: &mlua::Lua, channel: i8, vel: u8) -> mlua::Result<u8> {
+    let curves = l.named_registry_value::<_, mlua::Table>(VELOCITY_CURVES_KEY)?;
+    let curve = match curves.get::<_, mlua::Value>(channel)? {
+        mlua::Value::Nil => curves.get::<_, mlua::Value>(0)?,
+        curve => curve,
+    };
+    let mapped = match curve {
+        mlua::Value::Function(f) => f.call::<_, f64>(vel)?,
+        // Lua tables start at 1, so velocity 0 is the first entry
+        mlua::Value::Table(t) => t.get::<_, f64>(vel as i64 + 1)?,
+        _ => return Ok(vel),
+    };
+
+    Ok(mapped.round().clamp(0.0, 127.0) as u8)
+}
+
+// Per-control alpha from midi.enable_cc_smoothing, and the filter state per (channel, control)
+#[derive(Default)]
+struct CcSmoothing {
+    alpha: HashMap<u8, f64>,
+    state: HashMap<(i8, u8), (f64, u8)>,
+}
+
+// How far the filtered value has to drift from the last value sent before it changes.
+// Without this, a knob jittering between 63 and 64 settles around 63.5 and the rounded value keeps flipping.
+const CC_HYSTERESIS: f64 = 0.75;
+
+/// Runs a CC value through the smoothing filter for its control, if it has one
+pub fn smooth_cc(l: &mlua::Lua, channel: i8, control: u8, value: u8) -> u8 {
+    let mut smoothing = match l.app_data_mut::<CcSmoothing>() {
+        Some(smoothing) => smoothing,
+        None => return value,
+    };
+    let alpha = match smoothing.alpha.get(&control) {
+        Some(alpha) => *alpha,
+        None => return value,
+    };
+
+    let (filtered, output) = smoothing.state.entry((channel, control)).or_insert((value as f64, value));
+    *filtered += alpha * (value as f64 - *filtered);
+    if (*filtered - *output as f64).abs() > CC_HYSTERESIS {
+        *output = filtered.round() as u8;
+    }
+
+    *output
+}
+
+const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+// Added to the octave of every note name, so -1 gives the "60 = C3" convention.
+// Lives in the VM's app data like HeldNotes.
+struct OctaveOffset(i32);
+
+fn note_name(number: u8, octave_offset: i32) -> String {
+    let octave = number as i32 / 12 - 1 + octave_offset;
+    format!("{}{}", NOTE_NAMES[number as usize % 12], octave)
+}
+
+// Accepts things like "C4", "f#3", "Bb-1"
+fn note_number(name: &str, octave_offset: i32) -> Option<u8> {
+    let mut chars = name.chars();
+    let mut semitone: i32 = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let octave = match rest.chars().next() {
+        Some('#') => { semitone += 1; &rest[1..] },
+        Some('b') => { semitone -= 1; &rest[1..] },
+        _ => rest,
+    };
+    let octave: i32 = octave.parse().ok()?;
+
+    let number = (octave - octave_offset + 1) * 12 + semitone;
+    u8::try_from(number).ok().filter(|n| *n <= 127)
+}
+
+// Equal temperament, A4 (note 69) = 440Hz
+fn note_frequency(number: f64) -> f64 {
+    440.0 * 2f64.powf((number - 69.0) / 12.0)
 }
 
 pub struct Midi;
 impl ApiProvider for Midi {
-    type Arguments = ();
+    type Arguments = (Arc<AppState>,);
 
-    fn register_api(l: &mlua::Lua, _: Self::Arguments) -> anyhow::Result<()> {
+    fn register_api(l: &mlua::Lua, args: Self::Arguments) -> anyhow::Result<()> {
+        let (state,) = args;
         let tab = l.create_table()?;
+        l.set_app_data(HeldNotes::new());
+
+        tab.set("notes_held", l.create_function(|l, _: ()| {
+            let held = l.app_data_ref::<HeldNotes>().unwrap();
+            notes_table(l, held.iter().map(|((channel, key), (vel, _))| (*channel, *key, *vel)))
+        })?)?;
+
+        tab.set("is_held", l.create_function(|l, (channel, key): (i8, u8)| {
+            let held = l.app_data_ref::<HeldNotes>().unwrap();
+            Ok(held.contains_key(&(channel, key)))
+        })?)?;
+
+        // Helpers for turning 0-127 values into whatever range a mapping needs
+        tab.set("map", l.create_function(|_l, (value, in_min, in_max, out_min, out_max): (f64, f64, f64, f64, f64)| {
+            if in_min == in_max {
+                return Err(mlua::Error::RuntimeError(format!("Input range {}..{} is empty", in_min, in_max)));
+            }
+
+            Ok(out_min + (value - in_min) * (out_max - out_min) / (in_max - in_min))
+        })?)?;
+
+        tab.set("clamp", l.create_function(|_l, (value, min, max): (f64, f64, f64)| {
+            if min > max {
+                return Err(mlua::Error::RuntimeError(format!("Invalid range {}..{}", min, max)));
+            }
+
+            Ok(value.clamp(min, max))
+        })?)?;
+
+        // Takes a value from 0.0 to 1.0, gamma > 1 makes the low end less sensitive
+        tab.set("curve", l.create_function(|_l, (value, gamma): (f64, f64)| {
+            if gamma <= 0.0 || gamma.is_nan() {
+                return Err(mlua::Error::RuntimeError(format!("Invalid gamma {}", gamma)));
+            }
+
+            Ok(value.clamp(0.0, 1.0).powf(gamma))
+        })?)?;
+
+        // -1.0 is all the way down, 1.0 all the way up
+        tab.set("pitch_bend_to_f32", l.create_function(|_l, (value,): (u16,)| {
+            Ok(util::pitch_bend_to_f32(value))
+        })?)?;
+
+        l.set_app_data(OctaveOffset(0));
+
+        tab.set("set_octave_offset", l.create_function(|l, (offset,): (i32,)| {
+            l.app_data_mut::<OctaveOffset>().unwrap().0 = offset;
+            Ok(())
+        })?)?;
+
+        l.set_app_data(Transpose::default());
+
+        // Both add up, so set_octave(-1) with set_transpose(2) moves everything down 10 semitones
+        tab.set("set_transpose", l.create_function(|l, (semitones,): (i8,)| {
+            l.app_data_mut::<Transpose>().unwrap().semitones = semitones;
+            Ok(())
+        })?)?;
+
+        tab.set("set_octave", l.create_function(|l, (octave,): (i8,)| {
+            if !(-10..=10).contains(&octave) {
+                return Err(mlua::Error::RuntimeError(format!("Invalid octave shift {}", octave)));
+            }
+            l.app_data_mut::<Transpose>().unwrap().octave = octave;
+            Ok(())
+        })?)?;
+
+        tab.set("get_transpose", l.create_function(|l, _: ()| {
+            let transpose = l.app_data_ref::<Transpose>().unwrap();
+            Ok((transpose.semitones, transpose.octave))
+        })?)?;
+
+        // midi.set_scale("F#", "minor"), or nothing to stop snapping notes
+        tab.set("set_scale", l.create_function(|l, (root, mode): (Option<String>, Option<String>)| {
+            let root = match root {
+                Some(root) => root,
+                None => {
+                    l.app_data_mut::<Transpose>().unwrap().scale = None;
+                    return Ok(());
+                },
+            };
+            // Any octave would do, only the note in it matters
+            let semitone = note_number(&format!("{}4", root), 0)
+                .ok_or_else(|| mlua::Error::RuntimeError(format!("Invalid root note {:?}", root)))? % 12;
+            let mode = mode.unwrap_or_else(|| "major".to_owned());
+            let intervals = SCALES.iter()
+                .find(|(name, _)| *name == mode)
+                .map(|(_, intervals)| *intervals)
+                .ok_or_else(|| mlua::Error::RuntimeError(format!("Unknown scale {:?}", mode)))?;
+            l.app_data_mut::<Transpose>().unwrap().scale = Some((semitone, intervals));
+            Ok(())
+        })?)?;
+
+        tab.set("note_name", l.create_function(|l, (number,): (u8,)| {
+            if number > 127 {
+                return Err(mlua::Error::RuntimeError(format!("Invalid note number {}", number)));
+            }
+
+            Ok(note_name(number, l.app_data_ref::<OctaveOffset>().unwrap().0))
+        })?)?;
+
+        tab.set("note_number", l.create_function(|l, (name,): (String,)| {
+            note_number(&name, l.app_data_ref::<OctaveOffset>().unwrap().0)
+                .ok_or_else(|| mlua::Error::RuntimeError(format!("Invalid note name {:?}", name)))
+        })?)?;
+
+        tab.set("note_frequency", l.create_function(|_l, (number,): (f64,)| {
+            Ok(note_frequency(number))
+        })?)?;
+
+        l.set_app_data(ChordDetector::default());
+
+        // Turns on on_chord, 0 turns it off again
+        tab.set("set_chord_window", l.create_function(|l, (ms,): (u64,)| {
+            let timer = {
+                let mut chords = l.app_data_mut::<ChordDetector>().unwrap();
+                chords.window = if ms > 0 { Some(Duration::from_millis(ms)) } else { None };
+                chords.notes.clear();
+                chords.timer.take()
+            };
+            match timer {
+                Some(handle) => super::misc::cancel(l, handle),
+                None => Ok(()),
+            }
+        })?)?;
+
+        // Takes note numbers, or note tables like on_chord and midi.notes_held() give.
+        // nil if it's not a chord in the (fairly short) list.
+        tab.set("chord_name", l.create_function(|l, (notes,): (mlua::Table,)| {
+            let keys = notes.sequence_values::<mlua::Value>()
+                .map(|note| match note? {
+                    mlua::Value::Table(note) => note.get::<_, u8>("key"),
+                    value => mlua::FromLua::from_lua(value, l),
+                })
+                .collect::<mlua::Result<Vec<u8>>>()?;
+
+            Ok(chord_name(&keys))
+        })?)?;
+
+        // Made once out here rather than in start_arp, an async function keeps the Lua
+        // it was made with, and start_arp's could be a coroutine that's since yielded
+        {
+            let state = state.clone();
+            let tick = l.create_async_function(move |l, _: ()| {
+                let state = state.clone();
+                async move {
+                    // A running MIDI clock takes over, see arp_clock
+                    if MIDI_CLOCK.lock().interval(Instant::now()).is_some() {
+                        return Ok(());
+                    }
+                    let length = match l.app_data_ref::<Arpeggiator>() {
+                        Some(arp) => arp.step.mul_f64(arp.gate),
+                        None => return Ok(()),
+                    };
+                    arp_step(l, &state, length).await
+                }
+            })?;
+            l.set_named_registry_value(ARP_TICK_KEY, tick)?;
+        }
+
+        // Calling it again replaces the running one
+        {
+            let state = state.clone();
+            tab.set("start_arp", l.create_function(move |l, (pattern, bpm, options): (mlua::Function, f64, Option<mlua::Table>)| {
+                if !(bpm > 0.0 && bpm.is_finite()) {
+                    return Err(mlua::Error::RuntimeError(format!("Invalid BPM {}", bpm)));
+                }
+                let (steps, gate) = match options {
+                    Some(options) => (
+                        options.get::<_, Option<u32>>("steps")?.unwrap_or(1),
+                        options.get::<_, Option<f64>>("gate")?.unwrap_or(0.5),
+                    ),
+                    None => (1, 0.5),
+                };
+                if !(1..=CLOCKS_PER_BEAT as u32).contains(&steps) {
+                    return Err(mlua::Error::RuntimeError(format!("Invalid number of steps per beat {}", steps)));
+                }
+                if !(gate > 0.0 && gate <= 1.0) {
+                    return Err(mlua::Error::RuntimeError(format!("Invalid gate {}", gate)));
+                }
+
+                stop_arp(l)?;
+                l.set_named_registry_value(ARP_KEY, pattern)?;
+                let step = Duration::from_secs_f64(60.0 / bpm / steps as f64);
+                let tick = l.named_registry_value::<_, mlua::Function>(ARP_TICK_KEY)?;
+                let interval = super::misc::start_interval(l, &state, Duration::ZERO, step, tick)?;
+                l.set_app_data(Arpeggiator { steps, gate, step, interval, ticks: 0 });
+
+                Ok(())
+            })?)?;
+        }
+
+        tab.set("stop_arp", l.create_function(|l, _: ()| {
+            stop_arp(l)
+        })?)?;
+
+        l.set_app_data(Layers::default());
+        tab.set("set_layer", l.create_function(|l, (layer,): (u8,)| {
+            l.app_data_mut::<Layers>().unwrap().current = layer;
+            Ok(())
+        })?)?;
+
+        tab.set("get_layer", l.create_function(|l, _: ()| {
+            Ok(layer(l))
+        })?)?;
+
+        tab.set("set_auto_layer", l.create_function(|l, (count,): (Option<u8>,)| {
+            // 0 would be a division by zero, so it turns it off as well
+            l.app_data_mut::<Layers>().unwrap().auto = count.filter(|count| *count > 0);
+            Ok(())
+        })?)?;
+
+        // Not a handler like on_cc, on_midi_recv still gets the program change
+        tab.set("on_program_change", l.create_function(|l, (f,): (Option<mlua::Function>,)| {
+            l.set_named_registry_value(PROGRAM_CHANGE_KEY, f)
+        })?)?;
+
+        tab.set("panic", l.create_function(|l, _: ()| panic(l))?)?;
+
+        // Everything that comes in goes straight back out of --midi-out too, before the script sees it
+        l.set_app_data(Thru { enabled: false, channels: 0xFFFF });
+        tab.set("set_thru", l.create_function(|l, (enabled,): (bool,)| {
+            if enabled && !super::midi_out::can_send(l) {
+                return Err(mlua::Error::RuntimeError("No MIDI output open for thru, pass --midi-out".to_owned()));
+            }
+            l.app_data_mut::<Thru>().unwrap().enabled = enabled;
+            Ok(())
+        })?)?;
+
+        // 0xFFFF (the default) lets every channel through, 1 just channel 1, 2 channel 2, 3 both...
+        tab.set("set_thru_filter", l.create_function(|l, (channels,): (u16,)| {
+            l.app_data_mut::<Thru>().unwrap().channels = channels;
+            Ok(())
+        })?)?;
+
+        // Every device on --midi-out that supports it answers, each reply goes to midi.on_identity
+        tab.set("send_identity_request", l.create_function(|l, _: ()| {
+            super::midi_out::send_from(l, &IDENTITY_REQUEST)
+        })?)?;
+
+        // Not a handler like on_cc either, on_midi_recv still gets the SysEx
+        tab.set("on_identity", l.create_function(|l, (f,): (Option<mlua::Function>,)| {
+            l.set_named_registry_value(IDENTITY_KEY, f)
+        })?)?;
+
+        // The next message goes to f instead of on_midi_recv. Calling it again replaces f.
+        tab.set("learn", l.create_function(|l, (f,): (mlua::Function,)| {
+            l.set_named_registry_value(LEARN_KEY, f)
+        })?)?;
+
+        l.set_named_registry_value(CHANNEL_HANDLERS_KEY, l.create_table()?)?;
+        let event_handlers = l.create_table()?;
+        event_handlers.set("cc", l.create_table()?)?;
+        event_handlers.set("cc_change", l.create_table()?)?;
+        event_handlers.set("cc_threshold", l.create_table()?)?;
+        l.set_app_data(CcHistory::default());
+        l.set_named_registry_value(EVENT_HANDLERS_KEY, event_handlers)?;
+        l.set_app_data(HandlerModes {
+            device: HandlerMode::Exclusive,
+            channel: HandlerMode::Exclusive,
+            event: HandlerMode::AlsoGlobal,
+        });
+        l.set_named_registry_value(DEVICE_HANDLERS_KEY, l.create_table()?)?;
+        tab.set("ANY_CHANNEL", 0)?;
+
+        // All of these take nil to remove the handler again
+
+        // Messages on this channel (1-16, or 0 for all of them) go to f
+        tab.set("on_channel", l.create_function(|l, (channel, f): (i8, Option<mlua::Function>)| {
+            if !(0..=16).contains(&channel) {
+                return Err(mlua::Error::RuntimeError(format!("Invalid MIDI channel {}, expected 0 to 16", channel)));
+            }
+            l.named_registry_value::<_, mlua::Table>(CHANNEL_HANDLERS_KEY)?.set(channel, f)
+        })?)?;
 
+        // Messages from devices with pattern (a Lua pattern) in their name go to f. Only the
+        // first matching handler gets them, the same pattern again replaces its handler.
+        tab.set("on_device", l.create_function(|l, (pattern, f): (String, Option<mlua::Function>)| {
+            let old = l.named_registry_value::<_, mlua::Table>(DEVICE_HANDLERS_KEY)?;
+            let mut replaced = false;
+            let handlers = l.create_table()?;
+            for handler in old.sequence_values::<mlua::Table>() {
+                let handler = handler?;
+                if handler.get::<_, String>("pattern")? == pattern {
+                    replaced = true;
+                    match &f {
+                        Some(f) => handler.set("f", f.clone())?,
+                        None => continue,
+                    }
+                }
+                handlers.raw_set(handlers.raw_len() + 1, handler)?;
+            }
+            if let (false, Some(f)) = (replaced, f) {
+                let handler = l.create_table()?;
+                handler.set("pattern", pattern)?;
+                handler.set("f", f)?;
+                handlers.raw_set(handlers.raw_len() + 1, handler)?;
+            }
+            l.set_named_registry_value(DEVICE_HANDLERS_KEY, handlers)
+        })?)?;
+
+        // Both note_on and note_off
+        tab.set("on_note", l.create_function(|l, (f,): (Option<mlua::Function>,)| {
+            l.named_registry_value::<_, mlua::Table>(EVENT_HANDLERS_KEY)?.set("note", f)
+        })?)?;
+
+        tab.set("on_pitch_bend", l.create_function(|l, (f,): (Option<mlua::Function>,)| {
+            l.named_registry_value::<_, mlua::Table>(EVENT_HANDLERS_KEY)?.set("pitch_bend", f)
+        })?)?;
 
-        tab.set("open", l.create_function(|_l, (portno,): (usize,)| {
-            let mut midi_in = midir::MidiInput::new("handcake MIDI input").unwrap();
-            midi_in.ignore(Ignore::None);
+        // Note offs, with how long the note was held as evt.duration_ms
+        tab.set("on_note_duration", l.create_function(|l, (f,): (Option<mlua::Function>,)| {
+            l.named_registry_value::<_, mlua::Table>(EVENT_HANDLERS_KEY)?.set("note_duration", f)
+        })?)?;
+
+        tab.set("on_cc", l.create_function(|l, (control, f): (u8, Option<mlua::Function>)| {
+            if control > 127 {
+                return Err(mlua::Error::RuntimeError(format!("Invalid control number {}", control)));
+            }
+            l.named_registry_value::<_, mlua::Table>(EVENT_HANDLERS_KEY)?.get::<_, mlua::Table>("cc")?.set(control, f)
+        })?)?;
+
+        // Like on_cc, but only when the value isn't the same as last time
+        tab.set("on_cc_change", l.create_function(|l, (control, f): (u8, Option<mlua::Function>)| {
+            if control > 127 {
+                return Err(mlua::Error::RuntimeError(format!("Invalid control number {}", control)));
+            }
+            l.named_registry_value::<_, mlua::Table>(EVENT_HANDLERS_KEY)?.get::<_, mlua::Table>("cc_change")?.set(control, f)
+        })?)?;
+
+        // Only when the value goes from below threshold to threshold or above ("rising"), or
+        // the other way ("falling"). A control can have several, the same threshold and
+        // direction again replaces its handler.
+        tab.set("on_cc_threshold", l.create_function(|l, (control, threshold, direction, f): (u8, u8, String, Option<mlua::Function>)| {
+            if control > 127 {
+                return Err(mlua::Error::RuntimeError(format!("Invalid control number {}", control)));
+            }
+            if threshold > 127 {
+                return Err(mlua::Error::RuntimeError(format!("Invalid threshold {}, expected 0 to 127", threshold)));
+            }
+            Crossing::parse(&direction)?;
+
+            let thresholds = l.named_registry_value::<_, mlua::Table>(EVENT_HANDLERS_KEY)?.get::<_, mlua::Table>("cc_threshold")?;
+            let triggers = l.create_table()?;
+            if let Some(old) = thresholds.get::<_, Option<mlua::Table>>(control)? {
+                for trigger in old.sequence_values::<mlua::Table>() {
+                    let trigger = trigger?;
+                    if trigger.get::<_, u8>("threshold")? != threshold || trigger.get::<_, String>("direction")? != direction {
+                        triggers.raw_set(triggers.raw_len() + 1, trigger)?;
+                    }
+                }
+            }
+            if let Some(f) = f {
+                let trigger = l.create_table()?;
+                trigger.set("threshold", threshold)?;
+                trigger.set("direction", direction)?;
+                trigger.set("f", f)?;
+                triggers.raw_set(triggers.raw_len() + 1, trigger)?;
+            }
+            thresholds.set(control, if is_empty(&triggers) { None } else { Some(triggers) })
+        })?)?;
+
+        // "exclusive" (the default) skips on_midi_recv when a channel handler took the message,
+        // "also_global" calls on_midi_recv afterwards as well
+        tab.set("set_channel_mode", l.create_function(|l, (mode,): (String,)| {
+            l.app_data_mut::<HandlerModes>().unwrap().channel = HandlerMode::parse(&mode)?;
+            Ok(())
+        })?)?;
+
+        // Same for on_device
+        tab.set("set_device_mode", l.create_function(|l, (mode,): (String,)| {
+            l.app_data_mut::<HandlerModes>().unwrap().device = HandlerMode::parse(&mode)?;
+            Ok(())
+        })?)?;
+
+        // Same for on_note/on_cc/on_pitch_bend, except "also_global" is the default
+        tab.set("set_dispatch_mode", l.create_function(|l, (mode,): (String,)| {
+            l.app_data_mut::<HandlerModes>().unwrap().event = HandlerMode::parse(&mode)?;
+            Ok(())
+        })?)?;
+
+        l.set_app_data(Mpe::default());
+
+        // midi.enable_mpe(1, 2, 16) is a lower zone on a controller that uses all 16 channels
+        tab.set("enable_mpe", l.create_function(|l, (main, start, end): (u8, u8, u8)| {
+            for channel in [main, start, end] {
+                if !(1..=16).contains(&channel) {
+                    return Err(mlua::Error::RuntimeError(format!("Invalid MIDI channel {}, expected 1 to 16", channel)));
+                }
+            }
+            if start > end || (start..=end).contains(&main) {
+                return Err(mlua::Error::RuntimeError(format!("Invalid MPE zone {}-{} with main channel {}", start, end, main)));
+            }
+            let mut mpe = l.app_data_mut::<Mpe>().unwrap();
+            mpe.zone = Some((main, start, end));
+            mpe.notes.clear();
+            Ok(())
+        })?)?;
+
+        tab.set("disable_mpe", l.create_function(|l, _: ()| {
+            *l.app_data_mut::<Mpe>().unwrap() = Mpe::default();
+            Ok(())
+        })?)?;
+
+        l.set_app_data(NoteGates::default());
+
+        // Note ons below the threshold never reach the script. Channel 0 is all of them,
+        // and a threshold of 0 lets everything through again.
+        tab.set("set_note_threshold", l.create_function(|l, (channel, threshold): (i8, u8)| {
+            let channel = gate_channel(channel)?;
+            if threshold > 127 {
+                return Err(mlua::Error::RuntimeError(format!("Invalid velocity threshold {}, expected 0 to 127", threshold)));
+            }
+            l.app_data_mut::<NoteGates>().unwrap().thresholds[channel] = Some(threshold);
+            Ok(())
+        })?)?;
+
+        // What happens to the velocity of notes that get past the threshold
+        tab.set("set_note_velocity_mode", l.create_function(|l, (mode, channel): (String, Option<i8>)| {
+            let channel = gate_channel(channel.unwrap_or(0))?;
+            l.app_data_mut::<NoteGates>().unwrap().modes[channel] = Some(VelocityMode::parse(&mode)?);
+            Ok(())
+        })?)?;
+
+        l.set_named_registry_value(VELOCITY_CURVES_KEY, l.create_table()?)?;
+
+        // Either a function from raw velocity to new velocity, or a table of 128 velocities.
+        // Without a channel it applies to all of them, nil removes it.
+        tab.set("set_velocity_curve", l.create_function(|l, (curve, channel): (mlua::Value, Option<i8>)| {
+            let channel = channel.unwrap_or(0);
+            if !(0..=16).contains(&channel) {
+                return Err(mlua::Error::RuntimeError(format!("Invalid MIDI channel {}, expected 0 to 16", channel)));
+            }
+            match &curve {
+                mlua::Value::Nil | mlua::Value::Function(_) => {},
+                mlua::Value::Table(t) if t.raw_len() == 128 => {},
+                mlua::Value::Table(t) => {
+                    return Err(mlua::Error::RuntimeError(format!("Velocity curve table has {} entries, expected 128", t.raw_len())));
+                },
+                _ => return Err(mlua::Error::RuntimeError("Velocity curve has to be a function or a table".into())),
+            }
+            l.named_registry_value::<_, mlua::Table>(VELOCITY_CURVES_KEY)?.set(channel, curve)
+        })?)?;
+
+        l.set_app_data(CcSmoothing::default());
+
+        // Exponential moving average on one CC number, on every channel. 1.0 turns it off again,
+        // around 0.1 smooths out even really noisy knobs (but they'll lag a bit).
+        tab.set("enable_cc_smoothing", l.create_function(|l, (control, alpha): (u8, f64)| {
+            if control > 127 {
+                return Err(mlua::Error::RuntimeError(format!("Invalid control number {}", control)));
+            }
+            if !(alpha > 0.0 && alpha <= 1.0) {
+                return Err(mlua::Error::RuntimeError(format!("Invalid smoothing alpha {}, expected more than 0.0 up to 1.0", alpha)));
+            }
+            let mut smoothing = l.app_data_mut::<CcSmoothing>().unwrap();
+            smoothing.state.retain(|(_, c), _| *c != control);
+            if alpha >= 1.0 {
+                smoothing.alpha.remove(&control);
+            } else {
+                smoothing.alpha.insert(control, alpha);
+            }
+
+            Ok(())
+        })?)?;
+
+        // Either works as note_or_cc, whichever the controller sends
+        l.set_app_data(TapTempo::default());
+        tab.set("tap_tempo", l.create_function(|l, (channel, number): (i8, u8)| {
+            let mut tempo = l.app_data_mut::<TapTempo>().unwrap();
+            tempo.source = Some((channel, number));
+            tempo.taps.clear();
+            Ok(())
+        })?)?;
+
+        let tap_state = state.clone();
+        tab.set("tap_bpm", l.create_function(move |l, _: ()| {
+            Ok(l.app_data_ref::<TapTempo>().unwrap().bpm(tap_state.timers.now()))
+        })?)?;
+
+        // Both are nil until a clock has been running for a couple of ticks
+        tab.set("bpm", l.create_function(|_l, _: ()| {
+            Ok(MIDI_CLOCK.lock().bpm(Instant::now()))
+        })?)?;
+
+        tab.set("beat_phase", l.create_function(|_l, _: ()| {
+            Ok(MIDI_CLOCK.lock().beat_phase(Instant::now()))
+        })?)?;
+
+        {
+            let state = state.clone();
+            // midi.play_file("song.mid", { loop = true, speed = 2.0 }), playing one file stops the last
+            tab.set("play_file", l.create_function(move |l, (path, options): (String, Option<mlua::Table>)| {
+                let (looped, speed) = match &options {
+                    Some(options) => (options.get::<_, Option<bool>>("loop")?.unwrap_or(false), options.get::<_, Option<f64>>("speed")?.unwrap_or(1.0)),
+                    None => (false, 1.0),
+                };
+                if !(speed > 0.0 && speed.is_finite()) {
+                    return Err(mlua::Error::RuntimeError(format!("Invalid playback speed {}", speed)));
+                }
+                let path = std::path::Path::new(&path);
+                let file = super::midi_file::MidiFile::load(path)
+                    .map_err(|e| mlua::Error::RuntimeError(format!("Could not load MIDI file {:?}: {}", path, e)))?;
+                let device = path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().to_string();
+                l.set_app_data(super::midi_file::play(&state, file, device, looped, speed));
+
+                Ok(())
+            })?)?;
+        }
+
+        tab.set("stop_playback", l.create_function(|l, _: ()| {
+            l.remove_app_data::<super::midi_file::Playback>();
+            Ok(())
+        })?)?;
+
+        // midi.start_recording("take.mid", { bpm = 90, time_signature = { 3, 4 } })
+        tab.set("start_recording", l.create_function(|_l, (path, options): (String, Option<mlua::Table>)| {
+            let (bpm, time_signature) = match &options {
+                Some(options) => (
+                    options.get::<_, Option<f64>>("bpm")?.unwrap_or(120.0),
+                    match options.get::<_, Option<Vec<u8>>>("time_signature")?.as_deref() {
+                        Some(&[numerator, denominator]) => (numerator, denominator),
+                        Some(_) => return Err(mlua::Error::RuntimeError("time_signature has to be two numbers, like { 3, 4 }".into())),
+                        None => (4, 4),
+                    },
+                ),
+                None => (120.0, (4, 4)),
+            };
+            if !(bpm > 0.0 && bpm.is_finite()) {
+                return Err(mlua::Error::RuntimeError(format!("Invalid BPM {}", bpm)));
+            }
+            if time_signature.0 == 0 || !time_signature.1.is_power_of_two() {
+                return Err(mlua::Error::RuntimeError(format!("Invalid time signature {}/{}", time_signature.0, time_signature.1)));
+            }
+            super::midi_file::start_recording(path.into(), bpm, time_signature);
+
+            Ok(())
+        })?)?;
+
+        tab.set("stop_recording", l.create_function(|_l, _: ()| {
+            super::midi_file::stop_recording()
+                .map_err(|e| mlua::Error::RuntimeError(format!("Could not write MIDI file: {}", e)))?;
+            Ok(())
+        })?)?;
+
+        tab.set("open", l.create_function(move |_l, (portno,): (usize,)| {
+            if state.replaying.load(Ordering::Relaxed) {
+                debug!("Replaying, not opening MIDI port {}", portno);
+                return Ok(());
+            }
+            let midi_in = new_input().map_err(|e| ExternalError(Arc::new(e)))?;
             let in_ports = midi_in.ports();
             let port = match in_ports.len() {
                 0 => {
@@ -52,23 +1896,7 @@ impl ApiProvider for Midi {
                 }
             };
 
-            let name = midi_in.port_name(port).unwrap();
-
-            {
-                let (_snd, _) = crate::MESSAGE.clone();
-                let _snd = _snd.lock();
-                let sender = _snd.clone();
-                drop(_snd);
-
-                let conn = midi_in.connect(port, &name, |_ts, data, sender|
-                {
-                    sender.send(Message::Midi(MidiMessage::from(data))).unwrap();
-                },
-                sender).unwrap();
-
-                *MIDI_CONN.as_ref().lock() = Some(conn);
-            }
-
+            connect_port(&state, midi_in, port).map_err(|e| ExternalError(Arc::new(e)))?;
 
             Ok(())
         })?)?;
@@ -77,4 +1905,81 @@ impl ApiProvider for Midi {
 
         Ok(())
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ports_match_up_with_devices() {
+        let device = |path: &str, name: &str, subdevice_count| MidiDeviceInfo { path: path.into(), name: name.into(), subdevice_count };
+        let devices = [
+            device("/dev/snd/midiC1D0", "MPK mini 3", 1),
+            device("/dev/snd/midiC2D0", "UM-ONE", 2),
+        ];
+        let ports: Vec<String> = ["Midi Through:Midi Through Port-0 14:0", "UM-ONE:UM-ONE MIDI 1 24:0", "UM-ONE:UM-ONE MIDI 2 24:1", "UM-ONE:UM-ONE MIDI 3 24:2", "MPK mini 3:MPK mini 3 MIDI 1 20:0"]
+            .iter().map(|port| port.to_string()).collect();
+        assert_eq!(port_paths(&ports, &devices), [
+            None,
+            Some("/dev/snd/midiC2D0".into()),
+            Some("/dev/snd/midiC2D0".into()),
+            None,
+            Some("/dev/snd/midiC1D0".into()),
+        ]);
+    }
+
+    #[test]
+    fn sharps_and_flats() {
+        assert_eq!(note_number("C#4", 0), Some(61));
+        assert_eq!(note_number("Db4", 0), Some(61));
+        assert_eq!(note_number("H4", 0), None);
+        assert_eq!(note_number("G#9", 0), None, "past 127");
+    }
+
+    #[test]
+    fn names_at_both_ends() {
+        assert_eq!(note_name(0, 0), "C-1");
+        assert_eq!(note_name(127, 0), "G9");
+    }
+
+    #[test]
+    fn a4_is_440() {
+        assert_eq!(note_frequency(69.0), 440.0);
+        assert_eq!(note_frequency(81.0), 880.0);
+    }
+
+    #[test]
+    fn octave_offset_round_trip() {
+        assert_eq!(note_name(60, -1), "C3");
+        for number in 0..=127 {
+            assert_eq!(note_number(&note_name(number, -1), -1), Some(number));
+        }
+    }
+    #[test]
+    fn clock_at_120_bpm() {
+        let tick = Duration::from_micros(20_833);
+        let start = Instant::now();
+        let mut clock = MidiClock::default();
+        clock.start();
+        for i in 0..CLOCKS_PER_BEAT as u32 {
+            clock.tick(start + tick * i);
+        }
+        let last = start + tick * (CLOCKS_PER_BEAT as u32 - 1);
+
+        let bpm = clock.bpm(last).unwrap();
+        assert!((bpm - 120.0).abs() < 0.01, "{}", bpm);
+        // The first tick after a start is the start of the beat, so the last one is 23/24 of the way through
+        let phase = clock.beat_phase(last).unwrap();
+        assert!((phase - 23.0 / 24.0).abs() < 0.001, "{}", phase);
+        assert_eq!(clock.bpm(last + Duration::from_secs(1)), None, "the clock has stopped");
+    }
+
+    #[test]
+    fn no_clock_no_bpm() {
+        let mut clock = MidiClock::default();
+        assert_eq!(clock.bpm(Instant::now()), None);
+        clock.tick(Instant::now());
+        assert_eq!(clock.bpm(Instant::now()), None, "one tick isn't an interval");
+    }
 }
\ No newline at end of file
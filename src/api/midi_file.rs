@@ -0,0 +1,320 @@
+use std::{path::{Path, PathBuf}, sync::mpsc::{self, RecvTimeoutError, Sender}, time::{Duration, Instant}};
+use parking_lot::Mutex;
+use crate::{AppState, util};
+use super::midi;
+
+// 120 BPM, until a tempo event says otherwise
+const DEFAULT_TEMPO: u32 = 500_000;
+
+// What recordings are written with, plenty for anything played by hand
+const TICKS_PER_BEAT: u16 = 480;
+
+/// Every MIDI message in a standard MIDI file, all tracks merged, with the
+/// time it's due from the start
+pub struct MidiFile {
+    events: Vec<(Duration, Vec<u8>)>,
+    // Up to the last end of track, which can be after the last message
+    length: Duration,
+}
+
+enum Timing {
+    TicksPerBeat(u16),
+    // SMPTE timing doesn't care about tempo
+    TickLength(Duration),
+}
+
+enum Event {
+    Midi(Vec<u8>),
+    Tempo(u32),
+    EndOfTrack,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> anyhow::Result<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos + n)
+            .ok_or_else(|| anyhow::anyhow!("Unexpected end of file at byte {}", self.pos))?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> anyhow::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    // Variable length quantity, 7 bits per byte with the top bit meaning "more"
+    fn vlq(&mut self) -> anyhow::Result<u32> {
+        let mut value = 0u32;
+        for _ in 0..4 {
+            let b = self.byte()?;
+            value = (value << 7) | (b & 0x7F) as u32;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        anyhow::bail!("Variable length number too long at byte {}", self.pos);
+    }
+
+    fn chunk(&mut self) -> anyhow::Result<(&'a [u8], Reader<'a>)> {
+        let kind = self.bytes(4)?;
+        let len = self.u32()? as usize;
+        Ok((kind, Reader { data: self.bytes(len)?, pos: 0 }))
+    }
+}
+
+fn read_track(track: &mut Reader, events: &mut Vec<(u64, Event)>) -> anyhow::Result<()> {
+    let mut tick = 0u64;
+    let mut running_status = None;
+    while track.pos < track.data.len() {
+        tick += track.vlq()? as u64;
+        let mut status = track.byte()?;
+        match status {
+            0xFF => {
+                let kind = track.byte()?;
+                let len = track.vlq()? as usize;
+                let data = track.bytes(len)?;
+                match (kind, data) {
+                    (0x51, [a, b, c]) => events.push((tick, Event::Tempo(u32::from_be_bytes([0, *a, *b, *c])))),
+                    (0x2F, _) => {
+                        events.push((tick, Event::EndOfTrack));
+                        return Ok(());
+                    },
+                    _ => {},
+                }
+            },
+            0xF0 => {
+                let len = track.vlq()? as usize;
+                let mut sysex = vec![0xF0];
+                sysex.extend_from_slice(track.bytes(len)?);
+                events.push((tick, Event::Midi(sysex)));
+            },
+            // Escaped bytes and SysEx continuations, nothing a script would make sense of
+            0xF7 => {
+                let len = track.vlq()? as usize;
+                track.bytes(len)?;
+            },
+            _ => {
+                // A data byte means the status is the same as last time
+                let mut message = Vec::with_capacity(3);
+                if status < 0x80 {
+                    message.push(status);
+                    status = running_status.ok_or_else(|| anyhow::anyhow!("Data byte without a status at byte {}", track.pos))?;
+                } else {
+                    running_status = Some(status);
+                }
+                message.insert(0, status);
+                while message.len() < 1 + util::midi_data_len(status) {
+                    message.push(track.byte()?);
+                }
+                events.push((tick, Event::Midi(message)));
+            },
+        }
+    }
+
+    Ok(())
+}
+
+impl MidiFile {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data = std::fs::read(path)?;
+        let mut file = Reader { data: &data, pos: 0 };
+
+        let (kind, mut header) = file.chunk()?;
+        if kind != b"MThd" {
+            anyhow::bail!("Not a MIDI file");
+        }
+        let _format = header.u16()?;
+        let tracks = header.u16()?;
+        let division = header.u16()?;
+        let timing = if division & 0x8000 == 0 {
+            Timing::TicksPerBeat(division.max(1))
+        } else {
+            // Negative frames per second in the top byte, ticks per frame in the bottom one
+            let fps = -((division >> 8) as u8 as i8 as i16) as f64;
+            let ticks_per_frame = (division & 0xFF).max(1) as f64;
+            Timing::TickLength(Duration::from_secs_f64(1.0 / (fps.max(1.0) * ticks_per_frame)))
+        };
+
+        let mut events = Vec::new();
+        for _ in 0..tracks {
+            let (kind, mut track) = file.chunk()?;
+            // Unknown chunks are allowed, and should be skipped
+            if kind == b"MTrk" {
+                read_track(&mut track, &mut events)?;
+            }
+        }
+        // Stable, so events on the same tick stay in file order
+        events.sort_by_key(|(tick, _)| *tick);
+
+        // Tempo changes apply to every track, so the times can only be worked out once they're merged
+        let mut tempo = DEFAULT_TEMPO;
+        let (mut last_tick, mut time) = (0, Duration::ZERO);
+        let mut timed = Vec::with_capacity(events.len());
+        for (tick, event) in events {
+            let ticks = (tick - last_tick) as u32;
+            time += match timing {
+                Timing::TicksPerBeat(per_beat) => Duration::from_micros(tempo as u64) * ticks / per_beat as u32,
+                Timing::TickLength(length) => length * ticks,
+            };
+            last_tick = tick;
+            match event {
+                Event::Tempo(t) => tempo = t,
+                Event::Midi(message) => timed.push((time, message)),
+                Event::EndOfTrack => {},
+            }
+        }
+
+        Ok(MidiFile { events: timed, length: time })
+    }
+}
+
+/// Playback stops when this is dropped
+pub struct Playback {
+    _stop: Sender<()>,
+}
+
+/// Sends the file's messages through the queue like any other MIDI input,
+/// `speed` times faster than written, with `device` as where they came from
+pub fn play(state: &AppState, file: MidiFile, device: String, looped: bool, speed: f64) -> Playback {
+    let (stop, stopped) = mpsc::channel::<()>();
+    let sender = state.sender.clone();
+
+    std::thread::spawn(move || loop {
+        let start = Instant::now();
+        for (time, message) in &file.events {
+            let due = start + time.div_f64(speed);
+            // Waiting on the channel rather than sleeping, so stopping doesn't wait for the next message
+            match stopped.recv_timeout(due.saturating_duration_since(Instant::now())) {
+                Err(RecvTimeoutError::Timeout) => {},
+                _ => return,
+            }
+            midi::forward_blocking(&device, message, &sender);
+        }
+        if !looped || file.events.is_empty() {
+            debug!("Finished playing {:?}", device);
+            return;
+        }
+        let end = start + file.length.div_f64(speed);
+        if !matches!(stopped.recv_timeout(end.saturating_duration_since(Instant::now())), Err(RecvTimeoutError::Timeout)) {
+            return;
+        }
+    });
+
+    Playback { _stop: stop }
+}
+
+/// What midi.start_recording() has heard so far. Everything stays in memory
+/// until the recording is stopped, then gets written in one go.
+struct Recording {
+    path: PathBuf,
+    start: Instant,
+    bpm: f64,
+    time_signature: (u8, u8),
+    events: Vec<(Duration, Vec<u8>)>,
+}
+
+lazy_static::lazy_static! {
+    static ref RECORDING: Mutex<Option<Recording>> = Mutex::new(None);
+}
+
+/// Starts a recording, throwing away one that's still going. `bpm` and the
+/// time signature only say how the file's ticks line up with beats and bars,
+/// the timing is kept exactly as played either way.
+pub fn start_recording(path: PathBuf, bpm: f64, time_signature: (u8, u8)) {
+    *RECORDING.lock() = Some(Recording {
+        path,
+        start: Instant::now(),
+        bpm,
+        time_signature,
+        events: Vec::new(),
+    });
+}
+
+pub fn record(data: &[u8]) {
+    if let Some(recording) = RECORDING.lock().as_mut() {
+        recording.events.push((recording.start.elapsed(), data.to_vec()));
+    }
+}
+
+fn write_vlq(out: &mut Vec<u8>, mut value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    value >>= 7;
+    while value > 0 {
+        bytes.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.extend(bytes.iter().rev());
+}
+
+fn write_track(out: &mut Vec<u8>, track: &[u8]) {
+    out.extend_from_slice(b"MTrk");
+    out.extend_from_slice(&(track.len() as u32).to_be_bytes());
+    out.extend_from_slice(track);
+}
+
+/// Writes out the recording as a type 1 file, with the tempo map in the
+/// first track and everything played in the second. Returns where it went,
+/// or None if nothing was being recorded.
+pub fn stop_recording() -> std::io::Result<Option<PathBuf>> {
+    let recording = match RECORDING.lock().take() {
+        Some(recording) => recording,
+        None => return Ok(None),
+    };
+    let tempo = (60_000_000.0 / recording.bpm).round() as u32;
+    let (numerator, denominator) = recording.time_signature;
+
+    let mut tempo_track = Vec::new();
+    write_vlq(&mut tempo_track, 0);
+    tempo_track.extend_from_slice(&[0xFF, 0x51, 0x03]);
+    tempo_track.extend_from_slice(&tempo.to_be_bytes()[1..]);
+    write_vlq(&mut tempo_track, 0);
+    // The denominator is stored as a power of two, then MIDI clocks per click and 32nds per beat
+    tempo_track.extend_from_slice(&[0xFF, 0x58, 0x04, numerator, denominator.trailing_zeros() as u8, 24, 8]);
+    write_vlq(&mut tempo_track, 0);
+    tempo_track.extend_from_slice(&[0xFF, 0x2F, 0x00]);
+
+    let mut events = Vec::new();
+    let mut last_tick = 0u64;
+    for (time, message) in &recording.events {
+        let tick = (time.as_secs_f64() * 1_000_000.0 / tempo as f64 * TICKS_PER_BEAT as f64).round() as u64;
+        write_vlq(&mut events, (tick.max(last_tick) - last_tick) as u32);
+        last_tick = tick.max(last_tick);
+        match message.split_first() {
+            // SysEx is stored with its length after the F0
+            Some((0xF0, rest)) => {
+                events.push(0xF0);
+                write_vlq(&mut events, rest.len() as u32);
+                events.extend_from_slice(rest);
+            },
+            _ => events.extend_from_slice(message),
+        }
+    }
+    write_vlq(&mut events, 0);
+    events.extend_from_slice(&[0xFF, 0x2F, 0x00]);
+
+    let mut out = Vec::new();
+    out.extend_from_slice(b"MThd");
+    out.extend_from_slice(&6u32.to_be_bytes());
+    out.extend_from_slice(&1u16.to_be_bytes());
+    out.extend_from_slice(&2u16.to_be_bytes());
+    out.extend_from_slice(&TICKS_PER_BEAT.to_be_bytes());
+    write_track(&mut out, &tempo_track);
+    write_track(&mut out, &events);
+    std::fs::write(&recording.path, out)?;
+
+    Ok(Some(recording.path))
+}
\ No newline at end of file
@@ -0,0 +1,152 @@
+use std::sync::Arc;
+use midi_control::consts;
+use midir::{MidiOutput, MidiOutputConnection};
+use mlua::{Error::ExternalError};
+use parking_lot::Mutex;
+
+use super::{ApiProvider, RECORDED_MIDI, midi::MidiError};
+
+fn open_output(selector: &str) -> Result<MidiOutputConnection, MidiError> {
+    let midi_out = MidiOutput::new("handcake MIDI output").map_err(|e| MidiError(e.to_string()))?;
+    let out_ports = midi_out.ports();
+    let port = match selector.parse::<usize>() {
+        Ok(portno) => out_ports.get(portno),
+        Err(_) => out_ports.iter().find(|p| {
+            midi_out.port_name(p).map(|n| n.contains(selector)).unwrap_or(false)
+        }),
+    };
+    let port = match port {
+        Some(port) => port,
+        None => return Err(MidiError(format!("No MIDI output matching {:?}", selector))),
+    };
+
+    let name = midi_out.port_name(port).map_err(|e| MidiError(e.to_string()))?;
+    let conn = midi_out.connect(port, &name).map_err(|e| MidiError(e.to_string()))?;
+    info!("Sending MIDI to {:?}", name);
+
+    Ok(conn)
+}
+
+// Channels are 1-16 on the Lua side, same as in on_midi_recv
+pub(super) fn status(kind: u8, channel: u8) -> mlua::Result<u8> {
+    if !(1..=16).contains(&channel) {
+        return Err(mlua::Error::RuntimeError(format!("Invalid MIDI channel {}", channel)));
+    }
+
+    Ok(kind | (channel - 1))
+}
+
+fn send(conn: &Mutex<Option<MidiOutputConnection>>, bytes: &[u8]) -> mlua::Result<()> {
+    // Tests don't have an output, they get to see what would've been sent instead
+    if let Some(recorded) = RECORDED_MIDI.lock().as_mut() {
+        recorded.push(bytes.to_vec());
+        return Ok(());
+    }
+    let mut conn = conn.lock();
+    let conn = match conn.as_mut() {
+        Some(conn) => conn,
+        None => return Err(ExternalError(Arc::new(MidiError("No MIDI output open, pass --midi-out".into())))),
+    };
+    conn.send(bytes).map_err(|e| ExternalError(Arc::new(MidiError(e.to_string()))))?;
+
+    Ok(())
+}
+
+// So other modules can send through the same connection, see send_from
+struct Output(Arc<Mutex<Option<MidiOutputConnection>>>);
+
+/// Sends bytes out of the --midi-out port, for the midi functions that need to talk back
+pub(super) fn send_from(l: &mlua::Lua, bytes: &[u8]) -> mlua::Result<()> {
+    match l.app_data_ref::<Output>() {
+        Some(output) => send(&output.0, bytes),
+        None => Err(ExternalError(Arc::new(MidiError("No MIDI output open, pass --midi-out".into())))),
+    }
+}
+
+/// Whether send_from has anywhere to send to
+pub(super) fn can_send(l: &mlua::Lua) -> bool {
+    RECORDED_MIDI.lock().is_some() || l.app_data_ref::<Output>().is_some_and(|output| output.0.lock().is_some())
+}
+
+pub struct MidiOut;
+impl ApiProvider for MidiOut {
+    type Arguments = (Option<String>,);
+
+    fn register_api(l: &mlua::Lua, args: Self::Arguments) -> anyhow::Result<()> {
+        let (selector,) = args;
+        let conn = match selector {
+            Some(selector) => Some(open_output(&selector)?),
+            None => None,
+        };
+        let outest = Arc::new(Mutex::new(conn));
+        l.set_app_data(Output(outest.clone()));
+
+        let tab = l.create_table()?;
+
+        {
+            let conn = outest.clone();
+            tab.set("note_on", l.create_function(move |_l, (channel, key, vel): (u8, u8, u8)| {
+                send(&conn, &[status(consts::NOTE_ON, channel)?, key & 0x7F, vel & 0x7F])
+            })?)?;
+        }
+
+        {
+            let conn = outest.clone();
+            tab.set("note_off", l.create_function(move |_l, (channel, key, vel): (u8, u8, Option<u8>)| {
+                send(&conn, &[status(consts::NOTE_OFF, channel)?, key & 0x7F, vel.unwrap_or(0) & 0x7F])
+            })?)?;
+        }
+
+        {
+            let conn = outest.clone();
+            tab.set("control_change", l.create_function(move |_l, (channel, control, value): (u8, u8, u8)| {
+                send(&conn, &[status(consts::CONTROL_CHANGE, channel)?, control & 0x7F, value & 0x7F])
+            })?)?;
+        }
+
+        {
+            let conn = outest.clone();
+            tab.set("pitch_bend", l.create_function(move |_l, (channel, value): (u8, u16)| {
+                // 14-bit value, 8192 is centre
+                let value = value.min(0x3FFF);
+                send(&conn, &[status(consts::PITCH_BEND_CHANGE, channel)?, (value & 0x7F) as u8, (value >> 7) as u8])
+            })?)?;
+        }
+
+        {
+            let conn = outest.clone();
+            tab.set("raw", l.create_function(move |_l, (bytes,): (Vec<u8>,)| {
+                send(&conn, &bytes)
+            })?)?;
+        }
+
+        {
+            // Data bytes only, the F0/F7 framing is added here
+            let conn = outest.clone();
+            tab.set("sysex", l.create_function(move |_l, (data,): (Vec<i64>,)| {
+                let mut bytes = Vec::with_capacity(data.len() + 2);
+                bytes.push(consts::system_event::SYSEX);
+                for (i, byte) in data.iter().enumerate() {
+                    if !(0..=0x7F).contains(byte) {
+                        return Err(mlua::Error::RuntimeError(format!("SysEx byte {} is {}, expected 0 to 127", i + 1, byte)));
+                    }
+                    bytes.push(*byte as u8);
+                }
+                bytes.push(consts::system_event::EOX);
+                send(&conn, &bytes)
+            })?)?;
+        }
+
+        {
+            // No checks at all, the script has to include F0 and F7 itself
+            let conn = outest.clone();
+            tab.set("sysex_raw", l.create_function(move |_l, (bytes,): (Vec<u8>,)| {
+                send(&conn, &bytes)
+            })?)?;
+        }
+
+        l.globals().set("midi_out", tab)?;
+
+        Ok(())
+    }
+}
@@ -1,9 +1,91 @@
-use std::{time::Duration, sync::Arc};
+use std::{time::Duration, sync::{Arc, atomic::Ordering}};
 
+use mlua::LuaSerdeExt;
 use parking_lot::Mutex;
 
+use crate::AppState;
 use super::ApiProvider;
 
+api_entries! { "misc",
+    "sleep" => "fun(seconds: number)", "Waits, holding up everything else";
+    "sleep_ms" => "fun(ms: integer)", "Same as sleep, in milliseconds";
+    "time" => "fun(): number", "Seconds since handcake started";
+    "getenv" => "fun(name: string, default?: string): string?", "Reads an environment variable";
+    "delta_time" => "fun(): number", "Seconds since the last call";
+    "schedule" => "fun(delay_ms: integer, f: fun()): integer", "Calls f once after a while";
+    "cancel" => "fun(handle: integer)", "Cancels a misc.schedule";
+    "interval" => "fun(period_ms: integer, f: fun()): integer", "Calls f every period_ms";
+    "clear_interval" => "fun(handle: integer)", "Stops a misc.interval";
+    "set_init_delay" => "fun(ms: integer)", "Waits this long before on_script_init, instead of --init-delay-ms";
+    "yield_now" => "fun()", "Lets everything else run for a moment, in the middle of a long callback";
+    "check_signals" => "fun(): boolean", "Whether handcake has been told to stop, so a long callback can finish early";
+    "queue_depth" => "fun(): integer", "How many messages are waiting for the script";
+    "queue_stats" => "fun(): QueueStats", "How many messages are waiting, were dropped and have been handled";
+}
+
+// Registry tables of timer handle -> function, per VM so a reload drops the old script's timers.
+// Intervals get their own table so misc.cancel can't clear one and vice versa.
+const TIMERS_KEY: &str = "handcake_timers";
+const INTERVALS_KEY: &str = "handcake_intervals";
+
+/// Looks up the function for a timer that just fired, returns None if it was
+/// cancelled (or belonged to a script that has since been reloaded).
+/// One-shot timers are removed, intervals stay until they're cleared.
+pub fn timer_callback(l: &mlua::Lua, handle: u64, repeating: bool) -> mlua::Result<Option<mlua::Function<'_>>> {
+    if repeating {
+        let intervals = l.named_registry_value::<_, mlua::Table>(INTERVALS_KEY)?;
+        return intervals.get::<_, Option<mlua::Function>>(handle);
+    }
+
+    let timers = l.named_registry_value::<_, mlua::Table>(TIMERS_KEY)?;
+    let f = timers.get::<_, Option<mlua::Function>>(handle)?;
+    timers.set(handle, mlua::Value::Nil)?;
+
+    Ok(f)
+}
+
+/// Calls `f` once after `delay` unless cancelled, same as misc.schedule but
+/// usable from the other APIs
+pub fn schedule(l: &mlua::Lua, state: &AppState, delay: Duration, f: mlua::Function) -> mlua::Result<u64> {
+    let handle = state.timers.new_handle();
+    l.named_registry_value::<_, mlua::Table>(TIMERS_KEY)?.set(handle, f)?;
+    state.timers.start(handle, delay, None);
+
+    Ok(handle)
+}
+
+pub fn cancel(l: &mlua::Lua, handle: u64) -> mlua::Result<()> {
+    l.named_registry_value::<_, mlua::Table>(TIMERS_KEY)?.set(handle, mlua::Value::Nil)
+}
+
+/// Calls `f` after `delay` and then every `period` until cleared, same as
+/// misc.interval but usable from the other APIs
+pub fn start_interval(l: &mlua::Lua, state: &AppState, delay: Duration, period: Duration, f: mlua::Function) -> mlua::Result<u64> {
+    let handle = state.timers.new_handle();
+    l.named_registry_value::<_, mlua::Table>(INTERVALS_KEY)?.set(handle, f)?;
+    state.timers.start(handle, delay, Some(period));
+
+    Ok(handle)
+}
+
+pub fn clear_interval(l: &mlua::Lua, handle: u64) -> mlua::Result<()> {
+    l.named_registry_value::<_, mlua::Table>(INTERVALS_KEY)?.set(handle, mlua::Value::Nil)
+}
+
+// From misc.set_init_delay(), done once on_script_init is about to be called
+struct InitDelay {
+    delay: Option<Duration>,
+    done: bool,
+}
+
+/// Whatever the script asked for with misc.set_init_delay(), if anything. From here
+/// on misc.set_init_delay() is an error, it would be too late to make a difference.
+pub fn take_init_delay(l: &mlua::Lua) -> Option<Duration> {
+    let mut init_delay = l.app_data_mut::<InitDelay>().unwrap();
+    init_delay.done = true;
+    init_delay.delay
+}
+
 lazy_static::lazy_static! {
     static ref START_TIME: std::time::Instant = {
         std::time::Instant::now()
@@ -16,16 +98,65 @@ lazy_static::lazy_static! {
 
 pub struct Misc;
 impl ApiProvider for Misc {
-    type Arguments = ();
+    type Arguments = (Arc<AppState>,);
 
-    fn register_api(l: &mlua::Lua, _args: Self::Arguments) -> anyhow::Result<()> {
+    fn register_api(l: &mlua::Lua, args: Self::Arguments) -> anyhow::Result<()> {
+        let (state,) = args;
         let tab = l.create_table()?;
+        l.set_named_registry_value(TIMERS_KEY, l.create_table()?)?;
+        l.set_named_registry_value(INTERVALS_KEY, l.create_table()?)?;
+
+        tab.set("sleep", l.create_async_function(|_l, (time,): (f32,)| async move {
+            tokio::time::sleep(Duration::from_secs_f32(time)).await;
+            Ok(())
+        })?)?;
+
+        // Messages that arrive in the meantime wait in the queue (until it's full, see
+        // --queue-depth), reloads included, so the VM never changes under a running callback.
+        // misc.schedule is the way to wait without holding everything else up.
+        tab.set("sleep_ms", l.create_async_function(|_l, (ms,): (u64,)| async move {
+            tokio::time::sleep(Duration::from_millis(ms)).await;
+            Ok(())
+        })?)?;
+
+        l.set_app_data(InitDelay { delay: None, done: false });
 
-        tab.set("sleep", l.create_function(|_l, (time,): (f32,)| {
-            std::thread::sleep(Duration::from_secs_f32(time));
+        // Only from the top of the script, that's what runs before on_script_init
+        tab.set("set_init_delay", l.create_function(|l, (ms,): (u64,)| {
+            let mut init_delay = l.app_data_mut::<InitDelay>().unwrap();
+            if init_delay.done {
+                return Err(mlua::Error::RuntimeError("misc.set_init_delay has to be called before on_script_init".to_owned()));
+            }
+            init_delay.delay = Some(Duration::from_millis(ms));
             Ok(())
         })?)?;
 
+        // Only yields where it can, so it does nothing from a velocity curve and the like
+        // instead of raising an error
+        let yield_now = l.create_async_function(|_l, _: ()| async move {
+            tokio::task::yield_now().await;
+            Ok(())
+        })?;
+        let isyieldable = l.globals().get::<_, mlua::Table>("coroutine")?.get::<_, mlua::Function>("isyieldable")?;
+        let yield_if_possible = l.load(r#"
+            local yield_now, isyieldable = ...
+            return function()
+                if isyieldable() then
+                    yield_now()
+                end
+            end
+        "#).set_name("=misc.yield_now")?.call::<_, mlua::Function>((yield_now, isyieldable))?;
+        tab.set("yield_now", yield_if_possible)?;
+
+        // The signal itself is handled elsewhere, on_script_exit still runs once the
+        // callback that's asking returns
+        {
+            let state = state.clone();
+            tab.set("check_signals", l.create_function(move |_l, _: ()| {
+                Ok(state.stopping.load(Ordering::Acquire))
+            })?)?;
+        }
+
         tab.set("time", l.create_function(|_l, _: ()| {
             let t = std::time::Instant::now();
             let elapsed = t.duration_since(*START_TIME);
@@ -35,6 +166,11 @@ impl ApiProvider for Misc {
             Ok(millis / 1000f64)
         })?)?;
 
+        // Read-only on purpose, anything set here would leak into child processes
+        tab.set("getenv", l.create_function(|_l, (name, default): (String, Option<String>)| {
+            Ok(std::env::var(&name).ok().or(default))
+        })?)?;
+
         tab.set("delta_time", l.create_function(|_l, _: ()| {
             let t = DELTA.lock().elapsed();
             let millis = t.as_millis() as u32;
@@ -44,8 +180,86 @@ impl ApiProvider for Misc {
             Ok(millis / 1000f64)
         })?)?;
 
+        {
+            let state = state.clone();
+            tab.set("schedule", l.create_function(move |l, (delay_ms, f): (u64, mlua::Function)| {
+                schedule(l, &state, Duration::from_millis(delay_ms), f)
+            })?)?;
+        }
+
+        tab.set("cancel", l.create_function(|l, (handle,): (u64,)| {
+            cancel(l, handle)
+        })?)?;
+
+        {
+            let state = state.clone();
+            tab.set("interval", l.create_function(move |l, (period_ms, f): (i64, mlua::Function)| {
+                if period_ms <= 0 {
+                    return Err(mlua::Error::RuntimeError(format!("Invalid interval period {}", period_ms)));
+                }
+                let period = Duration::from_millis(period_ms as u64);
+                start_interval(l, &state, period, period, f)
+            })?)?;
+        }
+
+        tab.set("clear_interval", l.create_function(|l, (handle,): (u64,)| {
+            clear_interval(l, handle)
+        })?)?;
+
+        // Only ever read, so a script can keep an eye on whether it's keeping up
+        {
+            let state = state.clone();
+            tab.set("queue_depth", l.create_function(move |_l, _: ()| {
+                Ok(state.sender.stats().depth)
+            })?)?;
+        }
+
+        {
+            let state = state.clone();
+            tab.set("queue_stats", l.create_function(move |l, _: ()| {
+                let stats = state.sender.stats();
+                let tab = l.create_table()?;
+                tab.set("depth", stats.depth)?;
+                tab.set("dropped", stats.dropped)?;
+                tab.set("processed", stats.processed)?;
+                Ok(tab)
+            })?)?;
+        }
+
         l.globals().set("misc", tab)?;
 
+        // log.info(...) etc, goes through the same logger as handcake itself
+        let log_tab = l.create_table()?;
+        for (name, level) in [
+            ("debug", log::Level::Debug),
+            ("info", log::Level::Info),
+            ("warn", log::Level::Warn),
+            ("error", log::Level::Error),
+        ] {
+            log_tab.set(name, l.create_function(move |l, args: mlua::Variadic<mlua::Value>| {
+                let tostring = l.globals().get::<&str, mlua::Function>("tostring")?;
+                let parts = args.into_iter()
+                    .map(|v| tostring.call::<_, String>(v))
+                    .collect::<mlua::Result<Vec<String>>>()?;
+                log!(target: "lua_script", level, "{}", parts.join(" "));
+
+                Ok(())
+            })?)?;
+        }
+        l.globals().set("log", log_tab)?;
+
+        let json_tab = l.create_table()?;
+        json_tab.set("encode", l.create_function(|l, (value,): (mlua::Value,)| {
+            let value = l.from_value::<serde_json::Value>(value)?;
+            serde_json::to_string(&value).map_err(mlua::Error::external)
+        })?)?;
+
+        json_tab.set("decode", l.create_function(|l, (text,): (String,)| {
+            let value = serde_json::from_str::<serde_json::Value>(&text).map_err(mlua::Error::external)?;
+            crate::util::json_to_lua(l, &value)
+        })?)?;
+        l.globals().set("json", json_tab)?;
+
         Ok(())
     }
 }
\ No newline at end of file
@@ -1,6 +1,363 @@
+/// Lists a module's functions for API_REGISTRY, as `"name" => "signature", "description";`.
+/// Signatures use lua-language-server's fun() syntax.
+macro_rules! api_entries {
+    ($module:literal, $($name:literal => $signature:literal, $description:literal;)*) => {
+        pub const API: &[super::ApiEntry] = &[
+            $(super::ApiEntry { module: $module, name: $name, signature: $signature, description: $description },)*
+        ];
+    };
+}
+
 pub mod midi;
+pub mod midi_out;
+pub mod midi_file;
+pub mod hotplug;
+pub mod serial_midi;
+pub mod rtp_midi;
+pub mod ble_midi;
 pub mod gamepad;
 pub mod misc;
+pub mod keyboard;
+pub mod mouse;
+pub mod touch;
+pub mod evdev_input;
+pub mod osc;
+pub mod fifo;
+pub mod ipc;
+pub mod dbus;
+pub mod state;
+pub mod buffer;
+pub mod test;
+
+use std::{collections::HashSet, fs::File, io, os::unix::prelude::OpenOptionsExt, path::Path, sync::{Arc, atomic::{AtomicBool, Ordering}}};
+use input_linux::{
+    UInputHandle,
+    EventTime,
+    InputEvent,
+    SynchronizeEvent,
+    SynchronizeKind,
+    EventKind,
+    Key,
+    AbsoluteAxis,
+    RelativeAxis,
+    InputProperty,
+    InputId,
+    AbsoluteInfoSetup,
+    sys::input_event,
+};
+use parking_lot::Mutex;
+use serde::Serialize;
+
+/// One function scripts can call, see --api-schema
+#[derive(Serialize, Debug)]
+pub struct ApiEntry {
+    /// The global table it's in, e.g. "midi"
+    pub module: &'static str,
+    /// Methods on objects the module returns are written as "Object:method"
+    pub name: &'static str,
+    pub signature: &'static str,
+    pub description: &'static str,
+}
+
+/// Everything that's documented so far, one list per module
+pub static API_REGISTRY: &[&[ApiEntry]] = &[midi::API, gamepad::API, misc::API, buffer::API, evdev_input::API, test::API];
+
+/// The registry as JSON, what --api-schema prints and api_schema.json has in it
+pub fn schema() -> serde_json::Value {
+    serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "functions": API_REGISTRY.iter().flat_map(|entries| entries.iter()).collect::<Vec<_>>(),
+    })
+}
+
+/// Warns about functions scripts can call that aren't in the registry, and
+/// entries for ones that don't exist, so the two don't drift apart. Only
+/// checks modules that are in the registry at all, and that the script has
+/// (test is only there with --test).
+pub fn check_registry(l: &mlua::Lua) -> mlua::Result<()> {
+    let entries = API_REGISTRY.iter().flat_map(|entries| entries.iter());
+    let documented: HashSet<(&str, &str)> = entries.clone().map(|e| (e.module, e.name)).collect();
+    let modules: HashSet<&str> = entries.clone().map(|e| e.module).collect();
+
+    for module in modules {
+        let tab = match l.globals().get::<_, Option<mlua::Table>>(module)? {
+            Some(tab) => tab,
+            None => continue,
+        };
+        for pair in tab.pairs::<String, mlua::Value>() {
+            let (name, value) = pair?;
+            if matches!(value, mlua::Value::Function(_)) && !documented.contains(&(module, name.as_str())) {
+                warn!("{}.{} is missing from the API registry", module, name);
+            }
+        }
+    }
+    for entry in entries.filter(|e| !e.name.contains(':')) {
+        let tab = match l.globals().get::<_, Option<mlua::Table>>(entry.module)? {
+            Some(tab) => tab,
+            None => continue,
+        };
+        if !matches!(tab.get::<_, mlua::Value>(entry.name)?, mlua::Value::Function(_)) {
+            warn!("{}.{} is in the API registry but doesn't exist", entry.module, entry.name);
+        }
+    }
+
+    Ok(())
+}
+
+/// How a virtual device identifies itself, i.e. what shows up in /proc/bus/input/devices.
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub vendor: u16,
+    pub product: u16,
+}
+
+impl DeviceInfo {
+    pub fn new(name: &str, vendor: u16, product: u16) -> Self {
+        DeviceInfo {
+            name: name.into(),
+            vendor,
+            product,
+        }
+    }
+
+    /// Replaces whichever fields were given and keeps the rest.
+    pub fn with_overrides(self, name: Option<String>, vendor: Option<u16>, product: Option<u16>) -> Self {
+        DeviceInfo {
+            name: name.unwrap_or(self.name),
+            vendor: vendor.unwrap_or(self.vendor),
+            product: product.unwrap_or(self.product),
+        }
+    }
+}
+
+/// The parts of a uinput handle the devices use, so --dry-run can swap in
+/// one that prints everything instead
+pub trait UInput: Send {
+    fn set_evbit(&self, kind: EventKind) -> io::Result<()>;
+    fn set_keybit(&self, key: Key) -> io::Result<()>;
+    fn set_absbit(&self, axis: AbsoluteAxis) -> io::Result<()>;
+    fn set_relbit(&self, axis: RelativeAxis) -> io::Result<()>;
+    fn set_propbit(&self, property: InputProperty) -> io::Result<()>;
+    fn create(&self, id: &InputId, name: &[u8], ff_effects_max: u32, abs: &[AbsoluteInfoSetup]) -> io::Result<()>;
+    fn write(&self, events: &[input_event]) -> io::Result<usize>;
+    fn dev_destroy(&self) -> io::Result<()>;
+}
+
+impl UInput for UInputHandle<File> {
+    fn set_evbit(&self, kind: EventKind) -> io::Result<()> { UInputHandle::set_evbit(self, kind) }
+    fn set_keybit(&self, key: Key) -> io::Result<()> { UInputHandle::set_keybit(self, key) }
+    fn set_absbit(&self, axis: AbsoluteAxis) -> io::Result<()> { UInputHandle::set_absbit(self, axis) }
+    fn set_relbit(&self, axis: RelativeAxis) -> io::Result<()> { UInputHandle::set_relbit(self, axis) }
+    fn set_propbit(&self, property: InputProperty) -> io::Result<()> { UInputHandle::set_propbit(self, property) }
+    fn create(&self, id: &InputId, name: &[u8], ff_effects_max: u32, abs: &[AbsoluteInfoSetup]) -> io::Result<()> {
+        UInputHandle::create(self, id, name, ff_effects_max, abs)
+    }
+    fn write(&self, events: &[input_event]) -> io::Result<usize> { UInputHandle::write(self, events) }
+    fn dev_destroy(&self) -> io::Result<()> { UInputHandle::dev_destroy(self) }
+}
+
+/// Set with --dry-run, open_uinput() then hands out devices that print what
+/// they're sent instead of touching /dev/uinput
+pub static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+/// One event a --dry-run device was sent
+#[derive(Clone, Debug)]
+pub struct RecordedEvent {
+    pub device: String,
+    pub kind: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+lazy_static::lazy_static! {
+    /// Set with --test, dry run devices keep their events in here instead of printing them
+    pub static ref RECORDED_EVENTS: Mutex<Option<Vec<RecordedEvent>>> = Mutex::new(None);
+    /// Same for --midi-out, every message sent as its raw bytes
+    pub static ref RECORDED_MIDI: Mutex<Option<Vec<Vec<u8>>>> = Mutex::new(None);
+    /// And evdev.grab() and friends, each device and whether it was grabbed or released
+    pub static ref RECORDED_GRABS: Mutex<Option<Vec<(String, bool)>>> = Mutex::new(None);
+}
+
+// Prints every event as a line of JSON on stdout, named after the device it went to
+struct DryRunDevice {
+    name: Mutex<String>,
+}
+
+impl UInput for DryRunDevice {
+    fn set_evbit(&self, _kind: EventKind) -> io::Result<()> { Ok(()) }
+    fn set_keybit(&self, _key: Key) -> io::Result<()> { Ok(()) }
+    fn set_absbit(&self, _axis: AbsoluteAxis) -> io::Result<()> { Ok(()) }
+    fn set_relbit(&self, _axis: RelativeAxis) -> io::Result<()> { Ok(()) }
+    fn set_propbit(&self, _property: InputProperty) -> io::Result<()> { Ok(()) }
+    fn create(&self, _id: &InputId, name: &[u8], _ff_effects_max: u32, _abs: &[AbsoluteInfoSetup]) -> io::Result<()> {
+        *self.name.lock() = String::from_utf8_lossy(name).into_owned();
+        Ok(())
+    }
+
+    fn write(&self, events: &[input_event]) -> io::Result<usize> {
+        let name = self.name.lock();
+        if let Some(recorded) = RECORDED_EVENTS.lock().as_mut() {
+            recorded.extend(events.iter().map(|event| RecordedEvent {
+                device: name.clone(),
+                kind: event.type_,
+                code: event.code,
+                value: event.value,
+            }));
+            return Ok(events.len());
+        }
+        for event in events {
+            let line = serde_json::json!({
+                "device": *name,
+                "type": event.type_,
+                "code": event.code,
+                "value": event.value,
+            });
+            println!("{}", line);
+        }
+        Ok(events.len())
+    }
+
+    fn dev_destroy(&self) -> io::Result<()> { Ok(()) }
+}
+
+pub fn open_uinput(path: &Path) -> io::Result<Box<dyn UInput>> {
+    if DRY_RUN.load(Ordering::Relaxed) {
+        return Ok(Box::new(DryRunDevice { name: Mutex::new(String::new()) }));
+    }
+    let fd = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)?;
+
+    Ok(Box::new(UInputHandle::new(fd)))
+}
+
+/// A handle that outlives the script it was opened for, so a reload carries
+/// on with the same virtual device instead of opening /dev/uinput again,
+/// which isn't allowed any more after --user. Only the first script sets the
+/// device up, setting it up again after that does nothing.
+#[derive(Clone)]
+pub struct SharedUInput {
+    uinput: Arc<Mutex<Box<dyn UInput>>>,
+    created: Arc<AtomicBool>,
+}
+
+impl SharedUInput {
+    pub fn new(uinput: Box<dyn UInput>) -> Self {
+        SharedUInput { uinput: Arc::new(Mutex::new(uinput)), created: Arc::new(AtomicBool::new(false)) }
+    }
+
+    fn set_up(&self, f: impl FnOnce(&dyn UInput) -> io::Result<()>) -> io::Result<()> {
+        if self.created.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        f(self.uinput.lock().as_ref())
+    }
+}
+
+impl UInput for SharedUInput {
+    fn set_evbit(&self, kind: EventKind) -> io::Result<()> { self.set_up(|u| u.set_evbit(kind)) }
+    fn set_keybit(&self, key: Key) -> io::Result<()> { self.set_up(|u| u.set_keybit(key)) }
+    fn set_absbit(&self, axis: AbsoluteAxis) -> io::Result<()> { self.set_up(|u| u.set_absbit(axis)) }
+    fn set_relbit(&self, axis: RelativeAxis) -> io::Result<()> { self.set_up(|u| u.set_relbit(axis)) }
+    fn set_propbit(&self, property: InputProperty) -> io::Result<()> { self.set_up(|u| u.set_propbit(property)) }
+    fn create(&self, id: &InputId, name: &[u8], ff_effects_max: u32, abs: &[AbsoluteInfoSetup]) -> io::Result<()> {
+        self.set_up(|u| u.create(id, name, ff_effects_max, abs))?;
+        self.created.store(true, Ordering::Release);
+        Ok(())
+    }
+    fn write(&self, events: &[input_event]) -> io::Result<usize> { self.uinput.lock().write(events) }
+    fn dev_destroy(&self) -> io::Result<()> {
+        self.uinput.lock().dev_destroy()?;
+        self.created.store(false, Ordering::Release);
+        Ok(())
+    }
+}
+
+/// The keyboard, mouse and touchscreen every script gets, see SharedUInput.
+/// Gamepads come and go with gamepad.create() instead.
+#[derive(Clone)]
+pub struct VirtualDevices {
+    pub keyboard: SharedUInput,
+    pub mouse: SharedUInput,
+    pub touch: SharedUInput,
+}
+
+impl VirtualDevices {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(VirtualDevices {
+            keyboard: SharedUInput::new(open_uinput(path)?),
+            mouse: SharedUInput::new(open_uinput(path)?),
+            touch: SharedUInput::new(open_uinput(path)?),
+        })
+    }
+}
+
+/// A virtual device that sends a SYN_REPORT after every write, so nothing sits
+/// in the kernel waiting for a sync. Between begin_batch() and end_batch()
+/// writes pile up instead, and all go out as one report at the end.
+pub struct SyncedDevice {
+    pub uinput: Box<dyn UInput>,
+    batching: bool,
+}
+
+impl SyncedDevice {
+    pub fn new(uinput: Box<dyn UInput>) -> Self {
+        SyncedDevice { uinput, batching: false }
+    }
+
+    pub fn write(&self, events: &[input_linux::sys::input_event]) -> std::io::Result<()> {
+        self.uinput.write(events)?;
+        if !self.batching {
+            self.sync()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn begin_batch(&mut self) {
+        self.batching = true;
+    }
+
+    /// Does nothing if there's no batch going
+    pub fn end_batch(&mut self) -> std::io::Result<()> {
+        if std::mem::take(&mut self.batching) {
+            self.sync()?;
+        }
+
+        Ok(())
+    }
+
+    fn sync(&self) -> std::io::Result<()> {
+        const ZERO: EventTime = EventTime::new(0, 0);
+        self.uinput.write(&[*InputEvent::from(SynchronizeEvent::new(ZERO, SynchronizeKind::Report, 0)).as_raw()])?;
+
+        Ok(())
+    }
+}
+
+/// Adds begin_batch() and end_batch() for a device to its Lua table
+pub fn register_batch(l: &mlua::Lua, tab: &mlua::Table, device: &std::sync::Arc<Mutex<SyncedDevice>>) -> mlua::Result<()> {
+    {
+        let device = device.clone();
+        tab.set("begin_batch", l.create_function(move |_l, _: ()| {
+            device.lock().begin_batch();
+            Ok(())
+        })?)?;
+    }
+
+    {
+        let device = device.clone();
+        tab.set("end_batch", l.create_function(move |_l, _: ()| {
+            device.lock().end_batch()?;
+            Ok(())
+        })?)?;
+    }
+
+    Ok(())
+}
 
 pub trait ApiProvider {
     type Arguments;
@@ -0,0 +1,140 @@
+use std::{sync::Arc};
+use input_linux::{
+    EventKind,
+    Key,
+    RelativeAxis,
+    InputId,
+    InputEvent,
+    KeyEvent,
+    KeyState,
+    RelativeEvent,
+    EventTime,
+};
+use parking_lot::Mutex;
+use super::{ApiProvider, DeviceInfo, SyncedDevice, UInput};
+
+const BUTTONS: [Key; 5] = [
+    Key::ButtonLeft,
+    Key::ButtonRight,
+    Key::ButtonMiddle,
+    Key::ButtonSide,
+    Key::ButtonExtra,
+];
+
+fn index_to_button(a: usize) -> mlua::Result<Key> {
+    BUTTONS.get(a).copied().ok_or_else(|| mlua::Error::RuntimeError(format!("Invalid mouse button {}", a)))
+}
+
+fn write_button(ui: &SyncedDevice, button: Key, state: KeyState) -> std::io::Result<()> {
+    const ZERO: EventTime = EventTime::new(0, 0);
+    let event = [
+        *InputEvent::from(KeyEvent::new(ZERO, button, state)).as_raw(),
+    ];
+    ui.write(&event)?;
+
+    Ok(())
+}
+
+pub struct Mouse;
+impl Mouse {
+    pub fn default_device() -> DeviceInfo {
+        // Logitech, Inc. M105 Optical Mouse
+        DeviceInfo::new("handcake-mouse", 0x046d, 0xc077)
+    }
+}
+
+impl ApiProvider for Mouse {
+    type Arguments = (Box<dyn UInput>, DeviceInfo);
+
+    fn register_api(l: &mlua::Lua, args: Self::Arguments) -> anyhow::Result<()> {
+        let (uinput, info) = args;
+
+        uinput.set_evbit(EventKind::Key)?;
+        for button in BUTTONS {
+            uinput.set_keybit(button)?;
+        }
+
+        uinput.set_evbit(EventKind::Relative)?;
+        uinput.set_relbit(RelativeAxis::X)?;
+        uinput.set_relbit(RelativeAxis::Y)?;
+        uinput.set_relbit(RelativeAxis::Wheel)?;
+
+        let input_id = InputId {
+            bustype: input_linux::sys::BUS_USB,
+            vendor: info.vendor,
+            product: info.product,
+            version: 0,
+        };
+        uinput.create(&input_id, info.name.as_bytes(), 0, &[])?;
+
+        let outest = Arc::new(Mutex::new(SyncedDevice::new(uinput)));
+        let tab = l.create_table()?;
+
+        {
+            let uinput = outest.clone();
+            tab.set("move", l.create_function(move |_l, (dx, dy): (i32, i32)| {
+                let ui = uinput.lock();
+                const ZERO: EventTime = EventTime::new(0, 0);
+                let event = [
+                    *InputEvent::from(RelativeEvent::new(ZERO, RelativeAxis::X, dx)).as_raw(),
+                    *InputEvent::from(RelativeEvent::new(ZERO, RelativeAxis::Y, dy)).as_raw(),
+                ];
+                ui.write(&event)?;
+
+                Ok(())
+            })?)?;
+        }
+
+        {
+            let uinput = outest.clone();
+            tab.set("scroll", l.create_function(move |_l, (delta,): (i32,)| {
+                let ui = uinput.lock();
+                const ZERO: EventTime = EventTime::new(0, 0);
+                let event = [
+                    *InputEvent::from(RelativeEvent::new(ZERO, RelativeAxis::Wheel, delta)).as_raw(),
+                ];
+                ui.write(&event)?;
+
+                Ok(())
+            })?)?;
+        }
+
+        {
+            let uinput = outest.clone();
+            tab.set("down", l.create_function(move |_l, (button,): (usize,)| {
+                let button = index_to_button(button)?;
+                write_button(&uinput.lock(), button, KeyState::PRESSED)?;
+
+                Ok(())
+            })?)?;
+        }
+
+        {
+            let uinput = outest.clone();
+            tab.set("up", l.create_function(move |_l, (button,): (usize,)| {
+                let button = index_to_button(button)?;
+                write_button(&uinput.lock(), button, KeyState::RELEASED)?;
+
+                Ok(())
+            })?)?;
+        }
+
+        {
+            let uinput = outest.clone();
+            tab.set("click", l.create_function(move |_l, (button,): (usize,)| {
+                let button = index_to_button(button)?;
+                let ui = uinput.lock();
+                write_button(&ui, button, KeyState::PRESSED)?;
+                write_button(&ui, button, KeyState::RELEASED)?;
+
+                Ok(())
+            })?)?;
+        }
+
+        super::register_batch(l, &tab, &outest)?;
+
+        l.globals().set("mouse", tab)?;
+
+        Ok(())
+    }
+}
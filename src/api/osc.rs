@@ -0,0 +1,127 @@
+use std::{collections::HashMap, net::UdpSocket, sync::Arc};
+use parking_lot::Mutex;
+use rosc::{OscMessage, OscPacket, OscType};
+use crate::{AppState, Message};
+
+use super::ApiProvider;
+
+lazy_static::lazy_static! {
+    // One connected socket per destination, made the first time something's sent there
+    static ref SOCKETS: Arc<Mutex<HashMap<(String, u16), UdpSocket>>> = Arc::new(Mutex::new(HashMap::new()));
+}
+
+// Bundles can nest, everything in one gets sent on as a separate message
+fn forward(packet: OscPacket, state: &AppState) {
+    match packet {
+        OscPacket::Message(msg) => {
+            let _ = state.sender.send(Message::Osc { address: msg.addr, args: msg.args });
+        },
+        OscPacket::Bundle(bundle) => {
+            for packet in bundle.content {
+                forward(packet, state);
+            }
+        },
+    }
+}
+
+/// Listens for OSC packets on a UDP port and passes their messages to on_osc_recv
+pub fn listen(state: &Arc<AppState>, port: u16) -> anyhow::Result<()> {
+    let socket = UdpSocket::bind(("0.0.0.0", port))?;
+    info!("Listening for OSC on port {}", port);
+
+    let state = state.clone();
+    std::thread::spawn(move || {
+        let mut buf = [0u8; rosc::decoder::MTU];
+        loop {
+            let n = match socket.recv(&mut buf) {
+                Ok(n) => n,
+                Err(e) => {
+                    warn!("Stopped listening for OSC: {}", e);
+                    return;
+                },
+            };
+
+            match rosc::decoder::decode_udp(&buf[..n]) {
+                Ok((_, packet)) => forward(packet, &state),
+                Err(e) => debug!("Bad OSC packet: {:?}", e),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn arg_to_value<'lua>(l: &'lua mlua::Lua, arg: &OscType) -> mlua::Result<mlua::Value<'lua>> {
+    use mlua::ToLua;
+
+    match arg {
+        OscType::Int(i) => (*i as f64).to_lua(l),
+        OscType::Float(f) => (*f as f64).to_lua(l),
+        OscType::Long(i) => (*i as f64).to_lua(l),
+        OscType::Double(f) => f.to_lua(l),
+        OscType::String(s) => s.as_str().to_lua(l),
+        OscType::Char(c) => c.to_string().to_lua(l),
+        OscType::Bool(b) => b.to_lua(l),
+        OscType::Blob(bytes) => bytes.clone().to_lua(l),
+        OscType::Time(t) => (t.seconds as f64 + t.fractional as f64 / u32::MAX as f64).to_lua(l),
+        OscType::Color(c) => vec![c.red, c.green, c.blue, c.alpha].to_lua(l),
+        OscType::Midi(m) => vec![m.port, m.status, m.data1, m.data2].to_lua(l),
+        OscType::Array(a) => args_to_table(l, &a.content)?.to_lua(l),
+        OscType::Inf => f64::INFINITY.to_lua(l),
+        OscType::Nil => Ok(mlua::Value::Nil),
+    }
+}
+
+/// Numbers all become Lua numbers, blobs become tables of bytes
+pub fn args_to_table<'lua>(l: &'lua mlua::Lua, args: &[OscType]) -> mlua::Result<mlua::Table<'lua>> {
+    let tab = l.create_table_with_capacity(args.len() as i32, 0)?;
+    for (i, arg) in args.iter().enumerate() {
+        tab.set(i + 1, arg_to_value(l, arg)?)?;
+    }
+
+    Ok(tab)
+}
+
+fn value_to_arg(value: mlua::Value) -> mlua::Result<OscType> {
+    match value {
+        mlua::Value::Integer(i) => Ok(OscType::Float(i as f32)),
+        mlua::Value::Number(n) => Ok(OscType::Float(n as f32)),
+        mlua::Value::String(s) => Ok(OscType::String(s.to_str()?.to_string())),
+        mlua::Value::Boolean(b) => Ok(OscType::Bool(b)),
+        v => Err(mlua::Error::RuntimeError(format!("Can't send a {} over OSC", v.type_name()))),
+    }
+}
+
+fn send(host: String, port: u16, packet: &OscPacket) -> mlua::Result<()> {
+    let bytes = rosc::encoder::encode(packet).map_err(|e| mlua::Error::RuntimeError(format!("Could not encode OSC message: {:?}", e)))?;
+
+    let mut sockets = SOCKETS.lock();
+    let key = (host, port);
+    if !sockets.contains_key(&key) {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect((key.0.as_str(), port))?;
+        sockets.insert(key.clone(), socket);
+    }
+    sockets[&key].send(&bytes)?;
+
+    Ok(())
+}
+
+pub struct Osc;
+impl ApiProvider for Osc {
+    type Arguments = ();
+
+    fn register_api(l: &mlua::Lua, _args: Self::Arguments) -> anyhow::Result<()> {
+        let tab = l.create_table()?;
+
+        // osc.send("localhost", 9000, "/some/address", 1, "two", true)
+        tab.set("send", l.create_function(|_l, (host, port, address, args): (String, u16, String, mlua::Variadic<mlua::Value>)| {
+            let args = args.into_iter().map(value_to_arg).collect::<mlua::Result<Vec<OscType>>>()?;
+            send(host, port, &OscPacket::Message(OscMessage { addr: address, args }))
+        })?)?;
+
+        l.globals().set("osc", tab)?;
+
+        Ok(())
+    }
+}
\ No newline at end of file
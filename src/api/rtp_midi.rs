@@ -0,0 +1,310 @@
+use std::{collections::HashMap, net::{SocketAddr, UdpSocket}, sync::Arc, time::{Instant, SystemTime, UNIX_EPOCH}};
+use parking_lot::Mutex;
+use crate::{AppState, queue, util};
+use super::midi;
+
+// What the other end sees us as in its session list
+const NAME: &str = "handcake";
+const PROTOCOL_VERSION: u32 = 2;
+
+fn be32(data: &[u8], at: usize) -> Option<u32> {
+    data.get(at..at + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn be64(data: &[u8], at: usize) -> Option<u64> {
+    data.get(at..at + 8).map(|b| u64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+}
+
+/// Shared by the control and data sockets. Every session is known by the SSRC
+/// the other end sent in its invitation.
+struct Sessions {
+    ssrc: u32,
+    started: Instant,
+    names: Mutex<HashMap<u32, String>>,
+    last_seq: Mutex<HashMap<u32, u16>>,
+    sender: queue::Sender,
+}
+
+impl Sessions {
+    // Clock sync timestamps are in units of 100 microseconds
+    fn now(&self) -> u64 {
+        (self.started.elapsed().as_micros() / 100) as u64
+    }
+
+    fn name(&self, ssrc: u32, from: SocketAddr) -> String {
+        self.names.lock().get(&ssrc).cloned().unwrap_or_else(|| from.to_string())
+    }
+
+    // The AppleMIDI session protocol, which runs on both ports.
+    // Packets start with 0xFFFF and a two letter command.
+    fn handle_command(&self, socket: &UdpSocket, from: SocketAddr, packet: &[u8]) {
+        match &packet[2..4] {
+            // Invitation: version, token, SSRC, then a null-terminated name
+            b"IN" => {
+                let (token, ssrc) = match (be32(packet, 8), be32(packet, 12)) {
+                    (Some(token), Some(ssrc)) => (token, ssrc),
+                    _ => return,
+                };
+                let name = packet[16.min(packet.len())..].split(|b| *b == 0).next().unwrap_or_default();
+                let name = String::from_utf8_lossy(name).into_owned();
+                if self.names.lock().insert(ssrc, name.clone()).is_none() {
+                    info!("RTP-MIDI session from {:?} ({})", name, from);
+                }
+
+                let mut reply = vec![0xFF, 0xFF, b'O', b'K'];
+                reply.extend(PROTOCOL_VERSION.to_be_bytes());
+                reply.extend(token.to_be_bytes());
+                reply.extend(self.ssrc.to_be_bytes());
+                reply.extend(NAME.as_bytes());
+                reply.push(0);
+                if let Err(e) = socket.send_to(&reply, from) {
+                    warn!("Could not accept RTP-MIDI invitation from {}: {}", from, e);
+                }
+            },
+            // Clock sync: SSRC, count, 3 bytes padding, then three timestamps.
+            // The other end starts at count 0, we answer with 1, it finishes with 2.
+            b"CK" => {
+                let (count, ts1) = match (packet.get(8), be64(packet, 12)) {
+                    (Some(count), Some(ts1)) => (*count, ts1),
+                    _ => return,
+                };
+                if count != 0 {
+                    return;
+                }
+                let mut reply = vec![0xFF, 0xFF, b'C', b'K'];
+                reply.extend(self.ssrc.to_be_bytes());
+                reply.extend([1, 0, 0, 0]);
+                reply.extend(ts1.to_be_bytes());
+                reply.extend(self.now().to_be_bytes());
+                reply.extend(0u64.to_be_bytes());
+                let _ = socket.send_to(&reply, from);
+            },
+            // Goodbye: version, token, SSRC
+            b"BY" => {
+                if let Some(ssrc) = be32(packet, 12) {
+                    if let Some(name) = self.names.lock().remove(&ssrc) {
+                        info!("RTP-MIDI session from {:?} ended", name);
+                    }
+                    self.last_seq.lock().remove(&ssrc);
+                }
+            },
+            // Receiver feedback (RS) is only useful to a sender
+            cmd => debug!("Ignoring RTP-MIDI command {:?}", String::from_utf8_lossy(cmd)),
+        }
+    }
+
+    // An RTP packet carrying a MIDI command section (RFC 6295 section 3)
+    fn handle_rtp(&self, from: SocketAddr, packet: &[u8]) {
+        if packet.len() < 13 || packet[0] >> 6 != 2 {
+            return;
+        }
+        let seq = u16::from_be_bytes([packet[2], packet[3]]);
+        let ssrc = be32(packet, 8).unwrap();
+        let device = self.name(ssrc, from);
+
+        // Lost packets would have to be rebuilt from the recovery journal, which isn't
+        // read, so say something rather than silently missing a note off
+        if let Some(last) = self.last_seq.lock().insert(ssrc, seq) {
+            let lost = seq.wrapping_sub(last).wrapping_sub(1);
+            if lost > 0 && lost < 0x8000 {
+                warn!("Lost {} RTP-MIDI packets from {:?}", lost, device);
+            }
+        }
+
+        let mut at = 12 + 4 * (packet[0] & 0x0F) as usize;
+        let flags = match packet.get(at) {
+            Some(flags) => *flags,
+            None => return,
+        };
+        // B means a 12 bit length, Z a delta time before the first command
+        let mut len = (flags & 0x0F) as usize;
+        at += 1;
+        if flags & 0x80 != 0 {
+            len = (len << 8) | *packet.get(at).unwrap_or(&0) as usize;
+            at += 1;
+        }
+        let list = &packet[at.min(packet.len())..(at + len).min(packet.len())];
+        parse_command_list(list, flags & 0x20 != 0, |message| midi::forward(&device, message, &self.sender));
+    }
+}
+
+// Every command after the first always has a delta time in front of it, the first only with Z set.
+// Running status carries on within a packet; only whole SysEx messages (no segments) are passed on.
+fn parse_command_list(list: &[u8], first_has_delta: bool, mut emit: impl FnMut(&[u8])) {
+    let mut at = 0;
+    let mut running_status = None;
+    let mut first = true;
+    while at < list.len() {
+        if !first || first_has_delta {
+            // Delta times are 1 to 4 bytes, high bit set on all but the last
+            for _ in 0..4 {
+                let more = list.get(at).map(|b| b & 0x80 != 0).unwrap_or(false);
+                at += 1;
+                if !more {
+                    break;
+                }
+            }
+        }
+        first = false;
+
+        let byte = match list.get(at) {
+            Some(byte) => *byte,
+            None => return,
+        };
+        if byte == 0xF0 {
+            let end = match list[at + 1..].iter().position(|b| matches!(b, 0xF0 | 0xF4 | 0xF7)) {
+                Some(end) => at + 1 + end,
+                None => return,
+            };
+            if list[end] == 0xF7 {
+                emit(&list[at..=end]);
+            }
+            running_status = None;
+            at = end + 1;
+            continue;
+        }
+        if byte >= 0xF8 {
+            emit(&[byte]);
+            at += 1;
+            continue;
+        }
+
+        let status = if byte & 0x80 != 0 {
+            at += 1;
+            running_status = if byte < 0xF0 { Some(byte) } else { None };
+            byte
+        } else {
+            match running_status {
+                Some(status) => status,
+                None => return,
+            }
+        };
+        let end = at + util::midi_data_len(status);
+        if end > list.len() {
+            return;
+        }
+        let mut message = vec![status];
+        message.extend(&list[at..end]);
+        emit(&message);
+        at = end;
+    }
+}
+
+fn serve(sessions: Arc<Sessions>, socket: UdpSocket, data: bool) {
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 2048];
+        loop {
+            let (n, from) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e) => {
+                    warn!("Stopped listening for RTP-MIDI: {}", e);
+                    return;
+                },
+            };
+            let packet = &buf[..n];
+            if n >= 4 && packet[..2] == [0xFF, 0xFF] {
+                sessions.handle_command(&socket, from, packet);
+            } else if data {
+                sessions.handle_rtp(from, packet);
+            }
+        }
+    });
+}
+
+/// Accepts RTP-MIDI (AppleMIDI) sessions on `port` (control) and `port + 1`
+/// (data), the way macOS and iOS network MIDI expect. Whatever gets sent
+/// goes through the same path as any other MIDI input.
+pub fn listen(state: &AppState, port: u16) -> anyhow::Result<()> {
+    let control = UdpSocket::bind(("0.0.0.0", port))?;
+    let data = UdpSocket::bind(("0.0.0.0", port.checked_add(1).ok_or_else(|| anyhow::anyhow!("No room for the data port after {}", port))?))?;
+    info!("Listening for RTP-MIDI sessions on ports {} and {}", port, port + 1);
+
+    let seed = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+    let sessions = Arc::new(Sessions {
+        ssrc: seed ^ std::process::id(),
+        started: Instant::now(),
+        names: Mutex::new(HashMap::new()),
+        last_seq: Mutex::new(HashMap::new()),
+        sender: state.sender.clone(),
+    });
+    serve(sessions.clone(), control, false);
+    serve(sessions, data, true);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use midi_control::{Channel, MidiMessage};
+    use crate::Message;
+    use super::*;
+
+    fn parse(list: &[u8], first_has_delta: bool) -> Vec<Vec<u8>> {
+        let mut messages = Vec::new();
+        parse_command_list(list, first_has_delta, |message| messages.push(message.to_vec()));
+        messages
+    }
+
+    #[test]
+    fn delta_times() {
+        // Only the first command can go without one, and they can be more than a byte long
+        let messages = parse(&[0x90, 60, 100, 0x81, 0x80, 0x00, 0x80, 60, 0], false);
+        assert_eq!(messages, [vec![0x90, 60, 100], vec![0x80, 60, 0]]);
+        // Z says the first has one too
+        let messages = parse(&[0x05, 0xB0, 7, 64, 0x00, 0xC0, 5], true);
+        assert_eq!(messages, [vec![0xB0, 7, 64], vec![0xC0, 5]]);
+    }
+
+    #[test]
+    fn running_status() {
+        let messages = parse(&[0x90, 60, 100, 0x00, 62, 90, 0x10, 64, 80], false);
+        assert_eq!(messages, [vec![0x90, 60, 100], vec![0x90, 62, 90], vec![0x90, 64, 80]]);
+        // Nothing to run on
+        assert!(parse(&[60, 100], false).is_empty());
+    }
+
+    #[test]
+    fn sysex() {
+        let messages = parse(&[0x90, 60, 100, 0x00, 0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7, 0x00, 62, 90], false);
+        // Running status doesn't carry on past it
+        assert_eq!(messages, [vec![0x90, 60, 100], vec![0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7]]);
+        // A segment, ending in F0 instead of F7, is dropped
+        let messages = parse(&[0xF0, 0x7E, 0x7F, 0xF0, 0x00, 0xC0, 5], false);
+        assert_eq!(messages, [vec![0xC0, 5]]);
+    }
+
+    #[test]
+    fn invite_then_data() {
+        let (sender, receiver) = queue::channel(8);
+        let sessions = Sessions {
+            ssrc: 0x1234,
+            started: Instant::now(),
+            names: Mutex::new(HashMap::new()),
+            last_seq: Mutex::new(HashMap::new()),
+            sender,
+        };
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let other_end = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let from = other_end.local_addr().unwrap();
+
+        // Version 2, token 7, SSRC 0xABCD, name iPad
+        let mut invite = vec![0xFF, 0xFF, b'I', b'N', 0, 0, 0, 2, 0, 0, 0, 7, 0, 0, 0xAB, 0xCD];
+        invite.extend(b"iPad\0");
+        sessions.handle_command(&socket, from, &invite);
+        let mut buf = [0u8; 64];
+        let n = other_end.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..12], [0xFF, 0xFF, b'O', b'K', 0, 0, 0, 2, 0, 0, 0, 7]);
+        assert_eq!(&buf[12..n], b"\0\0\x12\x34handcake\0");
+
+        // RTP header with the same SSRC, then a 3 byte command list with one note on
+        let packet = [0x80, 0x61, 0, 1, 0, 0, 0, 0, 0, 0, 0xAB, 0xCD, 0x03, 0x90, 60, 100];
+        sessions.handle_rtp(from, &packet);
+        match receiver.try_recv() {
+            Ok(Message::Midi { device, message }) => {
+                assert_eq!(device, "iPad");
+                assert!(matches!(message, MidiMessage::NoteOn(Channel::Ch1, e) if e.key == 60 && e.value == 100));
+            },
+            _ => panic!("Expected a note on"),
+        }
+    }
+}
\ No newline at end of file
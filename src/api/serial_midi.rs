@@ -0,0 +1,181 @@
+use std::{fs::File, io::Read, os::unix::{io::AsRawFd, prelude::OpenOptionsExt}, path::Path};
+use crate::{AppState, util};
+use super::midi;
+
+// The MIDI DIN baud rate, which termios doesn't have a Bxxxx constant for
+const MIDI_BAUD: libc::speed_t = 31250;
+
+fn check(result: libc::c_int) -> std::io::Result<()> {
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+// Raw mode so the tty doesn't eat or translate any bytes, then the odd baud rate through termios2.
+// USB CDC-ACM devices ignore the baud rate entirely, but real serial ports need it.
+fn configure(file: &File) -> std::io::Result<()> {
+    let fd = file.as_raw_fd();
+    unsafe {
+        let mut tio: libc::termios = std::mem::zeroed();
+        check(libc::tcgetattr(fd, &mut tio))?;
+        libc::cfmakeraw(&mut tio);
+        tio.c_cflag |= libc::CLOCAL | libc::CREAD;
+        tio.c_cc[libc::VMIN] = 1;
+        tio.c_cc[libc::VTIME] = 0;
+        check(libc::tcsetattr(fd, libc::TCSANOW, &tio))?;
+
+        let mut tio2: libc::termios2 = std::mem::zeroed();
+        check(libc::ioctl(fd, libc::TCGETS2, &mut tio2))?;
+        tio2.c_cflag &= !libc::CBAUD;
+        tio2.c_cflag |= libc::BOTHER;
+        tio2.c_ispeed = MIDI_BAUD;
+        tio2.c_ospeed = MIDI_BAUD;
+        check(libc::ioctl(fd, libc::TCSETS2, &tio2))?;
+    }
+
+    Ok(())
+}
+
+/// Puts a raw MIDI byte stream back together into whole messages, the same
+/// as what midir hands over for a regular port. Handles running status,
+/// SysEx, and realtime bytes turning up in the middle of other messages.
+#[derive(Default)]
+pub struct StreamParser {
+    running_status: Option<u8>,
+    message: Vec<u8>,
+    in_sysex: bool,
+}
+
+impl StreamParser {
+    pub fn push(&mut self, byte: u8, mut emit: impl FnMut(&[u8])) {
+        match byte {
+            // Realtime, can show up anywhere without breaking up what's around it
+            0xF8..=0xFF => emit(&[byte]),
+            0xF0 => {
+                self.message = vec![byte];
+                self.in_sysex = true;
+                self.running_status = None;
+            },
+            0xF7 => {
+                if self.in_sysex {
+                    self.message.push(byte);
+                    emit(&self.message);
+                }
+                self.message.clear();
+                self.in_sysex = false;
+            },
+            // Any other status byte ends an unfinished SysEx, which just gets dropped
+            0x80..=0xEF | 0xF1..=0xF6 => {
+                self.in_sysex = false;
+                self.message = vec![byte];
+                // System common messages cancel running status
+                self.running_status = if byte < 0xF0 { Some(byte) } else { None };
+                if util::midi_data_len(byte) == 0 {
+                    emit(&self.message);
+                    self.message.clear();
+                }
+            },
+            _ if self.in_sysex => self.message.push(byte),
+            _ => {
+                if self.message.is_empty() {
+                    match self.running_status {
+                        Some(status) => self.message.push(status),
+                        // Data with no status to go with it, e.g. from opening the port mid-message
+                        None => return,
+                    }
+                }
+                self.message.push(byte);
+                if self.message.len() > util::midi_data_len(self.message[0]) {
+                    emit(&self.message);
+                    self.message.clear();
+                }
+            },
+        }
+    }
+}
+
+/// Reads MIDI from a serial port (/dev/ttyUSB0, /dev/ttyACM0...), for cheap
+/// interfaces and DIY devices that don't show up as ALSA devices.
+/// Messages go through the same path as any other MIDI input.
+pub fn open(state: &AppState, path: &Path) -> anyhow::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(path)?;
+    configure(&file)?;
+    let device = path.to_string_lossy().into_owned();
+    info!("Reading MIDI from serial port {}", device);
+
+    let sender = state.sender.clone();
+    std::thread::spawn(move || {
+        let mut parser = StreamParser::default();
+        let mut buf = [0u8; 256];
+        loop {
+            let n = match file.read(&mut buf) {
+                Ok(0) => {
+                    warn!("Serial port {} was closed", device);
+                    return;
+                },
+                Ok(n) => n,
+                Err(e) => {
+                    warn!("Stopped reading serial port {}: {}", device, e);
+                    return;
+                },
+            };
+
+            for byte in &buf[..n] {
+                parser.push(*byte, |message| midi::forward(&device, message, &sender));
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(parser: &mut StreamParser, bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut messages = Vec::new();
+        for byte in bytes {
+            parser.push(*byte, |message| messages.push(message.to_vec()));
+        }
+        messages
+    }
+
+    #[test]
+    fn running_status() {
+        let mut parser = StreamParser::default();
+        let messages = parse(&mut parser, &[0x90, 60, 100, 62, 90, 0xC0, 5, 6]);
+        assert_eq!(messages, [vec![0x90, 60, 100], vec![0x90, 62, 90], vec![0xC0, 5], vec![0xC0, 6]]);
+    }
+
+    #[test]
+    fn realtime_in_the_middle() {
+        let mut parser = StreamParser::default();
+        let messages = parse(&mut parser, &[0x90, 60, 0xF8, 100]);
+        assert_eq!(messages, [vec![0xF8], vec![0x90, 60, 100]]);
+    }
+
+    #[test]
+    fn sysex() {
+        let mut parser = StreamParser::default();
+        let messages = parse(&mut parser, &[0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7]);
+        assert_eq!(messages, [vec![0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7]]);
+        // Running status doesn't survive it
+        assert!(parse(&mut parser, &[60, 100]).is_empty());
+        // Nor does an unfinished SysEx survive another status byte
+        let messages = parse(&mut parser, &[0xF0, 0x7E, 0x80, 60, 0, 0xF7]);
+        assert_eq!(messages, [vec![0x80, 60, 0]]);
+    }
+
+    #[test]
+    fn data_before_any_status() {
+        let mut parser = StreamParser::default();
+        let messages = parse(&mut parser, &[60, 100, 0xB0, 7, 64]);
+        assert_eq!(messages, [vec![0xB0, 7, 64]]);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,92 @@
+use std::{collections::HashMap, path::{Path, PathBuf}, sync::Arc};
+use mlua::LuaSerdeExt;
+use parking_lot::Mutex;
+
+use crate::AppState;
+use super::ApiProvider;
+
+/// Values saved with state.save(). They live out here instead of in the VM, so
+/// a reloaded script can pick up where the old one left off.
+#[derive(Default)]
+pub struct SavedState {
+    values: Mutex<HashMap<String, serde_json::Value>>,
+    file: Mutex<Option<PathBuf>>,
+}
+
+impl SavedState {
+    /// Reads back whatever a previous run left in `path`, and rewrites it on every save from now on
+    pub fn persist_to(&self, path: &Path) {
+        match std::fs::read_to_string(path) {
+            Ok(text) => match serde_json::from_str(&text) {
+                Ok(values) => *self.values.lock() = values,
+                Err(e) => warn!("Ignoring saved state in {:?}: {}", path, e),
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+            Err(e) => warn!("Could not read saved state from {:?}: {}", path, e),
+        }
+        *self.file.lock() = Some(path.into());
+    }
+
+    fn write(&self) {
+        let file = self.file.lock();
+        if let Some(path) = file.as_ref() {
+            let text = serde_json::to_string(&*self.values.lock()).unwrap();
+            if let Err(e) = std::fs::write(path, text) {
+                warn!("Could not write saved state to {:?}: {}", path, e);
+            }
+        }
+    }
+}
+
+/// Where the state of a script is kept between runs, one file per script name
+pub fn state_file(script_path: &Path) -> PathBuf {
+    let name = script_path.file_stem().unwrap_or_default().to_string_lossy();
+    std::env::temp_dir().join(format!("handcake-{}.state.json", name))
+}
+
+pub struct State;
+impl ApiProvider for State {
+    type Arguments = (Arc<AppState>,);
+
+    fn register_api(l: &mlua::Lua, args: Self::Arguments) -> anyhow::Result<()> {
+        let (outest,) = args;
+        let tab = l.create_table()?;
+
+        {
+            // Saving nil forgets the key
+            let state = outest.clone();
+            tab.set("save", l.create_function(move |l, (key, value): (String, mlua::Value)| {
+                let value = match value {
+                    mlua::Value::Nil => None,
+                    value => Some(l.from_value::<serde_json::Value>(value).map_err(|e| {
+                        mlua::Error::RuntimeError(format!("Can't save {:?}, only numbers, strings, booleans and tables of those are allowed: {}", key, e))
+                    })?),
+                };
+                {
+                    let mut values = state.saved.values.lock();
+                    match value {
+                        Some(value) => values.insert(key, value),
+                        None => values.remove(&key),
+                    };
+                }
+                state.saved.write();
+
+                Ok(())
+            })?)?;
+        }
+
+        {
+            let state = outest.clone();
+            tab.set("load", l.create_function(move |l, (key,): (String,)| {
+                match state.saved.values.lock().get(&key) {
+                    Some(value) => crate::util::json_to_lua(l, value),
+                    None => Ok(mlua::Value::Nil),
+                }
+            })?)?;
+        }
+
+        l.globals().set("state", tab)?;
+
+        Ok(())
+    }
+}
\ No newline at end of file
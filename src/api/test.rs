@@ -0,0 +1,294 @@
+use std::{panic::AssertUnwindSafe, time::Duration};
+use futures_util::FutureExt;
+use midi_control::consts;
+use crate::{AppState, Message, MidiRealtime, Script, util};
+
+use super::{ApiProvider, RECORDED_EVENTS, RECORDED_GRABS, RECORDED_MIDI, midi_out::status};
+
+api_entries! { "test",
+    "assert" => "fun(condition: any, message?: string)", "Fails the test if condition is false or nil";
+    "assert_eq" => "fun(a: any, b: any, message?: string)", "Fails the test unless a and b are equal, comparing tables by contents";
+    "advance" => "fun(ms: integer)", "Lets ms go by for misc.schedule and friends, running every timer that comes due, only inside a test";
+    "run" => "fun(tests: table<string, fun(inject: fun(event: table), recorded_events: fun(): table[], recorded_midi: fun(): integer[][], recorded_grabs: fun(): table[])>)", "Adds tests to run once the script has loaded, with --test";
+}
+
+// Every table passed to test.run(), in order
+const SUITES_KEY: &str = "handcake_test_suites";
+
+// Close enough to Lua's own idea of equality, except tables are compared by contents
+fn equal(a: &mlua::Value, b: &mlua::Value) -> mlua::Result<bool> {
+    let (a, b) = match (a, b) {
+        (mlua::Value::Table(a), mlua::Value::Table(b)) => (a, b),
+        _ => return Ok(a == b),
+    };
+    for pair in a.clone().pairs::<mlua::Value, mlua::Value>() {
+        let (key, value) = pair?;
+        if !equal(&value, &b.raw_get(key)?)? {
+            return Ok(false);
+        }
+    }
+    for pair in b.clone().pairs::<mlua::Value, mlua::Value>() {
+        let (key, _) = pair?;
+        if a.raw_get::<_, mlua::Value>(key)? == mlua::Value::Nil {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+// For failure messages, so a table shows what's in it instead of its address
+fn describe(value: &mlua::Value, depth: usize) -> String {
+    match value {
+        mlua::Value::Table(tab) if depth < 3 => {
+            let items: Vec<String> = tab.clone().pairs::<mlua::Value, mlua::Value>()
+                .filter_map(Result::ok)
+                .map(|(key, value)| match key {
+                    mlua::Value::Integer(_) => describe(&value, depth + 1),
+                    mlua::Value::String(key) => format!("{} = {}", key.to_string_lossy(), describe(&value, depth + 1)),
+                    key => format!("{} = {}", describe(&key, depth + 1), describe(&value, depth + 1)),
+                })
+                .collect();
+            format!("{{ {} }}", items.join(", "))
+        },
+        mlua::Value::String(s) => format!("{:?}", s.to_string_lossy()),
+        mlua::Value::Nil => "nil".to_owned(),
+        mlua::Value::Boolean(b) => b.to_string(),
+        mlua::Value::Integer(i) => i.to_string(),
+        mlua::Value::Number(n) => n.to_string(),
+        value => value.type_name().to_owned(),
+    }
+}
+
+// Just the message and where it came from, a whole traceback per failure is a lot
+fn failure(e: &mlua::Error) -> String {
+    match e {
+        mlua::Error::CallbackError { traceback, cause } => {
+            let location = traceback.lines()
+                .map(str::trim)
+                .find(|line| !line.starts_with("[C]") && !line.starts_with("stack traceback"))
+                .and_then(|line| line.split(": in ").next());
+            match location {
+                Some(location) => format!("{}: {}", location, failure(cause)),
+                None => failure(cause),
+            }
+        },
+        mlua::Error::RuntimeError(message) => message.clone(),
+        e => e.to_string(),
+    }
+}
+
+// Takes the same kind of table on_midi_recv gets
+fn event_to_message(event: &mlua::Table) -> mlua::Result<Message> {
+    let device = event.get::<_, Option<String>>("device")?.unwrap_or_else(|| "test".to_owned());
+    let kind = event.get::<_, String>("event")?;
+    let channel = || -> mlua::Result<u8> { Ok(event.get::<_, Option<u8>>("channel")?.unwrap_or(1)) };
+    let field = |name: &str| -> mlua::Result<u8> { Ok(event.get::<_, Option<u8>>(name)?.unwrap_or(0) & 0x7F) };
+
+    let data = match kind.as_str() {
+        "note_on" => vec![status(consts::NOTE_ON, channel()?)?, field("key")?, event.get::<_, Option<u8>>("vel")?.unwrap_or(127) & 0x7F],
+        "note_off" => vec![status(consts::NOTE_OFF, channel()?)?, field("key")?, field("vel")?],
+        "poly_aftertouch" => vec![status(consts::POLYPHONIC_KEY_PRESSURE, channel()?)?, field("key")?, field("value")?],
+        "control_change" => vec![status(consts::CONTROL_CHANGE, channel()?)?, field("control")?, field("value")?],
+        "program_change" => vec![status(consts::PROGRAM_CHANGE, channel()?)?, field("program")?],
+        "channel_pressure" => vec![status(consts::CHANNEL_KEY_PRESSURE, channel()?)?, field("value")?],
+        "pitch_bend" => {
+            let value = event.get::<_, Option<u16>>("value")?.unwrap_or(8192).min(16383);
+            vec![status(consts::PITCH_BEND_CHANGE, channel()?)?, (value & 0x7F) as u8, (value >> 7) as u8]
+        },
+        "sysex" => {
+            let mut data = vec![0xF0];
+            data.extend(event.get::<_, Vec<u8>>("data")?);
+            data.push(0xF7);
+            data
+        },
+        realtime => {
+            let byte = match realtime {
+                "timing_clock" => 0xF8,
+                "start" => 0xFA,
+                "continue" => 0xFB,
+                "stop" => 0xFC,
+                _ => return Err(mlua::Error::RuntimeError(format!("Unknown event {:?}", realtime))),
+            };
+            return Ok(Message::MidiRealtime { device, message: MidiRealtime::from_byte(byte).unwrap() });
+        },
+    };
+
+    Ok(Message::Midi { device, message: util::parse_midi(&data) })
+}
+
+/// test.assert and friends, only there with --test
+pub struct Test;
+impl ApiProvider for Test {
+    type Arguments = ();
+
+    fn register_api(l: &mlua::Lua, _args: Self::Arguments) -> anyhow::Result<()> {
+        let tab = l.create_table()?;
+        l.set_named_registry_value(SUITES_KEY, l.create_table()?)?;
+
+        tab.set("assert", l.create_function(|_l, (condition, message): (mlua::Value, Option<String>)| {
+            match condition {
+                mlua::Value::Nil | mlua::Value::Boolean(false) => {
+                    Err(mlua::Error::RuntimeError(message.unwrap_or_else(|| "assertion failed".to_owned())))
+                },
+                _ => Ok(()),
+            }
+        })?)?;
+
+        tab.set("assert_eq", l.create_function(|_l, (a, b, message): (mlua::Value, mlua::Value, Option<String>)| {
+            if equal(&a, &b)? {
+                return Ok(());
+            }
+            let values = format!("{} ~= {}", describe(&a, 0), describe(&b, 0));
+            Err(mlua::Error::RuntimeError(match message {
+                Some(message) => format!("{}: {}", message, values),
+                None => values,
+            }))
+        })?)?;
+
+        // Only collects them, they're run once the script has finished loading
+        tab.set("run", l.create_function(|l, (suite,): (mlua::Table,)| {
+            let suites = l.named_registry_value::<_, mlua::Table>(SUITES_KEY)?;
+            suites.raw_set(suites.raw_len() + 1, suite)
+        })?)?;
+
+        l.globals().set("test", tab)?;
+
+        Ok(())
+    }
+}
+
+// What a test is waiting on the test loop for
+enum Request {
+    Inject(Message),
+    Advance(Duration),
+}
+
+type Requests = tokio::sync::mpsc::UnboundedSender<(Request, tokio::sync::oneshot::Sender<()>)>;
+
+// Hands the request to the test loop and waits for it to be done
+async fn request(requests: &Requests, request: Request) -> mlua::Result<()> {
+    let (done, handled) = tokio::sync::oneshot::channel();
+    requests.send((request, done)).map_err(|_| mlua::Error::RuntimeError("Tests are over".to_owned()))?;
+    let _ = handled.await;
+    Ok(())
+}
+
+/// Runs every test given to test.run(), in name order within each suite, and
+/// prints how they went. Returns whether they all passed.
+///
+/// Each test gets inject(event), which puts a MIDI message through the script
+/// like it had just come in and returns once it's been handled, along with
+/// anything the script queued up while handling it, and
+/// recorded_events(), everything the virtual devices have been sent since the
+/// test started, recorded_midi(), the same for --midi-out, and recorded_grabs(),
+/// every evdev.grab() and release as { device, grabbed }.
+///
+/// Timers don't go off by themselves with --test, test.advance(ms) moves their
+/// clock forward and runs each one that comes due on the way, in order.
+pub(crate) async fn run(state: &AppState, script: &Script) -> mlua::Result<bool> {
+    let l = &script.lua;
+    let (sender, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let inject = {
+        let sender = sender.clone();
+        l.create_async_function(move |_l, (event,): (mlua::Table,)| {
+            let sender = sender.clone();
+            async move { request(&sender, Request::Inject(event_to_message(&event)?)).await }
+        })?
+    };
+    // Only here and not in register_api because it needs the test loop
+    l.globals().get::<_, mlua::Table>("test")?.set("advance", l.create_async_function(move |_l, (ms,): (u64,)| {
+        let sender = sender.clone();
+        async move { request(&sender, Request::Advance(Duration::from_millis(ms))).await }
+    })?)?;
+    let recorded_events = l.create_function(|l, _: ()| {
+        let tab = l.create_table()?;
+        for (i, event) in RECORDED_EVENTS.lock().iter().flatten().enumerate() {
+            let row = l.create_table()?;
+            row.set("device", event.device.as_str())?;
+            row.set("type", event.kind)?;
+            row.set("code", event.code)?;
+            row.set("value", event.value)?;
+            tab.set(i + 1, row)?;
+        }
+        Ok(tab)
+    })?;
+    let recorded_midi = l.create_function(|_l, _: ()| {
+        Ok(RECORDED_MIDI.lock().clone().unwrap_or_default())
+    })?;
+    let recorded_grabs = l.create_function(|l, _: ()| {
+        let tab = l.create_table()?;
+        for (i, (device, grabbed)) in RECORDED_GRABS.lock().iter().flatten().enumerate() {
+            let row = l.create_table()?;
+            row.set("device", device.as_str())?;
+            row.set("grabbed", *grabbed)?;
+            tab.set(i + 1, row)?;
+        }
+        Ok(tab)
+    })?;
+
+    let (mut passed, mut failed) = (0, 0);
+    let suites = l.named_registry_value::<_, mlua::Table>(SUITES_KEY)?;
+    for suite in suites.sequence_values::<mlua::Table>() {
+        let mut tests = suite?.pairs::<String, mlua::Function>().collect::<mlua::Result<Vec<_>>>()?;
+        tests.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        for (name, f) in tests {
+            *RECORDED_EVENTS.lock() = Some(Vec::new());
+            *RECORDED_MIDI.lock() = Some(Vec::new());
+            *RECORDED_GRABS.lock() = Some(Vec::new());
+            // The test waits in inject() while its message is dispatched, same VM and all
+            let test = f.call_async::<_, ()>((inject.clone(), recorded_events.clone(), recorded_midi.clone(), recorded_grabs.clone()));
+            tokio::pin!(test);
+            let result = loop {
+                tokio::select! {
+                    result = &mut test => break result,
+                    Some((request, done)) = received.recv() => {
+                        match request {
+                            Request::Inject(message) => dispatch(state, script, message).await,
+                            Request::Advance(by) => {
+                                let until = state.timers.now() + by;
+                                while let Some(message) = state.timers.next_due(until) {
+                                    dispatch(state, script, message).await;
+                                }
+                            },
+                        }
+                        let _ = done.send(());
+                    },
+                }
+            };
+
+            match result {
+                Ok(()) => {
+                    println!("PASS {}", name);
+                    passed += 1;
+                },
+                Err(e) => {
+                    println!("FAIL {}\n    {}", name, failure(&e).replace('\n', "\n    "));
+                    failed += 1;
+                },
+            }
+        }
+    }
+    println!("{} passed, {} failed", passed, failed);
+
+    Ok(failed == 0)
+}
+
+// Same as the dispatch loop, an error or panic in one message leaves the script running for the next.
+// Whatever the script queued up meanwhile (the arpeggiator's notes, say) goes through straight after.
+async fn dispatch(state: &AppState, script: &Script, message: Message) {
+    let mut next = Some(message);
+    while let Some(message) = next {
+        match message {
+            // There's no script to swap or stop partway through a test
+            Message::Reload | Message::Exit(_) => debug!("Ignoring reload or exit during a test"),
+            message => match AssertUnwindSafe(crate::dispatch_message(state, script, message)).catch_unwind().await {
+                Ok(Ok(())) => {},
+                Ok(Err(e)) => error!("Error dispatching message: {}", e),
+                Err(_) => error!("Panic while dispatching message"),
+            },
+        }
+        next = state.receiver.lock().try_recv().ok();
+    }
+}
\ No newline at end of file
@@ -0,0 +1,227 @@
+use std::{sync::Arc};
+use input_linux::{
+    EventKind,
+    Key,
+    AbsoluteAxis,
+    InputId,
+    InputProperty,
+    AbsoluteInfoSetup,
+    AbsoluteInfo,
+    InputEvent,
+    KeyEvent,
+    KeyState,
+    AbsoluteEvent,
+    EventTime,
+};
+use parking_lot::Mutex;
+use super::{ApiProvider, DeviceInfo, SyncedDevice, UInput};
+
+// Most touchscreens the kernel knows about stop at 10 fingers
+const SLOTS: usize = 10;
+const MAX_PRESSURE: i32 = 255;
+
+struct TouchState {
+    uinput: SyncedDevice,
+    // Tracking ID of whatever is touching each slot
+    slots: [Option<i32>; SLOTS],
+    next_tracking_id: i32,
+}
+
+fn abs(axis: AbsoluteAxis, value: i32) -> input_linux::sys::input_event {
+    const ZERO: EventTime = EventTime::new(0, 0);
+    *InputEvent::from(AbsoluteEvent::new(ZERO, axis, value)).as_raw()
+}
+
+fn slot(id: usize) -> mlua::Result<usize> {
+    if id >= SLOTS {
+        return Err(mlua::Error::RuntimeError(format!("Invalid touch id {}, expected 0 to {}", id, SLOTS - 1)));
+    }
+
+    Ok(id)
+}
+
+pub struct Touch;
+impl Touch {
+    pub fn default_device() -> DeviceInfo {
+        // D-WAV Scientific Co., Ltd eGalax TouchScreen
+        DeviceInfo::new("handcake Virtual Touchscreen", 0x0eef, 0x0001)
+    }
+}
+
+impl ApiProvider for Touch {
+    /// Handle, device info, and screen size in pixels
+    type Arguments = (Box<dyn UInput>, DeviceInfo, (i32, i32));
+
+    fn register_api(l: &mlua::Lua, args: Self::Arguments) -> anyhow::Result<()> {
+        let (uinput, info, (width, height)) = args;
+        if width <= 0 || height <= 0 {
+            anyhow::bail!("Invalid touchscreen size {}x{}", width, height);
+        }
+
+        // Direct means it's a touchscreen rather than a touchpad, so positions map straight onto the screen
+        uinput.set_propbit(InputProperty::Direct)?;
+        uinput.set_evbit(EventKind::Key)?;
+        uinput.set_keybit(Key::ButtonTouch)?;
+        uinput.set_evbit(EventKind::Absolute)?;
+        for axis in [
+            AbsoluteAxis::MultitouchSlot,
+            AbsoluteAxis::MultitouchTrackingId,
+            AbsoluteAxis::MultitouchPositionX,
+            AbsoluteAxis::MultitouchPositionY,
+            AbsoluteAxis::MultitouchPressure,
+        ] {
+            uinput.set_absbit(axis)?;
+        }
+
+        const RANGE: AbsoluteInfo = AbsoluteInfo {
+            flat: 0,
+            value: 0,
+            minimum: 0,
+            maximum: 0,
+            fuzz: 0,
+            resolution: 0,
+        };
+
+        let input_id = InputId {
+            bustype: input_linux::sys::BUS_USB,
+            vendor: info.vendor,
+            product: info.product,
+            version: 0,
+        };
+        uinput.create(&input_id, info.name.as_bytes(), 0, &[
+            AbsoluteInfoSetup {
+                axis: AbsoluteAxis::MultitouchSlot,
+                info: AbsoluteInfo { maximum: SLOTS as i32 - 1, ..RANGE },
+            },
+            AbsoluteInfoSetup {
+                axis: AbsoluteAxis::MultitouchTrackingId,
+                info: AbsoluteInfo { maximum: u16::MAX as i32, ..RANGE },
+            },
+            AbsoluteInfoSetup {
+                axis: AbsoluteAxis::MultitouchPositionX,
+                info: AbsoluteInfo { maximum: width - 1, ..RANGE },
+            },
+            AbsoluteInfoSetup {
+                axis: AbsoluteAxis::MultitouchPositionY,
+                info: AbsoluteInfo { maximum: height - 1, ..RANGE },
+            },
+            AbsoluteInfoSetup {
+                axis: AbsoluteAxis::MultitouchPressure,
+                info: AbsoluteInfo { maximum: MAX_PRESSURE, ..RANGE },
+            },
+        ])?;
+
+        let outest = Arc::new(Mutex::new(TouchState {
+            uinput: SyncedDevice::new(uinput),
+            slots: [None; SLOTS],
+            next_tracking_id: 0,
+        }));
+        let tab = l.create_table()?;
+
+        let clamp_position = move |x: i32, y: i32| (x.clamp(0, width - 1), y.clamp(0, height - 1));
+
+        {
+            let touch = outest.clone();
+            tab.set("down", l.create_function(move |_l, (id, x, y, pressure): (usize, i32, i32, Option<i32>)| {
+                let id = slot(id)?;
+                let (x, y) = clamp_position(x, y);
+                let mut touch = touch.lock();
+                if touch.slots[id].is_some() {
+                    return Err(mlua::Error::RuntimeError(format!("Touch {} is already down", id)));
+                }
+                let first = touch.slots.iter().all(Option::is_none);
+                let tracking_id = touch.next_tracking_id;
+                touch.next_tracking_id = (tracking_id + 1) % (u16::MAX as i32 + 1);
+                touch.slots[id] = Some(tracking_id);
+
+                const ZERO: EventTime = EventTime::new(0, 0);
+                let mut event = vec![
+                    abs(AbsoluteAxis::MultitouchSlot, id as i32),
+                    abs(AbsoluteAxis::MultitouchTrackingId, tracking_id),
+                    abs(AbsoluteAxis::MultitouchPositionX, x),
+                    abs(AbsoluteAxis::MultitouchPositionY, y),
+                    abs(AbsoluteAxis::MultitouchPressure, pressure.unwrap_or(MAX_PRESSURE).clamp(0, MAX_PRESSURE)),
+                ];
+                if first {
+                    event.push(*InputEvent::from(KeyEvent::new(ZERO, Key::ButtonTouch, KeyState::PRESSED)).as_raw());
+                }
+                touch.uinput.write(&event)?;
+
+                Ok(())
+            })?)?;
+        }
+
+        {
+            let touch = outest.clone();
+            tab.set("move", l.create_function(move |_l, (id, x, y, pressure): (usize, i32, i32, Option<i32>)| {
+                let id = slot(id)?;
+                let (x, y) = clamp_position(x, y);
+                let touch = touch.lock();
+                if touch.slots[id].is_none() {
+                    return Err(mlua::Error::RuntimeError(format!("Touch {} isn't down", id)));
+                }
+
+                let mut event = vec![
+                    abs(AbsoluteAxis::MultitouchSlot, id as i32),
+                    abs(AbsoluteAxis::MultitouchPositionX, x),
+                    abs(AbsoluteAxis::MultitouchPositionY, y),
+                ];
+                if let Some(pressure) = pressure {
+                    event.push(abs(AbsoluteAxis::MultitouchPressure, pressure.clamp(0, MAX_PRESSURE)));
+                }
+                touch.uinput.write(&event)?;
+
+                Ok(())
+            })?)?;
+        }
+
+        {
+            // Lifting a finger that isn't down does nothing
+            let touch = outest.clone();
+            tab.set("up", l.create_function(move |_l, (id,): (usize,)| {
+                let id = slot(id)?;
+                let mut touch = touch.lock();
+                if touch.slots[id].take().is_none() {
+                    return Ok(());
+                }
+                let last = touch.slots.iter().all(Option::is_none);
+
+                const ZERO: EventTime = EventTime::new(0, 0);
+                let mut event = vec![
+                    abs(AbsoluteAxis::MultitouchSlot, id as i32),
+                    abs(AbsoluteAxis::MultitouchTrackingId, -1),
+                ];
+                if last {
+                    event.push(*InputEvent::from(KeyEvent::new(ZERO, Key::ButtonTouch, KeyState::RELEASED)).as_raw());
+                }
+                touch.uinput.write(&event)?;
+
+                Ok(())
+            })?)?;
+        }
+
+        // All fingers in a batch move at once, e.g. for a pinch
+        {
+            let touch = outest.clone();
+            tab.set("begin_batch", l.create_function(move |_l, _: ()| {
+                touch.lock().uinput.begin_batch();
+                Ok(())
+            })?)?;
+        }
+
+        {
+            let touch = outest.clone();
+            tab.set("end_batch", l.create_function(move |_l, _: ()| {
+                touch.lock().uinput.end_batch()?;
+                Ok(())
+            })?)?;
+        }
+
+        tab.set("width", width)?;
+        tab.set("height", height)?;
+
+        l.globals().set("touch", tab)?;
+
+        Ok(())
+    }
+}
\ No newline at end of file
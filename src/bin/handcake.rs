@@ -0,0 +1,7 @@
+use clap::Parser;
+use handcake::AppConfig;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    handcake::run(AppConfig::parse()).await
+}
\ No newline at end of file
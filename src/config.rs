@@ -0,0 +1,143 @@
+use std::{collections::HashMap, path::{Path, PathBuf}};
+use serde::Deserialize;
+
+/// Contents of handcake.toml. Everything is optional, and anything given on
+/// the command line wins over what's in here.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub midi: MidiConfig,
+    pub evdev: EvdevConfig,
+    pub osc: OscConfig,
+    pub fifo: FifoConfig,
+    pub ipc: IpcConfig,
+    pub dbus: DBusConfig,
+    pub uinput: UInputConfig,
+    pub log: LogConfig,
+    pub script: ScriptConfig,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct MidiConfig {
+    /// Same as --midi-device
+    pub inputs: Vec<String>,
+    /// Same as --auto-first
+    pub auto_first: bool,
+    /// Same as --midi-seq
+    pub seq: Option<String>,
+    /// Same as --midi-serial
+    pub serial: Vec<PathBuf>,
+    /// Same as --rtp-midi-port
+    pub rtp_port: Option<u16>,
+    /// Same as --ble-midi without an address
+    pub ble: bool,
+    /// Same as --ble-midi with an address
+    pub ble_address: Option<String>,
+    /// Same as --midi-out
+    pub output: Option<String>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct EvdevConfig {
+    /// Same as --evdev-device
+    pub devices: Vec<PathBuf>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct OscConfig {
+    /// Same as --osc-port
+    pub port: Option<u16>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct FifoConfig {
+    /// Same as --fifo-path
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct IpcConfig {
+    /// Same as --ipc-socket
+    pub socket: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DBusConfig {
+    /// Same as --dbus-match
+    pub matches: Vec<String>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct UInputConfig {
+    pub gamepad: DeviceConfig,
+    pub keyboard: DeviceConfig,
+    pub mouse: DeviceConfig,
+    /// Same as --max-gamepads
+    pub max_gamepads: Option<usize>,
+    /// Same as --touch-width
+    pub touch_width: Option<i32>,
+    /// Same as --touch-height
+    pub touch_height: Option<i32>,
+}
+
+/// Same as the `--<device>-name/vendor/product` flags
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceConfig {
+    pub name: Option<String>,
+    pub vendor: Option<u16>,
+    pub product: Option<u16>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// Anything RUST_LOG would accept, e.g. "info" or "handcake=debug"
+    pub level: Option<String>,
+    /// Same as --log-format
+    pub format: Option<LogFormat>,
+}
+
+#[derive(Deserialize, clap::ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line, for log collectors
+    Json,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScriptConfig {
+    /// Same as --script, relative to the config file
+    pub path: Option<PathBuf>,
+    /// Same as --arg, --arg wins for keys given in both
+    pub arguments: HashMap<String, String>,
+    /// Same as --sandbox
+    pub sandbox: bool,
+    /// Same as --lua-path, relative to the config file
+    pub lua_path: Vec<PathBuf>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let mut config: Config = toml::from_str(&text)?;
+
+        if let (Some(script), Some(dir)) = (&config.script.path, path.parent()) {
+            config.script.path = Some(dir.join(script));
+        }
+        if let Some(dir) = path.parent() {
+            config.script.lua_path = config.script.lua_path.iter().map(|p| dir.join(p)).collect();
+        }
+
+        Ok(config)
+    }
+}
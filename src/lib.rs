@@ -0,0 +1,1322 @@
+//! handcake turns MIDI (and a few other inputs) into virtual gamepads,
+//! keyboards, mice and touchscreens, with a Lua script deciding what goes where.
+//!
+//! The handcake binary is a thin wrapper around [`run`], which can just as well
+//! be called from another program with an [`AppConfig`] of its own.
+
+pub mod api;
+pub mod config;
+mod metrics;
+mod monitor;
+mod pidfile;
+mod privileges;
+mod queue;
+mod record;
+mod repl;
+mod systemd;
+mod util;
+mod timer;
+mod watch;
+
+use std::{io::Write, path::{PathBuf, Path}, sync::{Arc, atomic::{AtomicBool, Ordering}}, time::Duration};
+use clap::Parser;
+use midi_control::MidiMessage;
+use parking_lot::Mutex;
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::config::{Config, LogFormat};
+
+pub use api::ApiProvider;
+pub use config::DeviceConfig;
+
+#[macro_use]
+extern crate log;
+
+/// Everything handcake can be told on the command line. Anything not given
+/// here is read from the config file, see [`Config`].
+#[derive(Parser, Clone)]
+pub struct AppConfig {
+    #[clap(short='s',long="--script")]
+    pub script: Option<PathBuf>,
+
+    /// Passed to the script in the args table, as key=value. Can be given more than once.
+    #[clap(long="--arg", parse(try_from_str=util::parse_key_value))]
+    pub args: Vec<(String, String)>,
+
+    /// Config file to read, defaults to handcake.toml next to the script or in the current directory
+    #[clap(short='c',long="--config")]
+    pub config: Option<PathBuf>,
+
+    /// Write our PID to this file, and refuse to start if another instance already has
+    #[clap(long="--pid-file")]
+    pub pid_file: Option<PathBuf>,
+
+    /// User to switch to once the script is loaded and every device is open. Also read from HANDCAKE_USER.
+    #[clap(long="--user")]
+    pub user: Option<String>,
+
+    /// Serve Prometheus metrics on this port, at /metrics
+    #[clap(long="--metrics-port")]
+    pub metrics_port: Option<u16>,
+
+    /// How to format log output, text or json
+    #[clap(long="--log-format", arg_enum)]
+    pub log_format: Option<LogFormat>,
+
+    /// Show incoming MIDI messages live instead of running a script.
+    /// Listens to every MIDI input unless --midi-device is given.
+    #[clap(long="--monitor")]
+    pub monitor: bool,
+
+    /// Start an interactive Lua prompt with the whole API, after loading --script if there is one
+    #[clap(long="--repl")]
+    pub repl: bool,
+
+    /// List the available MIDI inputs and exit
+    #[clap(long="--list-midi-devices")]
+    pub list_midi_devices: bool,
+
+    /// Tell the instance listening on --ipc-socket to release every key and button and
+    /// send all notes off to its --midi-out, then exit
+    #[clap(long="--panic")]
+    pub panic: bool,
+
+    /// Print the scripting API as JSON and exit, see `cargo xtask api-schema`
+    #[clap(long="--api-schema")]
+    pub api_schema: bool,
+
+    /// MIDI input to listen to, by port number or name. Can be given more than once.
+    #[clap(long="--midi-device")]
+    pub midi_devices: Vec<String>,
+
+    /// With no --midi-device and several MIDI devices plugged in, use the first one
+    /// instead of listing them. With just one it gets used either way.
+    #[clap(long="--auto-first")]
+    pub auto_first: bool,
+
+    /// evdev device (/dev/input/eventN) to grab and send to on_evdev_recv. Can be given more than once.
+    #[clap(long="--evdev-device")]
+    pub evdev_devices: Vec<PathBuf>,
+
+    /// Create an ALSA sequencer port with this name and listen to whatever gets connected to it
+    #[clap(long="--midi-seq")]
+    pub midi_seq: Option<String>,
+
+    /// Read MIDI from a serial port, e.g. /dev/ttyUSB0, at 31250 baud. Can be given more than once.
+    #[clap(long="--midi-serial")]
+    pub midi_serial: Vec<PathBuf>,
+
+    /// Connect to a Bluetooth MIDI device, by address or the first one found
+    #[clap(long="--ble-midi")]
+    pub ble_midi: Option<Option<String>>,
+
+    /// Accept RTP-MIDI (network MIDI) sessions on this UDP port, and the one after it for data
+    #[clap(long="--rtp-midi-port")]
+    pub rtp_midi_port: Option<u16>,
+
+    /// UDP port to listen for OSC messages on, for on_osc_recv
+    #[clap(long="--osc-port")]
+    pub osc_port: Option<u16>,
+
+    /// FIFO to read JSON messages for on_external from, one per line. Made if it doesn't exist.
+    #[clap(long="--fifo-path")]
+    pub fifo_path: Option<PathBuf>,
+
+    /// Unix socket to receive messages from other instances on, for on_ipc_recv
+    #[clap(long="--ipc-socket")]
+    pub ipc_socket: Option<PathBuf>,
+
+    /// D-Bus match rule for session bus signals to pass to on_dbus_signal. Can be given more than once.
+    #[clap(long="--dbus-match")]
+    pub dbus_matches: Vec<String>,
+
+    /// How many messages can queue up while the script is busy. MIDI input that
+    /// arrives while the queue is full is dropped.
+    #[clap(long="--queue-depth", default_value="1024")]
+    pub queue_depth: usize,
+
+    /// Milliseconds to wait between opening MIDI devices and calling on_script_init,
+    /// for devices that miss the first few messages after they're plugged in
+    #[clap(long="--init-delay-ms", default_value="0")]
+    pub init_delay_ms: u64,
+
+    /// Write every MIDI message received to this file, to --replay later
+    #[clap(long="--record")]
+    pub record: Option<PathBuf>,
+
+    /// Play back a file made with --record instead of opening MIDI devices, then exit
+    #[clap(long="--replay")]
+    pub replay: Option<PathBuf>,
+
+    /// Run the tests the script passes to test.run(), then exit with 0 if they all
+    /// passed. Implies --dry-run.
+    #[clap(long="--test")]
+    pub test: bool,
+
+    /// Don't open uinput or any MIDI hardware, print what would have gone to uinput
+    /// as a line of JSON per event instead. Meant for use with --replay.
+    #[clap(long="--dry-run")]
+    pub dry_run: bool,
+
+    /// How much faster than real time to --replay at
+    #[clap(long="--replay-speed", default_value="1.0")]
+    pub replay_speed: f64,
+
+    /// MIDI output for the midi_out API, by port number or name
+    #[clap(long="--midi-out")]
+    pub midi_out: Option<String>,
+
+    /// Name the virtual gamepad shows up as
+    #[clap(long="--gamepad-name")]
+    pub gamepad_name: Option<String>,
+
+    /// USB vendor ID of the virtual gamepad, in hex
+    #[clap(long="--gamepad-vendor", parse(try_from_str=util::parse_hex_u16))]
+    pub gamepad_vendor: Option<u16>,
+
+    /// USB product ID of the virtual gamepad, in hex
+    #[clap(long="--gamepad-product", parse(try_from_str=util::parse_hex_u16))]
+    pub gamepad_product: Option<u16>,
+
+    /// How many times a script may call gamepad.create(), defaults to 4
+    #[clap(long="--max-gamepads")]
+    pub max_gamepads: Option<usize>,
+
+    /// Name the virtual keyboard shows up as
+    #[clap(long="--keyboard-name")]
+    pub keyboard_name: Option<String>,
+
+    /// USB vendor ID of the virtual keyboard, in hex
+    #[clap(long="--keyboard-vendor", parse(try_from_str=util::parse_hex_u16))]
+    pub keyboard_vendor: Option<u16>,
+
+    /// USB product ID of the virtual keyboard, in hex
+    #[clap(long="--keyboard-product", parse(try_from_str=util::parse_hex_u16))]
+    pub keyboard_product: Option<u16>,
+
+    /// Name the virtual mouse shows up as
+    #[clap(long="--mouse-name")]
+    pub mouse_name: Option<String>,
+
+    /// USB vendor ID of the virtual mouse, in hex
+    #[clap(long="--mouse-vendor", parse(try_from_str=util::parse_hex_u16))]
+    pub mouse_vendor: Option<u16>,
+
+    /// USB product ID of the virtual mouse, in hex
+    #[clap(long="--mouse-product", parse(try_from_str=util::parse_hex_u16))]
+    pub mouse_product: Option<u16>,
+
+    /// Width of the virtual touchscreen in pixels, defaults to 1920
+    #[clap(long="--touch-width")]
+    pub touch_width: Option<i32>,
+
+    /// Height of the virtual touchscreen in pixels, defaults to 1080
+    #[clap(long="--touch-height")]
+    pub touch_height: Option<i32>,
+
+    /// Only check that the script parses, then exit
+    #[clap(long="--check")]
+    pub check: bool,
+
+    /// Reload the script whenever it changes on disk
+    #[clap(long="--watch")]
+    pub watch: bool,
+
+    /// Extra directory to look for modules in when the script calls require(). Can be given more than once.
+    /// The script's own directory is always searched.
+    #[clap(long="--lua-path")]
+    pub lua_paths: Vec<PathBuf>,
+
+    /// Take away io, os, package, debug and require from the script
+    #[clap(long="--sandbox")]
+    pub sandbox: bool,
+}
+
+impl AppConfig {
+    fn config_path(&self) -> Option<PathBuf> {
+        if self.config.is_some() {
+            return self.config.clone();
+        }
+
+        let beside_script = self.script.as_ref()
+            .and_then(|s| s.parent())
+            .map(|dir| dir.join("handcake.toml"));
+        [beside_script, Some(PathBuf::from("handcake.toml"))]
+            .into_iter()
+            .flatten()
+            .find(|p| p.exists())
+    }
+
+    // Flags given on the command line always win
+    fn merge_config(&mut self, config: &Config) {
+        if self.script.is_none() {
+            self.script = config.script.path.clone();
+        }
+        // Unlike the other options these get merged, config first so --arg overrides it
+        let mut args = config.script.arguments.clone().into_iter().collect::<Vec<_>>();
+        args.append(&mut self.args);
+        self.args = args;
+        // A bool flag can't tell "not given" from false, so either one turns it on
+        self.sandbox |= config.script.sandbox;
+        if self.lua_paths.is_empty() {
+            self.lua_paths = config.script.lua_path.clone();
+        }
+        if self.midi_devices.is_empty() {
+            self.midi_devices = config.midi.inputs.clone();
+        }
+        self.auto_first |= config.midi.auto_first;
+        if self.evdev_devices.is_empty() {
+            self.evdev_devices = config.evdev.devices.clone();
+        }
+        if self.midi_seq.is_none() {
+            self.midi_seq = config.midi.seq.clone();
+        }
+        if self.midi_serial.is_empty() {
+            self.midi_serial = config.midi.serial.clone();
+        }
+        if self.rtp_midi_port.is_none() {
+            self.rtp_midi_port = config.midi.rtp_port;
+        }
+        if self.ble_midi.is_none() && (config.midi.ble || config.midi.ble_address.is_some()) {
+            self.ble_midi = Some(config.midi.ble_address.clone());
+        }
+        if self.osc_port.is_none() {
+            self.osc_port = config.osc.port;
+        }
+        if self.fifo_path.is_none() {
+            self.fifo_path = config.fifo.path.clone();
+        }
+        if self.ipc_socket.is_none() {
+            self.ipc_socket = config.ipc.socket.clone();
+        }
+        if self.dbus_matches.is_empty() {
+            self.dbus_matches = config.dbus.matches.clone();
+        }
+        if self.midi_out.is_none() {
+            self.midi_out = config.midi.output.clone();
+        }
+        self.gamepad_name = self.gamepad_name.take().or_else(|| config.uinput.gamepad.name.clone());
+        self.gamepad_vendor = self.gamepad_vendor.or(config.uinput.gamepad.vendor);
+        self.gamepad_product = self.gamepad_product.or(config.uinput.gamepad.product);
+        self.max_gamepads = self.max_gamepads.or(config.uinput.max_gamepads);
+        self.keyboard_name = self.keyboard_name.take().or_else(|| config.uinput.keyboard.name.clone());
+        self.keyboard_vendor = self.keyboard_vendor.or(config.uinput.keyboard.vendor);
+        self.keyboard_product = self.keyboard_product.or(config.uinput.keyboard.product);
+        self.mouse_name = self.mouse_name.take().or_else(|| config.uinput.mouse.name.clone());
+        self.mouse_vendor = self.mouse_vendor.or(config.uinput.mouse.vendor);
+        self.mouse_product = self.mouse_product.or(config.uinput.mouse.product);
+        self.touch_width = self.touch_width.or(config.uinput.touch_width);
+        self.touch_height = self.touch_height.or(config.uinput.touch_height);
+    }
+}
+
+#[cfg(not(unix))]
+compile_error!("This program is only for Unix-like systems.");
+
+// Best-effort cleanup for however run() ends, including errors before there's a
+// script to call on_script_exit in. Everything in here is fine to do twice.
+struct CleanupGuard;
+
+impl Drop for CleanupGuard {
+    fn drop(&mut self) {
+        api::midi::close_all();
+        // Don't lose a recording the script never got around to stopping
+        match api::midi_file::stop_recording() {
+            Ok(Some(path)) => info!("Saved MIDI recording to {:?}", path),
+            Ok(None) => {},
+            Err(e) => warn!("Could not save MIDI recording: {}", e),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MidiRealtime {
+    TimingClock,
+    Start,
+    Continue,
+    Stop,
+}
+
+impl MidiRealtime {
+    pub fn from_byte(b: u8) -> Option<Self> {
+        match b {
+            0xF8 => Some(MidiRealtime::TimingClock),
+            0xFA => Some(MidiRealtime::Start),
+            0xFB => Some(MidiRealtime::Continue),
+            0xFC => Some(MidiRealtime::Stop),
+            _ => None,
+        }
+    }
+
+    pub fn event_name(&self) -> &'static str {
+        match self {
+            MidiRealtime::TimingClock => "timing_clock",
+            MidiRealtime::Start => "start",
+            MidiRealtime::Continue => "continue",
+            MidiRealtime::Stop => "stop",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Message {
+    Midi { device: String, message: MidiMessage },
+    MidiRealtime { device: String, message: MidiRealtime },
+    Timer { handle: u64, period: Option<Duration> },
+    Evdev { device: String, kind: u16, code: u16, value: i32 },
+    Osc { address: String, args: Vec<rosc::OscType> },
+    External(serde_json::Value),
+    Ipc(serde_json::Value),
+    /// From handcake --panic, release everything and silence --midi-out
+    Panic,
+    /// A MIDI device was plugged in (or back in), see api::hotplug
+    DeviceConnected(String),
+    DeviceDisconnected(String),
+    DBus { interface: String, member: String, args: Vec<zbus::zvariant::OwnedValue> },
+    ReplayFinished,
+    /// Swap in a freshly loaded script, see request_reload
+    Reload,
+    /// Run on_script_exit and stop dispatching
+    Exit(tokio::sync::oneshot::Sender<mlua::Result<()>>),
+    /// A chunk typed into --repl
+    Repl { code: String, done: tokio::sync::oneshot::Sender<repl::Output> },
+}
+
+pub struct AppState {
+    pub sender: queue::Sender,
+    pub receiver: Mutex<queue::Receiver>,
+    /// Whether the current script defines on_midi_clock, so clock ticks can be
+    /// skipped without looking at the script
+    pub wants_midi_clock: AtomicBool,
+    pub reloading: AtomicBool,
+    /// Set once handcake has been told to stop, for misc.check_signals()
+    pub stopping: AtomicBool,
+    pub timers: timer::Timers,
+    /// Set with --replay or --dry-run, midi.open() does nothing so only the recording gets through
+    pub replaying: AtomicBool,
+    pub replay_done: tokio::sync::Notify,
+    /// Whatever the script passed to state.save(), kept across reloads
+    pub saved: api::state::SavedState,
+    /// Opened by the first script, and handed on to every reload
+    pub devices: Mutex<Option<api::VirtualDevices>>,
+}
+
+impl AppState {
+    /// `queue_depth` is how many messages can wait for the script before MIDI input gets dropped
+    pub fn new(queue_depth: usize) -> Self {
+        let (sender, receiver) = queue::channel(queue_depth);
+
+        let timers = timer::Timers::new(sender.clone());
+
+        AppState {
+            sender,
+            receiver: Mutex::new(receiver),
+            wants_midi_clock: AtomicBool::new(false),
+            reloading: AtomicBool::new(false),
+            stopping: AtomicBool::new(false),
+            timers,
+            replaying: AtomicBool::new(false),
+            replay_done: tokio::sync::Notify::new(),
+            saved: Default::default(),
+            devices: Mutex::new(None),
+        }
+    }
+}
+
+// Lua callbacks are looked up once after on_script_init instead of going
+// through the globals table for every message
+struct Callbacks {
+    on_midi_recv: Option<mlua::RegistryKey>,
+    on_midi_clock: Option<mlua::RegistryKey>,
+    on_script_exit: Option<mlua::RegistryKey>,
+    on_script_reload: Option<mlua::RegistryKey>,
+    on_evdev_recv: Option<mlua::RegistryKey>,
+    on_osc_recv: Option<mlua::RegistryKey>,
+    on_external: Option<mlua::RegistryKey>,
+    on_ipc_recv: Option<mlua::RegistryKey>,
+    on_device_connect: Option<mlua::RegistryKey>,
+    on_device_disconnect: Option<mlua::RegistryKey>,
+    on_dbus_signal: Option<mlua::RegistryKey>,
+}
+
+impl Callbacks {
+    fn resolve(lua: &mlua::Lua) -> anyhow::Result<Self> {
+        let resolve_one = |name: &str| -> anyhow::Result<Option<mlua::RegistryKey>> {
+            match lua.globals().get::<&str, Option<mlua::Function>>(name)? {
+                Some(f) => Ok(Some(lua.create_registry_value(f)?)),
+                None => Ok(None),
+            }
+        };
+
+        Ok(Callbacks {
+            on_midi_recv: resolve_one("on_midi_recv")?,
+            on_midi_clock: resolve_one("on_midi_clock")?,
+            on_script_exit: resolve_one("on_script_exit")?,
+            on_script_reload: resolve_one("on_script_reload")?,
+            on_evdev_recv: resolve_one("on_evdev_recv")?,
+            on_osc_recv: resolve_one("on_osc_recv")?,
+            on_external: resolve_one("on_external")?,
+            on_ipc_recv: resolve_one("on_ipc_recv")?,
+            on_device_connect: resolve_one("on_device_connect")?,
+            on_device_disconnect: resolve_one("on_device_disconnect")?,
+            on_dbus_signal: resolve_one("on_dbus_signal")?,
+        })
+    }
+
+    fn get<'lua>(lua: &'lua mlua::Lua, key: &mlua::RegistryKey) -> mlua::Function<'lua> {
+        lua.registry_value(key).unwrap()
+    }
+}
+
+/// A loaded and initialised script, along with the callbacks it defines.
+struct Script {
+    lua: mlua::Lua,
+    callbacks: Callbacks,
+}
+
+// --lua-path entries first, then the script's directory, then wherever Lua looks by default
+fn set_search_path(lua: &mlua::Lua, dirs: &[PathBuf], script_path: &Path) -> mlua::Result<()> {
+    let package = lua.globals().get::<&str, mlua::Table>("package")?;
+    let mut search_path = String::new();
+    for dir in dirs.iter().map(PathBuf::as_path).chain(script_path.parent()) {
+        let dir = dir.to_string_lossy();
+        let dir = if dir.is_empty() { ".".into() } else { dir };
+        search_path.push_str(&format!("{dir}/?.lua;{dir}/?/init.lua;"));
+    }
+    search_path.push_str(&package.get::<&str, String>("path")?);
+    package.set("path", search_path)
+}
+
+// Swaps out everything that can touch the filesystem or the rest of the system
+// with stand-ins that error, so the script gets a useful message instead of "attempt to index a nil value"
+fn sandbox(lua: &mlua::Lua) -> mlua::Result<()> {
+    let globals = lua.globals();
+    for lib in ["io", "os", "package", "debug"] {
+        let meta = lua.create_table()?;
+        meta.set("__index", lua.create_function(move |_l, (_, key): (mlua::Value, String)| -> mlua::Result<()> {
+            Err(mlua::Error::RuntimeError(format!("Permission denied: {}.{} is not available with --sandbox", lib, key)))
+        })?)?;
+        let stand_in = lua.create_table()?;
+        stand_in.set_metatable(Some(meta));
+        globals.set(lib, stand_in)?;
+    }
+    for func in ["require", "dofile", "loadfile"] {
+        globals.set(func, lua.create_function(move |_l, _: mlua::MultiValue| -> mlua::Result<()> {
+            Err(mlua::Error::RuntimeError(format!("Permission denied: {} is not available with --sandbox", func)))
+        })?)?;
+    }
+
+    Ok(())
+}
+
+// Builds a fresh VM with every API registered, runs the script and its on_script_init
+async fn load_script(cli: &AppConfig, script_path: &Path, state: &Arc<AppState>) -> anyhow::Result<Script> {
+    // --repl without --script starts out empty
+    let script_text = match &cli.script {
+        Some(_) => std::fs::read_to_string(script_path)?,
+        None => String::new(),
+    };
+    // Only the safe standard libraries, and a panic in a callback comes back
+    // as a Lua error instead of taking handcake down with it
+    let lua = if cli.sandbox {
+        mlua::Lua::new_with(mlua::StdLib::ALL_SAFE, mlua::LuaOptions::new().catch_rust_panics(true))?
+    } else {
+        mlua::Lua::new()
+    };
+
+    // Every virtual device needs its own handle, gamepads open theirs in gamepad.create().
+    // Reloads keep the first script's, /dev/uinput might not open any more after --user.
+    let uinput_path = Path::new("/dev").join("uinput");
+    let devices = {
+        let mut devices = state.devices.lock();
+        match &*devices {
+            Some(devices) => devices.clone(),
+            None => {
+                let opened = api::VirtualDevices::open(&uinput_path)?;
+                debug!("uinput opened");
+                devices.insert(opened).clone()
+            },
+        }
+    };
+
+    api::midi::Midi::register_api(&lua, (state.clone(),))?;
+    let midi_out = if cli.dry_run { None } else { cli.midi_out.clone() };
+    api::midi_out::MidiOut::register_api(&lua, (midi_out,))?;
+    let gamepad_info = api::gamepad::Gamepad::default_device()
+        .with_overrides(cli.gamepad_name.clone(), cli.gamepad_vendor, cli.gamepad_product);
+    let keyboard_info = api::keyboard::Keyboard::default_device()
+        .with_overrides(cli.keyboard_name.clone(), cli.keyboard_vendor, cli.keyboard_product);
+    let mouse_info = api::mouse::Mouse::default_device()
+        .with_overrides(cli.mouse_name.clone(), cli.mouse_vendor, cli.mouse_product);
+    api::gamepad::Gamepad::register_api(&lua, (uinput_path.clone(), gamepad_info, cli.max_gamepads.unwrap_or(4)))?;
+    api::keyboard::Keyboard::register_api(&lua, (Box::new(devices.keyboard), keyboard_info, state.clone()))?;
+    api::mouse::Mouse::register_api(&lua, (Box::new(devices.mouse), mouse_info))?;
+    let touch_size = (cli.touch_width.unwrap_or(1920), cli.touch_height.unwrap_or(1080));
+    api::touch::Touch::register_api(&lua, (Box::new(devices.touch), api::touch::Touch::default_device(), touch_size))?;
+    api::misc::Misc::register_api(&lua, (state.clone(),))?;
+    api::osc::Osc::register_api(&lua, ())?;
+    api::ipc::Ipc::register_api(&lua, ())?;
+    api::state::State::register_api(&lua, (state.clone(),))?;
+    api::buffer::Buffer::register_api(&lua, ())?;
+    api::evdev_input::Evdev::register_api(&lua, ())?;
+    if cfg!(debug_assertions) {
+        api::check_registry(&lua)?;
+    }
+    if cli.repl {
+        repl::register(&lua)?;
+    }
+    if cli.test {
+        api::test::Test::register_api(&lua, ())?;
+    }
+
+    let args = lua.create_table()?;
+    for (key, value) in &cli.args {
+        args.set(key.as_str(), value.as_str())?;
+    }
+    lua.globals().set("args", args)?;
+
+    set_search_path(&lua, &cli.lua_paths, script_path)?;
+    if cli.sandbox {
+        sandbox(&lua)?;
+    }
+
+    debug!("Evaluating initial script");
+    lua.load(&script_text).set_name(&script_path.to_string_lossy().as_bytes())?.exec_async().await?;
+
+    // Only when starting up, on a reload the devices have been open for a while.
+    // misc.set_init_delay() from the script wins over the flag.
+    let init_delay = api::misc::take_init_delay(&lua).unwrap_or(Duration::from_millis(cli.init_delay_ms));
+    if !init_delay.is_zero() && !state.reloading.load(Ordering::Acquire) {
+        debug!("Waiting {}ms before init (--init-delay-ms)", init_delay.as_millis());
+        tokio::time::sleep(init_delay).await;
+    }
+
+    // Like every other callback this is optional, but a non-function value is still an error
+    match lua.globals().get::<&str, Option<mlua::Function>>("on_script_init")? {
+        Some(on_script_init) => {
+            debug!("Calling on_script_init()");
+            on_script_init.call_async::<_, ()>(()).await?;
+        },
+        None => debug!("Script has no on_script_init(), skipping"),
+    }
+
+    let callbacks = Callbacks::resolve(&lua)?;
+
+    Ok(Script { lua, callbacks })
+}
+
+// Reloads go through the queue like everything else, so the dispatch thread
+// is the only one that ever touches the script
+fn request_reload(state: &AppState) {
+    if state.reloading.swap(true, Ordering::AcqRel) {
+        info!("reload suppressed: another reload in progress");
+        return;
+    }
+    let _ = state.sender.send(Message::Reload);
+}
+
+// Nothing gets dispatched while this runs, so no message ever sees a half-loaded script
+async fn reload_script(cli: &AppConfig, script_path: &Path, state: &Arc<AppState>, script: &mut Script) {
+    info!("Reloading script {:?}", script_path);
+
+    if let Some(key) = &script.callbacks.on_script_reload {
+        debug!("Calling on_script_reload()");
+        if let Err(e) = Callbacks::get(&script.lua, key).call_async::<_, ()>(()).await {
+            warn!("Lua error in on_script_reload: {}", e);
+        }
+    }
+
+    // The new script can't grab them while the old one still has them
+    api::evdev_input::release_all(&script.lua);
+    match load_script(cli, script_path, state).await {
+        Ok(new_script) => {
+            *script = new_script;
+            state.wants_midi_clock.store(script.callbacks.on_midi_clock.is_some(), Ordering::Relaxed);
+            info!("Script reloaded");
+        },
+        Err(e) => {
+            error!("Failed to reload script, keeping the old one: {}", e);
+        },
+    }
+
+    state.reloading.store(false, Ordering::Release);
+}
+
+// Lua errors in a callback are logged and the message is dropped, so a broken
+// script doesn't take the dispatch thread down with it. Callbacks run as
+// coroutines, so they can wait on async functions like misc.sleep_ms.
+async fn call_callback<'lua, A: mlua::ToLuaMulti<'lua>>(name: &str, f: &mlua::Function<'lua>, args: A) {
+    if let Err(e) = metrics::time_callback(f.call_async::<_, ()>(args)).await {
+        error!("Lua error in {}: {}", name, e);
+    }
+}
+
+async fn exit_script(script: &Script) -> mlua::Result<()> {
+    let result = match &script.callbacks.on_script_exit {
+        Some(key) => {
+            debug!("Calling on_script_exit()");
+            Callbacks::get(&script.lua, key).call_async::<_, ()>(()).await
+        },
+        None => Ok(()),
+    };
+    // Whatever the script left held, so nothing stays stuck down after handcake's gone
+    if let Err(e) = api::keyboard::release_all(&script.lua) {
+        warn!("Could not release held keys: {}", e);
+    }
+    api::gamepad::destroy_all(&script.lua);
+    api::evdev_input::release_all(&script.lua);
+
+    result
+}
+
+// For errors once the script is running, it still gets its on_script_exit
+async fn exit_with_error(script: &Script, error: anyhow::Error) -> anyhow::Result<()> {
+    if let Err(e) = exit_script(script).await {
+        warn!("Lua error in on_script_exit: {}", e);
+    }
+    Err(error)
+}
+
+async fn dispatch_message(state: &AppState, script: &Script, message: Message) -> mlua::Result<()> {
+    match message {
+        Message::Midi { device, message: mut midi } => {
+            if let MidiMessage::Invalid = midi {
+                return Ok(());
+            }
+            let event = util::midi_event_name(&midi);
+            metrics::midi_event(event);
+            let lua = &script.lua;
+            let mut held_for = None;
+            // The arpeggiator's notes have been through all this already, as the notes it plays from
+            if device != api::midi::ARP_DEVICE {
+                api::midi::thru(lua, &midi);
+                // Before anything else, so everything the script sees agrees on the note numbers
+                if !api::midi::transpose(lua, &mut midi) {
+                    return Ok(());
+                }
+                if !api::midi::note_gate(lua, &mut midi) {
+                    return Ok(());
+                }
+                held_for = api::midi::track_notes(lua, state, &midi);
+                api::midi::track_chords(lua, state, &midi)?;
+                api::midi::track_taps(lua, state, &midi);
+            }
+            if let MidiMessage::ProgramChange(channel, program) = &midi {
+                // Before the switch, so it can still tell which layer it was on
+                if let Some(f) = api::midi::program_change_handler(lua)? {
+                    call_callback("on_program_change", &f, (*program, util::midi_channel_to_num(channel))).await;
+                }
+                api::midi::auto_layer(lua, *program);
+            }
+            if let MidiMessage::SysEx(sysex) = &midi {
+                if let Some((f, identity)) = api::midi::identity_reply(lua, &device, sysex)? {
+                    call_callback("on_identity", &f, identity).await;
+                }
+            }
+            if let Some((f, note)) = api::midi::mpe_note(lua, &midi)? {
+                call_callback("on_mpe_note", &f, note).await;
+            }
+            repl::show_midi(&device, &midi);
+            let (key, velocity) = match &midi {
+                MidiMessage::NoteOn(_, key) | MidiMessage::NoteOff(_, key) | MidiMessage::PolyKeyPressure(_, key) => (Some(key.key), Some(key.value)),
+                _ => (None, None),
+            };
+            tracing::trace!(device = device.as_str(), channel = util::midi_message_channel(&midi), key, velocity, "{}", event);
+            if script.callbacks.on_midi_recv.is_none() && !api::midi::is_learning(lua) && !api::midi::has_handlers(lua) {
+                return Ok(());
+            }
+
+            let tab = lua.create_table()?;
+            tab.set("device", device)?;
+            tab.set("event", event)?;
+            tab.set("layer", api::midi::layer(lua))?;
+
+            match &midi {
+                MidiMessage::NoteOn(channel, key) => {
+                    let channel = util::midi_channel_to_num(channel);
+                    tab.set("channel", channel)?;
+                    tab.set("key", key.key)?;
+                    tab.set("vel", api::midi::apply_velocity_curve(lua, channel, key.value)?)?;
+                    tab.set("is_note", true)?;
+                    if key.value == 0 {
+                        tab.set("duration_ms", held_for.unwrap_or_default().as_millis() as u64)?;
+                    }
+                },
+                MidiMessage::NoteOff(channel, key) => {
+                    let channel = util::midi_channel_to_num(channel);
+                    tab.set("channel", channel)?;
+                    tab.set("key", key.key)?;
+                    tab.set("vel", api::midi::apply_velocity_curve(lua, channel, key.value)?)?;
+                    tab.set("is_note", true)?;
+                    tab.set("duration_ms", held_for.unwrap_or_default().as_millis() as u64)?;
+                },
+                MidiMessage::PolyKeyPressure(channel, key) => {
+                    tab.set("channel", util::midi_channel_to_num(channel))?;
+                    tab.set("key", key.key)?;
+                    tab.set("value", key.value)?;
+                },
+                MidiMessage::ControlChange(channel, cc) => {
+                    let channel = util::midi_channel_to_num(channel);
+                    tab.set("channel", channel)?;
+                    tab.set("control", cc.control)?;
+                    tab.set("value", api::midi::smooth_cc(lua, channel, cc.control, cc.value))?;
+                },
+                MidiMessage::ProgramChange(channel, prgm) => {
+                    tab.set("channel", util::midi_channel_to_num(channel))?;
+                    tab.set("program", *prgm)?;
+                },
+                MidiMessage::ChannelPressure(channel, value) => {
+                    // Unlike poly_aftertouch this applies to the whole channel, so there's no key field
+                    tab.set("channel", util::midi_channel_to_num(channel))?;
+                    tab.set("value", *value)?;
+                },
+                MidiMessage::PitchBend(channel, lsb, msb) => {
+                    tab.set("channel", util::midi_channel_to_num(channel))?;
+                    let true_val = util::pitch_bend_value(*lsb, *msb);
+                    tab.set("value", true_val)?;
+                },
+                MidiMessage::SysEx(sysex) => {
+                    tab.set("data", util::sysex_to_table(lua, sysex)?)?;
+                },
+                x => {
+                    debug!("Unknown MIDI message seen: {:?}", x);
+                    return Ok(());
+                },
+            }
+
+            // Only taken once the message is known to be one the script would see
+            if let Some(f) = api::midi::take_learn(lua)? {
+                call_callback("on_midi_recv", &f, tab).await;
+                return Ok(());
+            }
+
+            let (handlers, also_global) = api::midi::handlers_for(lua, &tab)?;
+            for f in &handlers {
+                call_callback("midi handler", f, tab.clone()).await;
+            }
+            if also_global {
+                if let Some(key) = &script.callbacks.on_midi_recv {
+                    call_callback("on_midi_recv", &Callbacks::get(lua, key), tab).await;
+                }
+            }
+        },
+        Message::MidiRealtime { device, message: MidiRealtime::TimingClock } => {
+            metrics::midi_event(MidiRealtime::TimingClock.event_name());
+            api::midi::arp_clock(&script.lua, state).await?;
+            // Clock ticks arrive 24 times per beat, so bail out early unless the script wants them
+            if !state.wants_midi_clock.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            let lua = &script.lua;
+            let on_midi_clock = match &script.callbacks.on_midi_clock {
+                Some(key) => Callbacks::get(lua, key),
+                None => return Ok(()),
+            };
+            let tab = lua.create_table()?;
+            tab.set("event", MidiRealtime::TimingClock.event_name())?;
+            tab.set("device", device)?;
+
+            call_callback("on_midi_clock", &on_midi_clock, tab).await;
+        },
+        Message::MidiRealtime { device, message: rt } => {
+            metrics::midi_event(rt.event_name());
+            let lua = &script.lua;
+            let on_midi_recv = match &script.callbacks.on_midi_recv {
+                Some(key) => Callbacks::get(lua, key),
+                None => return Ok(()),
+            };
+            let tab = lua.create_table()?;
+            tab.set("event", rt.event_name())?;
+            tab.set("device", device)?;
+            tab.set("layer", api::midi::layer(lua))?;
+
+            call_callback("on_midi_recv", &on_midi_recv, tab).await;
+        },
+        Message::Evdev { device, kind, code, value } => {
+            let lua = &script.lua;
+            let on_evdev_recv = match &script.callbacks.on_evdev_recv {
+                Some(key) => Callbacks::get(lua, key),
+                None => return Ok(()),
+            };
+            let tab = lua.create_table()?;
+            tab.set("device", device)?;
+            tab.set("type_", kind)?;
+            tab.set("code", code)?;
+            tab.set("value", value)?;
+
+            call_callback("on_evdev_recv", &on_evdev_recv, tab).await;
+        },
+        Message::Osc { address, args } => {
+            let lua = &script.lua;
+            let on_osc_recv = match &script.callbacks.on_osc_recv {
+                Some(key) => Callbacks::get(lua, key),
+                None => return Ok(()),
+            };
+            let args = api::osc::args_to_table(lua, &args)?;
+
+            call_callback("on_osc_recv", &on_osc_recv, (address, args)).await;
+        },
+        Message::External(value) => {
+            let lua = &script.lua;
+            let on_external = match &script.callbacks.on_external {
+                Some(key) => Callbacks::get(lua, key),
+                None => return Ok(()),
+            };
+            let data = util::json_to_lua(lua, &value)?;
+
+            call_callback("on_external", &on_external, data).await;
+        },
+        Message::Ipc(value) => {
+            let lua = &script.lua;
+            let on_ipc_recv = match &script.callbacks.on_ipc_recv {
+                Some(key) => Callbacks::get(lua, key),
+                None => return Ok(()),
+            };
+            let data = util::json_to_lua(lua, &value)?;
+
+            call_callback("on_ipc_recv", &on_ipc_recv, data).await;
+        },
+        Message::Panic => {
+            warn!("Panic! Releasing every key and button");
+            let lua = &script.lua;
+            api::keyboard::release_all(lua)?;
+            api::gamepad::release_all(lua)?;
+            // Without --midi-out there's nothing to silence
+            if let Err(e) = api::midi::panic(lua) {
+                debug!("Not sending MIDI panic: {}", e);
+            }
+        },
+        Message::DeviceConnected(device) => {
+            if let Some(key) = &script.callbacks.on_device_connect {
+                call_callback("on_device_connect", &Callbacks::get(&script.lua, key), device).await;
+            }
+        },
+        Message::DeviceDisconnected(device) => {
+            if let Some(key) = &script.callbacks.on_device_disconnect {
+                call_callback("on_device_disconnect", &Callbacks::get(&script.lua, key), device).await;
+            }
+        },
+        Message::DBus { interface, member, args } => {
+            let lua = &script.lua;
+            let on_dbus_signal = match &script.callbacks.on_dbus_signal {
+                Some(key) => Callbacks::get(lua, key),
+                None => return Ok(()),
+            };
+            let args = api::dbus::args_to_table(lua, &args)?;
+
+            call_callback("on_dbus_signal", &on_dbus_signal, (interface, member, args)).await;
+        },
+        Message::Repl { code, done } => {
+            let _ = done.send(repl::eval(&script.lua, &code).await);
+        },
+        Message::ReplayFinished => {
+            // Everything before this in the queue has been dispatched by now
+            state.replay_done.notify_one();
+        },
+        Message::Timer { handle, period } => {
+            let f = api::misc::timer_callback(&script.lua, handle, period.is_some())?;
+            // Re-arm before calling, so a slow callback doesn't push the next tick back
+            if let (Some(_), Some(period)) = (&f, period) {
+                state.timers.start(handle, period, Some(period));
+            }
+            if let Some(f) = f {
+                call_callback("timer callback", &f, ()).await;
+            }
+        },
+        // The dispatch loop takes care of these itself
+        Message::Reload | Message::Exit(_) => unreachable!(),
+    }
+
+    Ok(())
+}
+
+// --log-format json: one object per line with timestamp, level, target and
+// message, plus whatever fields a tracing event has (channel, key, velocity...).
+// Everything logged through log's macros comes in through the tracing-log bridge.
+fn init_json_logging(config: &Config) {
+    let filters = std::env::var("RUST_LOG").ok()
+        .or_else(|| config.log.level.clone())
+        .unwrap_or_else(|| if cfg!(debug_assertions) { "debug" } else { "error" }.to_owned());
+    let subscriber = tracing_subscriber::fmt()
+        .json()
+        .flatten_event(true)
+        .with_env_filter(tracing_subscriber::EnvFilter::new(filters))
+        .with_writer(std::io::stderr)
+        .finish();
+    // Whatever's embedding handcake may have its own subscriber already
+    if tracing::subscriber::set_global_default(subscriber).is_ok() {
+        let _ = tracing_log::LogTracer::init();
+    }
+}
+
+/// Runs handcake until it's told to stop, going by `cli` the same way the binary
+/// goes by its arguments. Sets up logging and signal handlers, and exits the
+/// process once the script has shut down. Errors while starting are returned
+/// instead, after closing whatever was opened and calling on_script_exit if
+/// the script had got that far.
+pub async fn run(mut cli: AppConfig) -> anyhow::Result<()> {
+    let config_path = cli.config_path();
+    let config = match &config_path {
+        Some(path) => Config::load(path).map_err(|e| anyhow::anyhow!("Could not load config {:?}: {}", path, e))?,
+        None => Config::default(),
+    };
+    cli.merge_config(&config);
+    // Tests only ever see what they inject
+    cli.dry_run |= cli.test;
+
+    if cli.log_format.or(config.log.format) == Some(LogFormat::Json) {
+        init_json_logging(&config);
+    } else {
+        let mut logger = pretty_env_logger::formatted_builder();
+        if cfg!(debug_assertions) {
+            logger.filter_level(log::LevelFilter::Debug);
+        }
+        if let Some(level) = &config.log.level {
+            logger.parse_filters(level);
+        }
+        if let Ok(filters) = std::env::var("RUST_LOG") {
+            logger.parse_filters(&filters);
+        }
+        if cli.repl {
+            // The terminal is in raw mode, so a plain \n wouldn't go back to the start of the line
+            logger.format(|buf, record| writeln!(buf, "\r{:<5} {} > {}\r", record.level(), record.target(), record.args()));
+        }
+        // Whatever's embedding handcake may have its own logger already
+        let _ = logger.try_init();
+    }
+
+    info!("handcake v{} starting - (c)2022 rin", env!("CARGO_PKG_VERSION"));
+    if let Some(path) = &config_path {
+        info!("Using config {:?}", path);
+    }
+
+    if cli.list_midi_devices {
+        let devices = api::midi::list_devices()?;
+        if devices.is_empty() {
+            println!("No MIDI devices found. Is your controller plugged in?");
+        }
+        let paths = api::midi::port_paths(&devices, &api::midi::alsa_enumerate());
+        for (i, (name, path)) in devices.iter().zip(paths).enumerate() {
+            match path {
+                Some(path) => println!("{}: {} ({})", i, path.display(), name),
+                None => println!("{}: {}", i, name),
+            }
+        }
+        return Ok(());
+    }
+
+    if cli.panic {
+        let path = cli.ipc_socket.as_ref().ok_or_else(|| anyhow::anyhow!("--panic needs --ipc-socket"))?;
+        api::ipc::send_panic(path)?;
+        info!("Sent panic to {:?}", path);
+        return Ok(());
+    }
+
+    if cli.api_schema {
+        println!("{}", serde_json::to_string_pretty(&api::schema())?);
+        return Ok(());
+    }
+
+    let cleanup = CleanupGuard;
+
+    if cli.monitor {
+        let state = Arc::new(AppState::new(cli.queue_depth));
+        let devices = if cli.midi_devices.is_empty() {
+            (0..api::midi::list_devices()?.len()).map(|i| i.to_string()).collect()
+        } else {
+            cli.midi_devices.clone()
+        };
+        for device in &devices {
+            if let Err(e) = api::midi::open_device(&state, device) {
+                return Err(anyhow::anyhow!("Could not open MIDI device {:?}: {}", device, e));
+            }
+        }
+
+        tokio::task::spawn_blocking(move || monitor::run(state)).await??;
+        return Ok(());
+    }
+
+    let script_path = match cli.script.clone() {
+        Some(path) => path,
+        // Only used to name the state file
+        None if cli.repl => PathBuf::from("repl"),
+        None => return Err(anyhow::anyhow!("No script given. Pass --script or set path under [script] in handcake.toml.")),
+    };
+    if cli.script.is_some() && !script_path.exists() {
+        return Err(anyhow::anyhow!("Script at path {:?} does not exist, aborting.", script_path));
+    }
+
+    if cli.check {
+        let script_text = std::fs::read_to_string(&script_path)?;
+        let lua = mlua::Lua::new();
+        // The @ makes Lua report errors as file:line instead of [string "file"]:line
+        let chunk_name = format!("@{}", script_path.to_string_lossy());
+        if let Err(e) = lua.load(&script_text).set_name(&chunk_name)?.into_function() {
+            return Err(anyhow::anyhow!("{}", e));
+        }
+        info!("Script {:?} is valid", script_path);
+        return Ok(());
+    }
+
+    info!("Running script {:?}", script_path);
+
+    // Kept until the end of run(), everything that exits before then is an error anyway
+    let pid_file = match &cli.pid_file {
+        Some(path) => match pidfile::PidFile::create(path) {
+            Ok(pid_file) => Some(pid_file),
+            Err(e) => return Err(anyhow::anyhow!("Could not create PID file {:?}: {}", path, e)),
+        },
+        None => None,
+    };
+
+    if cli.dry_run {
+        api::DRY_RUN.store(true, Ordering::Relaxed);
+    } else if !Path::new("/dev").join("uinput").exists() {
+        return Err(anyhow::anyhow!("Could not find /dev/uinput. Is uinput installed?"));
+    }
+
+    if let Some(port) = cli.metrics_port {
+        metrics::serve(port).await?;
+    }
+
+    let mut state = AppState::new(cli.queue_depth);
+    if cli.test {
+        // Timers only go off when a test calls test.advance()
+        state.timers = timer::Timers::manual();
+    }
+    let state = Arc::new(state);
+    state.saved.persist_to(&api::state::state_file(&script_path));
+
+    if let Some(path) = &cli.record {
+        if let Err(e) = record::start(path) {
+            return Err(anyhow::anyhow!("Could not record to {:?}: {}", path, e));
+        }
+    }
+
+    if cli.replay.is_some() && cli.replay_speed <= 0.0 {
+        return Err(anyhow::anyhow!("--replay-speed has to be more than 0"));
+    }
+    if cli.replay.is_some() || cli.dry_run {
+        state.replaying.store(true, Ordering::Relaxed);
+    } else {
+        for device in &cli.midi_devices {
+            if let Err(e) = api::midi::open_device(&state, device) {
+                return Err(anyhow::anyhow!("Could not open MIDI device {:?}: {}", device, e));
+            }
+        }
+
+        if let Some(name) = &cli.midi_seq {
+            if let Err(e) = api::midi::open_virtual(&state, name) {
+                return Err(anyhow::anyhow!("Could not create MIDI sequencer port {:?}: {}", name, e));
+            }
+        }
+
+        for path in &cli.midi_serial {
+            if let Err(e) = api::serial_midi::open(&state, path) {
+                return Err(anyhow::anyhow!("Could not open serial port {:?}: {}", path, e));
+            }
+        }
+
+        if let Some(port) = cli.rtp_midi_port {
+            if let Err(e) = api::rtp_midi::listen(&state, port) {
+                return Err(anyhow::anyhow!("Could not listen for RTP-MIDI on port {}: {}", port, e));
+            }
+        }
+
+        if let Some(address) = &cli.ble_midi {
+            api::ble_midi::connect(&state, address.clone());
+        }
+
+        // Only if nothing at all was asked for, anything more specific is up to the user
+        let no_midi_input = cli.midi_devices.is_empty() && cli.midi_seq.is_none() && cli.midi_serial.is_empty()
+            && cli.rtp_midi_port.is_none() && cli.ble_midi.is_none();
+        if no_midi_input {
+            api::midi::open_discovered(&state, cli.auto_first);
+        }
+
+        // Not having it isn't worth stopping for, there's just no hot-plugging then
+        if let Err(e) = api::hotplug::watch(&state) {
+            warn!("Not watching for MIDI devices being plugged in: {}", e);
+        }
+    }
+
+    if let Some(port) = cli.osc_port {
+        if let Err(e) = api::osc::listen(&state, port) {
+            return Err(anyhow::anyhow!("Could not listen for OSC on port {}: {}", port, e));
+        }
+    }
+
+    if let Some(path) = &cli.fifo_path {
+        if let Err(e) = api::fifo::listen(&state, path) {
+            return Err(anyhow::anyhow!("Could not open FIFO {:?}: {}", path, e));
+        }
+    }
+
+    if let Some(path) = &cli.ipc_socket {
+        if let Err(e) = api::ipc::listen(&state, path) {
+            return Err(anyhow::anyhow!("Could not listen on {:?}: {}", path, e));
+        }
+    }
+
+    if !cli.dbus_matches.is_empty() {
+        if let Err(e) = api::dbus::subscribe(&state, &cli.dbus_matches).await {
+            return Err(anyhow::anyhow!("Could not subscribe to D-Bus signals: {}", e));
+        }
+    }
+
+    for path in cli.evdev_devices.iter().filter(|_| !cli.dry_run) {
+        if let Err(e) = api::evdev_input::open_device(&state, path) {
+            return Err(anyhow::anyhow!("Could not open evdev device {:?}: {}", path, e));
+        }
+    }
+
+    if cli.test {
+        // So --midi-out works from on_script_init too, each test starts with it empty
+        *api::RECORDED_MIDI.lock() = Some(Vec::new());
+        *api::RECORDED_GRABS.lock() = Some(Vec::new());
+    }
+    let script = load_script(&cli, &script_path, &state).await?;
+    state.wants_midi_clock.store(script.callbacks.on_midi_clock.is_some(), Ordering::Relaxed);
+
+    if cli.test {
+        let passed = api::test::run(&state, &script).await?;
+        drop(cleanup);
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+
+    if cli.watch {
+        let state = state.clone();
+        watch::watch(&script_path, move || {
+            info!("script file changed, reloading");
+            request_reload(&state);
+        })?;
+    }
+
+    // Only once the script has been loaded, so on_script_init doesn't miss the start
+    if let Some(path) = &cli.replay {
+        if let Err(e) = record::replay(&state, path, cli.replay_speed) {
+            return exit_with_error(&script, anyhow::anyhow!("Could not replay {:?}: {}", path, e)).await;
+        }
+    }
+
+    // Last thing before running, so the first script's devices and every input are already open
+    match cli.user.clone().or_else(|| std::env::var("HANDCAKE_USER").ok()) {
+        Some(user) => {
+            if let Err(e) = privileges::drop_to(&user) {
+                return exit_with_error(&script, anyhow::anyhow!("Could not switch to user {:?}: {}", user, e)).await;
+            }
+        },
+        None if privileges::is_root() => warn!("Running as root, consider passing --user"),
+        None => {},
+    }
+
+    // on_script_init has run and every input is open
+    systemd::notify("READY=1");
+    systemd::start_watchdog();
+
+    debug!("Receiving messages");
+
+    // The script moves in here for good, so it only ever sees one message at a time
+    let dispatch = {
+        let (cli, script_path, state) = (cli.clone(), script_path.clone(), state.clone());
+        let mut script = script;
+        let runtime = tokio::runtime::Handle::current();
+        // Waiting for messages blocks, so this stays on its own thread. Each message's
+        // callbacks then run to completion on the runtime before the next one is taken.
+        tokio::task::spawn_blocking(move || {
+            let lock = state.receiver.lock();
+            while let Ok(message) = lock.recv() {
+                match message {
+                    Message::Reload => runtime.block_on(reload_script(&cli, &script_path, &state, &mut script)),
+                    Message::Exit(done) => {
+                        let _ = done.send(runtime.block_on(exit_script(&script)));
+                        return;
+                    },
+                    message => {
+                        // A panic partway through a message doesn't break the script, so carry on
+                        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| runtime.block_on(dispatch_message(&state, &script, message))));
+                        match result {
+                            Ok(Ok(())) => {},
+                            Ok(Err(e)) => error!("Error dispatching message: {}", e),
+                            Err(_) => error!("Panic while dispatching message"),
+                        }
+                    },
+                }
+            }
+        })
+    };
+    tokio::pin!(dispatch);
+
+    let repl = async {
+        match cli.repl {
+            true => {
+                let state = state.clone();
+                tokio::task::spawn_blocking(move || repl::run(state)).await
+            },
+            false => std::future::pending().await,
+        }
+    };
+    tokio::pin!(repl);
+
+    let mut dispatch_stopped = false;
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sighup = signal(SignalKind::hangup())?;
+    loop {
+        tokio::select! {
+            _ = sigterm.recv() => {
+                info!("Received SIGTERM, shutting down");
+                break;
+            },
+            _ = sigint.recv() => {
+                info!("Received SIGINT, shutting down");
+                break;
+            },
+            _ = sighup.recv() => {
+                let state = state.clone();
+                tokio::task::spawn_blocking(move || request_reload(&state));
+            },
+            _ = state.replay_done.notified() => {
+                info!("Replay finished, shutting down");
+                break;
+            },
+            result = &mut repl => {
+                if let Ok(Err(e)) = result {
+                    error!("REPL stopped: {}", e);
+                }
+                break;
+            },
+            _ = &mut dispatch => {
+                error!("Message dispatch stopped unexpectedly");
+                dispatch_stopped = true;
+                break;
+            },
+        }
+    }
+
+    systemd::notify("STOPPING=1");
+    state.stopping.store(true, Ordering::Release);
+
+    // Without the dispatch thread there's nobody left to run on_script_exit
+    if !dispatch_stopped {
+        let (done, on_script_exit) = tokio::sync::oneshot::channel();
+        let sender = state.sender.clone();
+        // Sending waits if the queue is full, which shouldn't hold up the timeout below
+        tokio::task::spawn_blocking(move || sender.send(Message::Exit(done)));
+
+        match tokio::time::timeout(Duration::from_secs(2), on_script_exit).await {
+            Ok(Ok(Ok(()))) => {},
+            Ok(Ok(Err(e))) => warn!("Lua error in on_script_exit: {}", e),
+            Ok(Err(_)) => warn!("on_script_exit panicked"),
+            Err(_) => warn!("on_script_exit did not return within 2 seconds, exiting anyway"),
+        }
+    }
+
+    // exit() skips destructors
+    drop(pid_file);
+    drop(cleanup);
+
+    // The dispatch thread is still blocked waiting for messages (as is the REPL's, waiting
+    // for a key), so returning would wait for them forever
+    std::process::exit(if dispatch_stopped { 1 } else { 0 });
+}
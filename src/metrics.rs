@@ -0,0 +1,110 @@
+use std::time::Instant;
+use prometheus::{Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, Opts, TextEncoder};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener};
+
+lazy_static::lazy_static! {
+    static ref MIDI_EVENTS: IntCounterVec = {
+        let counter = IntCounterVec::new(
+            Opts::new("handcake_midi_events_total", "MIDI messages received, by event type"),
+            &["event_type"],
+        ).unwrap();
+        prometheus::register(Box::new(counter.clone())).unwrap();
+        counter
+    };
+
+    static ref MIDI_EVENTS_DROPPED: IntCounter = {
+        let counter = IntCounter::new("handcake_midi_events_dropped_total", "MIDI messages dropped because the queue was full").unwrap();
+        prometheus::register(Box::new(counter.clone())).unwrap();
+        counter
+    };
+
+    static ref LUA_ERRORS: IntCounter = {
+        let counter = IntCounter::new("handcake_lua_errors_total", "Lua callbacks that raised an error").unwrap();
+        prometheus::register(Box::new(counter.clone())).unwrap();
+        counter
+    };
+
+    static ref LUA_CALLBACK_DURATION: Histogram = {
+        let histogram = Histogram::with_opts(
+            HistogramOpts::new("handcake_lua_callback_duration_seconds", "Time spent in Lua callbacks")
+                .buckets(vec![0.0001, 0.001, 0.01]),
+        ).unwrap();
+        prometheus::register(Box::new(histogram.clone())).unwrap();
+        histogram
+    };
+}
+
+pub fn midi_event(event_type: &str) {
+    MIDI_EVENTS.with_label_values(&[event_type]).inc();
+}
+
+/// Returns how many have been dropped so far
+pub fn midi_event_dropped() -> u64 {
+    MIDI_EVENTS_DROPPED.inc();
+    MIDI_EVENTS_DROPPED.get()
+}
+
+/// How many have been dropped so far, without counting another one
+pub fn midi_events_dropped() -> u64 {
+    MIDI_EVENTS_DROPPED.get()
+}
+
+/// Runs a Lua callback, recording how long it took and whether it failed
+pub async fn time_callback<T>(f: impl std::future::Future<Output = mlua::Result<T>>) -> mlua::Result<T> {
+    let start = Instant::now();
+    let result = f.await;
+    LUA_CALLBACK_DURATION.observe(start.elapsed().as_secs_f64());
+    if result.is_err() {
+        LUA_ERRORS.inc();
+    }
+
+    result
+}
+
+fn render() -> String {
+    let mut buf = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buf).unwrap();
+    String::from_utf8(buf).unwrap()
+}
+
+/// Serves /metrics in the Prometheus text format. This is all a scraper needs,
+/// so it's plain HTTP/1.0 on a TcpListener rather than a whole web framework.
+pub async fn serve(port: u16) -> anyhow::Result<()> {
+    // Make sure every metric shows up from the start, even before anything's happened
+    lazy_static::initialize(&MIDI_EVENTS);
+    lazy_static::initialize(&MIDI_EVENTS_DROPPED);
+    lazy_static::initialize(&LUA_ERRORS);
+    lazy_static::initialize(&LUA_CALLBACK_DURATION);
+
+    // Only for scrapers on this machine, nothing here is meant for the outside
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    info!("Serving metrics on {}", listener.local_addr()?);
+
+    tokio::spawn(async move {
+        loop {
+            let mut stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!("Error accepting metrics connection: {}", e);
+                    continue;
+                },
+            };
+
+            tokio::spawn(async move {
+                let mut request = [0u8; 1024];
+                let n = stream.read(&mut request).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&request[..n]);
+
+                let response = if request.starts_with("GET /metrics ") {
+                    let body = render();
+                    format!("HTTP/1.0 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}", TextEncoder::new().format_type(), body.len(), body)
+                } else {
+                    "HTTP/1.0 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string()
+                };
+                let _ = stream.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+
+    Ok(())
+}
\ No newline at end of file
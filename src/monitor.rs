@@ -0,0 +1,110 @@
+use std::{collections::{HashSet, VecDeque}, io::Write, sync::Arc, time::{Duration, Instant}};
+use crossterm::{cursor, event::{self, Event, KeyCode, KeyModifiers}, execute, queue, style::Print, terminal::{self, ClearType}};
+use midi_control::MidiMessage;
+
+use crate::{AppState, Message, MidiRealtime, queue::QueueStats, util};
+
+const HISTORY: usize = 20;
+// C2 to C7, about as much as fits in a normal terminal
+const PIANO_KEYS: std::ops::RangeInclusive<u8> = 36..=96;
+
+struct Row {
+    time: f64,
+    device: String,
+    event: &'static str,
+    channel: String,
+    value: String,
+}
+
+pub fn describe(message: &MidiMessage) -> (String, String) {
+    let ch = |c| util::midi_channel_to_num(c).to_string();
+    match message {
+        MidiMessage::NoteOn(c, k) | MidiMessage::NoteOff(c, k) | MidiMessage::PolyKeyPressure(c, k) => (ch(c), format!("key {} value {}", k.key, k.value)),
+        MidiMessage::ControlChange(c, cc) => (ch(c), format!("control {} value {}", cc.control, cc.value)),
+        MidiMessage::ProgramChange(c, p) | MidiMessage::ChannelPressure(c, p) => (ch(c), p.to_string()),
+        MidiMessage::PitchBend(c, lsb, msb) => (ch(c), util::pitch_bend_value(*lsb, *msb).to_string()),
+        MidiMessage::SysEx(sysex) => (String::new(), format!("{} bytes", sysex.get_data().len())),
+        _ => (String::new(), String::new()),
+    }
+}
+
+fn is_black_key(note: u8) -> bool {
+    matches!(note % 12, 1 | 3 | 6 | 8 | 10)
+}
+
+fn draw(out: &mut impl Write, rows: &VecDeque<Row>, held: &HashSet<(i8, u8)>, stats: QueueStats) -> std::io::Result<()> {
+    queue!(out, cursor::MoveTo(0, 0), terminal::Clear(ClearType::All))?;
+    queue!(out, Print("handcake MIDI monitor, q to quit\r\n\r\n"))?;
+    queue!(out, Print(format!("{:>10}  {:<24} {:<17} {:>3}  {}\r\n", "time", "device", "event", "ch", "value")))?;
+    for row in rows {
+        queue!(out, Print(format!("{:>10.3}  {:<24.24} {:<17} {:>3}  {}\r\n", row.time, row.device, row.event, row.channel, row.value)))?;
+    }
+
+    let piano: String = PIANO_KEYS.map(|note| {
+        if held.iter().any(|(_, key)| *key == note) {
+            '█'
+        } else if is_black_key(note) {
+            '▒'
+        } else {
+            '░'
+        }
+    }).collect();
+    let octaves: String = PIANO_KEYS.map(|note| if note % 12 == 0 { 'C' } else { ' ' }).collect();
+    queue!(out, Print(format!("\r\n{}\r\n{}\r\n", piano, octaves)))?;
+    queue!(out, Print(format!("\r\nqueue: {} waiting, {} dropped, {} processed\r\n", stats.depth, stats.dropped, stats.processed)))?;
+
+    out.flush()
+}
+
+/// Shows incoming MIDI instead of running a script, until q or Ctrl-C is pressed
+pub fn run(state: Arc<AppState>) -> anyhow::Result<()> {
+    let mut out = std::io::stdout();
+    terminal::enable_raw_mode()?;
+    execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+
+    let result = (|| -> anyhow::Result<()> {
+        let start = Instant::now();
+        let mut rows = VecDeque::new();
+        let mut held = HashSet::new();
+        let receiver = state.receiver.lock();
+        draw(&mut out, &rows, &held, state.sender.stats())?;
+
+        loop {
+            while event::poll(Duration::ZERO)? {
+                if let Event::Key(key) = event::read()? {
+                    let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                    if key.code == KeyCode::Char('q') || ctrl_c {
+                        return Ok(());
+                    }
+                }
+            }
+
+            let (device, event, channel, value) = match receiver.recv_timeout(Duration::from_millis(50)) {
+                Ok(Message::Midi { device, message }) => {
+                    match &message {
+                        MidiMessage::NoteOn(c, k) if k.value > 0 => { held.insert((util::midi_channel_to_num(c), k.key)); },
+                        MidiMessage::NoteOn(c, k) | MidiMessage::NoteOff(c, k) => { held.remove(&(util::midi_channel_to_num(c), k.key)); },
+                        _ => {},
+                    }
+                    let (channel, value) = describe(&message);
+                    (device, util::midi_event_name(&message), channel, value)
+                },
+                // 24 of these a beat would push everything else off the screen
+                Ok(Message::MidiRealtime { message: MidiRealtime::TimingClock, .. }) => continue,
+                Ok(Message::MidiRealtime { device, message }) => (device, message.event_name(), String::new(), String::new()),
+                Ok(_) | Err(_) => continue,
+            };
+
+            rows.push_back(Row { time: start.elapsed().as_secs_f64(), device, event, channel, value });
+            if rows.len() > HISTORY {
+                rows.pop_front();
+            }
+            draw(&mut out, &rows, &held, state.sender.stats())?;
+        }
+    })();
+
+    execute!(out, cursor::Show, terminal::LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()?;
+
+    result
+}
\ No newline at end of file
@@ -0,0 +1,80 @@
+use std::{fs::OpenOptions, io::{ErrorKind, Write}, path::{Path, PathBuf}};
+
+/// A file holding our PID, removed again when this is dropped
+pub struct PidFile {
+    path: PathBuf,
+}
+
+fn is_alive(pid: libc::pid_t) -> bool {
+    // Signal 0 only checks whether the process exists. EPERM means it does,
+    // it just belongs to someone else.
+    unsafe { libc::kill(pid, 0) == 0 || *libc::__errno_location() == libc::EPERM }
+}
+
+impl PidFile {
+    /// Fails if another instance is running with the same file. A file left
+    /// behind by one that died is replaced.
+    pub fn create(path: &Path) -> anyhow::Result<Self> {
+        for _ in 0..2 {
+            // create_new is O_EXCL, so two instances starting at once can't both get here
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(mut file) => {
+                    writeln!(file, "{}", std::process::id())?;
+                    return Ok(PidFile { path: path.to_owned() });
+                },
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {},
+                Err(e) => return Err(e.into()),
+            }
+
+            let old = std::fs::read_to_string(path)?;
+            if let Ok(pid) = old.trim().parse::<libc::pid_t>() {
+                if pid > 0 && is_alive(pid) {
+                    anyhow::bail!("handcake is already running with PID {}", pid);
+                }
+            }
+            warn!("Removing stale PID file {:?}", path);
+            std::fs::remove_file(path)?;
+        }
+
+        anyhow::bail!("{:?} keeps coming back, is another instance starting?", path);
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("Could not remove PID file {:?}: {}", self.path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("handcake-{}-{}.pid", name, std::process::id()))
+    }
+
+    #[test]
+    fn written_and_removed() {
+        let path = temp_path("written");
+        let pid_file = PidFile::create(&path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap().trim(), std::process::id().to_string());
+        assert!(PidFile::create(&path).is_err(), "we're still running");
+
+        drop(pid_file);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn stale_file_is_replaced() {
+        let path = temp_path("stale");
+        let mut exited = std::process::Command::new("true").spawn().unwrap();
+        exited.wait().unwrap();
+        std::fs::write(&path, format!("{}\n", exited.id())).unwrap();
+
+        let _pid_file = PidFile::create(&path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap().trim(), std::process::id().to_string());
+    }
+}
\ No newline at end of file
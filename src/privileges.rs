@@ -0,0 +1,40 @@
+use std::ffi::CString;
+
+/// Switches to `user` (and their groups) for good. Whatever is already open
+/// stays usable, anything opened from here on is checked against `user`.
+pub fn drop_to(user: &str) -> anyhow::Result<()> {
+    let name = CString::new(user)?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 16384];
+    let err = unsafe { libc::getpwnam_r(name.as_ptr(), &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if err != 0 {
+        return Err(std::io::Error::from_raw_os_error(err).into());
+    }
+    if result.is_null() {
+        anyhow::bail!("No such user {:?}", user);
+    }
+    let (uid, gid) = (passwd.pw_uid, passwd.pw_gid);
+
+    if unsafe { libc::getuid() } == uid {
+        return Ok(());
+    }
+
+    // Groups first, we can't change them anymore once we're not root
+    unsafe {
+        if libc::initgroups(name.as_ptr(), gid) != 0 || libc::setgid(gid) != 0 || libc::setuid(uid) != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        if libc::setuid(0) == 0 {
+            anyhow::bail!("Still able to get root back after switching to {:?}", user);
+        }
+    }
+
+    info!("Dropped privileges, now running as {:?} (uid {}, gid {})", user, uid, gid);
+
+    Ok(())
+}
+
+pub fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
\ No newline at end of file
@@ -0,0 +1,116 @@
+use std::{sync::{Arc, atomic::{AtomicU64, AtomicUsize, Ordering}, mpsc::{self, RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError}}, time::Duration};
+
+use crate::{Message, metrics};
+
+// std's channel won't say how much is in it, so both ends keep count
+#[derive(Default)]
+struct Counters {
+    depth: AtomicUsize,
+    processed: AtomicU64,
+}
+
+/// How the queue between the inputs and the script is doing, for misc.queue_stats()
+/// and the monitor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueStats {
+    /// Messages waiting for the script
+    pub depth: usize,
+    /// MIDI messages that were thrown away because the queue was full
+    pub dropped: u64,
+    /// Messages taken off the queue so far
+    pub processed: u64,
+}
+
+/// The sending half of the message queue, same as a `SyncSender` except it
+/// counts what goes in
+#[derive(Clone)]
+pub struct Sender {
+    sender: mpsc::SyncSender<Message>,
+    counters: Arc<Counters>,
+}
+
+impl Sender {
+    /// Waits for room in the queue
+    pub fn send(&self, message: Message) -> Result<(), SendError<Message>> {
+        // Counted before it goes in, so the receiver can never take it off first
+        self.counters.depth.fetch_add(1, Ordering::Relaxed);
+        self.sender.send(message).inspect_err(|_| {
+            self.counters.depth.fetch_sub(1, Ordering::Relaxed);
+        })
+    }
+
+    /// Gives the message back instead of waiting if the queue is full
+    pub fn try_send(&self, message: Message) -> Result<(), TrySendError<Message>> {
+        self.counters.depth.fetch_add(1, Ordering::Relaxed);
+        self.sender.try_send(message).inspect_err(|_| {
+            self.counters.depth.fetch_sub(1, Ordering::Relaxed);
+        })
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        QueueStats {
+            depth: self.counters.depth.load(Ordering::Relaxed),
+            dropped: metrics::midi_events_dropped(),
+            processed: self.counters.processed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The receiving half, only the dispatch thread (or the monitor) takes from it
+pub struct Receiver {
+    receiver: mpsc::Receiver<Message>,
+    counters: Arc<Counters>,
+}
+
+impl Receiver {
+    fn taken(&self) {
+        self.counters.depth.fetch_sub(1, Ordering::Relaxed);
+        self.counters.processed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn recv(&self) -> Result<Message, RecvError> {
+        let message = self.receiver.recv()?;
+        self.taken();
+        Ok(message)
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Message, RecvTimeoutError> {
+        let message = self.receiver.recv_timeout(timeout)?;
+        self.taken();
+        Ok(message)
+    }
+
+    pub fn try_recv(&self) -> Result<Message, TryRecvError> {
+        let message = self.receiver.try_recv()?;
+        self.taken();
+        Ok(message)
+    }
+}
+
+/// A queue for up to `depth` messages
+pub fn channel(depth: usize) -> (Sender, Receiver) {
+    let (sender, receiver) = mpsc::sync_channel(depth);
+    let counters = Arc::new(Counters::default());
+
+    (Sender { sender, counters: counters.clone() }, Receiver { receiver, counters })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_what_goes_in_and_out() {
+        let (sender, receiver) = channel(2);
+        sender.send(Message::Reload).unwrap();
+        sender.try_send(Message::Reload).unwrap();
+        assert_eq!(sender.stats().depth, 2);
+
+        assert!(sender.try_send(Message::Reload).is_err(), "the queue should be full");
+        assert_eq!(sender.stats().depth, 2, "a message that didn't fit isn't waiting");
+
+        receiver.recv().unwrap();
+        let stats = sender.stats();
+        assert_eq!((stats.depth, stats.processed), (1, 1));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,80 @@
+use std::{fs::File, io::{BufRead, BufReader, BufWriter, Write}, path::Path, sync::Arc, time::{Duration, Instant, SystemTime}};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::{AppState, Message, api};
+
+/// One line of a recording
+#[derive(Serialize, Deserialize)]
+struct Event {
+    time: String,
+    device: String,
+    data: Vec<u8>,
+}
+
+lazy_static::lazy_static! {
+    static ref RECORDER: Mutex<Option<BufWriter<File>>> = Mutex::new(None);
+}
+
+/// Starts writing every MIDI message received to `path`, a JSON object per line
+pub fn start(path: &Path) -> anyhow::Result<()> {
+    *RECORDER.lock() = Some(BufWriter::new(File::create(path)?));
+    info!("Recording MIDI to {:?}", path);
+
+    Ok(())
+}
+
+pub fn record(device: &str, data: &[u8]) {
+    let mut recorder = RECORDER.lock();
+    let file = match recorder.as_mut() {
+        Some(file) => file,
+        None => return,
+    };
+
+    let event = Event {
+        time: humantime::format_rfc3339_micros(SystemTime::now()).to_string(),
+        device: device.to_string(),
+        data: data.to_vec(),
+    };
+    // Flushed every time, so nothing's lost when handcake gets killed
+    let result = serde_json::to_writer(&mut *file, &event)
+        .map_err(std::io::Error::from)
+        .and_then(|_| writeln!(file))
+        .and_then(|_| file.flush());
+    if let Err(e) = result {
+        warn!("Stopped recording: {}", e);
+        *recorder = None;
+    }
+}
+
+/// Feeds a recording back in with the same timing, `speed` times faster.
+/// Sends Message::ReplayFinished once everything has been sent.
+pub fn replay(state: &Arc<AppState>, path: &Path, speed: f64) -> anyhow::Result<()> {
+    let events = BufReader::new(File::open(path)?)
+        .lines()
+        .filter(|line| !matches!(line, Ok(l) if l.trim().is_empty()))
+        .map(|line| -> anyhow::Result<(SystemTime, Event)> {
+            let event: Event = serde_json::from_str(&line?)?;
+            Ok((humantime::parse_rfc3339(&event.time)?, event))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    info!("Replaying {} events from {:?}", events.len(), path);
+
+    let sender = state.sender.clone();
+    std::thread::spawn(move || {
+        let start = Instant::now();
+        let first = events.first().map(|(time, _)| *time);
+
+        for (time, event) in events {
+            let offset = first.and_then(|first| time.duration_since(first).ok()).unwrap_or_default();
+            let due = start + Duration::from_secs_f64(offset.as_secs_f64() / speed);
+            std::thread::sleep(due.saturating_duration_since(Instant::now()));
+
+            api::midi::forward_blocking(&event.device, &event.data, &sender);
+        }
+
+        let _ = sender.send(Message::ReplayFinished);
+    });
+
+    Ok(())
+}
\ No newline at end of file
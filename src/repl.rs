@@ -0,0 +1,249 @@
+use std::{io::Write, path::PathBuf, sync::{Arc, mpsc::{self, Receiver, SyncSender}}, time::Duration};
+use crossterm::{cursor, event::{self, Event, KeyCode, KeyEventKind, KeyModifiers}, queue, style::Print, terminal::{self, ClearType}};
+use midi_control::MidiMessage;
+use parking_lot::Mutex;
+
+use crate::{AppState, Message, api, monitor, util};
+
+const PROMPT: &str = "handcake> ";
+// For the rest of a chunk that isn't finished yet, like a function
+const CONTINUE_PROMPT: &str = "      ... ";
+const HISTORY_LENGTH: usize = 500;
+
+/// What running a line gave back
+#[derive(Debug)]
+pub enum Output {
+    Values(String),
+    Error(String),
+    // Needs more lines before it can run
+    Incomplete,
+}
+
+lazy_static::lazy_static! {
+    // Set while the prompt is up, MIDI messages get shown through here
+    static ref EVENTS: Mutex<Option<SyncSender<String>>> = Mutex::new(None);
+}
+
+/// Shows a message above the prompt, if there is one. Called for every MIDI
+/// message the script sees.
+pub fn show_midi(device: &str, message: &MidiMessage) {
+    if let Some(events) = EVENTS.lock().as_ref() {
+        let (channel, value) = monitor::describe(message);
+        // Dropped if they're piling up because something's being typed
+        let _ = events.try_send(format!("> MIDI: {} {} {} {}", device, util::midi_event_name(message), channel, value));
+    }
+}
+
+fn tostring(l: &mlua::Lua, value: mlua::Value) -> String {
+    l.globals().get::<_, mlua::Function>("tostring")
+        .and_then(|tostring| tostring.call::<_, String>(value))
+        .unwrap_or_else(|e| format!("<{}>", e))
+}
+
+/// Runs a chunk in the script's VM. Like the standalone lua prompt it's tried
+/// as an expression first, so "1 + 1" shows 2.
+pub async fn eval(l: &mlua::Lua, code: &str) -> Output {
+    let expression = format!("return {}", code);
+    let f = match l.load(&expression).set_name("=repl").and_then(|chunk| chunk.into_function()) {
+        Ok(f) => f,
+        Err(_) => match l.load(code).set_name("=repl").and_then(|chunk| chunk.into_function()) {
+            Ok(f) => f,
+            Err(mlua::Error::SyntaxError { incomplete_input: true, .. }) => return Output::Incomplete,
+            Err(e) => return Output::Error(e.to_string()),
+        },
+    };
+
+    match f.call_async::<_, mlua::MultiValue>(()).await {
+        Ok(values) => Output::Values(values.into_iter().map(|v| tostring(l, v)).collect::<Vec<_>>().join("\t")),
+        Err(e) => Output::Error(e.to_string()),
+    }
+}
+
+/// Adds help(), and swaps print() for one that still lines up while the
+/// terminal is in raw mode
+pub fn register(l: &mlua::Lua) -> mlua::Result<()> {
+    l.globals().set("help", l.create_function(|_l, _: ()| {
+        let mut text = String::new();
+        for entry in api::API_REGISTRY.iter().flat_map(|entries| entries.iter()) {
+            let name = if entry.name.contains(':') { entry.name.to_owned() } else { format!("{}.{}", entry.module, entry.name) };
+            text.push_str(&format!("{} {}\n    {}\n", name, entry.signature, entry.description));
+        }
+        Ok(text.trim_end().to_owned())
+    })?)?;
+
+    l.globals().set("print", l.create_function(|l, args: mlua::Variadic<mlua::Value>| {
+        let line = args.into_iter().map(|v| tostring(l, v)).collect::<Vec<_>>().join("\t");
+        print!("\r{}\r\n", line.replace('\n', "\r\n"));
+        Ok(())
+    })?)?;
+
+    Ok(())
+}
+
+fn history_file() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".handcake_history"))
+}
+
+struct Editor {
+    line: Vec<char>,
+    cursor: usize,
+    history: Vec<String>,
+    // Where up and down have got to in the history, history.len() is the line being typed
+    position: usize,
+    // Lines of a chunk that isn't finished yet
+    pending: String,
+}
+
+impl Editor {
+    fn prompt(&self) -> &'static str {
+        if self.pending.is_empty() { PROMPT } else { CONTINUE_PROMPT }
+    }
+
+    fn redraw(&self, out: &mut impl Write) -> std::io::Result<()> {
+        let line: String = self.line.iter().collect();
+        queue!(out, cursor::MoveToColumn(0), terminal::Clear(ClearType::CurrentLine), Print(self.prompt()), Print(line))?;
+        queue!(out, cursor::MoveToColumn((self.prompt().len() + self.cursor) as u16))?;
+        out.flush()
+    }
+
+    // Prints a line above the prompt, then puts the prompt back
+    fn print_above(&self, out: &mut impl Write, text: &str) -> std::io::Result<()> {
+        queue!(out, cursor::MoveToColumn(0), terminal::Clear(ClearType::CurrentLine), Print(text.replace('\n', "\r\n")), Print("\r\n"))?;
+        self.redraw(out)
+    }
+
+    fn set_line(&mut self, line: &str) {
+        self.line = line.chars().collect();
+        self.cursor = self.line.len();
+    }
+}
+
+// Hands a line to the dispatch thread, so it runs between messages like everything else
+fn run_line(state: &AppState, code: String) -> Output {
+    let (done, output) = tokio::sync::oneshot::channel();
+    if state.sender.send(Message::Repl { code, done }).is_err() {
+        return Output::Error("Dispatch thread is gone".to_owned());
+    }
+    output.blocking_recv().unwrap_or_else(|_| Output::Error("Panicked".to_owned()))
+}
+
+/// The prompt itself, until Ctrl-D. Blocks, so it gets its own thread.
+pub fn run(state: Arc<AppState>) -> anyhow::Result<()> {
+    let (events, received): (_, Receiver<String>) = mpsc::sync_channel(256);
+    *EVENTS.lock() = Some(events);
+
+    let mut editor = Editor {
+        line: Vec::new(),
+        cursor: 0,
+        history: history_file()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .map(|text| text.lines().map(str::to_owned).collect())
+            .unwrap_or_default(),
+        position: 0,
+        pending: String::new(),
+    };
+    editor.position = editor.history.len();
+
+    let mut out = std::io::stdout();
+    terminal::enable_raw_mode()?;
+    let result = (|| -> anyhow::Result<()> {
+        out.write_all(b"Type help() for a list of functions, Ctrl-D to quit\r\n")?;
+        editor.redraw(&mut out)?;
+
+        loop {
+            // Only while nothing's been typed, so they don't get in the way
+            if editor.line.is_empty() && editor.pending.is_empty() {
+                while let Ok(event) = received.try_recv() {
+                    editor.print_above(&mut out, &event)?;
+                }
+            }
+            if !event::poll(Duration::from_millis(50))? {
+                continue;
+            }
+            let key = match event::read()? {
+                Event::Key(key) if key.kind != KeyEventKind::Release => key,
+                _ => continue,
+            };
+            let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+
+            match key.code {
+                KeyCode::Char('d') if ctrl && editor.line.is_empty() => {
+                    out.write_all(b"\r\n")?;
+                    return Ok(());
+                },
+                // Throws away the line, raw mode means it's not a SIGINT anyway
+                KeyCode::Char('c') if ctrl => {
+                    out.write_all(b"^C\r\n")?;
+                    editor.pending.clear();
+                    editor.set_line("");
+                    editor.position = editor.history.len();
+                },
+                KeyCode::Char('a') if ctrl => editor.cursor = 0,
+                KeyCode::Char('e') if ctrl => editor.cursor = editor.line.len(),
+                KeyCode::Char(_) if ctrl => {},
+                KeyCode::Char(c) => {
+                    editor.line.insert(editor.cursor, c);
+                    editor.cursor += 1;
+                },
+                KeyCode::Backspace if editor.cursor > 0 => {
+                    editor.cursor -= 1;
+                    editor.line.remove(editor.cursor);
+                },
+                KeyCode::Delete if editor.cursor < editor.line.len() => {
+                    editor.line.remove(editor.cursor);
+                },
+                KeyCode::Left => editor.cursor = editor.cursor.saturating_sub(1),
+                KeyCode::Right => editor.cursor = (editor.cursor + 1).min(editor.line.len()),
+                KeyCode::Home => editor.cursor = 0,
+                KeyCode::End => editor.cursor = editor.line.len(),
+                KeyCode::Up if editor.position > 0 => {
+                    editor.position -= 1;
+                    let line = editor.history[editor.position].clone();
+                    editor.set_line(&line);
+                },
+                KeyCode::Down if editor.position < editor.history.len() => {
+                    editor.position += 1;
+                    let line = editor.history.get(editor.position).cloned().unwrap_or_default();
+                    editor.set_line(&line);
+                },
+                KeyCode::Enter => {
+                    out.write_all(b"\r\n")?;
+                    let line: String = editor.line.iter().collect();
+                    editor.set_line("");
+                    if !line.trim().is_empty() && editor.history.last() != Some(&line) {
+                        editor.history.push(line.clone());
+                    }
+                    editor.position = editor.history.len();
+
+                    let code = if editor.pending.is_empty() { line } else { format!("{}\n{}", editor.pending, line) };
+                    if code.trim().is_empty() {
+                        editor.redraw(&mut out)?;
+                        continue;
+                    }
+                    editor.pending.clear();
+                    match run_line(&state, code.clone()) {
+                        Output::Values(values) if values.is_empty() => {},
+                        Output::Values(values) => out.write_all(format!("{}\r\n", values.replace('\n', "\r\n")).as_bytes())?,
+                        Output::Error(e) => out.write_all(format!("error: {}\r\n", e.replace('\n', "\r\n")).as_bytes())?,
+                        Output::Incomplete => editor.pending = code,
+                    }
+                },
+                _ => {},
+            }
+            editor.redraw(&mut out)?;
+        }
+    })();
+    terminal::disable_raw_mode()?;
+    *EVENTS.lock() = None;
+
+    if let Some(path) = history_file() {
+        let start = editor.history.len().saturating_sub(HISTORY_LENGTH);
+        let mut text = editor.history[start..].join("\n");
+        text.push('\n');
+        if let Err(e) = std::fs::write(&path, text) {
+            warn!("Could not save REPL history to {:?}: {}", path, e);
+        }
+    }
+
+    result
+}
\ No newline at end of file
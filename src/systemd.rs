@@ -0,0 +1,42 @@
+use std::{os::{linux::net::SocketAddrExt, unix::net::{SocketAddr, UnixDatagram}}, time::Duration};
+
+/// Sends a state line like "READY=1" to systemd. Does nothing when we weren't
+/// started by systemd (or not as Type=notify), and only warns if it fails,
+/// since handcake works the same either way.
+pub fn notify(state: &str) {
+    let path = match std::env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return,
+    };
+
+    let result = (|| {
+        let path = path.to_string_lossy();
+        // A leading @ means a socket in the abstract namespace
+        let addr = match path.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name)?,
+            None => SocketAddr::from_pathname(path.as_ref())?,
+        };
+        UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)?;
+
+        std::io::Result::Ok(())
+    })();
+    if let Err(e) = result {
+        warn!("Could not notify systemd ({}): {}", state, e);
+    }
+}
+
+/// Pings the watchdog at half the interval systemd asked for, if it asked
+pub fn start_watchdog() {
+    let usec = match std::env::var("WATCHDOG_USEC").ok().and_then(|v| v.parse::<u64>().ok()) {
+        Some(usec) if usec > 0 => usec,
+        _ => return,
+    };
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_micros(usec / 2));
+        loop {
+            interval.tick().await;
+            notify("WATCHDOG=1");
+        }
+    });
+}
\ No newline at end of file
@@ -0,0 +1,123 @@
+use std::{cmp::Reverse, collections::BinaryHeap, sync::{atomic::{AtomicU64, Ordering}, mpsc::{Sender, RecvTimeoutError}}, time::{Duration, Instant}};
+use parking_lot::Mutex;
+
+use crate::{Message, queue};
+
+/// Keeps track of when timers are due and sends a `Message::Timer` for each
+/// one, so the callback runs on the dispatch thread like everything else.
+/// The Lua side of a timer (the function to call) lives in the script's VM.
+pub struct Timers {
+    clock: Clock,
+    next_handle: AtomicU64,
+}
+
+// Due time, handle, and how often it repeats (if at all)
+type Pending = (Instant, u64, Option<Duration>);
+
+enum Clock {
+    // A thread that sends each timer to the queue once it's due
+    Real(Mutex<Sender<Pending>>),
+    // With --test, time only passes when test.advance() says so
+    Manual(Mutex<ManualClock>),
+}
+
+struct ManualClock {
+    now: Instant,
+    pending: BinaryHeap<Reverse<Pending>>,
+}
+
+impl Timers {
+    pub fn new(messages: queue::Sender) -> Self {
+        let (sender, receiver) = std::sync::mpsc::channel::<Pending>();
+
+        std::thread::spawn(move || {
+            let mut pending: BinaryHeap<Reverse<Pending>> = BinaryHeap::new();
+            loop {
+                let next = pending.peek().map(|Reverse((due, _, _))| *due);
+                let new = match next {
+                    Some(due) => receiver.recv_timeout(due.saturating_duration_since(Instant::now())),
+                    None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+
+                match new {
+                    Ok(timer) => pending.push(Reverse(timer)),
+                    Err(RecvTimeoutError::Timeout) => {},
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+
+                let now = Instant::now();
+                while let Some(Reverse((due, handle, period))) = pending.peek().copied() {
+                    if due > now {
+                        break;
+                    }
+                    pending.pop();
+                    if messages.send(Message::Timer { handle, period }).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        Timers {
+            clock: Clock::Real(Mutex::new(sender)),
+            next_handle: AtomicU64::new(1),
+        }
+    }
+
+    /// Timers that never go off by themselves, see `next_due`
+    pub fn manual() -> Self {
+        Timers {
+            clock: Clock::Manual(Mutex::new(ManualClock { now: Instant::now(), pending: BinaryHeap::new() })),
+            next_handle: AtomicU64::new(1),
+        }
+    }
+
+    /// What time the timers think it is, only different from `Instant::now()` for manual timers
+    pub fn now(&self) -> Instant {
+        match &self.clock {
+            Clock::Real(_) => Instant::now(),
+            Clock::Manual(clock) => clock.lock().now,
+        }
+    }
+
+    /// For manual timers, takes the next one that's due by `until` and moves the
+    /// clock up to when it was due. Once there's nothing left the clock is moved
+    /// to `until`. Real timers send themselves, so this is always None for them.
+    pub fn next_due(&self, until: Instant) -> Option<Message> {
+        let mut clock = match &self.clock {
+            Clock::Real(_) => return None,
+            Clock::Manual(clock) => clock.lock(),
+        };
+        match clock.pending.peek().copied() {
+            Some(Reverse((due, handle, period))) if due <= until => {
+                clock.pending.pop();
+                clock.now = clock.now.max(due);
+                Some(Message::Timer { handle, period })
+            },
+            _ => {
+                clock.now = clock.now.max(until);
+                None
+            },
+        }
+    }
+
+    /// Handles are never reused, even across reloads
+    pub fn new_handle(&self) -> u64 {
+        self.next_handle.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Repeating timers don't re-arm by themselves, the dispatcher calls this
+    /// again after each firing until the interval is cleared
+    pub fn start(&self, handle: u64, delay: Duration, period: Option<Duration>) {
+        match &self.clock {
+            Clock::Real(sender) => {
+                let _ = sender.lock().send((Instant::now() + delay, handle, period));
+            },
+            Clock::Manual(clock) => {
+                let mut clock = clock.lock();
+                let due = clock.now + delay;
+                clock.pending.push(Reverse((due, handle, period)));
+            },
+        }
+    }
+}
\ No newline at end of file
@@ -1,4 +1,5 @@
-use midi_control::Channel;
+use mlua::LuaSerdeExt;
+use midi_control::{Channel, MidiMessage, SysExEvent, message::SysExType, sysex::ManufacturerId};
 
 pub fn midi_channel_to_num(ch: &Channel) -> i8 {
     match ch {
@@ -20,4 +21,215 @@ pub fn midi_channel_to_num(ch: &Channel) -> i8 {
         Channel::Ch16 => 16,
         Channel::Invalid => -1,
     }
+}
+
+// 1-16 for anything that's on a channel, None for SysEx and the like
+pub fn midi_message_channel(message: &MidiMessage) -> Option<i8> {
+    match message {
+        MidiMessage::NoteOn(ch, _)
+        | MidiMessage::NoteOff(ch, _)
+        | MidiMessage::PolyKeyPressure(ch, _)
+        | MidiMessage::ControlChange(ch, _)
+        | MidiMessage::ProgramChange(ch, _)
+        | MidiMessage::ChannelPressure(ch, _)
+        | MidiMessage::PitchBend(ch, ..) => Some(midi_channel_to_num(ch)).filter(|ch| (1..=16).contains(ch)),
+        _ => None,
+    }
+}
+
+// What on_midi_recv sees as evt.event
+pub fn midi_event_name(message: &MidiMessage) -> &'static str {
+    match message {
+        MidiMessage::NoteOn(..) => "note_on",
+        MidiMessage::NoteOff(..) => "note_off",
+        MidiMessage::PolyKeyPressure(..) => "poly_aftertouch",
+        MidiMessage::ControlChange(..) => "control_change",
+        MidiMessage::ProgramChange(..) => "program_change",
+        MidiMessage::ChannelPressure(..) => "channel_pressure",
+        MidiMessage::PitchBend(..) => "pitch_bend",
+        MidiMessage::SysEx(..) => "sysex",
+        _ => "unknown",
+    }
+}
+
+// How many data bytes follow a status byte. SysEx has no fixed length, so it's 0 here.
+pub fn midi_data_len(status: u8) -> usize {
+    match status {
+        0x80..=0xBF | 0xE0..=0xEF | 0xF2 => 2,
+        0xC0..=0xDF | 0xF1 | 0xF3 => 1,
+        _ => 0,
+    }
+}
+
+// Pitch bend is 14 bits, with 7 in each data byte. Centre is 8192.
+pub fn pitch_bend_value(lsb: u8, msb: u8) -> u16 {
+    ((msb as u16 & 0x7F) << 7) | (lsb as u16 & 0x7F)
+}
+
+// -1.0 to 1.0, there's one less step above the centre than below it
+pub fn pitch_bend_to_f32(value: u16) -> f32 {
+    let value = value.min(16383) as f32 - 8192.0;
+    if value < 0.0 { value / 8192.0 } else { value / 8191.0 }
+}
+
+// The other way around, for sending a message back out as it came in. SysEx
+// goes back together the same way sysex_to_table does it.
+pub fn midi_to_bytes(message: &MidiMessage) -> Vec<u8> {
+    use midi_control::consts;
+    let ch = |ch: &Channel| *ch as u8;
+    match message {
+        MidiMessage::NoteOff(c, e) => vec![consts::NOTE_OFF | ch(c), e.key, e.value],
+        MidiMessage::NoteOn(c, e) => vec![consts::NOTE_ON | ch(c), e.key, e.value],
+        MidiMessage::PolyKeyPressure(c, e) => vec![consts::POLYPHONIC_KEY_PRESSURE | ch(c), e.key, e.value],
+        MidiMessage::ControlChange(c, e) => vec![consts::CONTROL_CHANGE | ch(c), e.control, e.value],
+        MidiMessage::ProgramChange(c, p) => vec![consts::PROGRAM_CHANGE | ch(c), *p],
+        MidiMessage::ChannelPressure(c, p) => vec![consts::CHANNEL_KEY_PRESSURE | ch(c), *p],
+        MidiMessage::PitchBend(c, lsb, msb) => vec![consts::PITCH_BEND_CHANGE | ch(c), *lsb, *msb],
+        MidiMessage::SysEx(sysex) => {
+            let mut bytes = vec![0xF0];
+            match sysex.get_type() {
+                SysExType::Manufacturer(ManufacturerId::Id(m)) => bytes.push(*m),
+                SysExType::Manufacturer(ManufacturerId::ExtId(m1, m2)) => bytes.extend([0x00, *m1, *m2]),
+                SysExType::NonRealTime(device, [id1, id2]) => bytes.extend([0x7E, *device, *id1, *id2]),
+                SysExType::RealTime(device, [id1, id2]) => bytes.extend([0x7F, *device, *id1, *id2]),
+            }
+            bytes.extend(sysex.get_data());
+            bytes
+        },
+        MidiMessage::Invalid => Vec::new(),
+    }
+}
+
+// MidiMessage::from rejects anything shorter than 3 bytes, which throws away
+// program change and channel pressure since those only have one data byte.
+// It also panics on a SysEx too short for its own header, so those are
+// caught here, counting the 0xF7 on the end.
+pub fn parse_midi(data: &[u8]) -> MidiMessage {
+    if data.first() == Some(&0xF0) {
+        let header = match data.get(1) {
+            Some(0x7E | 0x7F) => 6,
+            Some(0x00) => 5,
+            _ => 3,
+        };
+        if data.len() < header {
+            return MidiMessage::Invalid;
+        }
+    }
+    if data.len() == 2 {
+        return MidiMessage::from(&[data[0], data[1], 0][..]);
+    }
+
+    MidiMessage::from(data)
+}
+
+// Rebuilds the whole SysEx message (0xF0 through 0xF7) straight into a Lua
+// sequence so scripts get back exactly what the device sent
+pub fn sysex_to_table<'lua>(lua: &'lua mlua::Lua, sysex: &SysExEvent) -> mlua::Result<mlua::Table<'lua>> {
+    let mut header = [0xF0u8; 5];
+    let header_len = match sysex.get_type() {
+        SysExType::Manufacturer(ManufacturerId::Id(m)) => {
+            header[1] = *m;
+            2
+        },
+        SysExType::Manufacturer(ManufacturerId::ExtId(m1, m2)) => {
+            header[1..4].copy_from_slice(&[0x00, *m1, *m2]);
+            4
+        },
+        SysExType::NonRealTime(device, [id1, id2]) => {
+            header[1..5].copy_from_slice(&[0x7E, *device, *id1, *id2]);
+            5
+        },
+        SysExType::RealTime(device, [id1, id2]) => {
+            header[1..5].copy_from_slice(&[0x7F, *device, *id1, *id2]);
+            5
+        },
+    };
+
+    let data = sysex.get_data();
+    let tab = lua.create_table_with_capacity((header_len + data.len()) as i32, 0)?;
+    for (i, byte) in header[..header_len].iter().chain(data.iter()).enumerate() {
+        tab.raw_set(i + 1, *byte)?;
+    }
+
+    Ok(tab)
+}
+
+// For vendor/product IDs given on the command line, with or without 0x
+pub fn parse_hex_u16(s: &str) -> Result<u16, std::num::ParseIntError> {
+    u16::from_str_radix(s.trim_start_matches("0x"), 16)
+}
+
+// --arg key=value
+pub fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.into(), value.into())),
+        _ => Err(format!("expected key=value, got {:?}", s)),
+    }
+}
+
+// JSON null becomes nil, so it drops out of tables like any other nil
+pub fn json_to_lua<'lua>(lua: &'lua mlua::Lua, value: &serde_json::Value) -> mlua::Result<mlua::Value<'lua>> {
+    let options = mlua::SerializeOptions::new().serialize_none_to_null(false).serialize_unit_to_null(false);
+    lua.to_value_with(value, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pitch_bend_value_joins_both_bytes() {
+        assert_eq!(pitch_bend_value(0, 64), 8192);
+        assert_eq!(pitch_bend_value(0x7F, 0x7F), 16383);
+        assert_eq!(pitch_bend_value(0, 0), 0);
+    }
+
+    #[test]
+    fn pitch_bend_to_f32_ends() {
+        assert_eq!(pitch_bend_to_f32(0), -1.0);
+        assert_eq!(pitch_bend_to_f32(8192), 0.0);
+        assert_eq!(pitch_bend_to_f32(16383), 1.0);
+        assert_eq!(pitch_bend_to_f32(u16::MAX), 1.0);
+    }
+
+    #[test]
+    fn parse_midi_pads_one_data_byte() {
+        assert!(matches!(parse_midi(&[0xD1, 70]), MidiMessage::ChannelPressure(Channel::Ch2, 70)));
+        assert!(matches!(parse_midi(&[0xC0, 5]), MidiMessage::ProgramChange(Channel::Ch1, 5)));
+        assert!(matches!(parse_midi(&[0x90, 60, 100]), MidiMessage::NoteOn(Channel::Ch1, e) if e.key == 60 && e.value == 100));
+    }
+
+    #[test]
+    fn sysex_round_trip() {
+        let lua = mlua::Lua::new();
+        for bytes in [
+            &[0xF0, 0x41, 0x10, 0x42, 0x12, 0x7F, 0xF7][..],
+            &[0xF0, 0x00, 0x20, 0x6B, 0x7F, 0x42, 0x02, 0xF7],
+            &[0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7],
+            &[0xF0, 0x7F, 0x7F, 0x04, 0x01, 0x00, 0x40, 0xF7],
+        ] {
+            let message = parse_midi(bytes);
+            let sysex = match &message {
+                MidiMessage::SysEx(sysex) => sysex,
+                other => panic!("{:02X?} parsed as {:?}", bytes, other),
+            };
+            let tab = sysex_to_table(&lua, sysex).unwrap();
+            let from_lua = tab.sequence_values::<u8>().collect::<mlua::Result<Vec<_>>>().unwrap();
+
+            assert_eq!(from_lua, bytes);
+            assert_eq!(midi_to_bytes(&message), bytes);
+        }
+
+        // Too short for their headers, midi-control would panic on these
+        for bytes in [
+            &[0xF0, 0x7E, 0x00, 0xF7][..],
+            &[0xF0, 0x7E, 0x01, 0x02, 0xF7],
+            &[0xF0, 0x7F, 0xF7],
+            &[0xF0, 0x00, 0x20, 0xF7],
+            &[0xF0, 0x00],
+            &[0xF0, 0xF7],
+        ] {
+            assert!(matches!(parse_midi(bytes), MidiMessage::Invalid), "{:02X?}", bytes);
+        }
+    }
 }
\ No newline at end of file
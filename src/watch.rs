@@ -0,0 +1,100 @@
+use std::{path::{Path, PathBuf}, sync::mpsc::{Receiver, RecvTimeoutError}, time::Duration};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+
+/// How long the file has to stay untouched before a change counts.
+/// Editors usually write a file several times per save.
+pub const QUIET_PERIOD: Duration = Duration::from_millis(250);
+
+/// Blocks until a change arrives and then until no more changes have arrived
+/// for `quiet`. Returns false once the sending side is gone.
+pub fn debounce(rx: &Receiver<()>, quiet: Duration) -> bool {
+    if rx.recv().is_err() {
+        return false;
+    }
+
+    loop {
+        match rx.recv_timeout(quiet) {
+            Ok(()) => continue,
+            Err(RecvTimeoutError::Timeout) => return true,
+            Err(RecvTimeoutError::Disconnected) => return false,
+        }
+    }
+}
+
+/// Calls `on_change` from a background thread whenever the file at `path` changes.
+pub fn watch<F>(path: &Path, on_change: F) -> anyhow::Result<()>
+where
+    F: Fn() + Send + 'static,
+{
+    let path = path.canonicalize()?;
+    // Watch the directory rather than the file itself, since a lot of editors
+    // save by writing a new file and renaming it over the old one
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_else(|| PathBuf::from("/"));
+
+    let (send, recv) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                warn!("Error watching script: {}", e);
+                return;
+            },
+        };
+
+        if matches!(event.kind, EventKind::Modify(_) | EventKind::Create(_)) && event.paths.contains(&path) {
+            let _ = send.send(());
+        }
+    })?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+    std::thread::spawn(move || {
+        // Keep the watcher alive for as long as the thread runs
+        let _watcher = watcher;
+        while debounce(&recv, QUIET_PERIOD) {
+            on_change();
+        }
+    });
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{TryRecvError, channel};
+
+    const QUIET: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn burst_is_one_reload() {
+        let (tx, rx) = channel();
+        for _ in 0..5 {
+            tx.send(()).unwrap();
+        }
+        assert!(debounce(&rx, QUIET));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty), "the whole burst should have been taken");
+    }
+
+    #[test]
+    fn change_after_quiet_period_is_another_reload() {
+        let (tx, rx) = channel();
+        let sender = std::thread::spawn(move || {
+            tx.send(()).unwrap();
+            std::thread::sleep(QUIET * 4);
+            tx.send(()).unwrap();
+            tx
+        });
+        assert!(debounce(&rx, QUIET));
+        assert!(debounce(&rx, QUIET));
+        drop(sender.join().unwrap());
+        assert!(!debounce(&rx, QUIET));
+    }
+
+    #[test]
+    fn disconnected_is_false() {
+        let (tx, rx) = channel::<()>();
+        drop(tx);
+        assert!(!debounce(&rx, QUIET));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,41 @@
+// --check only compiles the script, so it works without uinput and never runs
+// any of the script (which is the only thing that would open a device)
+
+use std::{path::PathBuf, process::{Command, Output}};
+
+fn check(name: &str, source: &str) -> (Output, PathBuf) {
+    let script = std::env::temp_dir().join(format!("handcake-check-{}-{}.lua", name, std::process::id()));
+    std::fs::write(&script, source).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_handcake"))
+        .arg("--check")
+        .arg("--script")
+        .arg(&script)
+        .env("RUST_LOG", "debug")
+        .output()
+        .expect("Could not run handcake");
+    let _ = std::fs::remove_file(&script);
+
+    (output, script)
+}
+
+#[test]
+fn syntax_error_fails() {
+    let (output, script) = check("bad", "function on_midi_recv(evt)\n    if evt.key == 60\nend");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    // Reported as file:line, so editors can jump to it
+    assert!(stderr.contains(&format!("{}:3:", script.display())), "{}", stderr);
+}
+
+#[test]
+fn valid_script_passes_without_running() {
+    let (output, _) = check("good", "print(\"script ran\")\ngamepad.create()\nfunction on_midi_recv(evt) end");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert_eq!(output.status.code(), Some(0), "{}", stderr);
+    assert!(!stdout.contains("script ran"), "the script shouldn't have been run");
+    assert!(!stderr.contains("uinput opened"), "{}", stderr);
+}
\ No newline at end of file
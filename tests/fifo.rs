@@ -0,0 +1,41 @@
+// JSON written to --fifo-path reaches on_external, and the FIFO is opened again
+// for the next writer once one has closed it
+
+use std::{io::Write, process::{Command, Stdio}, time::{Duration, Instant}};
+
+#[test]
+fn each_writer_reaches_on_external() {
+    let dir = std::env::temp_dir().join(format!("handcake-fifo-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (fifo, script) = (dir.join("handcake"), dir.join("external.lua"));
+    std::fs::write(&script, "function on_external(data) print(data.event, data.n) end").unwrap();
+
+    let child = Command::new(env!("CARGO_BIN_EXE_handcake"))
+        .args(["--dry-run", "--script"])
+        .arg(&script)
+        .arg("--fifo-path")
+        .arg(&fifo)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Could not run handcake");
+    let start = Instant::now();
+    while !fifo.exists() {
+        assert!(start.elapsed() < Duration::from_secs(5), "handcake never made the FIFO");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+
+    // Each open blocks until handcake has the other end open
+    for line in ["{\"event\":\"reload\"}\n", "\n{\"event\":\"again\",\"n\":2}\n"] {
+        let mut writer = std::fs::OpenOptions::new().write(true).open(&fifo).unwrap();
+        writer.write_all(line.as_bytes()).unwrap();
+        drop(writer);
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    Command::new("kill").args(["-TERM", &child.id().to_string()]).status().unwrap();
+    let output = child.wait_with_output().unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), ["reload\tnil", "again\t2"], "{}", String::from_utf8_lossy(&output.stderr));
+}
\ No newline at end of file
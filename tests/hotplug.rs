@@ -0,0 +1,27 @@
+// Device events go through api::hotplug::device_event whether they came from
+// /dev/snd or not, so a made up device is enough to check what the script hears
+
+use std::{path::Path, sync::Arc, time::Duration};
+use handcake::{AppState, Message, api::hotplug::{self, DeviceEvent}};
+
+fn next_message(state: &AppState) -> Message {
+    state.receiver.lock().recv_timeout(Duration::from_secs(1)).expect("No message was sent")
+}
+
+#[test]
+fn unknown_device_is_reported_by_path() {
+    let state = Arc::new(AppState::new(16));
+    let path = Path::new("/dev/snd/midiC99D0");
+
+    hotplug::device_event(&state, path, DeviceEvent::Added);
+    match next_message(&state) {
+        Message::DeviceConnected(device) => assert_eq!(device, "/dev/snd/midiC99D0"),
+        message => panic!("Expected DeviceConnected, got {:?}", message),
+    }
+
+    hotplug::device_event(&state, path, DeviceEvent::Removed);
+    match next_message(&state) {
+        Message::DeviceDisconnected(device) => assert_eq!(device, "/dev/snd/midiC99D0"),
+        message => panic!("Expected DeviceDisconnected, got {:?}", message),
+    }
+}
\ No newline at end of file
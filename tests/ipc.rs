@@ -0,0 +1,34 @@
+// handcake --panic is a message on the IPC socket like any other, but it
+// shouldn't reach on_ipc_recv
+
+use std::{os::unix::net::UnixStream, io::Write, sync::Arc, time::Duration};
+use handcake::{AppState, Message, api::ipc};
+
+fn next_message(state: &AppState) -> Message {
+    state.receiver.lock().recv_timeout(Duration::from_secs(1)).expect("No message was sent")
+}
+
+#[test]
+fn panic_is_not_an_ipc_message() {
+    let state = Arc::new(AppState::new(16));
+    let path = std::env::temp_dir().join(format!("handcake-test-{}.sock", std::process::id()));
+    ipc::listen(&state, &path).unwrap();
+
+    ipc::send_panic(&path).unwrap();
+    match next_message(&state) {
+        Message::Panic => {},
+        message => panic!("Expected Panic, got {:?}", message),
+    }
+
+    // Anything else still goes to the script
+    let data = br#"{"handcake":"something else"}"#;
+    let mut stream = UnixStream::connect(&path).unwrap();
+    stream.write_all(&(data.len() as u32).to_be_bytes()).unwrap();
+    stream.write_all(data).unwrap();
+    match next_message(&state) {
+        Message::Ipc(value) => assert_eq!(value["handcake"], "something else"),
+        message => panic!("Expected Ipc, got {:?}", message),
+    }
+
+    let _ = std::fs::remove_file(&path);
+}
\ No newline at end of file
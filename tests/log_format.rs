@@ -0,0 +1,51 @@
+// --log-format json writes one object per line to stderr, with timestamp,
+// level, target and message, and the fields of structured events as well
+
+use std::process::Command;
+
+fn json_lines(name: &str, source: &str) -> Vec<serde_json::Value> {
+    let script = std::env::temp_dir().join(format!("handcake-log-{}-{}.test.lua", name, std::process::id()));
+    std::fs::write(&script, source).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_handcake"))
+        .args(["--test", "--log-format", "json", "--script"])
+        .arg(&script)
+        .env("RUST_LOG", "handcake=trace")
+        .output()
+        .expect("Could not run handcake");
+    let _ = std::fs::remove_file(&script);
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    stderr.lines()
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("{}: {:?}", e, line)))
+        .collect()
+}
+
+#[test]
+fn every_line_is_an_object() {
+    let lines = json_lines("plain", "test.run({})");
+
+    assert!(!lines.is_empty());
+    for line in &lines {
+        for field in ["timestamp", "level", "target", "message"] {
+            assert!(line.get(field).is_some(), "no {} in {}", field, line);
+        }
+    }
+    // Logged through log's macros, but still under handcake's own target
+    assert!(lines.iter().any(|line| line["target"] == "handcake" && line["level"] == "INFO"), "{:?}", lines);
+}
+
+#[test]
+fn midi_messages_have_fields() {
+    let lines = json_lines("midi", r#"
+function on_midi_recv(evt) end
+test.run({
+    note = function(inject)
+        inject({ event = "note_on", channel = 2, key = 60, vel = 90 })
+    end,
+})"#);
+
+    let note = lines.iter().find(|line| line["message"] == "note_on").expect("no note_on logged");
+    assert_eq!((&note["channel"], &note["key"], &note["velocity"]), (&2.into(), &60.into(), &90.into()));
+}
\ No newline at end of file
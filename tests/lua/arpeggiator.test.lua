@@ -0,0 +1,76 @@
+-- midi.start_arp and midi.stop_arp, run with:
+--   handcake --test --lua-path examples --script tests/lua/arpeggiator.test.lua
+
+local helpers = require("helpers")
+
+local played = {}
+
+function on_midi_recv(evt)
+    if evt.device == "arp" then
+        table.insert(played, evt)
+    end
+end
+
+-- Up through the held notes, starting over at the bottom
+local function up()
+    local step = 0
+    return function(chord)
+        step = step % #chord + 1
+        return chord[step].key
+    end
+end
+
+local function hold_c_major(inject)
+    for _, key in ipairs({ 60, 64, 67 }) do
+        inject({ event = "note_on", key = key, vel = 100 })
+    end
+end
+
+local function release_c_major(inject)
+    for _, key in ipairs({ 60, 64, 67 }) do
+        inject({ event = "note_off", key = key })
+    end
+end
+
+test.run({
+    three_steps_in_a_beat = function(inject)
+        hold_c_major(inject)
+        played = {}
+        -- 120 BPM is 500ms a beat, so a step every 166ms held for half of it
+        midi.start_arp(up(), 120, { steps = 3, gate = 0.5 })
+        test.advance(499)
+        midi.stop_arp()
+        release_c_major(inject)
+
+        test.assert_eq(helpers.fields(played, "event", "key", "vel"), {
+            { "note_on", 60, 100 }, { "note_off", 60, 0 },
+            { "note_on", 64, 100 }, { "note_off", 64, 0 },
+            { "note_on", 67, 100 }, { "note_off", 67, 0 },
+        })
+    end,
+
+    nothing_held_nothing_played = function()
+        played = {}
+        midi.start_arp(up(), 120, { steps = 3 })
+        test.advance(500)
+        midi.stop_arp()
+        test.assert_eq(#played, 0)
+    end,
+
+    stop_still_lets_the_last_note_go = function(inject)
+        hold_c_major(inject)
+        played = {}
+        midi.start_arp(up(), 120)
+        test.advance(0)
+        midi.stop_arp()
+        test.advance(1000)
+        release_c_major(inject)
+        test.assert_eq(helpers.fields(played, "event", "key"), { { "note_on", 60 }, { "note_off", 60 } })
+    end,
+
+    bad_options = function()
+        test.assert(not pcall(midi.start_arp, up(), 0))
+        test.assert(not pcall(midi.start_arp, up(), 120, { gate = 0 }))
+        test.assert(not pcall(midi.start_arp, up(), 120, { steps = 25 }))
+    end,
+})
\ No newline at end of file
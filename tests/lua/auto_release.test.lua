@@ -0,0 +1,39 @@
+-- Tests for auto_release.lua, run with:
+--   handcake --test --lua-path examples --script tests/lua/auto_release.test.lua
+
+require("auto_release")
+local helpers = require("helpers")
+local keys = require("keys")
+
+-- Presses (1) and releases (0) of space, in order
+local function space(recorded_events)
+    return helpers.values(recorded_events, helpers.EV_KEY, keys.SPACE)
+end
+
+test.run({
+    held_until_the_timer_fires = function(inject, recorded_events)
+        inject({ event = "note_on", key = 60 })
+        test.advance(99)
+        test.assert_eq(space(recorded_events), { 1 }, "still held at 99ms")
+        test.advance(1)
+        test.assert_eq(space(recorded_events), { 1, 0 })
+    end,
+
+    note_off_does_not_release = function(inject, recorded_events)
+        inject({ event = "note_on", key = 60 })
+        inject({ event = "note_off", key = 60 })
+        test.assert_eq(space(recorded_events), { 1 })
+        test.advance(100)
+        test.assert_eq(space(recorded_events), { 1, 0 })
+    end,
+
+    cancelled_timer_never_runs = function()
+        local ran = {}
+        local first = misc.schedule(10, function() table.insert(ran, "first") end)
+        local second = misc.schedule(20, function() table.insert(ran, "second") end)
+        test.assert(first ~= second, "each timer gets its own handle")
+        misc.cancel(first)
+        test.advance(20)
+        test.assert_eq(ran, { "second" })
+    end,
+})
\ No newline at end of file
@@ -0,0 +1,52 @@
+-- midi.enable_cc_smoothing, run with:
+--   handcake --test --lua-path examples --script tests/lua/cc_smoothing.test.lua
+
+local values = {}
+
+function on_midi_recv(evt)
+    if evt.event == "control_change" then
+        table.insert(values, evt.value)
+    end
+end
+
+-- The values on_midi_recv got for CC `control` being sent each of `sent`
+local function smoothed(inject, control, sent)
+    values = {}
+    for _, value in ipairs(sent) do
+        inject({ event = "control_change", control = control, value = value })
+    end
+    return values
+end
+
+test.run({
+    jitter_does_not_oscillate = function(inject)
+        midi.enable_cc_smoothing(1, 0.1)
+        local sent = {}
+        for i = 1, 40 do
+            sent[i] = i % 2 == 0 and 64 or 63
+        end
+        local seen = smoothed(inject, 1, sent)
+        for i = 2, #seen do
+            test.assert_eq(seen[i], seen[1], "value " .. i .. " changed")
+        end
+    end,
+
+    step_is_approached_gradually = function(inject)
+        midi.enable_cc_smoothing(2, 0.1)
+        local sent = { 0 }
+        for i = 2, 60 do
+            sent[i] = 127
+        end
+        local seen = smoothed(inject, 2, sent)
+        test.assert(seen[2] < 20, "jumped straight to " .. seen[2])
+        for i = 2, #seen do
+            test.assert(seen[i] >= seen[i - 1], "went back down at " .. i)
+        end
+        test.assert(seen[#seen] > 120, "still only at " .. seen[#seen])
+    end,
+
+    alpha_1_passes_through = function(inject)
+        midi.enable_cc_smoothing(3, 1.0)
+        test.assert_eq(smoothed(inject, 3, { 63, 64, 63, 0, 127 }), { 63, 64, 63, 0, 127 })
+    end,
+})
\ No newline at end of file
@@ -0,0 +1,48 @@
+-- Tests for cc_stick.lua, run with:
+--   handcake --test --lua-path examples --script tests/lua/cc_stick.test.lua
+
+require("cc_stick")
+local helpers = require("helpers")
+
+local function values(recorded_events, axis)
+    return helpers.values(recorded_events, helpers.EV_ABS, axis)
+end
+
+test.run({
+    deadzone_sends_zero = function(_, recorded_events)
+        local pad = gamepad.create()
+        pad.set_axis_config(gamepad.ABS.X, { deadzone = 10 })
+        pad.axis(gamepad.ABS.X, 8)
+        pad.axis(gamepad.ABS.X, 15)
+        local sent = values(recorded_events, gamepad.ABS.X)
+        test.assert_eq(sent[1], 0)
+        test.assert(sent[2] ~= 0, "past the deadzone should move the stick")
+    end,
+
+    axis_raw_skips_the_config = function(_, recorded_events)
+        local pad = gamepad.create()
+        pad.set_axis_config(gamepad.ABS.X, { center = 64, deadzone = 10 })
+        pad.axis_raw(gamepad.ABS.X, 0.5)
+        pad.set_axis_config(gamepad.ABS.X, nil)
+        pad.axis(gamepad.ABS.X, 0.25)
+        test.assert_eq(values(recorded_events, gamepad.ABS.X), { 16384, 8192 })
+    end,
+
+    knobs_are_centred = function(inject, recorded_events)
+        inject({ event = "control_change", control = 1, value = 66 })
+        inject({ event = "control_change", control = 1, value = 0 })
+        inject({ event = "control_change", control = 1, value = 96 })
+        inject({ event = "control_change", control = 2, value = 127 })
+        test.assert_eq(values(recorded_events, gamepad.ABS.X), { 0, -32767, 16384 })
+        -- 63 / 64 of the way
+        test.assert_eq(values(recorded_events, gamepad.ABS.Y), { 32255 })
+    end,
+
+    sensitivity_scales = function(_, recorded_events)
+        local pad = gamepad.create()
+        pad.set_axis_config(gamepad.ABS.RX, { center = 64, sensitivity = 2.0 })
+        pad.axis(gamepad.ABS.RX, 80)
+        pad.axis(gamepad.ABS.RX, 127)
+        test.assert_eq(values(recorded_events, gamepad.ABS.RX), { 16384, 32767 })
+    end,
+})
\ No newline at end of file
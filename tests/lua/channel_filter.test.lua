@@ -0,0 +1,19 @@
+-- Tests for channel_filter.lua, run by cargo test with --arg channel=2, or by hand with:
+--   handcake --test --lua-path examples --script tests/lua/channel_filter.test.lua --arg channel=2
+
+require("channel_filter")
+local helpers = require("helpers")
+local keys = require("keys")
+
+test.run({
+    args_are_strings = function()
+        test.assert_eq(args.channel, "2")
+    end,
+
+    only_notes_on_that_channel = function(inject, recorded_events)
+        helpers.tap(inject, 1, 60)
+        test.assert_eq(#recorded_events(), 0, "channel 1 should be ignored")
+        helpers.tap(inject, 2, 60)
+        test.assert_eq(helpers.values(recorded_events, helpers.EV_KEY, keys.SPACE), { 1, 0 })
+    end,
+})
\ No newline at end of file
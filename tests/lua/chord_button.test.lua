@@ -0,0 +1,55 @@
+-- Tests for chord_button.lua, run with:
+--   handcake --test --lua-path examples --script tests/lua/chord_button.test.lua
+
+require("chord_button")
+local helpers = require("helpers")
+
+test.run({
+    button_state_follows_button = function(_, recorded_events)
+        local pad = gamepad.create()
+        pad.button(gamepad.BTN.SOUTH, true)
+        pad.axis(gamepad.ABS.X, 0.5)
+        local sent = #recorded_events()
+
+        test.assert_eq(pad.button_state(gamepad.BTN.SOUTH), true)
+        test.assert_eq(pad.button_state(gamepad.BTN.EAST), false)
+        test.assert_eq(pad.all_buttons(), { [gamepad.BTN.SOUTH] = true })
+        test.assert_eq(pad.axis_state(gamepad.ABS.X), 0.5)
+        test.assert_eq(#recorded_events(), sent, "reading the state shouldn't send anything")
+    end,
+
+    chord_presses_once = function(inject, recorded_events)
+        for _, key in ipairs({ 60, 64, 67, 72 }) do
+            inject({ event = "note_on", key = key, vel = 100 })
+        end
+        inject({ event = "note_off", key = 72 })
+        inject({ event = "note_off", key = 64 })
+        test.assert_eq(helpers.values(recorded_events, helpers.EV_KEY, gamepad.BTN.SOUTH), { 1, 0 })
+    end,
+
+    notes_held_follows_note_on_and_off = function(inject)
+        -- Channel 2, away from anything the other tests left down
+        local function held()
+            local found = {}
+            for _, note in ipairs(midi.notes_held()) do
+                if note.channel == 2 then
+                    table.insert(found, note)
+                end
+            end
+            table.sort(found, function(a, b) return a.key < b.key end)
+            return helpers.fields(found, "key", "vel")
+        end
+
+        inject({ event = "note_on", channel = 2, key = 62, vel = 80 })
+        inject({ event = "note_on", channel = 2, key = 50, vel = 40 })
+        test.assert_eq(held(), { { 50, 40 }, { 62, 80 } })
+        test.assert(midi.is_held(2, 62))
+        test.assert(not midi.is_held(1, 62), "held on channel 2, not 1")
+
+        inject({ event = "note_off", channel = 2, key = 62 })
+        -- Note on with velocity 0 counts as a note off
+        inject({ event = "note_on", channel = 2, key = 50, vel = 0 })
+        test.assert_eq(held(), {})
+        test.assert(not midi.is_held(2, 62))
+    end,
+})
\ No newline at end of file
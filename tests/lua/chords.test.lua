@@ -0,0 +1,50 @@
+-- midi.set_chord_window, on_chord and midi.chord_name, run with:
+--   handcake --test --lua-path examples --script tests/lua/chords.test.lua
+
+local helpers = require("helpers")
+
+midi.set_chord_window(50)
+
+local function keys(notes)
+    return helpers.fields(notes, "key")
+end
+
+test.run({
+    notes_within_the_window_are_one_chord = function(inject)
+        local chords = {}
+        function on_chord(notes)
+            table.insert(chords, notes)
+        end
+        inject({ event = "note_on", key = 60, vel = 90 })
+        test.advance(20)
+        inject({ event = "note_on", key = 64, vel = 80 })
+        test.advance(20)
+        inject({ event = "note_on", key = 67, vel = 70 })
+        test.assert_eq(#chords, 0, "the window's still open")
+        test.advance(10)
+        on_chord = nil
+
+        test.assert_eq(#chords, 1)
+        test.assert_eq(helpers.fields(chords[1], "channel", "key", "vel"), { { 1, 60, 90 }, { 1, 64, 80 }, { 1, 67, 70 } })
+        test.assert_eq(midi.chord_name(chords[1]), "Cmaj")
+    end,
+
+    a_late_note_starts_another_chord = function(inject)
+        local chords = helpers.capture_global("on_chord", function()
+            inject({ event = "note_on", key = 62 })
+            inject({ event = "note_on", key = 65 })
+            test.advance(60)
+            inject({ event = "note_on", key = 69 })
+            test.advance(50)
+        end)
+        test.assert_eq({ keys(chords[1]), keys(chords[2]) }, { { { 62 }, { 65 } }, { { 69 } } })
+    end,
+
+    names = function()
+        test.assert_eq(midi.chord_name({ 60, 64, 67, 71 }), "Cmaj7")
+        test.assert_eq(midi.chord_name({ 57, 60, 64 }), "Amin")
+        -- An inversion is still named after its root
+        test.assert_eq(midi.chord_name({ 64, 67, 72 }), "Cmaj")
+        test.assert_eq(midi.chord_name({ 60, 61, 62 }), nil)
+    end,
+})
\ No newline at end of file
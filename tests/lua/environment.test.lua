@@ -0,0 +1,19 @@
+-- misc.getenv, run by cargo test with HANDCAKE_TEST_VAR=hello and
+-- HANDCAKE_UNSET_VAR unset, or by hand with:
+--   HANDCAKE_TEST_VAR=hello handcake --test --lua-path examples --script tests/lua/environment.test.lua
+
+test.run({
+    reads_a_set_variable = function()
+        test.assert_eq(misc.getenv("HANDCAKE_TEST_VAR"), "hello")
+        test.assert_eq(misc.getenv("HANDCAKE_TEST_VAR", "default"), "hello", "the default is only for unset variables")
+    end,
+
+    unset_is_nil_or_the_default = function()
+        test.assert_eq(misc.getenv("HANDCAKE_UNSET_VAR"), nil)
+        test.assert_eq(misc.getenv("HANDCAKE_UNSET_VAR", "default"), "default")
+    end,
+
+    there_is_no_setenv = function()
+        test.assert_eq(misc.setenv, nil)
+    end,
+})
\ No newline at end of file
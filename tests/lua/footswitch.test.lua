@@ -0,0 +1,47 @@
+-- Tests for footswitch.lua, run with:
+--   handcake --test --lua-path examples --script tests/lua/footswitch.test.lua
+
+require("footswitch")
+local helpers = require("helpers")
+local keys = require("keys")
+
+test.run({
+    pedal_presses_and_releases_once = function(inject, recorded_events)
+        for _, value in ipairs({ 0, 127, 127, 100, 127, 20, 0, 0 }) do
+            inject({ event = "control_change", control = 64, value = value })
+        end
+        test.assert_eq(helpers.events(recorded_events, helpers.EV_KEY), {
+            { keys.SPACE, 1 },
+            { keys.SPACE, 0 },
+        })
+    end,
+
+    wheel_ignores_repeats = function(inject, recorded_events)
+        local calls = 0
+        midi.on_cc_change(2, function()
+            calls = calls + 1
+        end)
+        for _, value in ipairs({ 10, 10, 10, 12, 12 }) do
+            inject({ event = "control_change", control = 2, value = value })
+        end
+        test.assert_eq(calls, 2)
+        midi.on_cc_change(2, nil)
+
+        inject({ event = "control_change", control = 1, value = 5 })
+        inject({ event = "control_change", control = 1, value = 5 })
+        inject({ event = "control_change", control = 1, value = 3 })
+        test.assert_eq(#helpers.events(recorded_events, helpers.EV_REL), 2)
+    end,
+
+    channels_are_tracked_separately = function(inject)
+        local rising = {}
+        midi.on_cc_threshold(20, 64, "rising", function(evt)
+            table.insert(rising, evt.channel)
+        end)
+        inject({ event = "control_change", channel = 1, control = 20, value = 100 })
+        inject({ event = "control_change", channel = 2, control = 20, value = 100 })
+        inject({ event = "control_change", channel = 1, control = 20, value = 110 })
+        test.assert_eq(rising, { 1, 2 })
+        midi.on_cc_threshold(20, 64, "rising", nil)
+    end,
+})
\ No newline at end of file
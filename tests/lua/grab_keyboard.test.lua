@@ -0,0 +1,32 @@
+-- Tests for grab_keyboard.lua, run with:
+--   handcake --test --lua-path examples --script tests/lua/grab_keyboard.test.lua
+
+require("grab_keyboard")
+local helpers = require("helpers")
+
+test.run({
+    grab_and_release = function(_, _, _, recorded_grabs)
+        local device = helpers.mock_device()
+        evdev.grab(device)
+        test.assert_eq(evdev.grabbed(), { device })
+        evdev.grab(device)
+        test.assert_eq(evdev.grabbed(), { device }, "grabbing twice should only grab once")
+        evdev.release(device)
+        test.assert_eq(evdev.grabbed(), {})
+        test.assert_eq(recorded_grabs(), {
+            { device = device, grabbed = true },
+            { device = device, grabbed = false },
+        })
+        os.remove(device)
+    end,
+
+    missing_device = function(_, _, _, recorded_grabs)
+        test.assert(not pcall(evdev.grab, "/dev/input/does-not-exist"))
+        test.assert_eq(evdev.grabbed(), {})
+        test.assert_eq(recorded_grabs(), {})
+    end,
+
+    release_without_grab = function()
+        test.assert(not pcall(evdev.release, "/dev/input/event0"))
+    end,
+})
\ No newline at end of file
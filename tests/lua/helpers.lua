@@ -0,0 +1,94 @@
+-- What the suites in this directory have in common, loaded with require("helpers").
+-- The examples they test are found through --lua-path examples.
+
+local helpers = {
+    EV_SYN = 0,
+    EV_KEY = 1,
+    EV_REL = 2,
+    EV_ABS = 3,
+}
+
+-- { code, value } of every recorded event of one type, in order
+function helpers.events(recorded_events, type)
+    local found = {}
+    for _, evt in ipairs(recorded_events()) do
+        if evt.type == type then
+            table.insert(found, { evt.code, evt.value })
+        end
+    end
+    return found
+end
+
+-- Just the values sent to one key, button or axis, in order
+function helpers.values(recorded_events, type, code)
+    local found = {}
+    for _, evt in ipairs(recorded_events()) do
+        if evt.type == type and evt.code == code then
+            table.insert(found, evt.value)
+        end
+    end
+    return found
+end
+
+-- Keys and buttons pressed, in order, ignoring releases
+function helpers.presses(recorded_events)
+    local found = {}
+    for _, evt in ipairs(recorded_events()) do
+        if evt.type == helpers.EV_KEY and evt.value == 1 then
+            table.insert(found, evt.code)
+        end
+    end
+    return found
+end
+
+-- A note on and its note off
+function helpers.tap(inject, channel, key, vel)
+    inject({ event = "note_on", channel = channel, key = key, vel = vel or 100 })
+    inject({ event = "note_off", channel = channel, key = key })
+end
+
+-- Everything a handler set with `set` (midi.on_note, midi.on_identity...) is given while
+-- f runs. The handler is set back to `previous` afterwards, or cleared.
+function helpers.capture(set, f, previous)
+    local seen = {}
+    set(function(evt)
+        table.insert(seen, evt)
+    end)
+    f()
+    set(previous)
+    return seen
+end
+
+-- Same for a callback the script defines as a global, which gets swapped out while f runs
+function helpers.capture_global(name, f)
+    local seen = {}
+    local previous = _G[name]
+    _G[name] = function(evt)
+        table.insert(seen, evt)
+    end
+    f()
+    _G[name] = previous
+    return seen
+end
+
+-- Picks fields out of each row, fields(notes, "event", "vel") gives { { "note_on", 100 }, ... }
+function helpers.fields(rows, ...)
+    local names = { ... }
+    local found = {}
+    for i, row in ipairs(rows) do
+        found[i] = {}
+        for j, name in ipairs(names) do
+            found[i][j] = row[name]
+        end
+    end
+    return found
+end
+
+-- --test implies --dry-run, where grabbing a device only opens it, so any file will do
+function helpers.mock_device()
+    local path = os.tmpname()
+    io.open(path, "w"):close()
+    return path
+end
+
+return helpers
\ No newline at end of file
@@ -0,0 +1,55 @@
+-- Tests for identify.lua, run with:
+--   handcake --test --lua-path examples --script tests/lua/identify.test.lua
+
+local identify = require("identify")
+local helpers = require("helpers")
+
+-- A Launchpad Mini MK3: Novation's 3 byte ID, family 0x0113, member 0, revision 0.5.9.1
+local LAUNCHPAD = { 0x7E, 0x00, 0x06, 0x02, 0x00, 0x20, 0x29, 0x13, 0x01, 0x00, 0x00, 0x00, 0x05, 0x09, 0x01 }
+-- Roland, one byte
+local ROLAND = { 0x7E, 0x10, 0x06, 0x02, 0x41, 0x2A, 0x02, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00 }
+
+-- Every identity reply while f runs, instead of them going to the example
+local function replies(f)
+    return helpers.capture(midi.on_identity, f, identify.choose_layout)
+end
+
+test.run({
+    identity_reply_is_parsed = function(inject)
+        local got = replies(function()
+            inject({ event = "sysex", device = "Launchpad", data = LAUNCHPAD })
+        end)
+        test.assert_eq(got, { {
+            device = "Launchpad",
+            manufacturer_id = 0x002029,
+            device_family = 0x13 | (0x01 << 7),
+            device_member = 0,
+            revision = { 0, 5, 9, 1 },
+        } })
+    end,
+
+    one_byte_manufacturer = function(inject)
+        local got = replies(function()
+            inject({ event = "sysex", data = ROLAND })
+        end)[1]
+        test.assert_eq(got.manufacturer_id, 0x41)
+        test.assert_eq(got.device_family, 0x12A)
+        test.assert_eq(got.device_member, 4)
+    end,
+
+    other_sysex_is_ignored = function(inject)
+        local got = replies(function()
+            -- The request itself, and one that's cut short
+            inject({ event = "sysex", data = { 0x7E, 0x7F, 0x06, 0x01 } })
+            inject({ event = "sysex", data = { 0x7E, 0x00, 0x06, 0x02, 0x41, 0x2A } })
+        end)
+        test.assert_eq(got, {})
+    end,
+
+    layout_follows_the_manufacturer = function(inject)
+        inject({ event = "sysex", data = LAUNCHPAD })
+        test.assert_eq(identify.pads()[1], 11)
+        inject({ event = "sysex", data = ROLAND })
+        test.assert_eq(identify.pads()[1], 36)
+    end,
+})
\ No newline at end of file
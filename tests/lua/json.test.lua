@@ -0,0 +1,35 @@
+-- json.encode and json.decode, run with:
+--   handcake --test --lua-path examples --script tests/lua/json.test.lua
+
+test.run({
+    round_trip = function()
+        local value = {
+            name = "pads",
+            count = 3,
+            ratio = 0.5,
+            enabled = true,
+            notes = { 36, 38, 42 },
+            nested = { layers = { { name = "drums" }, { name = "keys" } } },
+        }
+        test.assert_eq(json.decode(json.encode(value)), value)
+    end,
+
+    string_keys_are_objects_and_sequences_are_arrays = function()
+        test.assert_eq(json.encode({ 1, 2, 3 }), "[1,2,3]")
+        test.assert_eq(json.encode({ a = 1 }), '{"a":1}')
+        test.assert_eq(json.decode('{"list":[true,false]}'), { list = { true, false } })
+    end,
+
+    nil_values_are_left_out = function()
+        test.assert_eq(json.encode({ a = 1, b = nil }), '{"a":1}')
+        -- null comes back as nil, so the key isn't there at all
+        test.assert_eq(json.decode('{"a":1,"b":null}'), { a = 1 })
+    end,
+
+    malformed_json_is_an_error = function()
+        for _, text in ipairs({ "{", '{"a":}', "[1,2", "" }) do
+            local ok = pcall(json.decode, text)
+            test.assert(not ok, "decoded " .. text)
+        end
+    end,
+})
\ No newline at end of file
@@ -0,0 +1,39 @@
+-- keyboard.hold repeats the key until keyboard.unhold, run with:
+--   handcake --test --lua-path examples --script tests/lua/key_repeat.test.lua
+
+local helpers = require("helpers")
+local keys = require("keys")
+
+test.run({
+    held_key_repeats = function(_, recorded_events)
+        keyboard.set_repeat(50, 25)
+        keyboard.hold(keys.A)
+        test.advance(100)
+        -- Pressed, then repeats (value 2) at 50, 75 and 100 ms
+        test.assert_eq(helpers.values(recorded_events, helpers.EV_KEY, keys.A), { 1, 2, 2, 2 })
+
+        keyboard.unhold(keys.A)
+        test.advance(100)
+        test.assert_eq(helpers.values(recorded_events, helpers.EV_KEY, keys.A), { 1, 2, 2, 2, 0 }, "no repeats after unhold")
+    end,
+
+    nothing_before_the_delay = function(_, recorded_events)
+        keyboard.set_repeat(250, 33)
+        keyboard.hold(keys.S)
+        test.advance(200)
+        keyboard.unhold(keys.S)
+        test.assert_eq(helpers.values(recorded_events, helpers.EV_KEY, keys.S), { 1, 0 })
+    end,
+
+    release_all_stops_repeating = function(_, recorded_events)
+        keyboard.set_repeat(10, 10)
+        keyboard.hold(keys.D)
+        keyboard.release_all()
+        test.advance(50)
+        test.assert_eq(helpers.values(recorded_events, helpers.EV_KEY, keys.D), { 1, 0 })
+    end,
+
+    zero_period = function()
+        test.assert(not pcall(keyboard.set_repeat, 100, 0))
+    end,
+})
\ No newline at end of file
@@ -0,0 +1,36 @@
+-- Tests for keyboard_typing.lua, run with:
+--   handcake --test --lua-path examples --script tests/lua/keyboard_typing.test.lua
+
+require("keyboard_typing")
+local helpers = require("helpers")
+local keys = require("keys")
+
+test.run({
+    types_hello_on_note_on = function(inject, recorded_events)
+        inject({ event = "note_on", channel = 1, key = 60, vel = 100 })
+        test.assert_eq(helpers.presses(recorded_events), { keys.H, keys.E, keys.L, keys.L, keys.O })
+    end,
+
+    releases_every_key = function(inject, recorded_events)
+        inject({ event = "note_on", channel = 1, key = 60, vel = 100 })
+        local releases = 0
+        for _, evt in ipairs(recorded_events()) do
+            if evt.type == 1 and evt.value == 0 then
+                releases = releases + 1
+            end
+        end
+        test.assert_eq(releases, 5)
+    end,
+
+    press_and_release_are_separate = function(_, recorded_events)
+        keyboard.press(keys.SPACE)
+        test.assert_eq(helpers.values(recorded_events, helpers.EV_KEY, keys.SPACE), { 1 }, "still held")
+        keyboard.release(keys.SPACE)
+        test.assert_eq(helpers.values(recorded_events, helpers.EV_KEY, keys.SPACE), { 1, 0 })
+    end,
+
+    ignores_note_off = function(inject, recorded_events)
+        inject({ event = "note_off", channel = 1, key = 60 })
+        test.assert_eq(#recorded_events(), 0, "note off shouldn't type anything")
+    end,
+})
\ No newline at end of file
@@ -0,0 +1,27 @@
+-- Tests for layers.lua, run with:
+--   handcake --test --lua-path examples --script tests/lua/layers.test.lua
+
+require("layers")
+local helpers = require("helpers")
+local keys = require("keys")
+
+test.run({
+    program_change_switches_layer = function(inject)
+        midi.set_auto_layer(4)
+        local notes = helpers.capture(midi.on_note, function()
+            inject({ event = "program_change", channel = 1, program = 3 })
+            test.assert_eq(midi.get_layer(), 3)
+            inject({ event = "note_on", channel = 1, key = 60 })
+        end)
+        test.assert_eq(notes[1].layer, 3)
+        midi.set_auto_layer(2)
+    end,
+
+    pads_follow_the_layer = function(inject, recorded_events)
+        inject({ event = "program_change", channel = 1, program = 0 })
+        inject({ event = "note_on", channel = 10, key = 36 })
+        inject({ event = "program_change", channel = 1, program = 1 })
+        inject({ event = "note_on", channel = 10, key = 36 })
+        test.assert_eq(helpers.presses(recorded_events), { keys.LEFT, keys.A })
+    end,
+})
\ No newline at end of file
@@ -0,0 +1,34 @@
+-- Tests for led_blink.lua, run with:
+--   handcake --test --lua-path examples --script tests/lua/led_blink.test.lua
+
+require("led_blink")
+
+test.run({
+    blinks_while_held = function(inject, _, recorded_midi)
+        inject({ event = "note_on", key = 36 })
+        test.advance(1000)
+        inject({ event = "note_off", key = 36 })
+        test.assert_eq(#recorded_midi(), 8, "flashes at 250, 500, 750 and 1000ms, the last one cut short by the note off")
+        test.assert_eq(recorded_midi()[1], { 0x90, 36, 127 })
+        test.assert_eq(recorded_midi()[2], { 0x80, 36, 0 })
+    end,
+
+    flash_is_half_the_period = function(inject, _, recorded_midi)
+        inject({ event = "note_on", key = 36 })
+        test.advance(250)
+        test.assert_eq(#recorded_midi(), 1, "lit")
+        test.advance(124)
+        test.assert_eq(#recorded_midi(), 1, "still lit")
+        test.advance(1)
+        test.assert_eq(#recorded_midi(), 2, "off again")
+        inject({ event = "note_off", key = 36 })
+    end,
+
+    stops_on_note_off = function(inject, _, recorded_midi)
+        inject({ event = "note_on", key = 36 })
+        test.advance(250)
+        inject({ event = "note_off", key = 36 })
+        test.advance(500)
+        test.assert_eq(#recorded_midi(), 2, "one flash cut short by the note off, then nothing")
+    end,
+})
\ No newline at end of file
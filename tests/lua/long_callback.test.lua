@@ -0,0 +1,40 @@
+-- Tests for long_callback.lua, run with:
+--   handcake --test --lua-path examples --script tests/lua/long_callback.test.lua
+
+require("long_callback")
+local helpers = require("helpers")
+local keys = require("keys")
+
+test.run({
+    phrase_is_found = function(inject, recorded_events)
+        -- The same as PHRASES[4321], which is well past the first yield
+        for _, key in ipairs({ 60 + 4321 % 12, 62 + 4321 % 7, 64 + 4321 % 5 }) do
+            inject({ event = "note_on", key = key })
+        end
+        test.assert_eq(helpers.presses(recorded_events), { keys.SPACE })
+    end,
+
+    no_phrase = function(inject, recorded_events)
+        for _ = 1, 3 do
+            inject({ event = "note_on", key = 20 })
+        end
+        test.assert_eq(helpers.presses(recorded_events), {})
+    end,
+
+    not_stopping = function()
+        test.assert_eq(misc.check_signals(), false)
+    end,
+
+    yield_now_anywhere = function(inject)
+        -- Velocity curves can't wait, so this has to do nothing instead of failing the note
+        midi.set_velocity_curve(function(v)
+            misc.yield_now()
+            return v
+        end)
+        local notes = helpers.capture(midi.on_note, function()
+            inject({ event = "note_on", key = 20, vel = 90 })
+        end)
+        midi.set_velocity_curve(nil)
+        test.assert_eq(notes[1].vel, 90)
+    end,
+})
\ No newline at end of file
@@ -0,0 +1,84 @@
+-- What on_midi_recv gets for each kind of message, run with:
+--   handcake --test --lua-path examples --script tests/lua/midi_events.test.lua
+
+local helpers = require("helpers")
+
+local received = {}
+
+function on_midi_recv(evt)
+    table.insert(received, evt)
+end
+
+-- Everything on_midi_recv got while f ran
+local function receive(f)
+    received = {}
+    f()
+    return received
+end
+
+test.run({
+    poly_aftertouch_has_key_and_value = function(inject)
+        local seen = receive(function()
+            inject({ event = "poly_aftertouch", channel = 3, key = 64, value = 90 })
+        end)
+        test.assert_eq(#seen, 1)
+        test.assert_eq(seen[1].event, "poly_aftertouch")
+        test.assert_eq({ seen[1].channel, seen[1].key, seen[1].value }, { 3, 64, 90 })
+    end,
+
+    channel_pressure_has_no_key = function(inject)
+        local seen = receive(function()
+            inject({ event = "channel_pressure", channel = 2, value = 70 })
+        end)
+        test.assert_eq(#seen, 1)
+        test.assert_eq(seen[1].event, "channel_pressure")
+        test.assert_eq({ seen[1].channel, seen[1].value }, { 2, 70 })
+        test.assert(seen[1].key == nil, "channel pressure is for the whole channel")
+    end,
+
+    sysex_data_is_the_whole_message = function(inject)
+        local seen = receive(function()
+            inject({ event = "sysex", data = { 0x41, 0x10, 0x42, 0x12, 0x7F } })
+        end)
+        test.assert_eq(#seen, 1)
+        test.assert_eq(seen[1].event, "sysex")
+        test.assert_eq(seen[1].data, { 0xF0, 0x41, 0x10, 0x42, 0x12, 0x7F, 0xF7 })
+    end,
+
+    sysex_too_short_for_its_header_is_dropped = function(inject)
+        local seen = receive(function()
+            inject({ event = "sysex", data = { 0x7E } })
+            inject({ event = "sysex", data = { 0x7E, 0x01, 0x02 } })
+        end)
+        test.assert_eq(#seen, 0)
+    end,
+
+    transpose_moves_notes = function(inject)
+        midi.set_transpose(3)
+        local seen = receive(function()
+            helpers.tap(inject, 1, 60)
+        end)
+        test.assert_eq(helpers.fields(seen, "event", "key"), { { "note_on", 63 }, { "note_off", 63 } })
+
+        midi.set_octave(-1)
+        test.assert_eq({ midi.get_transpose() }, { 3, -1 })
+        seen = receive(function()
+            inject({ event = "note_on", key = 60 })
+            -- The note off goes where its note on went, even once the transpose changes
+            midi.set_transpose(0)
+            inject({ event = "note_off", key = 60 })
+        end)
+        test.assert_eq(helpers.fields(seen, "event", "key"), { { "note_on", 51 }, { "note_off", 51 } })
+        midi.set_octave(0)
+    end,
+
+    transposed_out_of_range_is_dropped = function(inject)
+        midi.set_transpose(10)
+        local seen = receive(function()
+            helpers.tap(inject, 1, 120)
+            helpers.tap(inject, 1, 117)
+        end)
+        midi.set_transpose(0)
+        test.assert_eq(helpers.fields(seen, "event", "key"), { { "note_on", 127 }, { "note_off", 127 } })
+    end,
+})
\ No newline at end of file
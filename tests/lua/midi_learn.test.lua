@@ -0,0 +1,36 @@
+-- Tests for midi_learn.lua, run with:
+--   handcake --test --lua-path examples --script tests/lua/midi_learn.test.lua
+
+require("midi_learn")
+local helpers = require("helpers")
+local keys = require("keys")
+
+test.run({
+    -- Runs first, while the learn from on_script_init is still waiting
+    a_first_pad_is_learned = function(inject, recorded_events)
+        inject({ event = "note_on", key = 40 })
+        test.assert_eq(#recorded_events(), 0, "the learned note shouldn't reach on_midi_recv too")
+        inject({ event = "note_off", key = 40 })
+        helpers.tap(inject, 1, 41)
+        helpers.tap(inject, 1, 40)
+        test.assert_eq(helpers.presses(recorded_events), { keys.SPACE })
+    end,
+
+    learn_takes_the_next_event_only = function(inject)
+        local seen = {}
+        midi.learn(function(evt)
+            table.insert(seen, evt)
+        end)
+        inject({ event = "control_change", channel = 1, control = 7, value = 64 })
+        inject({ event = "control_change", channel = 1, control = 8, value = 64 })
+        test.assert_eq(helpers.fields(seen, "event", "control"), { { "control_change", 7 } })
+    end,
+
+    learning_again_replaces_the_callback = function(inject)
+        local first, second = 0, 0
+        midi.learn(function() first = first + 1 end)
+        midi.learn(function() second = second + 1 end)
+        inject({ event = "program_change", program = 3 })
+        test.assert_eq({ first, second }, { 0, 1 })
+    end,
+})
\ No newline at end of file
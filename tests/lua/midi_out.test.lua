@@ -0,0 +1,21 @@
+-- What midi_out sends, byte for byte, run with:
+--   handcake --test --lua-path examples --script tests/lua/midi_out.test.lua
+
+test.run({
+    sysex_is_wrapped_in_f0_and_f7 = function(_, _, recorded_midi)
+        midi_out.sysex({ 0x7E, 0x7F, 0x06, 0x01 })
+        test.assert_eq(recorded_midi(), { { 0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7 } })
+    end,
+
+    sysex_raw_is_sent_as_is = function(_, _, recorded_midi)
+        midi_out.sysex_raw({ 0xF0, 0x41, 0x10, 0xF7 })
+        test.assert_eq(recorded_midi(), { { 0xF0, 0x41, 0x10, 0xF7 } })
+    end,
+
+    sysex_rejects_bytes_over_127 = function(_, _, recorded_midi)
+        local ok, err = pcall(midi_out.sysex, { 0x41, 0x80 })
+        test.assert(not ok, "0x80 isn't a data byte")
+        test.assert(tostring(err):find("SysEx byte 2 is 128", 1, true), tostring(err))
+        test.assert_eq(recorded_midi(), {}, "nothing sent")
+    end,
+})
\ No newline at end of file
@@ -0,0 +1,41 @@
+-- Tests for midi_thru.lua, run with:
+--   handcake --test --lua-path examples --script tests/lua/midi_thru.test.lua
+
+require("midi_thru")
+local helpers = require("helpers")
+local keys = require("keys")
+
+test.run({
+    note_on_goes_through = function(inject, _, recorded_midi)
+        midi.set_thru_filter(0xFFFF)
+        inject({ event = "note_on", channel = 1, key = 60, vel = 100 })
+        test.assert_eq(recorded_midi(), { { 0x90, 60, 100 } })
+        midi.set_thru_filter(0x0003)
+    end,
+
+    filter_drops_other_channels = function(inject, _, recorded_midi)
+        inject({ event = "note_on", channel = 2, key = 62, vel = 90 })
+        inject({ event = "note_on", channel = 3, key = 64 })
+        inject({ event = "program_change", channel = 1, program = 5 })
+        test.assert_eq(recorded_midi(), { { 0x91, 62, 90 }, { 0xC0, 5 } })
+    end,
+
+    script_still_sees_everything = function(inject, recorded_events)
+        inject({ event = "note_on", channel = 3, key = 60 })
+        test.assert_eq(helpers.events(recorded_events, helpers.EV_KEY)[1], { keys.SPACE, 1 })
+    end,
+
+    sysex_always_goes_through = function(inject, _, recorded_midi)
+        midi.set_thru_filter(0)
+        inject({ event = "sysex", data = { 0x7E, 0x7F, 0x06, 0x01 } })
+        test.assert_eq(recorded_midi(), { { 0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7 } })
+        midi.set_thru_filter(0x0003)
+    end,
+
+    thru_off = function(inject, _, recorded_midi)
+        midi.set_thru(false)
+        inject({ event = "note_on", channel = 1, key = 60 })
+        midi.set_thru(true)
+        test.assert_eq(recorded_midi(), {})
+    end,
+})
\ No newline at end of file
@@ -0,0 +1,60 @@
+-- Tests for mpe_sticks.lua, run with:
+--   handcake --test --lua-path examples --script tests/lua/mpe_sticks.test.lua
+
+require("mpe_sticks")
+local helpers = require("helpers")
+
+-- Every note on_mpe_note gets while f runs, instead of the example's
+local function mpe_notes(f)
+    return helpers.capture_global("on_mpe_note", f)
+end
+
+test.run({
+    note_and_bend_come_together = function(inject)
+        local seen = mpe_notes(function()
+            inject({ event = "note_on", channel = 3, key = 60, vel = 100 })
+            inject({ event = "pitch_bend", channel = 3, value = 12000 })
+        end)
+        test.assert_eq(#seen, 2)
+        test.assert_eq(seen[2], {
+            channel = 3, key = 60, vel = 100, pressure = 0, slide = 64, pitch_bend = 12000, held = true,
+        })
+        inject({ event = "note_off", channel = 3, key = 60 })
+    end,
+
+    expression_before_the_note_is_kept = function(inject)
+        local seen = mpe_notes(function()
+            inject({ event = "channel_pressure", channel = 4, value = 30 })
+            inject({ event = "control_change", channel = 4, control = 74, value = 90 })
+            inject({ event = "note_on", channel = 4, key = 62, vel = 80 })
+            inject({ event = "note_off", channel = 4, key = 62 })
+        end)
+        test.assert_eq(#seen, 2, "nothing until the note on")
+        test.assert_eq({ seen[1].pressure, seen[1].slide, seen[1].held }, { 30, 90, true })
+        test.assert_eq({ seen[2].key, seen[2].held }, { 62, false })
+    end,
+
+    main_channel_is_not_a_note = function(inject)
+        local seen = mpe_notes(function()
+            inject({ event = "note_on", channel = 1, key = 60 })
+            inject({ event = "pitch_bend", channel = 1, value = 0 })
+            inject({ event = "note_off", channel = 1, key = 60 })
+        end)
+        test.assert_eq(seen, {})
+    end,
+
+    steering_finger_moves_the_stick = function(inject, recorded_events)
+        inject({ event = "note_on", channel = 2, key = 60, vel = 100 })
+        inject({ event = "channel_pressure", channel = 2, value = 127 })
+        inject({ event = "note_off", channel = 2, key = 60 })
+        local events = helpers.events(recorded_events, helpers.EV_ABS)
+        test.assert_eq(events[#events - 3], { gamepad.ABS.RZ, gamepad.TRIGGER_MAX })
+        test.assert_eq(events[#events], { gamepad.ABS.RZ, 0 }, "letting go should let go of the trigger")
+    end,
+
+    invalid_zone = function()
+        test.assert(not pcall(midi.enable_mpe, 1, 1, 16), "main channel inside the zone")
+        test.assert(not pcall(midi.enable_mpe, 16, 15, 2))
+        test.assert(not pcall(midi.enable_mpe, 1, 2, 17))
+    end,
+})
\ No newline at end of file
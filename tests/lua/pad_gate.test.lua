@@ -0,0 +1,55 @@
+-- Tests for pad_gate.lua, run with:
+--   handcake --test --lua-path examples --script tests/lua/pad_gate.test.lua
+
+require("pad_gate")
+local helpers = require("helpers")
+
+-- Every note the script is given while f runs, on_midi_recv still gets them too
+local function received(f)
+    return helpers.fields(helpers.capture(midi.on_note, f), "event", "vel")
+end
+
+test.run({
+    soft_note_never_reaches_script = function(inject, recorded_events)
+        local seen = received(function()
+            inject({ event = "note_on", channel = 10, key = 36, vel = 50 })
+            inject({ event = "note_off", channel = 10, key = 36 })
+        end)
+        test.assert_eq(seen, {})
+        test.assert_eq(helpers.events(recorded_events, helpers.EV_KEY), {})
+    end,
+
+    hard_note_reaches_script = function(inject, recorded_events)
+        local seen = received(function()
+            inject({ event = "note_on", channel = 10, key = 36, vel = 80 })
+            inject({ event = "note_off", channel = 10, key = 36 })
+        end)
+        test.assert_eq(seen, { { "note_on", 127 }, { "note_off", 0 } })
+        test.assert_eq(helpers.events(recorded_events, helpers.EV_KEY), { { gamepad.BTN.SOUTH, 1 }, { gamepad.BTN.SOUTH, 0 } })
+    end,
+
+    other_channels_are_untouched = function(inject)
+        local seen = received(function()
+            inject({ event = "note_on", channel = 1, key = 36, vel = 20 })
+        end)
+        test.assert_eq(seen, { { "note_on", 20 } })
+    end,
+
+    relative_stretches_velocity = function(inject)
+        midi.set_note_threshold(2, 27)
+        midi.set_note_velocity_mode("relative", 2)
+        local seen = received(function()
+            inject({ event = "note_on", channel = 2, key = 60, vel = 27 })
+            inject({ event = "note_on", channel = 2, key = 62, vel = 77 })
+            inject({ event = "note_on", channel = 2, key = 64, vel = 127 })
+        end)
+        midi.set_note_threshold(2, 0)
+        midi.set_note_velocity_mode("normal", 2)
+        test.assert_eq(seen, { { "note_on", 1 }, { "note_on", 64 }, { "note_on", 127 } })
+    end,
+
+    invalid_mode = function()
+        test.assert(not pcall(midi.set_note_velocity_mode, "loud"))
+        test.assert(not pcall(midi.set_note_threshold, 17, 64))
+    end,
+})
\ No newline at end of file
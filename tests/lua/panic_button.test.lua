@@ -0,0 +1,47 @@
+-- Tests for panic_button.lua, run with:
+--   handcake --test --lua-path examples --script tests/lua/panic_button.test.lua
+
+require("panic_button")
+local helpers = require("helpers")
+local keys = require("keys")
+
+local function key_events(recorded_events)
+    return helpers.events(recorded_events, helpers.EV_KEY)
+end
+
+test.run({
+    panic_releases_whatever_is_down = function(inject, recorded_events)
+        inject({ event = "note_on", key = 60 })
+        inject({ event = "note_on", key = 62 })
+        inject({ event = "note_on", key = 99 })
+        test.assert_eq(key_events(recorded_events), {
+            { keys.SPACE, 1 },
+            { gamepad.BTN.SOUTH, 1 },
+            { keys.SPACE, 0 },
+            { gamepad.BTN.SOUTH, 0 },
+        })
+    end,
+
+    nothing_down_nothing_sent = function(inject, recorded_events)
+        inject({ event = "note_on", key = 99 })
+        test.assert_eq(key_events(recorded_events), {})
+    end,
+
+    held_keys_stop_repeating = function(_, recorded_events)
+        keyboard.hold(keys.A)
+        keyboard.release_all()
+        test.assert_eq(key_events(recorded_events), { { keys.A, 1 }, { keys.A, 0 } })
+        -- Already released, so unhold has nothing to do
+        keyboard.unhold(keys.A)
+        test.assert_eq(#key_events(recorded_events), 2)
+    end,
+
+    midi_panic_silences_every_channel = function(_, _, recorded_midi)
+        midi.panic()
+        local sent = recorded_midi()
+        test.assert_eq(#sent, 32)
+        test.assert_eq(sent[1], { 0xB0, 123, 0 })
+        test.assert_eq(sent[2], { 0xB0, 121, 0 })
+        test.assert_eq(sent[32], { 0xBF, 121, 0 })
+    end,
+})
\ No newline at end of file
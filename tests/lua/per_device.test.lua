@@ -0,0 +1,51 @@
+-- Tests for per_device.lua, run with:
+--   handcake --test --lua-path examples --script tests/lua/per_device.test.lua
+
+require("per_device")
+local helpers = require("helpers")
+local keys = require("keys")
+
+test.run({
+    each_device_has_its_own_handler = function(inject, recorded_events)
+        inject({ event = "note_on", key = 11, device = "Launchpad Mini MK3 LPMiniMK3 MIDI" })
+        inject({ event = "note_on", key = 60, device = "KeyStep" })
+        -- Same note, different device
+        inject({ event = "note_on", key = 60, device = "Launchpad Mini MK3 LPMiniMK3 MIDI" })
+        test.assert_eq(helpers.presses(recorded_events), { keys.LEFT, keys.A })
+    end,
+
+    first_match_wins = function(inject)
+        local got = {}
+        midi.on_device("Key", function()
+            table.insert(got, "Key")
+        end)
+        midi.on_device("KeyStep", function()
+            table.insert(got, "KeyStep replaced")
+        end)
+        inject({ event = "note_on", key = 60, device = "KeyStep" })
+        test.assert_eq(got, { "KeyStep replaced" })
+
+        midi.on_device("Key", nil)
+        midi.on_device("KeyStep", nil)
+        midi.on_device("%d+", function()
+            table.insert(got, "digits")
+        end)
+        inject({ event = "note_on", key = 60, device = "KeyStep" })
+        inject({ event = "note_on", key = 60, device = "Port 2" })
+        test.assert_eq(got, { "KeyStep replaced", "digits" })
+        midi.on_device("%d+", nil)
+    end,
+
+    other_devices_go_to_on_midi_recv = function(inject, recorded_events)
+        inject({ event = "note_on", key = 11, device = "Launchpad X" })
+        inject({ event = "note_on", key = 11, device = "Something else" })
+        test.assert_eq(helpers.presses(recorded_events), { keys.LEFT, keys.SPACE })
+    end,
+
+    also_global_mode = function(inject, recorded_events)
+        midi.set_device_mode("also_global")
+        inject({ event = "note_on", key = 11, device = "Launchpad X" })
+        midi.set_device_mode("exclusive")
+        test.assert_eq(helpers.presses(recorded_events), { keys.LEFT, keys.SPACE })
+    end,
+})
\ No newline at end of file
@@ -0,0 +1,31 @@
+-- Tests for queue_watch.lua, run with:
+--   handcake --test --lua-path examples --script tests/lua/queue_watch.test.lua
+
+local queue_watch = require("queue_watch")
+
+-- Nothing reads MIDI devices with --test, so the queue stays empty and the limit is
+-- moved instead to put it over or under
+test.run({
+    stats_agree_with_depth = function()
+        local stats = misc.queue_stats()
+        test.assert_eq(stats.depth, misc.queue_depth())
+        test.assert_eq(stats.dropped, 0)
+        test.assert_eq(stats.processed, 0)
+    end,
+
+    inject_skips_the_queue = function(inject)
+        local before = misc.queue_stats()
+        inject({ event = "note_on", key = 60 })
+        test.assert_eq(misc.queue_stats(), before)
+    end,
+
+    quiet_under_the_limit = function()
+        test.assert_eq(queue_watch.check_queue(misc.queue_depth() + 1), nil)
+    end,
+
+    warns_once_when_behind = function()
+        local warn_at = misc.queue_depth()
+        test.assert(queue_watch.check_queue(warn_at), "should warn once the queue gets to warn_at")
+        test.assert_eq(queue_watch.check_queue(warn_at), nil, "should only warn once")
+    end,
+})
\ No newline at end of file
@@ -0,0 +1,28 @@
+-- Run with --sandbox, which also takes require away, so this one doesn't use the helpers:
+--   handcake --test --sandbox --script tests/lua/sandbox.test.lua
+
+-- Calls f and gives back the error it should have raised
+local function denied(f)
+    local ok, err = pcall(f)
+    test.assert(not ok, "should have failed")
+    return tostring(err)
+end
+
+test.run({
+    io_open_is_denied = function()
+        test.assert(denied(function() io.open("/etc/passwd") end):find("Permission denied"))
+    end,
+
+    os_execute_is_denied = function()
+        test.assert(denied(function() os.execute("true") end):find("Permission denied"))
+    end,
+
+    require_is_denied = function()
+        test.assert(denied(function() require("keys") end):find("Permission denied"))
+    end,
+
+    everything_else_still_works = function()
+        test.assert_eq(string.format("%02d", math.floor(7.5)), "07")
+        test.assert_eq(midi.note_name(60), "C4")
+    end,
+})
\ No newline at end of file
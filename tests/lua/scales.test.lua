@@ -0,0 +1,56 @@
+-- midi.set_scale, run with:
+--   handcake --test --lua-path examples --script tests/lua/scales.test.lua
+
+local helpers = require("helpers")
+
+local notes = {}
+
+function on_midi_recv(evt)
+    if evt.is_note then
+        table.insert(notes, evt)
+    end
+end
+
+-- The keys on_midi_recv gets for note ons of each of `keys`
+local function snapped(inject, keys)
+    notes = {}
+    for _, key in ipairs(keys) do
+        helpers.tap(inject, 1, key)
+    end
+    local found = {}
+    for _, evt in ipairs(notes) do
+        if evt.event == "note_on" then
+            table.insert(found, evt.key)
+        end
+    end
+    return found
+end
+
+test.run({
+    c_major = function(inject)
+        midi.set_scale("C", "major")
+        -- Halfway between two notes of the scale goes down
+        test.assert_eq(snapped(inject, { 60, 61, 63, 64, 66, 70, 71 }), { 60, 60, 62, 64, 65, 69, 71 })
+        midi.set_scale(nil)
+    end,
+
+    pentatonic_minor_on_a = function(inject)
+        midi.set_scale("A", "pentatonic_minor")
+        test.assert_eq(snapped(inject, { 57, 58, 59, 61, 66 }), { 57, 57, 60, 60, 67 })
+        midi.set_scale(nil)
+    end,
+
+    note_off_follows_its_note_on = function(inject)
+        midi.set_scale("C", "major")
+        notes = {}
+        helpers.tap(inject, 1, 61)
+        midi.set_scale(nil)
+        test.assert_eq(helpers.fields(notes, "event", "key"), { { "note_on", 60 }, { "note_off", 60 } })
+    end,
+
+    off_and_unknown = function(inject)
+        test.assert_eq(snapped(inject, { 61 }), { 61 }, "no scale set")
+        test.assert(not pcall(midi.set_scale, "C", "klingon"))
+        test.assert(not pcall(midi.set_scale, "X", "major"))
+    end,
+})
\ No newline at end of file
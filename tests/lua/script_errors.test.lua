@@ -0,0 +1,25 @@
+-- A script that breaks on one message still gets the ones after it, run with:
+--   handcake --test --lua-path examples --script tests/lua/script_errors.test.lua
+
+local helpers = require("helpers")
+local keys = require("keys")
+
+local received = 0
+
+function on_midi_recv(evt)
+    received = received + 1
+    if received == 2 then
+        error("broken on purpose")
+    end
+    keyboard.tap(keys.SPACE)
+end
+
+test.run({
+    third_event_is_still_handled = function(inject, recorded_events)
+        for key = 60, 62 do
+            inject({ event = "note_on", key = key })
+        end
+        test.assert_eq(received, 3)
+        test.assert_eq(helpers.presses(recorded_events), { keys.SPACE, keys.SPACE })
+    end,
+})
\ No newline at end of file
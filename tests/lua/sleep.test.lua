@@ -0,0 +1,12 @@
+-- misc.sleep_ms waits in real time, test.advance or not, run with:
+--   handcake --test --lua-path examples --script tests/lua/sleep.test.lua
+
+test.run({
+    sleep_takes_at_least_that_long = function()
+        local start = misc.time()
+        misc.sleep_ms(10)
+        local slept = misc.time() - start
+        -- misc.time() is in whole milliseconds
+        test.assert(slept >= 0.009, "slept for " .. slept .. "s")
+    end,
+})
\ No newline at end of file
@@ -0,0 +1,39 @@
+-- Tests for slide_clicker.lua, run with:
+--   handcake --test --lua-path examples --script tests/lua/slide_clicker.test.lua
+
+require("slide_clicker")
+local helpers = require("helpers")
+local keys = require("keys")
+
+test.run({
+    tap_is_next_slide = function(inject, recorded_events)
+        inject({ event = "note_on", key = 36, vel = 100 })
+        test.advance(499)
+        inject({ event = "note_off", key = 36 })
+        test.assert_eq(helpers.presses(recorded_events), { keys.RIGHT })
+    end,
+
+    hold_is_previous_slide = function(inject, recorded_events)
+        inject({ event = "note_on", key = 36, vel = 100 })
+        test.advance(500)
+        -- Velocity 0 counts as a note off too
+        inject({ event = "note_on", key = 36, vel = 0 })
+        test.assert_eq(helpers.presses(recorded_events), { keys.LEFT })
+    end,
+
+    note_off_has_duration = function(inject)
+        -- Every handler gets it, not just the slide clicker's
+        local duration
+        midi.on_channel(1, function(evt)
+            duration = evt.duration_ms
+        end)
+        inject({ event = "note_on", key = 60, vel = 100 })
+        test.advance(200)
+        inject({ event = "note_off", key = 60 })
+        test.assert_eq(duration, 200)
+
+        inject({ event = "note_off", key = 61 })
+        test.assert_eq(duration, 0, "a note off without a note on")
+        midi.on_channel(1, nil)
+    end,
+})
\ No newline at end of file
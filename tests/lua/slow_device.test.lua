@@ -0,0 +1,24 @@
+-- Tests for slow_device.lua, run with:
+--   handcake --test --lua-path examples --script tests/lua/slow_device.test.lua
+
+local loaded_at = misc.time()
+require("slow_device")
+
+-- When the example's on_script_init ran, in seconds
+local init_at
+local init = on_script_init
+function on_script_init()
+    init_at = misc.time()
+    init()
+end
+
+test.run({
+    init_waits_for_the_device = function()
+        test.assert(init_at, "on_script_init should have run")
+        test.assert(init_at - loaded_at >= 0.5, "on_script_init ran after " .. (init_at - loaded_at) .. "s")
+    end,
+
+    too_late_after_init = function()
+        test.assert(not pcall(misc.set_init_delay, 100))
+    end,
+})
\ No newline at end of file
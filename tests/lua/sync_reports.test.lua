@@ -0,0 +1,36 @@
+-- Every write to a virtual device ends in a SYN_REPORT, unless it's batched, run with:
+--   handcake --test --lua-path examples --script tests/lua/sync_reports.test.lua
+
+local helpers = require("helpers")
+
+-- Event types in the order they were sent
+local function types(recorded_events)
+    local found = {}
+    for _, evt in ipairs(recorded_events()) do
+        table.insert(found, evt.type)
+    end
+    return found
+end
+
+local pad = gamepad.create()
+
+test.run({
+    one_report_per_button = function(_, recorded_events)
+        pad.button(gamepad.BTN.SOUTH, true)
+        test.assert_eq(types(recorded_events), { helpers.EV_KEY, helpers.EV_SYN })
+        test.assert_eq(helpers.events(recorded_events, helpers.EV_SYN), { { 0, 0 } }, "SYN_REPORT is code 0")
+        pad.button(gamepad.BTN.SOUTH, false)
+    end,
+
+    batch_is_one_report = function(_, recorded_events)
+        pad.begin_batch()
+        pad.axis(gamepad.ABS.X, 0.5)
+        pad.axis(gamepad.ABS.Y, -0.5)
+        test.assert_eq(types(recorded_events), { helpers.EV_ABS, helpers.EV_ABS }, "nothing synced until end_batch")
+        pad.end_batch()
+        test.assert_eq(types(recorded_events), { helpers.EV_ABS, helpers.EV_ABS, helpers.EV_SYN })
+
+        pad.end_batch()
+        test.assert_eq(#recorded_events(), 3, "end_batch without a batch sends nothing")
+    end,
+})
\ No newline at end of file
@@ -0,0 +1,26 @@
+-- Tests for tap_tempo.lua, run with:
+--   handcake --test --lua-path examples --script tests/lua/tap_tempo.test.lua
+
+require("tap_tempo")
+local helpers = require("helpers")
+
+test.run({
+    taps_500ms_apart_are_120_bpm = function(inject)
+        for i = 1, 4 do
+            if i > 1 then
+                test.advance(500)
+            end
+            helpers.tap(inject, 10, 36)
+        end
+        local bpm = midi.tap_bpm()
+        test.assert(bpm, "no tempo after 4 taps")
+        test.assert_eq(bpm, 120)
+    end,
+
+    other_notes_arent_taps = function(inject)
+        midi.tap_tempo(10, 36)
+        helpers.tap(inject, 10, 38)
+        helpers.tap(inject, 1, 36)
+        test.assert_eq(midi.tap_bpm(), nil)
+    end,
+})
\ No newline at end of file
@@ -0,0 +1,64 @@
+-- The multitouch protocol touch.down/move/up speak, run with:
+--   handcake --test --lua-path examples --script tests/lua/touch.test.lua
+
+local helpers = require("helpers")
+
+-- From linux/input-event-codes.h
+local ABS_MT_SLOT, ABS_MT_POSITION_X, ABS_MT_POSITION_Y, ABS_MT_TRACKING_ID, ABS_MT_PRESSURE = 0x2f, 0x35, 0x36, 0x39, 0x3a
+local BTN_TOUCH = 0x14a
+
+local function sent(recorded_events)
+    return helpers.fields(recorded_events(), "type", "code", "value")
+end
+
+test.run({
+    down_then_up = function(_, recorded_events)
+        touch.down(0, 960, 540, 100)
+        touch.up(0)
+        -- Whatever touched last, plus one
+        local id = helpers.values(recorded_events, helpers.EV_ABS, ABS_MT_TRACKING_ID)[1]
+        test.assert(id >= 0)
+        test.assert_eq(sent(recorded_events), {
+            { helpers.EV_ABS, ABS_MT_SLOT, 0 },
+            { helpers.EV_ABS, ABS_MT_TRACKING_ID, id },
+            { helpers.EV_ABS, ABS_MT_POSITION_X, 960 },
+            { helpers.EV_ABS, ABS_MT_POSITION_Y, 540 },
+            { helpers.EV_ABS, ABS_MT_PRESSURE, 100 },
+            { helpers.EV_KEY, BTN_TOUCH, 1 },
+            { helpers.EV_SYN, 0, 0 },
+            { helpers.EV_ABS, ABS_MT_SLOT, 0 },
+            { helpers.EV_ABS, ABS_MT_TRACKING_ID, -1 },
+            { helpers.EV_KEY, BTN_TOUCH, 0 },
+            { helpers.EV_SYN, 0, 0 },
+        })
+    end,
+
+    second_finger_keeps_btn_touch = function(_, recorded_events)
+        touch.down(0, 100, 100)
+        touch.down(1, 200, 200)
+        touch.up(0)
+        test.assert_eq(helpers.values(recorded_events, helpers.EV_KEY, BTN_TOUCH), { 1 }, "still touching until the last finger goes")
+        touch.up(1)
+        test.assert_eq(helpers.values(recorded_events, helpers.EV_KEY, BTN_TOUCH), { 1, 0 })
+        -- Every touch gets a new tracking ID
+        local ids = helpers.values(recorded_events, helpers.EV_ABS, ABS_MT_TRACKING_ID)
+        test.assert_eq(ids, { ids[1], ids[1] + 1, -1, -1 })
+    end,
+
+    positions_are_clamped = function(_, recorded_events)
+        touch.down(0, -5, 5000)
+        touch.move(0, touch.width + 10, 0, 50)
+        touch.up(0)
+        test.assert_eq(helpers.values(recorded_events, helpers.EV_ABS, ABS_MT_POSITION_X), { 0, 1919 })
+        test.assert_eq(helpers.values(recorded_events, helpers.EV_ABS, ABS_MT_POSITION_Y), { 1079, 0 })
+        test.assert_eq(helpers.values(recorded_events, helpers.EV_ABS, ABS_MT_PRESSURE), { 255, 50 })
+    end,
+
+    bad_ids = function()
+        test.assert(not pcall(touch.down, 10, 0, 0), "only 10 slots")
+        test.assert(not pcall(touch.move, 0, 0, 0), "not down")
+        touch.down(0, 0, 0)
+        test.assert(not pcall(touch.down, 0, 0, 0), "already down")
+        touch.up(0)
+    end,
+})
\ No newline at end of file
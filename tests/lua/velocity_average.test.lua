@@ -0,0 +1,38 @@
+-- Tests for velocity_average.lua, run with:
+--   handcake --test --lua-path examples --script tests/lua/velocity_average.test.lua
+
+require("velocity_average")
+local helpers = require("helpers")
+
+test.run({
+    get_is_newest_first = function()
+        local buf = buffer.new(3)
+        for i = 1, 5 do
+            buf:push(i)
+        end
+        test.assert_eq(buf:get(1), 5)
+        test.assert_eq(buf:get(3), 3)
+        test.assert_eq(buf:get(4), nil)
+        test.assert_eq(buf:to_table(), { 5, 4, 3 })
+        test.assert_eq(buf:size(), 3)
+    end,
+
+    stats = function()
+        local buf = buffer.new(4)
+        test.assert_eq(buf:average(), nil)
+        test.assert_eq(buf:sum(), 0)
+        for _, value in ipairs({ 2, -1, 7, 4 }) do
+            buf:push(value)
+        end
+        test.assert_eq(buf:sum(), 12)
+        test.assert_eq(buf:average(), 3)
+        test.assert_eq(buf:min(), -1)
+        test.assert_eq(buf:max(), 7)
+    end,
+
+    trigger_follows_the_average = function(inject, recorded_events)
+        inject({ event = "note_on", key = 60, vel = 127 })
+        inject({ event = "note_on", key = 62, vel = 1 })
+        test.assert_eq(helpers.values(recorded_events, helpers.EV_ABS, gamepad.ABS.RZ), { 255, 128 })
+    end,
+})
\ No newline at end of file
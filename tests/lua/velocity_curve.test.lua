@@ -0,0 +1,54 @@
+-- midi.set_velocity_curve, run with:
+--   handcake --test --lua-path examples --script tests/lua/velocity_curve.test.lua
+
+local helpers = require("helpers")
+
+local notes = {}
+
+function on_midi_recv(evt)
+    if evt.is_note then
+        table.insert(notes, evt)
+    end
+end
+
+-- The velocity of every note on_midi_recv got while f ran
+local function velocities(f)
+    notes = {}
+    f()
+    return helpers.fields(notes, "event", "vel")
+end
+
+test.run({
+    function_curve_inverts = function(inject)
+        midi.set_velocity_curve(function(vel) return 127 - vel end)
+        local seen = velocities(function()
+            inject({ event = "note_on", key = 60, vel = 100 })
+            inject({ event = "note_off", key = 60, vel = 64 })
+        end)
+        midi.set_velocity_curve(nil)
+        test.assert_eq(seen, { { "note_on", 27 }, { "note_off", 63 } })
+    end,
+
+    table_curve_is_looked_up = function(inject)
+        local halved = {}
+        for vel = 0, 127 do
+            halved[vel + 1] = vel // 2
+        end
+        midi.set_velocity_curve(halved)
+        local seen = velocities(function()
+            inject({ event = "note_on", key = 60, vel = 100 })
+        end)
+        midi.set_velocity_curve(nil)
+        test.assert_eq(seen, { { "note_on", 50 } })
+    end,
+
+    curve_for_one_channel = function(inject)
+        midi.set_velocity_curve(function() return 127 end, 2)
+        local seen = velocities(function()
+            inject({ event = "note_on", channel = 1, key = 60, vel = 40 })
+            inject({ event = "note_on", channel = 2, key = 60, vel = 40 })
+        end)
+        midi.set_velocity_curve(nil, 2)
+        test.assert_eq(seen, { { "note_on", 40 }, { "note_on", 127 } })
+    end,
+})
\ No newline at end of file
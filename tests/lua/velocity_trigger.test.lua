@@ -0,0 +1,46 @@
+-- Tests for velocity_trigger.lua, run with:
+--   handcake --test --lua-path examples --script tests/lua/velocity_trigger.test.lua
+
+require("velocity_trigger")
+local helpers = require("helpers")
+
+local function axis_events(recorded_events)
+    return helpers.events(recorded_events, helpers.EV_ABS)
+end
+
+test.run({
+    trigger_sends_pressure = function(_, recorded_events)
+        local pad = gamepad.create()
+        pad.trigger("left", 128)
+        pad.trigger("right", 300)
+        pad.trigger("right", -5)
+        test.assert_eq(axis_events(recorded_events), {
+            { gamepad.ABS.Z, 128 },
+            { gamepad.ABS.RZ, gamepad.TRIGGER_MAX },
+            { gamepad.ABS.RZ, 0 },
+        })
+        -- Kept as a float, so not exactly 128 / 255
+        test.assert(math.abs(pad.axis_state(gamepad.ABS.Z) - 128 / 255) < 0.001)
+    end,
+
+    axis_on_a_trigger_is_0_to_1 = function(_, recorded_events)
+        local pad = gamepad.create()
+        pad.axis(gamepad.ABS.Z, 1.0)
+        pad.axis(gamepad.ABS.RZ, -1.0)
+        test.assert_eq(axis_events(recorded_events), {
+            { gamepad.ABS.Z, 255 },
+            { gamepad.ABS.RZ, 0 },
+        })
+    end,
+
+    velocity_is_pressure = function(inject, recorded_events)
+        inject({ event = "note_on", key = 60, vel = 127 })
+        inject({ event = "note_on", key = 62, vel = 64 })
+        inject({ event = "note_off", key = 60 })
+        test.assert_eq(axis_events(recorded_events), {
+            { gamepad.ABS.RZ, 255 },
+            { gamepad.ABS.Z, 128 },
+            { gamepad.ABS.RZ, 0 },
+        })
+    end,
+})
\ No newline at end of file
@@ -0,0 +1,222 @@
+// Runs the Lua test suites in tests/lua/ through handcake --test, which doesn't
+// need uinput or any MIDI hardware. They require the examples they test, so
+// examples/ goes on the search path.
+
+use std::process::Command;
+
+fn run_suite(name: &str) {
+    run_suite_with(name, &[]);
+}
+
+fn run_suite_with(name: &str, args: &[&str]) {
+    passes(suite(name).args(args));
+}
+
+fn suite(name: &str) -> Command {
+    let script = format!("{}/tests/lua/{}.test.lua", env!("CARGO_MANIFEST_DIR"), name);
+    let examples = format!("{}/examples", env!("CARGO_MANIFEST_DIR"));
+    let mut command = Command::new(env!("CARGO_BIN_EXE_handcake"));
+    command.args(["--test", "--lua-path", &examples, "--script", &script]);
+    command
+}
+
+fn passes(command: &mut Command) {
+    let output = command.output().expect("Could not run handcake");
+
+    assert!(
+        output.status.success(),
+        "{}\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr),
+    );
+}
+
+#[test]
+fn keyboard_typing() {
+    run_suite("keyboard_typing");
+}
+
+#[test]
+fn tap_tempo() {
+    run_suite("tap_tempo");
+}
+
+#[test]
+fn layers() {
+    run_suite("layers");
+}
+
+#[test]
+fn slide_clicker() {
+    run_suite("slide_clicker");
+}
+
+#[test]
+fn chord_button() {
+    run_suite("chord_button");
+}
+
+#[test]
+fn per_device() {
+    run_suite("per_device");
+}
+
+#[test]
+fn footswitch() {
+    run_suite("footswitch");
+}
+
+#[test]
+fn velocity_trigger() {
+    run_suite("velocity_trigger");
+}
+
+#[test]
+fn cc_stick() {
+    run_suite("cc_stick");
+}
+
+#[test]
+fn velocity_average() {
+    run_suite("velocity_average");
+}
+
+#[test]
+fn identify() {
+    run_suite("identify");
+}
+
+#[test]
+fn panic_button() {
+    run_suite("panic_button");
+}
+
+#[test]
+fn midi_thru() {
+    run_suite("midi_thru");
+}
+
+#[test]
+fn pad_gate() {
+    run_suite("pad_gate");
+}
+
+#[test]
+fn queue_watch() {
+    run_suite("queue_watch");
+}
+
+#[test]
+fn slow_device() {
+    run_suite("slow_device");
+}
+
+#[test]
+fn long_callback() {
+    run_suite("long_callback");
+}
+
+#[test]
+fn mpe_sticks() {
+    run_suite("mpe_sticks");
+}
+
+#[test]
+fn grab_keyboard() {
+    run_suite("grab_keyboard");
+}
+
+#[test]
+fn auto_release() {
+    run_suite("auto_release");
+}
+
+#[test]
+fn led_blink() {
+    run_suite("led_blink");
+}
+
+#[test]
+fn sandbox() {
+    run_suite_with("sandbox", &["--sandbox"]);
+}
+
+#[test]
+fn script_errors() {
+    run_suite("script_errors");
+}
+
+#[test]
+fn midi_events() {
+    run_suite("midi_events");
+}
+
+#[test]
+fn midi_learn() {
+    run_suite("midi_learn");
+}
+
+#[test]
+fn environment() {
+    passes(suite("environment").env("HANDCAKE_TEST_VAR", "hello").env_remove("HANDCAKE_UNSET_VAR"));
+}
+
+#[test]
+fn sync_reports() {
+    run_suite("sync_reports");
+}
+
+#[test]
+fn midi_out() {
+    run_suite("midi_out");
+}
+
+#[test]
+fn json() {
+    run_suite("json");
+}
+
+#[test]
+fn channel_filter() {
+    run_suite_with("channel_filter", &["--arg", "channel=2"]);
+}
+
+#[test]
+fn sleep() {
+    run_suite("sleep");
+}
+
+#[test]
+fn velocity_curve() {
+    run_suite("velocity_curve");
+}
+
+#[test]
+fn cc_smoothing() {
+    run_suite("cc_smoothing");
+}
+
+#[test]
+fn chords() {
+    run_suite("chords");
+}
+
+#[test]
+fn scales() {
+    run_suite("scales");
+}
+
+#[test]
+fn arpeggiator() {
+    run_suite("arpeggiator");
+}
+
+#[test]
+fn touch() {
+    run_suite("touch");
+}
+
+#[test]
+fn key_repeat() {
+    run_suite("key_repeat");
+}
\ No newline at end of file
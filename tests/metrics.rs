@@ -0,0 +1,55 @@
+// --metrics-port serves every metric once there's something to count
+
+use std::{io::{Read, Write}, net::{TcpListener, TcpStream}, process::{Command, Stdio}, time::{Duration, Instant}};
+
+fn get_metrics(port: u16) -> Option<String> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).ok()?;
+    stream.write_all(b"GET /metrics HTTP/1.0\r\n\r\n").ok()?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).ok()?;
+    Some(response)
+}
+
+#[test]
+fn metrics_are_served() {
+    let dir = std::env::temp_dir().join(format!("handcake-metrics-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (replay, script) = (dir.join("in.jsonl"), dir.join("error.lua"));
+    // The second note keeps the replay going while the metrics are fetched
+    std::fs::write(&replay, [
+        r#"{"time":"2024-01-01T12:00:00.000000Z","device":"keys","data":[144,60,100]}"#,
+        r#"{"time":"2024-01-01T12:01:00.000000Z","device":"keys","data":[144,60,100]}"#,
+    ].join("\n")).unwrap();
+    std::fs::write(&script, "function on_midi_recv(evt) error(\"counted\") end").unwrap();
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_handcake"))
+        .args(["--dry-run", "--metrics-port", &port.to_string(), "--script"])
+        .arg(&script)
+        .arg("--replay")
+        .arg(&replay)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Could not run handcake");
+    let start = Instant::now();
+    let metrics = loop {
+        match get_metrics(port) {
+            Some(metrics) if metrics.contains("handcake_lua_errors_total 1") => break metrics,
+            _ if start.elapsed() > Duration::from_secs(5) => break get_metrics(port).unwrap_or_default(),
+            _ => std::thread::sleep(Duration::from_millis(50)),
+        }
+    };
+    let _ = child.kill();
+    let _ = child.wait();
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert!(metrics.starts_with("HTTP/1.0 200 OK"), "{}", metrics);
+    for name in [
+        "handcake_midi_events_total{event_type=\"note_on\"} 1",
+        "handcake_lua_errors_total 1",
+        "handcake_lua_callback_duration_seconds_count 1",
+    ] {
+        assert!(metrics.contains(name), "no {} in\n{}", name, metrics);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,87 @@
+// midi.play_file sends a standard MIDI file through on_midi_recv in order,
+// and what midi.start_recording writes plays back the same way
+
+use std::{path::Path, process::{Command, Stdio}, time::Duration};
+
+// Every event on_midi_recv gets, one per line, printed once handcake stops
+const PRINT_EVENTS: &str = r#"
+local seen = {}
+function on_midi_recv(evt)
+    table.insert(seen, table.concat({ evt.device, evt.event, evt.key, evt.vel }, " "))
+end
+function on_script_exit()
+    for _, line in ipairs(seen) do
+        print(line)
+    end
+end
+"#;
+
+// Runs the script until it's had time to play the file, then stops it
+fn play(script: &Path, wait: Duration) -> Vec<String> {
+    let child = Command::new(env!("CARGO_BIN_EXE_handcake"))
+        .args(["--dry-run", "--script"])
+        .arg(script)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Could not run handcake");
+    std::thread::sleep(wait);
+    Command::new("kill").args(["-TERM", &child.id().to_string()]).status().unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).lines().map(str::to_owned).collect()
+}
+
+#[test]
+fn play_file_keeps_the_order() {
+    let script = std::env::temp_dir().join(format!("handcake-play-{}.lua", std::process::id()));
+    // Four events a quarter of a second apart, the last one using running status
+    let file = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/four_notes.mid");
+    std::fs::write(&script, format!("{}\nmidi.play_file({:?}, {{ speed = 10 }})", PRINT_EVENTS, file)).unwrap();
+
+    let seen = play(&script, Duration::from_millis(800));
+    let _ = std::fs::remove_file(&script);
+
+    assert_eq!(seen, [
+        "four_notes.mid note_on 60 100",
+        "four_notes.mid note_on 64 90",
+        "four_notes.mid note_off 60 0",
+        "four_notes.mid note_off 64 0",
+    ]);
+}
+
+#[test]
+fn recording_plays_back() {
+    let dir = std::env::temp_dir().join(format!("handcake-recording-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (replay, take, record, playback) = (dir.join("in.jsonl"), dir.join("take.mid"), dir.join("record.lua"), dir.join("play.lua"));
+    std::fs::write(&replay, [
+        r#"{"time":"2024-01-01T12:00:00.000000Z","device":"keys","data":[144,60,100]}"#,
+        r#"{"time":"2024-01-01T12:00:00.100000Z","device":"keys","data":[144,67,80]}"#,
+        r#"{"time":"2024-01-01T12:00:00.200000Z","device":"keys","data":[128,60,0]}"#,
+    ].join("\n")).unwrap();
+    std::fs::write(&record, format!(r#"
+midi.start_recording({:?})
+function on_script_exit()
+    midi.stop_recording()
+end"#, take)).unwrap();
+    std::fs::write(&playback, format!("{}\nmidi.play_file({:?}, {{ speed = 10 }})", PRINT_EVENTS, take)).unwrap();
+
+    // --replay finishes by itself
+    let recorded = Command::new(env!("CARGO_BIN_EXE_handcake"))
+        .args(["--dry-run", "--script"])
+        .arg(&record)
+        .arg("--replay")
+        .arg(&replay)
+        .output()
+        .expect("Could not run handcake");
+    assert!(recorded.status.success(), "{}", String::from_utf8_lossy(&recorded.stderr));
+    let seen = play(&playback, Duration::from_millis(500));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert_eq!(seen, [
+        "take.mid note_on 60 100",
+        "take.mid note_on 67 80",
+        "take.mid note_off 60 0",
+    ]);
+}
\ No newline at end of file
@@ -0,0 +1,49 @@
+// OSC sent to --osc-port reaches on_osc_recv with its address and arguments
+
+use std::{net::UdpSocket, process::{Command, Stdio}, time::Duration};
+use rosc::{OscMessage, OscPacket, OscType};
+
+const SCRIPT: &str = r#"
+function on_osc_recv(address, args)
+    local parts = { address }
+    for _, arg in ipairs(args) do
+        table.insert(parts, type(arg) .. "=" .. (type(arg) == "number" and string.format("%g", arg) or tostring(arg)))
+    end
+    print(table.concat(parts, " "))
+end
+"#;
+
+#[test]
+fn messages_reach_on_osc_recv() {
+    let script = std::env::temp_dir().join(format!("handcake-osc-{}.lua", std::process::id()));
+    std::fs::write(&script, SCRIPT).unwrap();
+    let port = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+    let child = Command::new(env!("CARGO_BIN_EXE_handcake"))
+        .args(["--dry-run", "--osc-port", &port.to_string(), "--script"])
+        .arg(&script)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Could not run handcake");
+    std::thread::sleep(Duration::from_millis(500));
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    for (addr, args) in [
+        ("/handcake/note_on", vec![OscType::Int(1), OscType::Int(60), OscType::Int(100)]),
+        ("/handcake/label", vec![OscType::String("pad".to_owned()), OscType::Float(0.5), OscType::Bool(true)]),
+    ] {
+        let packet = rosc::encoder::encode(&OscPacket::Message(OscMessage { addr: addr.to_owned(), args })).unwrap();
+        socket.send_to(&packet, ("127.0.0.1", port)).unwrap();
+    }
+    std::thread::sleep(Duration::from_millis(200));
+    Command::new("kill").args(["-TERM", &child.id().to_string()]).status().unwrap();
+    let output = child.wait_with_output().unwrap();
+    let _ = std::fs::remove_file(&script);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), [
+        "/handcake/note_on number=1 number=60 number=100",
+        "/handcake/label string=pad number=0.5 boolean=true",
+    ], "{}", String::from_utf8_lossy(&output.stderr));
+}
\ No newline at end of file
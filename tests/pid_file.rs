@@ -0,0 +1,32 @@
+// A second instance with the same --pid-file refuses to start while the first
+// is running, and the first removes the file when it's stopped
+
+use std::{process::{Command, Stdio}, time::Duration};
+
+#[test]
+fn second_instance_exits() {
+    let pid_file = std::env::temp_dir().join(format!("handcake-instance-{}.pid", std::process::id()));
+    let script = std::env::temp_dir().join(format!("handcake-instance-{}.lua", std::process::id()));
+    std::fs::write(&script, "function on_midi_recv(evt) end").unwrap();
+    let handcake = || {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_handcake"));
+        command.args(["--dry-run", "--script"]).arg(&script).arg("--pid-file").arg(&pid_file);
+        command
+    };
+
+    let first = handcake().stdout(Stdio::null()).stderr(Stdio::null()).spawn().expect("Could not run handcake");
+    std::thread::sleep(Duration::from_millis(500));
+    let second = handcake().output().expect("Could not run handcake");
+    let written = std::fs::read_to_string(&pid_file).unwrap_or_default();
+
+    Command::new("kill").args(["-TERM", &first.id().to_string()]).status().unwrap();
+    let first_id = first.id();
+    let output = first.wait_with_output().unwrap();
+    let _ = std::fs::remove_file(&script);
+
+    assert_eq!(second.status.code(), Some(1), "{}", String::from_utf8_lossy(&second.stderr));
+    assert!(String::from_utf8_lossy(&second.stderr).contains("already running"));
+    assert_eq!(written.trim(), first_id.to_string());
+    assert!(output.status.success());
+    assert!(!pid_file.exists(), "the first instance should have removed its PID file");
+}
\ No newline at end of file
@@ -0,0 +1,57 @@
+// --user drops root once everything is open: the script ends up running as
+// that user, can't open what only root could, and can still be reloaded
+
+use std::{os::unix::fs::PermissionsExt, process::{Command, Stdio}, time::Duration};
+
+#[test]
+fn runs_as_the_user_after_dropping_root() {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("Skipping, only root can drop privileges");
+        return;
+    }
+    let nobody = Command::new("id").args(["-u", "nobody"]).output().expect("Could not look up nobody");
+    let nobody = String::from_utf8_lossy(&nobody.stdout).trim().to_owned();
+    let name = format!("handcake-privileges-{}", std::process::id());
+    let (script, secret) = (std::env::temp_dir().join(format!("{}.lua", name)), std::env::temp_dir().join(format!("{}.secret", name)));
+    std::fs::write(&secret, "").unwrap();
+    std::fs::set_permissions(&secret, std::fs::Permissions::from_mode(0o600)).unwrap();
+    // The top level runs before the switch, the scheduled function after it
+    std::fs::write(&script, format!(r#"
+print("loaded")
+misc.schedule(200, function()
+    for line in io.lines("/proc/self/status") do
+        local euid = line:match("^Uid:%s+%d+%s+(%d+)")
+        if euid then
+            print("euid " .. euid)
+        end
+    end
+    print("opened secret", io.open({:?}) ~= nil)
+end)"#, secret)).unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+    let child = Command::new(env!("CARGO_BIN_EXE_handcake"))
+        .args(["--dry-run", "--user", "nobody", "--script"])
+        .arg(&script)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Could not run handcake");
+    std::thread::sleep(Duration::from_millis(500));
+    Command::new("kill").args(["-HUP", &child.id().to_string()]).status().unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+    Command::new("kill").args(["-TERM", &child.id().to_string()]).status().unwrap();
+    let output = child.wait_with_output().unwrap();
+    let _ = std::fs::remove_file(&script);
+    let _ = std::fs::remove_file(&secret);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let euid = format!("euid {}", nobody);
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), [
+        "loaded",
+        &euid,
+        "opened secret\tfalse",
+        "loaded",
+        &euid,
+        "opened secret\tfalse",
+    ], "{}", String::from_utf8_lossy(&output.stderr));
+}
\ No newline at end of file
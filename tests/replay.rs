@@ -0,0 +1,49 @@
+// --replay feeds a recording through the script, and --record writes what
+// came in, so replaying with --record as well copies the recording
+
+use std::process::Command;
+
+#[test]
+fn replay_reaches_the_script_and_gets_recorded() {
+    let dir = std::env::temp_dir().join(format!("handcake-replay-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (replay, record, script) = (dir.join("in.jsonl"), dir.join("out.jsonl"), dir.join("count.lua"));
+    std::fs::write(&replay, [
+        r#"{"time":"2024-01-01T12:00:00.000000Z","device":"keys","data":[144,60,100]}"#,
+        r#"{"time":"2024-01-01T12:00:00.100000Z","device":"keys","data":[128,60,0]}"#,
+        r#"{"time":"2024-01-01T12:00:00.200000Z","device":"knobs","data":[176,1,64]}"#,
+    ].join("\n")).unwrap();
+    std::fs::write(&script, r#"
+local received = 0
+function on_midi_recv(evt)
+    received = received + 1
+end
+function on_script_exit()
+    print("received " .. received)
+end"#).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_handcake"))
+        .args(["--dry-run", "--replay-speed", "10", "--script"])
+        .arg(&script)
+        .arg("--replay")
+        .arg(&replay)
+        .arg("--record")
+        .arg(&record)
+        .output()
+        .expect("Could not run handcake");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let recorded = std::fs::read_to_string(&record).unwrap_or_default();
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("received 3"), "{}", stdout);
+
+    let recorded: Vec<serde_json::Value> = recorded.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    let data: Vec<_> = recorded.iter().map(|event| (event["device"].as_str().unwrap(), event["data"].clone())).collect();
+    assert_eq!(data, [
+        ("keys", serde_json::json!([144, 60, 100])),
+        ("keys", serde_json::json!([128, 60, 0])),
+        ("knobs", serde_json::json!([176, 1, 64])),
+    ]);
+    assert!(recorded.iter().all(|event| event["time"].is_string()));
+}
\ No newline at end of file
@@ -0,0 +1,111 @@
+// However handcake stops, a script that got as far as loading still gets its
+// on_script_exit, instead of the process exiting on the spot
+
+use std::{process::{Command, Stdio}, time::{Duration, Instant}};
+
+#[test]
+fn startup_error_calls_on_script_exit() {
+    let script = std::env::temp_dir().join(format!("handcake-shutdown-{}.lua", std::process::id()));
+    std::fs::write(&script, "function on_script_exit() print(\"on_script_exit ran\") end").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_handcake"))
+        .args(["--dry-run", "--script"])
+        .arg(&script)
+        .args(["--replay", "/nonexistent/recording.jsonl"])
+        .output()
+        .expect("Could not run handcake");
+    let _ = std::fs::remove_file(&script);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "Replaying a missing file should fail");
+    assert!(stderr.contains("Could not replay"), "{}", stderr);
+    assert!(stdout.contains("on_script_exit ran"), "{}\n{}", stdout, stderr);
+}
+
+#[test]
+fn error_before_the_script_is_returned() {
+    let output = Command::new(env!("CARGO_BIN_EXE_handcake"))
+        .args(["--dry-run", "--script", "/nonexistent/script.lua"])
+        .output()
+        .expect("Could not run handcake");
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("does not exist"));
+}
+
+// A callback that never returns by itself, except for misc.check_signals()
+const BUSY_SCRIPT: &str = r#"
+misc.schedule(0, function()
+    while not misc.check_signals() do
+        misc.yield_now()
+    end
+    print("busy callback stopped")
+end)
+
+function on_script_exit()
+    print("on_script_exit ran")
+end
+"#;
+
+#[test]
+fn check_signals_lets_a_busy_callback_stop() {
+    let script = std::env::temp_dir().join(format!("handcake-busy-{}.lua", std::process::id()));
+    std::fs::write(&script, BUSY_SCRIPT).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_handcake"))
+        .args(["--dry-run", "--script"])
+        .arg(&script)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Could not run handcake");
+    // Long enough for the script to load and the callback to get going
+    std::thread::sleep(Duration::from_millis(500));
+    Command::new("kill").args(["-TERM", &child.id().to_string()]).status().unwrap();
+
+    let start = Instant::now();
+    while child.try_wait().unwrap().is_none() {
+        if start.elapsed() > Duration::from_secs(5) {
+            let _ = child.kill();
+            panic!("handcake didn't stop");
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    let output = child.wait_with_output().unwrap();
+    let _ = std::fs::remove_file(&script);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}\n{}", stdout, String::from_utf8_lossy(&output.stderr));
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), ["busy callback stopped", "on_script_exit ran"]);
+}
+
+#[test]
+fn grabs_are_released_on_exit() {
+    let device = std::env::temp_dir().join(format!("handcake-grab-{}", std::process::id()));
+    std::fs::write(&device, "").unwrap();
+    let script = std::env::temp_dir().join(format!("handcake-grab-{}.lua", std::process::id()));
+    std::fs::write(&script, format!("function on_script_init() evdev.grab({:?}) end", device.to_string_lossy())).unwrap();
+
+    let child = Command::new(env!("CARGO_BIN_EXE_handcake"))
+        .args(["--dry-run", "--script"])
+        .arg(&script)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Could not run handcake");
+    std::thread::sleep(Duration::from_millis(500));
+    Command::new("kill").args(["-TERM", &child.id().to_string()]).status().unwrap();
+    let output = child.wait_with_output().unwrap();
+    let _ = std::fs::remove_file(&script);
+    let _ = std::fs::remove_file(&device);
+
+    // With --dry-run, every grab and release is a line of JSON
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let grabs: Vec<serde_json::Value> = stdout.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    let device = device.to_string_lossy();
+    assert_eq!(grabs, [
+        serde_json::json!({ "device": device, "grab": true }),
+        serde_json::json!({ "device": device, "grab": false }),
+    ], "{}", String::from_utf8_lossy(&output.stderr));
+}
\ No newline at end of file
@@ -0,0 +1,57 @@
+// state.save() values outlive a reload (SIGHUP here), and only plain data can
+// be saved, not functions or userdata
+
+use std::{process::{Command, Stdio}, time::Duration};
+
+const SCRIPT: &str = r#"
+local saved = state.load("settings")
+if saved then
+    print("loaded", saved.layer, saved.pads[2], saved.nested.on)
+else
+    state.save("settings", { layer = 2, pads = { 36, 38 }, nested = { on = true } })
+    print("saved")
+end
+local ok = pcall(state.save, "k", print)
+print("function saved", ok)
+ok = pcall(state.save, "k", { f = print })
+print("table with a function saved", ok)
+ok = pcall(state.save, "k", buffer.new(4))
+print("userdata saved", ok)
+"#;
+
+#[test]
+fn saved_state_survives_reload() {
+    let name = format!("handcake-state-{}", std::process::id());
+    let script = std::env::temp_dir().join(format!("{}.lua", name));
+    // Where state.rs keeps it, named after the script
+    let state_file = std::env::temp_dir().join(format!("handcake-{}.state.json", name));
+    let _ = std::fs::remove_file(&state_file);
+    std::fs::write(&script, SCRIPT).unwrap();
+
+    let child = Command::new(env!("CARGO_BIN_EXE_handcake"))
+        .args(["--dry-run", "--script"])
+        .arg(&script)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Could not run handcake");
+    std::thread::sleep(Duration::from_millis(500));
+    Command::new("kill").args(["-HUP", &child.id().to_string()]).status().unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+    Command::new("kill").args(["-TERM", &child.id().to_string()]).status().unwrap();
+    let output = child.wait_with_output().unwrap();
+    let _ = std::fs::remove_file(&script);
+    let _ = std::fs::remove_file(&state_file);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), [
+        "saved",
+        "function saved\tfalse",
+        "table with a function saved\tfalse",
+        "userdata saved\tfalse",
+        "loaded\t2\t38\ttrue",
+        "function saved\tfalse",
+        "table with a function saved\tfalse",
+        "userdata saved\tfalse",
+    ], "{}", String::from_utf8_lossy(&output.stderr));
+}
\ No newline at end of file
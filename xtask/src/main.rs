@@ -0,0 +1,54 @@
+//! Development tasks, run with `cargo xtask <task>`
+
+use std::{path::PathBuf, process::{Command, ExitCode}};
+
+const USAGE: &str = "Usage: cargo xtask api-schema [--check]
+
+api-schema    Write the scripting API to api_schema.json
+    --check   Only check that api_schema.json is up to date";
+
+fn workspace_root() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).parent().unwrap().to_path_buf()
+}
+
+fn api_schema(check: bool) -> Result<(), String> {
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_owned());
+    let output = Command::new(cargo)
+        .args(["run", "--quiet", "--package", "handcake", "--", "--api-schema"])
+        .current_dir(workspace_root())
+        .output()
+        .map_err(|e| format!("Could not run handcake: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("handcake --api-schema failed:\n{}", String::from_utf8_lossy(&output.stderr)));
+    }
+
+    let path = workspace_root().join("api_schema.json");
+    if check {
+        let current = std::fs::read(&path).unwrap_or_default();
+        if current != output.stdout {
+            return Err("api_schema.json is out of date, run cargo xtask api-schema".to_owned());
+        }
+        return Ok(());
+    }
+    std::fs::write(&path, &output.stdout).map_err(|e| format!("Could not write {:?}: {}", path, e))?;
+    println!("Wrote {:?}", path);
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["api-schema"] => api_schema(false),
+        ["api-schema", "--check"] => api_schema(true),
+        _ => Err(USAGE.to_owned()),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        },
+    }
+}
\ No newline at end of file